@@ -0,0 +1,47 @@
+use std::{env, sync::Mutex};
+
+use lazy_static::lazy_static;
+
+// the number of errors kept until the next message, the newest ones being dropped
+const MAX_PENDING_ALERTS: usize = 20;
+
+// extracts ADMIN_CHAT_ID from std::env
+// errors aren't reported if the variable is not set or empty
+fn get_admin_chat_id() -> Option<i64> {
+    env::var("ADMIN_CHAT_ID")
+        .ok()
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| id.parse::<i64>().expect("ADMIN_CHAT_ID is not a number"))
+}
+
+lazy_static! {
+    // the chat errors are reported to
+    static ref ADMIN_CHAT_ID: Option<i64> = get_admin_chat_id();
+    // errors waiting to be sent, along with the number of the ones that didn't fit
+    static ref PENDING: Mutex<(Vec<String>, usize)> = Mutex::new((Vec::new(), 0));
+}
+
+// queues an error to be sent to the admin chat (see take)
+pub fn report(err: &str) {
+    if ADMIN_CHAT_ID.is_none() {
+        return;
+    }
+
+    let mut pending = PENDING.lock().unwrap();
+    if pending.0.len() < MAX_PENDING_ALERTS {
+        pending.0.push(err.to_string());
+    } else {
+        pending.1 += 1;
+    }
+}
+
+// returns the chat errors are reported to, None if they aren't reported
+pub fn admin_chat_id() -> Option<i64> {
+    *ADMIN_CHAT_ID
+}
+
+// takes the queued errors, along with the number of the ones that didn't fit
+pub fn take() -> (Vec<String>, usize) {
+    let mut pending = PENDING.lock().unwrap();
+    (pending.0.split_off(0), std::mem::replace(&mut pending.1, 0))
+}
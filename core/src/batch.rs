@@ -0,0 +1,171 @@
+use crate::chain_wrapper::ChainWrapper;
+use crate::{alerts, chat_key, storage, utils, wal};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as SyncMutex},
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+
+// the number of buffered messages that gets them fed right away
+const BATCH_SIZE: usize = 50;
+
+// the longest time a message waits in the buffer before being fed
+const BATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// a message waiting to be fed into its chat's chain
+pub struct Incoming {
+    pub user_id: i64,
+    pub username: String,
+    // wide enough for the IDs of every platform, e.g. Discord's 64-bit snowflakes
+    pub message_id: i64,
+    pub is_forwarded: bool,
+    // set for the new text of an edited message, which replaces what was learnt from it
+    pub is_edit: bool,
+    pub text: String,
+}
+
+// messages of a chat waiting to be fed, each with the sequence number of its write-ahead log entry
+pub type Batch = Vec<(u64, Incoming)>;
+
+// lines shared with the global chain, each with the sequence number of its write-ahead log entry
+// and the ID of its author
+pub type SharedLines = Vec<(u64, i64, String)>;
+
+lazy_static! {
+    // buffered messages grouped by chat, each with the sequence number of its write-ahead log entry,
+    // along with the number of messages buffered since the last flush
+    static ref PENDING: SyncMutex<(HashMap<i64, Batch>, usize)> =
+        SyncMutex::new((HashMap::new(), 0));
+    // lines shared with the global chain that couldn't be fed yet, each with its write-ahead log entry
+    // they're fed with the next batch, ahead of the lines shared meanwhile
+    static ref SHARED: SyncMutex<SharedLines> = SyncMutex::new(Vec::new());
+    // held while a batch is being fed
+    static ref FLUSH_LOCK: Mutex<()> = Mutex::new(());
+    // held while a message is logged and buffered, so that messages are buffered in the order they were logged
+    static ref LOG_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// buffers a message sent in a specified chat
+// the message is written to the write-ahead log first, so that a crash doesn't lose the buffer
+// feeds the whole buffer once it holds BATCH_SIZE messages
+pub async fn feed(chain: &Mutex<ChainWrapper>, chat_id: i64, incoming: Incoming) {
+    let is_full = {
+        let _guard = LOG_LOCK.lock().await;
+
+        // the log is written on the blocking thread pool, the buffer is only locked to add the message
+        let (seq, incoming) = if storage::is_persistent() {
+            utils::run_blocking(move || (wal::append_message(chat_id, &incoming), incoming)).await
+        } else {
+            (0, incoming)
+        };
+
+        let mut pending = PENDING.lock().unwrap();
+        pending.0.entry(chat_id).or_default().push((seq, incoming));
+        pending.1 += 1;
+        pending.1 >= BATCH_SIZE
+    };
+
+    if is_full {
+        flush(chain).await;
+    }
+}
+
+// feeds all the buffered messages into their chains, taking the lock only once
+// flushes run one at a time, so that batches are fed in order
+pub async fn flush(chain: &Mutex<ChainWrapper>) {
+    let _guard = FLUSH_LOCK.lock().await;
+
+    let batches = {
+        let mut pending = PENDING.lock().unwrap();
+        if pending.0.is_empty() {
+            return;
+        }
+
+        pending.1 = 0;
+        std::mem::take(&mut pending.0)
+    };
+
+    // the chains are loaded without holding the lock, see ChainWrapper::lock_loaded
+    let chat_ids: Vec<i64> = batches.keys().cloned().collect();
+    let mut shared = Vec::new();
+    let mut unfed = Vec::new();
+    {
+        let mut chain = ChainWrapper::lock_loaded(chain, &chat_ids).await;
+        for (chat_id, messages) in batches {
+            match chain.feed_batch(chat_id, messages) {
+                Ok(lines) => shared.extend(lines),
+                Err(messages) => unfed.push((chat_id, messages)),
+            }
+        }
+    }
+
+    // chains that couldn't be loaded get their messages back in front of the ones buffered meanwhile
+    // they don't count towards BATCH_SIZE, so that a storage outage doesn't make every message flush
+    if !unfed.is_empty() {
+        let mut pending = PENDING.lock().unwrap();
+        for (chat_id, mut messages) in unfed {
+            let newer = pending.0.entry(chat_id).or_default();
+            messages.append(newer);
+            *newer = messages;
+        }
+    }
+
+    // the shared lines are logged on the blocking thread pool too, before the global chain is locked
+    let logged: SharedLines = if storage::is_persistent() {
+        utils::run_blocking(move || {
+            shared
+                .into_iter()
+                .map(|(user_id, line)| {
+                    (wal::append(chat_key::GLOBAL, user_id, &line), user_id, line)
+                })
+                .collect()
+        })
+        .await
+    } else {
+        shared
+            .into_iter()
+            .map(|(user_id, line)| (0, user_id, line))
+            .collect()
+    };
+
+    let mut lines = std::mem::take(&mut *SHARED.lock().unwrap());
+    let is_retry = !lines.is_empty();
+    lines.extend(logged);
+    if lines.is_empty() {
+        return;
+    }
+
+    let res = ChainWrapper::lock_loaded(chain, &[chat_key::GLOBAL])
+        .await
+        .feed_global(lines);
+
+    if let Err((err, lines)) = res {
+        // the outage is reported once, rather than with every batch
+        if !is_retry {
+            let err = format!(
+                "Feeding the global chain failed, {} lines wait for the next batch: {}",
+                lines.len(),
+                err
+            );
+            alerts::report(&err);
+            dbg!(err);
+        }
+        *SHARED.lock().unwrap() = lines;
+    }
+}
+
+// spawns a task that feeds the buffered messages every BATCH_INTERVAL
+pub fn spawn(chain: Arc<Mutex<ChainWrapper>>) {
+    let mut interval = tokio::time::interval(BATCH_INTERVAL);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            flush(&chain).await;
+        }
+    });
+}
@@ -0,0 +1,298 @@
+use std::{convert::TryInto, env};
+
+use aes_gcm::{
+    aead::{consts::U12, Aead, NewAead, Nonce},
+    Aes256Gcm,
+};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+// a header marking zstd-compressed blobs
+// blobs without it are treated as raw bincode written by older versions
+const COMPRESSED_MAGIC: &[u8] = b"MKVZ";
+
+// a header marking AES-GCM encrypted blobs
+const ENCRYPTED_MAGIC: &[u8] = b"MKVE";
+
+// a header marking blobs tagged with a schema version
+// blobs without it are treated as version 0
+const VERSIONED_MAGIC: &[u8] = b"MKVV";
+
+// a header marking blobs prefixed with a SHA-256 checksum
+const CHECKSUM_MAGIC: &[u8] = b"MKVS";
+
+// the length of a SHA-256 digest in bytes
+const DIGEST_LEN: usize = 32;
+
+// the length of an AES-GCM nonce in bytes
+const NONCE_LEN: usize = 12;
+
+// extracts COMPRESSION_LEVEL from std::env and returns a zstd level
+// defaults to zstd's own default if the variable is not set
+fn get_compression_level() -> i32 {
+    match env::var("COMPRESSION_LEVEL") {
+        Err(_) => zstd::DEFAULT_COMPRESSION_LEVEL,
+        Ok(level) => level
+            .parse::<i32>()
+            .expect("COMPRESSION_LEVEL is not a number"),
+    }
+}
+
+// extracts ENCRYPTION_KEY from std::env and returns a cipher
+// encryption is disabled if the variable is not set or empty
+fn get_cipher() -> Option<Aes256Gcm> {
+    match env::var("ENCRYPTION_KEY") {
+        Err(_) => None,
+        Ok(ref key_b64) if key_b64.trim().is_empty() => None,
+        Ok(key_b64) => {
+            let key = base64::decode(key_b64.trim()).expect("Failed to decode ENCRYPTION_KEY");
+            match Aes256Gcm::new_varkey(&key) {
+                Ok(cipher) => Some(cipher),
+                Err(_) => panic!("ENCRYPTION_KEY must be exactly 32 bytes long"),
+            }
+        }
+    }
+}
+
+lazy_static! {
+    // the zstd compression level used for uploaded blobs
+    static ref COMPRESSION_LEVEL: i32 = get_compression_level();
+    // the cipher used to encrypt uploaded blobs, if any
+    static ref CIPHER: Option<Aes256Gcm> = get_cipher();
+}
+
+// compresses a binary blob and prepends a magic header
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut res = COMPRESSED_MAGIC.to_vec();
+    let compressed = zstd::encode_all(bytes, *COMPRESSION_LEVEL).expect("Compression failed");
+    res.extend(compressed);
+    res
+}
+
+// decompresses a binary blob if it starts with a magic header
+// returns the blob unchanged otherwise
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    if bytes.starts_with(COMPRESSED_MAGIC) {
+        zstd::decode_all(&bytes[COMPRESSED_MAGIC.len()..])
+            .map_err(|e| format!("Decompression failed: {}", e))
+    } else {
+        Ok(bytes)
+    }
+}
+
+// encrypts a binary blob and prepends a magic header and a random nonce
+// returns the blob unchanged if no key is configured
+pub fn encrypt(bytes: Vec<u8>) -> Vec<u8> {
+    match CIPHER.as_ref() {
+        None => bytes,
+        Some(cipher) => encrypt_with(cipher, &bytes),
+    }
+}
+
+// encrypts a binary blob with a given cipher, see encrypt
+fn encrypt_with(cipher: &Aes256Gcm, bytes: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let encrypted = cipher
+        .encrypt(&Nonce::<U12>::from(nonce), bytes)
+        .expect("Encryption failed");
+
+    let mut res = ENCRYPTED_MAGIC.to_vec();
+    res.extend_from_slice(&nonce);
+    res.extend(encrypted);
+    res
+}
+
+// decrypts a binary blob if it starts with a magic header
+// returns the blob unchanged otherwise
+pub fn decrypt(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    decrypt_with(CIPHER.as_ref(), bytes)
+}
+
+// decrypts a binary blob with a given cipher, if any, see decrypt
+fn decrypt_with(cipher: Option<&Aes256Gcm>, bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !bytes.starts_with(ENCRYPTED_MAGIC) {
+        return Ok(bytes);
+    }
+
+    let cipher = match cipher {
+        Some(cipher) => cipher,
+        None => return Err("Blob is encrypted, but ENCRYPTION_KEY is not set".to_string()),
+    };
+
+    let body = &bytes[ENCRYPTED_MAGIC.len()..];
+    if body.len() < NONCE_LEN {
+        return Err("Encrypted blob is truncated".to_string());
+    }
+
+    let (nonce, encrypted) = body.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("Nonce has the wrong length");
+    cipher
+        .decrypt(&Nonce::<U12>::from(nonce), encrypted)
+        .map_err(|_| "Decryption failed".to_string())
+}
+
+// prepends a magic header and a schema version to serialized data
+pub fn tag_version(version: u32, bytes: &[u8]) -> Vec<u8> {
+    let mut res = VERSIONED_MAGIC.to_vec();
+    res.extend_from_slice(&version.to_le_bytes());
+    res.extend_from_slice(bytes);
+    res
+}
+
+// splits serialized data into its schema version and the actual payload
+pub fn read_version(bytes: &[u8]) -> (u32, &[u8]) {
+    let header_len = VERSIONED_MAGIC.len() + 4;
+
+    if bytes.starts_with(VERSIONED_MAGIC) && bytes.len() >= header_len {
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&bytes[VERSIONED_MAGIC.len()..header_len]);
+        (u32::from_le_bytes(version), &bytes[header_len..])
+    } else {
+        (0, bytes)
+    }
+}
+
+// prepends a magic header and a SHA-256 checksum of the contents
+pub fn add_checksum(bytes: &[u8]) -> Vec<u8> {
+    let mut res = CHECKSUM_MAGIC.to_vec();
+    res.extend_from_slice(&Sha256::digest(bytes));
+    res.extend_from_slice(bytes);
+    res
+}
+
+// verifies and strips the checksum of a blob if it starts with a magic header
+// returns the blob unchanged otherwise
+pub fn verify_checksum(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !bytes.starts_with(CHECKSUM_MAGIC) {
+        return Ok(bytes);
+    }
+
+    let body = &bytes[CHECKSUM_MAGIC.len()..];
+    if body.len() < DIGEST_LEN {
+        return Err("Checksummed blob is truncated".to_string());
+    }
+
+    let (digest, contents) = body.split_at(DIGEST_LEN);
+    if Sha256::digest(contents).as_slice() == digest {
+        Ok(contents.to_vec())
+    } else {
+        Err("Checksum mismatch".to_string())
+    }
+}
+
+// turns serialized data into a blob ready to be uploaded
+pub fn pack(bytes: &[u8]) -> Vec<u8> {
+    encrypt(compress(bytes))
+}
+
+// turns a downloaded blob back into serialized data
+pub fn unpack(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    decrypt(bytes).and_then(decompress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(key: u8) -> Aes256Gcm {
+        Aes256Gcm::new_varkey(&[key; 32]).unwrap()
+    }
+
+    #[test]
+    fn compression_round_trips() {
+        let bytes = b"the quick brown fox jumps over the lazy dog".repeat(10);
+        let compressed = compress(&bytes);
+
+        assert!(compressed.starts_with(COMPRESSED_MAGIC));
+        assert_eq!(decompress(compressed), Ok(bytes));
+    }
+
+    #[test]
+    fn uncompressed_blobs_are_left_alone() {
+        let bytes = b"raw bincode".to_vec();
+        assert_eq!(decompress(bytes.clone()), Ok(bytes));
+    }
+
+    #[test]
+    fn corrupted_compressed_blobs_are_rejected() {
+        let mut bytes = COMPRESSED_MAGIC.to_vec();
+        bytes.extend_from_slice(b"not zstd");
+        assert!(decompress(bytes).is_err());
+    }
+
+    #[test]
+    fn encryption_round_trips() {
+        let bytes = b"secret chain".to_vec();
+        let encrypted = encrypt_with(&cipher(1), &bytes);
+
+        assert!(encrypted.starts_with(ENCRYPTED_MAGIC));
+        assert_ne!(&encrypted[ENCRYPTED_MAGIC.len() + NONCE_LEN..], &bytes[..]);
+        assert_eq!(decrypt_with(Some(&cipher(1)), encrypted), Ok(bytes));
+    }
+
+    #[test]
+    fn encrypted_blobs_need_the_right_key() {
+        let encrypted = encrypt_with(&cipher(1), b"secret chain");
+
+        assert!(decrypt_with(Some(&cipher(2)), encrypted.clone()).is_err());
+        assert!(decrypt_with(None, encrypted).is_err());
+    }
+
+    #[test]
+    fn truncated_encrypted_blobs_are_rejected() {
+        let mut bytes = ENCRYPTED_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0; NONCE_LEN - 1]);
+        assert!(decrypt_with(Some(&cipher(1)), bytes).is_err());
+    }
+
+    #[test]
+    fn unencrypted_blobs_are_left_alone() {
+        let bytes = b"plain chain".to_vec();
+        assert_eq!(decrypt_with(Some(&cipher(1)), bytes.clone()), Ok(bytes));
+    }
+
+    #[test]
+    fn versions_round_trip() {
+        let tagged = tag_version(9, b"payload");
+        assert_eq!(read_version(&tagged), (9, &b"payload"[..]));
+    }
+
+    #[test]
+    fn untagged_blobs_are_version_0() {
+        assert_eq!(read_version(b"payload"), (0, &b"payload"[..]));
+        // a header without the version is treated as payload
+        assert_eq!(read_version(VERSIONED_MAGIC), (0, VERSIONED_MAGIC));
+    }
+
+    #[test]
+    fn checksums_round_trip() {
+        let bytes = b"stored chain".to_vec();
+        assert_eq!(verify_checksum(add_checksum(&bytes)), Ok(bytes));
+    }
+
+    #[test]
+    fn checksums_catch_corruption() {
+        let mut checksummed = add_checksum(b"stored chain");
+        let last = checksummed.len() - 1;
+        checksummed[last] ^= 1;
+        assert!(verify_checksum(checksummed).is_err());
+
+        let truncated = add_checksum(b"")[..CHECKSUM_MAGIC.len() + DIGEST_LEN - 1].to_vec();
+        assert!(verify_checksum(truncated).is_err());
+    }
+
+    #[test]
+    fn blobs_without_checksums_are_left_alone() {
+        let bytes = b"old blob".to_vec();
+        assert_eq!(verify_checksum(bytes.clone()), Ok(bytes));
+    }
+
+    #[test]
+    fn packing_round_trips() {
+        let bytes = tag_version(3, b"serialized chain");
+        assert_eq!(unpack(pack(&bytes)), Ok(bytes));
+    }
+}
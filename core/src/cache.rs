@@ -0,0 +1,90 @@
+use crate::utils;
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+
+// extracts BLOB_CACHE_DIR from std::env
+// returns None (meaning nothing is cached) if the variable is not set or empty
+fn get_cache_dir() -> Option<String> {
+    env::var("BLOB_CACHE_DIR")
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+}
+
+lazy_static! {
+    // the directory holding copies of downloaded files, if enabled
+    static ref CACHE_DIR: Option<String> = get_cache_dir();
+    // guards cached files against concurrent writes
+    static ref CACHE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// checks if downloaded files are cached
+pub fn is_enabled() -> bool {
+    CACHE_DIR.is_some()
+}
+
+// returns the common prefix of the cached copies of a file
+// names are hex-encoded, as they may contain characters that aren't allowed in paths
+fn prefix(name: &str) -> String {
+    format!("{}-", utils::hex_encode(name))
+}
+
+// returns the path a revision of a file is cached at
+fn path(dir: &str, name: &str, revision: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}{}", prefix(name), utils::hex_encode(revision)))
+}
+
+// returns the cached copy of a given revision of a file, if there is one
+pub fn load(name: &str, revision: &str) -> Option<Vec<u8>> {
+    let dir = CACHE_DIR.as_ref()?;
+    let _guard = CACHE_LOCK.lock().unwrap();
+
+    fs::read(path(dir, name, revision)).ok()
+}
+
+// caches a given revision of a file, replacing the copies of its older revisions
+pub fn store(name: &str, revision: &str, bytes: &[u8]) {
+    let dir = match CACHE_DIR.as_ref() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let _guard = CACHE_LOCK.lock().unwrap();
+
+    remove_copies(dir, name);
+
+    let res = fs::create_dir_all(dir).and_then(|_| fs::write(path(dir, name, revision), bytes));
+    if let Err(err) = res {
+        dbg!(err);
+    }
+}
+
+// forgets all the cached copies of a file, once it's been deleted
+pub fn remove(name: &str) {
+    let dir = match CACHE_DIR.as_ref() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let _guard = CACHE_LOCK.lock().unwrap();
+
+    remove_copies(dir, name);
+}
+
+// deletes the cached copies of a file, expects the lock to be held
+fn remove_copies(dir: &str, name: &str) {
+    let prefix = prefix(name);
+
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                if let Err(err) = fs::remove_file(entry.path()) {
+                    dbg!(err);
+                }
+            }
+        }
+    }
+}
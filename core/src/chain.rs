@@ -0,0 +1,900 @@
+use crate::tokenizer;
+
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+// the token ID marking the start or the end of a phrase
+const BOUNDARY: u32 = 0;
+
+// the longest phrase that can be generated, in words
+// guards against cycles in large chains
+pub const MAX_WORDS: usize = 100;
+
+// a Markov chain of words
+// every distinct word is stored once and referred to by its ID
+// states (the last `order` words) point to possible next words along with their weights
+#[derive(Deserialize)]
+#[serde(from = "ChainData")]
+pub struct Chain {
+    order: usize,
+    // interned words, indexed by their IDs (the first one stands for BOUNDARY)
+    tokens: Vec<String>,
+    // IDs of interned words, rebuilt after deserialization
+    ids: HashMap<String, u32>,
+    // weighted transitions from a state to the next word
+    edges: HashMap<Vec<u32>, Vec<(u32, u32)>>,
+    // the number of times each word was learnt, indexed by IDs and rebuilt after deserialization
+    counts: Vec<u32>,
+}
+
+// the serialized form of a Chain, without the derivable lookup table
+#[derive(Serialize, Deserialize)]
+struct ChainData {
+    order: usize,
+    tokens: Vec<String>,
+    edges: HashMap<Vec<u32>, Vec<(u32, u32)>>,
+}
+
+// a borrowed ChainData, so that serialization doesn't need to copy the chain
+#[derive(Serialize)]
+struct ChainDataRef<'a> {
+    order: usize,
+    tokens: &'a [String],
+    edges: &'a HashMap<Vec<u32>, Vec<(u32, u32)>>,
+}
+
+impl From<ChainData> for Chain {
+    fn from(data: ChainData) -> Chain {
+        let ids = data
+            .tokens
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(id, token)| (token.clone(), id as u32))
+            .collect();
+
+        let mut chain = Chain {
+            order: data.order,
+            tokens: data.tokens,
+            ids,
+            edges: data.edges,
+            counts: Vec::new(),
+        };
+        chain.recount();
+        chain
+    }
+}
+
+impl Serialize for Chain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ChainDataRef {
+            order: self.order,
+            tokens: &self.tokens,
+            edges: &self.edges,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Chain {
+    // creates an empty chain of a given order
+    pub fn of_order(order: usize) -> Chain {
+        assert!(order > 0, "Chain order must be positive");
+
+        Chain {
+            order,
+            tokens: vec![String::new()],
+            ids: HashMap::new(),
+            edges: HashMap::new(),
+            counts: vec![0],
+        }
+    }
+
+    // returns the number of preceding words the next word depends on
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    // checks if the chain has learnt anything
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    // returns the ID of a word, interning it if necessary
+    fn intern(&mut self, token: &str) -> u32 {
+        if let Some(id) = self.ids.get(token) {
+            return *id;
+        }
+
+        let id = self.tokens.len() as u32;
+        self.tokens.push(token.to_string());
+        self.ids.insert(token.to_string(), id);
+        self.counts.push(0);
+        id
+    }
+
+    // converts an optional word (None being a phrase boundary) to an ID
+    fn intern_token(&mut self, token: &Option<String>) -> u32 {
+        match token {
+            Some(token) => self.intern(token),
+            None => BOUNDARY,
+        }
+    }
+
+    // converts an ID to an optional word (None being a phrase boundary)
+    fn token(&self, id: u32) -> Option<String> {
+        if id == BOUNDARY {
+            None
+        } else {
+            Some(self.tokens[id as usize].clone())
+        }
+    }
+
+    // returns the IDs of a line's words (possibly read backwards) surrounded by phrase boundaries
+    fn line_ids(&mut self, line: &str, is_reversed: bool) -> Vec<u32> {
+        let mut words = tokenizer::tokenize(line);
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        if is_reversed {
+            words.reverse();
+        }
+
+        let mut ids = vec![BOUNDARY; self.order];
+        ids.extend(words.iter().map(|word| self.intern(word)));
+        ids.push(BOUNDARY);
+        ids
+    }
+
+    // adds a weight to a single transition
+    fn add_edge(&mut self, state: &[u32], next: u32, weight: u32) {
+        let targets = self.edges.entry(state.to_vec()).or_default();
+
+        match targets.iter_mut().find(|(id, _)| *id == next) {
+            Some((_, w)) => *w = w.saturating_add(weight),
+            None => targets.push((next, weight)),
+        }
+
+        // every occurrence of a word is the target of exactly one transition
+        if next != BOUNDARY {
+            let count = &mut self.counts[next as usize];
+            *count = count.saturating_add(weight);
+        }
+    }
+
+    // takes a weight away from a single transition, removing it once it reaches zero
+    fn remove_edge(&mut self, state: &[u32], next: u32, weight: u32) {
+        if let Some(targets) = self.edges.get_mut(state) {
+            if let Some((_, w)) = targets.iter_mut().find(|(id, _)| *id == next) {
+                let removed = weight.min(*w);
+                *w -= removed;
+
+                if next != BOUNDARY {
+                    let count = &mut self.counts[next as usize];
+                    *count = count.saturating_sub(removed);
+                }
+            }
+
+            targets.retain(|(_, w)| *w > 0);
+            if targets.is_empty() {
+                self.edges.remove(state);
+            }
+        }
+    }
+
+    // learns the transitions of a line's IDs
+    fn feed_ids(&mut self, ids: &[u32]) {
+        for window in ids.windows(self.order + 1) {
+            self.add_edge(&window[..self.order], window[self.order], 1);
+        }
+    }
+
+    // forgets the transitions of a line's IDs
+    fn unfeed_ids(&mut self, ids: &[u32]) {
+        for window in ids.windows(self.order + 1) {
+            self.remove_edge(&window[..self.order], window[self.order], 1);
+        }
+    }
+
+    // learns a single line of words
+    pub fn feed_str(&mut self, line: &str) {
+        let ids = self.line_ids(line, false);
+        self.feed_ids(&ids);
+    }
+
+    // undoes feeding a single line
+    pub fn unfeed_str(&mut self, line: &str) {
+        let ids = self.line_ids(line, false);
+        self.unfeed_ids(&ids);
+    }
+
+    // learns a single line read backwards, used by reverse chains (see reversed)
+    pub fn feed_str_reversed(&mut self, line: &str) {
+        let ids = self.line_ids(line, true);
+        self.feed_ids(&ids);
+    }
+
+    // undoes feeding a single line read backwards
+    pub fn unfeed_str_reversed(&mut self, line: &str) {
+        let ids = self.line_ids(line, true);
+        self.unfeed_ids(&ids);
+    }
+
+    // returns every transition as (state, next word, weight)
+    // None stands for a phrase boundary
+    pub fn transitions(&self) -> Vec<(Vec<Option<String>>, Option<String>, u32)> {
+        self.edges
+            .iter()
+            .flat_map(|(state, targets)| {
+                let from: Vec<Option<String>> = state.iter().map(|id| self.token(*id)).collect();
+                targets
+                    .iter()
+                    .map(move |(next, weight)| (from.clone(), self.token(*next), *weight))
+            })
+            .collect()
+    }
+
+    // adds a weight to a single transition given in the form returned by transitions
+    pub fn add_transition(&mut self, from: &[Option<String>], to: &Option<String>, weight: u32) {
+        let state: Vec<u32> = from.iter().map(|token| self.intern_token(token)).collect();
+        let next = self.intern_token(to);
+        self.add_edge(&state, next, weight);
+    }
+
+    // recomputes the number of times each word was learnt from the transitions
+    fn recount(&mut self) {
+        let mut counts = vec![0u32; self.tokens.len()];
+        for targets in self.edges.values() {
+            for (next, weight) in targets.iter().filter(|(next, _)| *next != BOUNDARY) {
+                let count = &mut counts[*next as usize];
+                *count = count.saturating_add(*weight);
+            }
+        }
+        self.counts = counts;
+    }
+
+    // returns the number of times a word was learnt
+    pub fn frequency(&self, token: &str) -> u32 {
+        match self.ids.get(token) {
+            Some(id) => self.counts[*id as usize],
+            None => 0,
+        }
+    }
+
+    // returns every word along with the number of times it was learnt
+    pub fn frequencies(&self) -> Vec<(&str, u32)> {
+        self.tokens
+            .iter()
+            .zip(self.counts.iter())
+            .skip(1)
+            .map(|(token, count)| (token.as_str(), *count))
+            .collect()
+    }
+
+    // identifies a transition by its words, so that it can be recognized after words are renumbered
+    fn fingerprint(&self, state: &[u32], next: u32) -> u64 {
+        let mut hasher = Sha256::new();
+        for id in state.iter().chain(std::iter::once(&next)) {
+            hasher.input(self.tokens[*id as usize].as_bytes());
+            // never a part of valid UTF-8, so words can't be mistaken for separators
+            hasher.input([0xff]);
+        }
+
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hasher.result()[..8]);
+        u64::from_be_bytes(bytes)
+    }
+
+    // returns the fingerprints of the transitions learnt only once
+    pub fn single_transitions(&self) -> HashSet<u64> {
+        self.edges
+            .iter()
+            .flat_map(|(state, targets)| {
+                targets
+                    .iter()
+                    .filter(|(_, weight)| *weight == 1)
+                    .map(move |(next, _)| self.fingerprint(state, *next))
+            })
+            .collect()
+    }
+
+    // removes the transitions learnt only once among the given ones (see single_transitions)
+    // words that are no longer referenced are dropped as well
+    // returns the number of removed transitions
+    pub fn remove_single_transitions(&mut self, fingerprints: &HashSet<u64>) -> usize {
+        let mut removed = Vec::new();
+        for (state, targets) in self.edges.iter() {
+            for (next, weight) in targets.iter() {
+                if *weight == 1 && fingerprints.contains(&self.fingerprint(state, *next)) {
+                    removed.push((state.clone(), *next));
+                }
+            }
+        }
+
+        for (state, next) in removed.iter() {
+            self.remove_edge(state, *next, 1);
+        }
+
+        if !removed.is_empty() {
+            self.drop_unused_tokens();
+        }
+        removed.len()
+    }
+
+    // returns the words that follow a given word, along with the total weights of the transitions
+    // None stands for a phrase boundary
+    pub fn followers(&self, token: &str) -> Vec<(Option<String>, u64)> {
+        let id = match self.ids.get(token) {
+            Some(id) => *id,
+            None => return Vec::new(),
+        };
+
+        let mut weights: HashMap<u32, u64> = HashMap::new();
+        for (state, targets) in self.edges.iter() {
+            if state.last() == Some(&id) {
+                for (next, weight) in targets.iter() {
+                    *weights.entry(*next).or_insert(0) += u64::from(*weight);
+                }
+            }
+        }
+
+        weights
+            .into_iter()
+            .map(|(next, weight)| (self.token(next), weight))
+            .collect()
+    }
+
+    // returns the transitions between single words, adding up the weights of all states ending with
+    // the same word, as (word, next word, weight)
+    // None stands for the start of a phrase as the first word, and for its end as the next one
+    pub fn word_transitions(&self) -> Vec<(Option<String>, Option<String>, u64)> {
+        let mut weights: HashMap<(u32, u32), u64> = HashMap::new();
+        for (state, targets) in self.edges.iter() {
+            let last = *state.last().unwrap_or(&BOUNDARY);
+            for (next, weight) in targets.iter() {
+                *weights.entry((last, *next)).or_insert(0) += u64::from(*weight);
+            }
+        }
+
+        weights
+            .into_iter()
+            .map(|((from, to), weight)| (self.token(from), self.token(to), weight))
+            .collect()
+    }
+
+    // estimates the memory taken by the chain, in bytes
+    // counts the heap allocations of words, states and transitions along with rough overheads
+    pub fn estimated_size(&self) -> usize {
+        // a String (or Vec) header plus a hash table slot
+        const ENTRY_OVERHEAD: usize = 24 + 16;
+
+        // every word is kept twice, in `tokens` and as a key of `ids`, along with its count
+        let tokens: usize = self
+            .tokens
+            .iter()
+            .map(|token| 2 * (token.len() + ENTRY_OVERHEAD) + 4)
+            .sum();
+
+        let edges: usize = self
+            .edges
+            .iter()
+            .map(|(state, targets)| state.len() * 4 + targets.capacity() * 8 + 2 * ENTRY_OVERHEAD)
+            .sum();
+
+        tokens + edges
+    }
+
+    // returns the number of distinct states
+    pub fn state_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    // returns the number of distinct words
+    pub fn token_count(&self) -> usize {
+        self.tokens.len() - 1
+    }
+
+    // returns the number of distinct transitions between states and words
+    pub fn transition_count(&self) -> usize {
+        self.edges.values().map(|targets| targets.len()).sum()
+    }
+
+    // returns the number of learnt phrases
+    // every phrase leaves the start state exactly once
+    pub fn phrase_count(&self) -> u64 {
+        match self.edges.get(&vec![BOUNDARY; self.order]) {
+            Some(targets) => targets.iter().map(|(_, w)| u64::from(*w)).sum(),
+            None => 0,
+        }
+    }
+
+    // removes the least frequently used states until at most max_states remain
+    // words that are no longer referenced are dropped as well
+    // returns the number of evicted states
+    pub fn evict(&mut self, max_states: usize) -> usize {
+        if self.edges.len() <= max_states {
+            return 0;
+        }
+
+        let mut totals: Vec<(u64, Vec<u32>)> = self
+            .edges
+            .iter()
+            .map(|(state, targets)| {
+                let total = targets.iter().map(|(_, w)| u64::from(*w)).sum();
+                (total, state.clone())
+            })
+            .collect();
+        totals.sort_unstable_by_key(|(total, _)| *total);
+
+        let evicted = self.edges.len() - max_states;
+        for (_, state) in totals.into_iter().take(evicted) {
+            self.edges.remove(&state);
+        }
+
+        self.drop_unused_tokens();
+        evicted
+    }
+
+    // removes words that don't appear in any transition and renumbers the rest
+    fn drop_unused_tokens(&mut self) {
+        let mut used = vec![false; self.tokens.len()];
+        used[BOUNDARY as usize] = true;
+        for (state, targets) in self.edges.iter() {
+            for id in state.iter().chain(targets.iter().map(|(id, _)| id)) {
+                used[*id as usize] = true;
+            }
+        }
+
+        let mut new_ids = vec![BOUNDARY; self.tokens.len()];
+        let mut tokens = Vec::new();
+        for (id, token) in self.tokens.drain(..).enumerate() {
+            if used[id] {
+                new_ids[id] = tokens.len() as u32;
+                tokens.push(token);
+            }
+        }
+
+        self.edges = self
+            .edges
+            .drain()
+            .map(|(state, targets)| {
+                let state = state.iter().map(|id| new_ids[*id as usize]).collect();
+                let targets = targets
+                    .into_iter()
+                    .map(|(id, w)| (new_ids[id as usize], w))
+                    .collect();
+                (state, targets)
+            })
+            .collect();
+
+        self.ids = tokens
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(id, token)| (token.clone(), id as u32))
+            .collect();
+        self.tokens = tokens;
+        self.recount();
+    }
+
+    // picks the next word of a state at random, proportionally to the weights
+    fn pick<R: Rng>(&self, state: &[u32], temperature: f64, rng: &mut R) -> Option<u32> {
+        sample(self.edges.get(state)?, temperature, rng)
+    }
+
+    // walks the chain from a given state until a phrase boundary is reached
+    // returns the given words followed by the visited ones
+    fn walk<R: Rng>(
+        &self,
+        mut state: Vec<u32>,
+        mut words: Vec<String>,
+        temperature: f64,
+        rng: &mut R,
+    ) -> Vec<String> {
+        while words.len() < MAX_WORDS {
+            match self.pick(&state, temperature, rng) {
+                Some(BOUNDARY) | None => break,
+                Some(id) => {
+                    words.push(self.tokens[id as usize].clone());
+                    state.remove(0);
+                    state.push(id);
+                }
+            }
+        }
+
+        words
+    }
+
+    // generates a phrase using a given sampling temperature and source of randomness
+    pub fn generate_str<R: Rng>(&self, temperature: f64, rng: &mut R) -> String {
+        let words = self.walk(vec![BOUNDARY; self.order], Vec::new(), temperature, rng);
+        tokenizer::join(&words)
+    }
+
+    // walks the chain from the start of a phrase beginning with a given word
+    // returns no words if no learnt phrase starts with it
+    fn walk_from_token<R: Rng>(&self, token: &str, temperature: f64, rng: &mut R) -> Vec<String> {
+        let id = match self.ids.get(token) {
+            Some(id) => *id,
+            None => return Vec::new(),
+        };
+
+        let mut state = vec![BOUNDARY; self.order - 1];
+        state.push(id);
+
+        if !self.edges.contains_key(&state) {
+            return Vec::new();
+        }
+
+        self.walk(state, vec![token.to_string()], temperature, rng)
+    }
+
+    // generates a phrase starting with a given word
+    // returns an empty string if no learnt phrase starts with it
+    pub fn generate_str_from_token<R: Rng>(
+        &self,
+        token: &str,
+        temperature: f64,
+        rng: &mut R,
+    ) -> String {
+        tokenizer::join(&self.walk_from_token(token, temperature, rng))
+    }
+
+    // generates a phrase ending with a given word, called on a reverse chain
+    // returns an empty string if no learnt phrase ends with it
+    pub fn generate_str_ending_with<R: Rng>(
+        &self,
+        token: &str,
+        temperature: f64,
+        rng: &mut R,
+    ) -> String {
+        let mut words = self.walk_from_token(token, temperature, rng);
+        words.reverse();
+        tokenizer::join(&words)
+    }
+
+    // generates a phrase containing a given word anywhere
+    // a state with the word is picked first, then the phrase is extended in both directions
+    // the reverse chain (see reversed) provides the words preceding the state
+    // returns an empty string if the word is unknown
+    pub fn generate_str_containing<R: Rng>(
+        &self,
+        reverse: &Chain,
+        token: &str,
+        temperature: f64,
+        rng: &mut R,
+    ) -> String {
+        let id = match self.ids.get(token) {
+            Some(id) => *id,
+            None => return String::new(),
+        };
+
+        let choices: Vec<(&Vec<u32>, u32)> = self
+            .edges
+            .iter()
+            .filter(|(state, _)| state.contains(&id))
+            .map(|(state, targets)| {
+                let total: u64 = targets.iter().map(|(_, w)| u64::from(*w)).sum();
+                (state, total.min(u64::from(u32::MAX)) as u32)
+            })
+            .collect();
+
+        let start = match sample(&choices, temperature, rng) {
+            Some(state) => state.clone(),
+            None => return String::new(),
+        };
+
+        // the reverse chain interns words on its own, translate the state
+        let reverse_state: Option<Vec<u32>> = start
+            .iter()
+            .rev()
+            .map(|id| match self.token(*id) {
+                Some(token) => reverse.ids.get(&token).copied(),
+                None => Some(BOUNDARY),
+            })
+            .collect();
+
+        // the state starting with a boundary begins the phrase already
+        let mut words = match reverse_state {
+            Some(state) if start[0] != BOUNDARY => {
+                reverse.walk(state, Vec::new(), temperature, rng)
+            }
+            _ => Vec::new(),
+        };
+
+        words.reverse();
+        words.extend(start.iter().filter_map(|id| self.token(*id)));
+
+        tokenizer::join(&self.walk(start, words, temperature, rng))
+    }
+
+    // builds a chain of the same phrases read backwards
+    // walking it from a state gives the words preceding that state in the original chain
+    pub fn reversed(&self) -> Chain {
+        let mut reverse = Chain {
+            order: self.order,
+            tokens: self.tokens.clone(),
+            ids: self.ids.clone(),
+            edges: HashMap::new(),
+            counts: vec![0; self.tokens.len()],
+        };
+
+        for (state, targets) in self.edges.iter() {
+            for (next, weight) in targets.iter() {
+                let mut window = state.clone();
+                window.push(*next);
+                window.reverse();
+
+                let padding = window
+                    .iter()
+                    .rev()
+                    .take_while(|id| **id == BOUNDARY)
+                    .count();
+
+                // a window ending a phrase starts it when reversed
+                // phrases have a single boundary at their end, so the reversed ones
+                // need the windows with more than one boundary at the start added
+                if *next == BOUNDARY {
+                    for extra in 1..self.order {
+                        let mut extended = vec![BOUNDARY; extra];
+                        extended.extend_from_slice(&window[..window.len() - extra]);
+
+                        if !extended.ends_with(&[BOUNDARY, BOUNDARY]) {
+                            reverse.add_edge(
+                                &extended[..self.order],
+                                extended[self.order],
+                                *weight,
+                            );
+                        }
+                    }
+                }
+
+                // windows with more than one boundary at the start were added above
+                if padding <= 1 {
+                    reverse.add_edge(&window[..self.order], window[self.order], *weight);
+                }
+            }
+        }
+
+        reverse
+    }
+}
+
+// picks one of the choices at random, proportionally to the weights
+// temperatures above 1 flatten the distribution, the ones below 1 sharpen it
+fn sample<T: Copy, R: Rng>(choices: &[(T, u32)], temperature: f64, rng: &mut R) -> Option<T> {
+    if (temperature - 1.0).abs() > f64::EPSILON {
+        let weights: Vec<f64> = choices
+            .iter()
+            .map(|(_, w)| f64::from(*w).powf(1.0 / temperature))
+            .collect();
+
+        let mut roll = rng.gen::<f64>() * weights.iter().sum::<f64>();
+        for ((choice, _), weight) in choices.iter().zip(weights.iter()) {
+            if roll < *weight {
+                return Some(*choice);
+            }
+            roll -= weight;
+        }
+
+        // rounding errors may leave a tiny remainder
+        return choices.last().map(|(choice, _)| *choice);
+    }
+
+    let total: u64 = choices.iter().map(|(_, w)| u64::from(*w)).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut roll = rng.gen_range(0, total);
+    for (choice, weight) in choices.iter() {
+        let weight = u64::from(*weight);
+        if roll < weight {
+            return Some(*choice);
+        }
+        roll -= weight;
+    }
+
+    None
+}
+
+// the layout of markov::Chain<String>, which was used before the in-crate engine
+// kept frozen, so that older blobs can still be read
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+pub struct LegacyChain {
+    map: HashMap<Vec<Option<String>>, HashMap<Option<String>, usize>>,
+    order: usize,
+}
+
+#[cfg(test)]
+impl LegacyChain {
+    // builds a first-order legacy chain that learnt a single line once
+    pub(crate) fn of_line(line: &str) -> LegacyChain {
+        let mut words: Vec<Option<String>> = vec![None];
+        words.extend(line.split_whitespace().map(|word| Some(word.to_string())));
+        words.push(None);
+
+        let mut map = HashMap::new();
+        for pair in words.windows(2) {
+            let mut next = HashMap::new();
+            next.insert(pair[1].clone(), 1);
+            map.insert(vec![pair[0].clone()], next);
+        }
+
+        LegacyChain { map, order: 1 }
+    }
+}
+
+impl From<LegacyChain> for Chain {
+    fn from(old: LegacyChain) -> Chain {
+        let mut chain = Chain::of_order(old.order);
+
+        for (from, next) in old.map.iter() {
+            for (to, count) in next.iter() {
+                let count = (*count).min(u32::MAX as usize) as u32;
+                chain.add_transition(from, to, count);
+            }
+        }
+
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(7)
+    }
+
+    fn chain_of(order: usize, lines: &[&str]) -> Chain {
+        let mut chain = Chain::of_order(order);
+        for line in lines {
+            chain.feed_str(line);
+        }
+        chain
+    }
+
+    #[test]
+    fn a_single_line_is_repeated() {
+        for order in 1..=3 {
+            let chain = chain_of(order, &["the cat sat on a mat"]);
+            assert_eq!(chain.generate_str(1.0, &mut rng()), "the cat sat on a mat");
+        }
+    }
+
+    #[test]
+    fn an_empty_chain_generates_nothing() {
+        let chain = Chain::of_order(2);
+        assert!(chain.is_empty());
+        assert_eq!(chain.generate_str(1.0, &mut rng()), "");
+    }
+
+    #[test]
+    fn feeding_counts_words_and_phrases() {
+        let chain = chain_of(1, &["a b a", "b c"]);
+        assert_eq!(chain.frequency("a"), 2);
+        assert_eq!(chain.frequency("b"), 2);
+        assert_eq!(chain.frequency("d"), 0);
+        assert_eq!(chain.phrase_count(), 2);
+        assert_eq!(chain.token_count(), 3);
+    }
+
+    #[test]
+    fn unfeeding_undoes_feeding() {
+        let mut chain = chain_of(2, &["one two three", "one two four"]);
+        chain.unfeed_str("one two four");
+        assert_eq!(chain.frequency("four"), 0);
+        assert_eq!(chain.generate_str(1.0, &mut rng()), "one two three");
+
+        chain.unfeed_str("one two three");
+        assert!(chain.is_empty());
+    }
+
+    #[test]
+    fn phrases_start_end_and_contain_words() {
+        let chain = chain_of(1, &["red green blue"]);
+        let reverse = chain.reversed();
+
+        assert_eq!(
+            chain.generate_str_from_token("red", 1.0, &mut rng()),
+            "red green blue"
+        );
+        // a first-order chain can't tell where phrases start, a second-order one can
+        let second = chain_of(2, &["red green blue"]);
+        assert_eq!(second.generate_str_from_token("green", 1.0, &mut rng()), "");
+        assert_eq!(
+            reverse.generate_str_ending_with("blue", 1.0, &mut rng()),
+            "red green blue"
+        );
+        assert_eq!(
+            chain.generate_str_containing(&reverse, "green", 1.0, &mut rng()),
+            "red green blue"
+        );
+        assert_eq!(
+            chain.generate_str_containing(&reverse, "purple", 1.0, &mut rng()),
+            ""
+        );
+    }
+
+    #[test]
+    fn reversed_chains_match_lines_fed_backwards() {
+        for order in 1..=3 {
+            let lines = ["a b c d", "b c", "c"];
+            let mut expected = Chain::of_order(order);
+            for line in lines.iter() {
+                expected.feed_str_reversed(line);
+            }
+
+            let mut got = chain_of(order, &lines).reversed().transitions();
+            let mut expected = expected.transitions();
+            got.sort();
+            expected.sort();
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn single_transitions_are_pruned() {
+        let mut chain = chain_of(1, &["a b", "a b", "a c"]);
+        let single = chain.single_transitions();
+
+        assert_eq!(chain.remove_single_transitions(&single), 2);
+        assert_eq!(chain.frequency("c"), 0);
+        assert_eq!(chain.token_count(), 2);
+        assert_eq!(chain.generate_str(1.0, &mut rng()), "a b");
+    }
+
+    #[test]
+    fn transitions_are_recognized_by_their_words() {
+        // the words get different IDs in each of the chains
+        let mut chain = chain_of(1, &["a b", "a b", "a c", "x y"]);
+        let single = chain_of(1, &["x y"]).single_transitions();
+
+        assert_eq!(chain.remove_single_transitions(&single), 3);
+        assert_eq!(chain.frequency("x"), 0);
+        assert_eq!(chain.frequency("c"), 1);
+    }
+
+    #[test]
+    fn eviction_drops_the_least_used_states() {
+        let mut chain = chain_of(1, &["a b", "a b", "c d"]);
+        let states = chain.state_count();
+
+        assert_eq!(chain.evict(states - 2), 2);
+        assert_eq!(chain.state_count(), states - 2);
+        assert_eq!(chain.frequency("d"), 0);
+        assert_eq!(chain.frequency("b"), 2);
+        assert_eq!(chain.evict(states), 0);
+    }
+
+    #[test]
+    fn serialization_round_trips() {
+        let chain = chain_of(2, &["one two three", "two three four"]);
+        let binc = bincode::serialize(&chain).unwrap();
+        let copy: Chain = bincode::deserialize(&binc).unwrap();
+
+        let mut got = copy.transitions();
+        let mut expected = chain.transitions();
+        got.sort();
+        expected.sort();
+        assert_eq!(got, expected);
+        assert_eq!(copy.frequency("three"), 2);
+    }
+
+    #[test]
+    fn legacy_chains_are_converted() {
+        let chain = Chain::from(LegacyChain::of_line("hi there"));
+        assert_eq!(chain.frequency("there"), 1);
+        assert_eq!(chain.phrase_count(), 1);
+        assert_eq!(chain.generate_str(1.0, &mut rng()), "hi there");
+    }
+}
@@ -0,0 +1,3074 @@
+use crate::chain::{self, Chain};
+use crate::daily::{self, Daily};
+use crate::error::Error;
+use crate::menu::{self, Action};
+use crate::quiz::Quiz;
+use crate::settings::{self, Settings};
+use crate::{alerts, batch, blob, chat_key, preprocess, stopwords, storage, tokenizer, utils, wal};
+
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+    env,
+    hash::{Hash, Hasher},
+    panic,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::stream::{self, StreamExt};
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+
+mod backups;
+mod generation;
+mod import_export;
+mod moderation;
+mod ratings;
+mod schema;
+
+use schema::Layout;
+
+// a Markov chain wrapper
+// holds the information for each chat
+#[derive(Serialize, Deserialize)]
+struct ChainInfo {
+    chain: Chain,
+    // the same phrases read backwards, used to generate text ending with a given word
+    reverse: Chain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    // lines learnt from each user, used to forget them on request
+    contributions: HashMap<i64, Vec<String>>,
+    // user IDs of known usernames (lowercase, without the leading @)
+    usernames: HashMap<String, i64>,
+    // the chat's configuration
+    #[serde(with = "settings::as_json")]
+    settings: Settings,
+    // file IDs of stickers sent in the chat, along with the number of times they were sent
+    stickers: HashMap<String, u32>,
+    // fingerprints of the transitions learnt only once, along with the time (as a UNIX timestamp)
+    // they were noted, the ones still learnt only once a while later are pruned (see prune_rare)
+    rare_transitions: Option<(u64, HashSet<u64>)>,
+    // lines (and their authors) stored in the chat's append log, replayed on top of the snapshot
+    #[serde(skip)]
+    log: Vec<(i64, String)>,
+    // lines (and their authors) learnt since the last flush
+    #[serde(skip)]
+    pending: Vec<(i64, String)>,
+    // set when the snapshot in storage doesn't reflect the current object
+    #[serde(skip)]
+    is_stale: bool,
+    // the time of the first change that's only stored with the next snapshot (e.g. a sticker count)
+    // such changes are too frequent to store a snapshot each, see MINOR_CHANGES_DELAY
+    #[serde(skip)]
+    minor_changes_since: Option<SystemTime>,
+    // the last time changes were sent to the storage backend
+    #[serde(skip)]
+    last_saved: Option<SystemTime>,
+    // the sequence number of the last write-ahead log entry the chain has learnt
+    // once the chain is stored, the entries up to it can be forgotten
+    #[serde(skip)]
+    logged_until: u64,
+    // set while changes taken by prepare_upload are being uploaded
+    #[serde(skip)]
+    is_uploading: bool,
+    // the number of snapshots stored so far, tells if one was stored while an upload was in flight
+    #[serde(skip)]
+    compactions: u64,
+    // hashes of recently learnt lines, used to avoid repeating them word for word
+    #[serde(skip)]
+    recent: VecDeque<u64>,
+    // hashes of the messages received within REPEAT_WINDOW and the last one sent by each user
+    // used to keep copypasta floods from skewing the chain
+    #[serde(skip)]
+    seen: VecDeque<(SystemTime, u64)>,
+    #[serde(skip)]
+    last_messages: HashMap<i64, u64>,
+    // IDs of recently learnt messages along with their authors and lines
+    // used to unlearn the original text of an edited message
+    #[serde(skip)]
+    learnt_messages: VecDeque<(i64, i64, Vec<String>)>,
+    // the last few messages sent in the chat, /speak without a prompt picks a word from them
+    #[serde(skip)]
+    context: VecDeque<String>,
+    // the last time each user made the bot generate a message
+    #[serde(skip)]
+    last_generated: HashMap<i64, SystemTime>,
+    // the times, fuzzy hashes and IDs of messages each user sent within FLOOD_WINDOW
+    #[serde(skip)]
+    activity: HashMap<i64, VecDeque<(SystemTime, u64, i64)>>,
+    // users caught flooding, along with the time their messages are learnt again
+    #[serde(skip)]
+    muted: HashMap<i64, SystemTime>,
+    // the contents of the settings file as last stored, None if it's not in storage yet
+    #[serde(skip)]
+    stored_settings: Option<Vec<u8>>,
+    // the time (as a UNIX timestamp) the oldest data still kept was learnt, kept in the settings file
+    #[serde(skip)]
+    learnt_since: Option<u64>,
+    // the time (as a UNIX timestamp) the chat was last used, kept in the settings file
+    #[serde(skip)]
+    last_used: Option<u64>,
+}
+
+// the ID used for lines of unknown authorship
+// (posted on behalf of a channel or by an anonymous admin, or imported)
+// they're kept like any other contribution, so that /set_order can relearn them
+pub const UNKNOWN_USER: i64 = 0;
+
+// the order of newly created chains
+const DEFAULT_ORDER: usize = 1;
+
+// the highest order that can be set with /set_order
+const MAX_ORDER: usize = 3;
+
+// the range of sampling temperatures that can be set with /set_temperature
+const MIN_TEMPERATURE: f64 = 0.1;
+const MAX_TEMPERATURE: f64 = 5.0;
+
+// the number of words listed by /top_words, by default and at most
+const DEFAULT_TOP_WORDS: usize = 10;
+const MAX_TOP_WORDS: usize = 50;
+
+// the number of days after which transitions learnt only once are removed by /prune_rare
+// used unless RARE_TRANSITION_DAYS is set
+const DEFAULT_RARE_TRANSITION_DAYS: u64 = 30;
+
+// the number of following words listed by /word_info
+const WORD_INFO_FOLLOWERS: usize = 5;
+
+// the number of recently learnt lines generated messages are compared against
+const ECHO_MEMORY: usize = 1000;
+
+// the number of recent messages /speak without a prompt picks a word from
+const CONTEXT_MEMORY: usize = 20;
+
+// a message seen that many times within REPEAT_WINDOW isn't learnt anymore
+const MAX_REPEATS: usize = 3;
+const REPEAT_WINDOW: Duration = Duration::from_secs(60);
+
+// a user sending more than FLOOD_MAX_MESSAGES messages, or FLOOD_MAX_SIMILAR near-identical ones,
+// within FLOOD_WINDOW isn't learnt from for FLOOD_MUTE
+const FLOOD_WINDOW: Duration = Duration::from_secs(30);
+const FLOOD_MAX_MESSAGES: usize = 15;
+const FLOOD_MAX_SIMILAR: usize = 4;
+const FLOOD_MUTE: Duration = Duration::from_secs(15 * 60);
+
+// the number of distinct stickers remembered per chat
+// the least used one is forgotten to make room for a new one
+const STICKER_MEMORY: usize = 500;
+
+// the longest minor changes (sticker counts and usernames) wait for a snapshot to be stored in
+// they're stored right away once a chain is dropped
+const MINOR_CHANGES_DELAY: Duration = Duration::from_secs(30 * 60);
+
+// the chance (in percent) of replying to a sticker with another one, given that it always happens
+const MAX_STICKER_CHANCE: u32 = 100;
+
+// the number of recently learnt messages that can be unlearnt once edited
+const EDIT_MEMORY: usize = 200;
+
+// the prefix of chains deleted with /clear_data and the time (in seconds) they're kept for
+const TRASH_PREFIX: &str = "trash.";
+const TRASH_TTL: u64 = 24 * 60 * 60;
+
+// the number of chains saved at the same time on shutdown
+// enough to hide the latency of the storage backend without hitting its rate limits
+const SHUTDOWN_CONCURRENCY: usize = 8;
+
+// the share (in percent) of MEMORY_BUDGET above which chains are pruned without waiting for UPDATE_FREQUENCY
+const EARLY_PRUNE_THRESHOLD: usize = 90;
+
+// the name of the file listing the most recently used chats, which are loaded on startup
+const ACTIVITY_FILE: &str = "activity";
+
+// the number of chats listed in a storage report
+const REPORT_TOP_CHATS: usize = 5;
+
+// how often a chain that's being uploaded is checked while waiting to store its snapshot
+const UPLOAD_POLL: Duration = Duration::from_millis(100);
+
+// the time (in seconds) that has to pass before the stored time a chat was last used is updated
+const LAST_USED_PRECISION: u64 = 24 * 60 * 60;
+
+// the version of the serialized ChainInfo layout
+// bump it and add a step to Layout whenever the struct changes
+// (new chat options belong to Settings, which doesn't need a migration)
+const SCHEMA_VERSION: u32 = 9;
+
+// what a chain uploads next to its settings
+enum Contents {
+    Nothing,
+    // a full snapshot (serialized, but not packed yet) replacing the append log
+    Snapshot(Vec<u8>),
+    // the whole append log, the lines stored before along with the new ones
+    Log(Vec<(i64, String)>),
+}
+
+// the changes of a chain taken by ChainInfo::prepare_upload
+// they're uploaded without touching the chain, so that the wrapper doesn't have to be locked meanwhile
+struct Upload {
+    chat_id: i64,
+    // the settings file, if the settings changed since they were last stored
+    settings: Option<Vec<u8>>,
+    contents: Contents,
+    // the lines learnt since the last flush, put back if the upload fails
+    pending: Vec<(i64, String)>,
+    // set if the chain is empty, its snapshot isn't backed up
+    is_empty: bool,
+    // the state of the chain when the changes were taken
+    logged_until: u64,
+    compactions: u64,
+    last_accessed: SystemTime,
+}
+
+impl Upload {
+    // sends the changes to the storage backend
+    async fn store(&mut self) -> Option<Error> {
+        if let Some(record) = &self.settings {
+            let name = ChainInfo::settings_name(self.chat_id);
+            if let Some(err) = storage::update_or_create_file(record, &name).await {
+                return Some(err);
+            }
+        }
+
+        match &mut self.contents {
+            Contents::Nothing => None,
+            Contents::Snapshot(binc) => {
+                let packed = ChainInfo::pack(std::mem::take(binc)).await;
+                ChainInfo::store_snapshot(self.chat_id, &packed, self.is_empty).await
+            }
+            Contents::Log(lines) => ChainInfo::upload_log(self.chat_id, lines).await,
+        }
+    }
+}
+
+impl ChainInfo {
+    // serializes the current object, so that it can be packed (see ChainInfo::pack) without holding the lock
+    fn to_bincode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Serialization failed")
+    }
+
+    // packs a serialized object into a compressed (and possibly encrypted) binary blob
+    // packing a large chain takes a while, it's done on a blocking thread
+    async fn pack(binc: Vec<u8>) -> Vec<u8> {
+        utils::run_blocking(move || blob::pack(&blob::tag_version(SCHEMA_VERSION, &binc))).await
+    }
+
+    // deserializes a ChainInfo written with a given schema version
+    // older layouts are converted to the current one a version at a time
+    fn migrate(version: u32, binc: &[u8]) -> Result<ChainInfo, String> {
+        let mut layout = Layout::decode(version, binc)?;
+        loop {
+            layout = match layout {
+                Layout::Current(chain) => return Ok(*chain),
+                old => old.upgrade(),
+            };
+        }
+    }
+
+    // unpacks and deserializes a downloaded binary blob
+    // failures are reported to the admin chat, as they mean corrupted data
+    fn from_blob(chat_id: i64, v_u8: Vec<u8>) -> Result<ChainInfo, Error> {
+        let res = match blob::unpack(v_u8) {
+            Err(e) => Err(Error::Deserialization(format!("{} for {}", e, chat_id))),
+            Ok(binc) => {
+                let (version, payload) = blob::read_version(&binc);
+                ChainInfo::migrate(version, payload).map_err(|e| {
+                    Error::Deserialization(format!("Deserialization failed for {}: {}", chat_id, e))
+                })
+            }
+        };
+
+        if let Err(e) = &res {
+            alerts::report(&e.to_string());
+        }
+
+        res
+    }
+
+    // like from_blob, but runs on a blocking thread, as decrypting, decompressing
+    // and deserializing a large chain would otherwise stall every other chat
+    async fn load_blob(chat_id: i64, v_u8: Vec<u8>) -> Result<ChainInfo, Error> {
+        utils::run_blocking(move || ChainInfo::from_blob(chat_id, v_u8)).await
+    }
+
+    // returns the name of the chat's snapshot file
+    fn snapshot_name(chat_id: i64) -> String {
+        chat_id.to_string()
+    }
+
+    // returns the name of the chat's append log file
+    fn log_name(chat_id: i64) -> String {
+        format!("{}.log", chat_id)
+    }
+
+    // returns the name of the chat's settings file
+    fn settings_name(chat_id: i64) -> String {
+        format!("{}.settings", chat_id)
+    }
+
+    // returns the common prefix of the chat's backup files
+    fn backup_prefix(chat_id: i64) -> String {
+        format!("{}.bak.", chat_id)
+    }
+
+    // returns the name of the chat's backup file taken at a given time
+    fn backup_name(chat_id: i64, timestamp: u64) -> String {
+        format!("{}{}", ChainInfo::backup_prefix(chat_id), timestamp)
+    }
+
+    // returns the common prefix of the chat's cleared chains, kept for TRASH_TTL
+    // the prefix doesn't start with the chat's ID, so that the files aren't taken for the chat's own
+    fn trash_prefix(chat_id: i64) -> String {
+        format!("{}{}.", TRASH_PREFIX, chat_id)
+    }
+
+    // returns the name of the chat's chain cleared at a given time
+    fn trash_name(chat_id: i64, timestamp: u64) -> String {
+        format!("{}{}", ChainInfo::trash_prefix(chat_id), timestamp)
+    }
+
+    // returns the common prefix of the chat's named snapshots (saved with /snapshot)
+    fn named_snapshot_prefix(chat_id: i64) -> String {
+        format!("{}.snap.", chat_id)
+    }
+
+    // returns the file name of the chat's snapshot with a given name
+    fn named_snapshot_name(chat_id: i64, name: &str) -> String {
+        format!("{}{}", ChainInfo::named_snapshot_prefix(chat_id), name)
+    }
+
+    // uploads a list of lines as a chat's append log
+    // every entry is stored as "user_id\tline"
+    async fn upload_log(chat_id: i64, entries: &[(i64, String)]) -> Option<Error> {
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|(user_id, line)| format!("{}\t{}", user_id, line))
+            .collect();
+
+        let packed = blob::pack(lines.join("\n").as_bytes());
+        storage::update_or_create_file(&packed, &ChainInfo::log_name(chat_id)).await
+    }
+
+    // splits an append log entry into its author and the line itself
+    // entries written by older versions lack the author
+    fn parse_log_entry(entry: &str) -> (i64, String) {
+        let mut parts = entry.splitn(2, '\t');
+
+        match (parts.next().map(|id| id.parse::<i64>()), parts.next()) {
+            (Some(Ok(user_id)), Some(line)) => (user_id, line.to_string()),
+            _ => (UNKNOWN_USER, entry.to_string()),
+        }
+    }
+
+    // downloads the chat's append log
+    async fn download_log(chat_id: i64) -> Result<Vec<(i64, String)>, Error> {
+        match storage::download_file(&ChainInfo::log_name(chat_id)).await {
+            Err(e) => Err(e),
+            Ok(None) => Ok(Vec::new()),
+            Ok(Some(v_u8)) => match blob::unpack(v_u8) {
+                Err(e) => Err(Error::Deserialization(format!(
+                    "{} for {} (log)",
+                    e, chat_id
+                ))),
+                Ok(bytes) => Ok(String::from_utf8_lossy(&bytes)
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(ChainInfo::parse_log_entry)
+                    .collect()),
+            },
+        }
+    }
+
+    // uploads a packed snapshot of a chat's chain and empties its append log
+    // a non-empty chain is also backed up if it's due
+    async fn store_snapshot(chat_id: i64, packed: &[u8], is_empty: bool) -> Option<Error> {
+        let name = ChainInfo::snapshot_name(chat_id);
+        if let Some(err) = storage::update_or_create_file(packed, &name).await {
+            return Some(err);
+        }
+
+        // a failed backup shouldn't be reported as a failed upload
+        if !is_empty {
+            if let Some(err) = ChainInfo::backup_if_due(chat_id, packed).await {
+                dbg!(err);
+            }
+        }
+
+        ChainInfo::upload_log(chat_id, &[]).await
+    }
+
+    // downloads the chat's settings file and applies it to the current object
+    // the file takes precedence over the settings kept in the snapshot
+    // a corrupted file is reported and replaced with the current settings on the next save
+    async fn load_settings(&mut self) -> Option<Error> {
+        match storage::download_file(&ChainInfo::settings_name(self.chat_id)).await {
+            Err(e) => Some(e),
+            Ok(None) => None,
+            Ok(Some(bytes)) => match settings::from_record(&bytes) {
+                Err(e) => {
+                    let err = format!(
+                        "Settings deserialization failed for {}: {}",
+                        self.chat_id, e
+                    );
+                    alerts::report(&err);
+                    dbg!(err);
+                    self.stored_settings = None;
+                    None
+                }
+                Ok(record) => {
+                    self.is_learning = record.is_learning;
+                    self.settings = record.settings;
+                    self.learnt_since = record.learnt_since.or(self.learnt_since);
+                    self.last_used = record.last_used.or(self.last_used);
+                    self.stored_settings = Some(bytes);
+                    None
+                }
+            },
+        }
+    }
+
+    // takes the changes made since the last flush, so that they can be uploaded without holding the lock
+    // new lines go to the append log, which is compacted into a snapshot once it grows too long
+    // settings are stored separately, so that they're kept even while the chain is empty
+    // minor changes wait for MINOR_CHANGES_DELAY, unless the chain is about to be dropped
+    // returns None if there's nothing to upload or another upload is still in flight
+    fn prepare_upload(&mut self, is_final: bool) -> Option<Upload> {
+        if !storage::is_persistent() || self.is_uploading {
+            return None;
+        }
+
+        let settings = self.changed_settings();
+
+        let are_minor_changes_due = self.minor_changes_since.is_some_and(|since| {
+            is_final
+                || since
+                    .elapsed()
+                    .map_or(true, |age| age >= MINOR_CHANGES_DELAY)
+        });
+
+        let contents = if self.chain.is_empty() {
+            Contents::Nothing
+        } else if self.is_stale
+            || are_minor_changes_due
+            || self.log.len() + self.pending.len() > config(|c| c.log_compaction_threshold)
+        {
+            self.is_stale = false;
+            self.minor_changes_since = None;
+            Contents::Snapshot(self.to_bincode())
+        } else if !self.pending.is_empty() {
+            let mut lines = self.log.clone();
+            lines.extend(self.pending.iter().cloned());
+            Contents::Log(lines)
+        } else {
+            Contents::Nothing
+        };
+
+        if settings.is_none() {
+            if let Contents::Nothing = contents {
+                return None;
+            }
+        }
+
+        let pending = match contents {
+            Contents::Nothing => Vec::new(),
+            _ => std::mem::take(&mut self.pending),
+        };
+
+        self.is_uploading = true;
+        Some(Upload {
+            chat_id: self.chat_id,
+            settings,
+            contents,
+            pending,
+            is_empty: self.chain.is_empty(),
+            logged_until: self.logged_until,
+            compactions: self.compactions,
+            last_accessed: self.last_accessed,
+        })
+    }
+
+    // takes a full snapshot of the current object (and its settings, if they changed) to be uploaded
+    // like prepare_upload, but the snapshot replaces the append log even if the chain is empty
+    // used after changes that can't be expressed by the append log, see ChainWrapper::persist
+    // the caller has to make sure no other upload is in flight
+    fn prepare_snapshot(&mut self) -> Upload {
+        self.is_stale = false;
+        self.minor_changes_since = None;
+        self.is_uploading = true;
+
+        Upload {
+            chat_id: self.chat_id,
+            settings: self.changed_settings(),
+            contents: Contents::Snapshot(self.to_bincode()),
+            pending: std::mem::take(&mut self.pending),
+            is_empty: self.chain.is_empty(),
+            logged_until: self.logged_until,
+            compactions: self.compactions,
+            last_accessed: self.last_accessed,
+        }
+    }
+
+    // returns the chat's settings file if the settings changed since it was last stored
+    fn changed_settings(&self) -> Option<Vec<u8>> {
+        let record = settings::to_record(
+            self.is_learning,
+            &self.settings,
+            self.learnt_since,
+            self.last_used,
+        );
+
+        if self.stored_settings.as_ref() == Some(&record) {
+            None
+        } else {
+            Some(record)
+        }
+    }
+
+    // applies the outcome of an upload taken by prepare_upload
+    // a failed upload puts the changes back, so that the next flush sends them again
+    // returns the error of the upload, or None if the chain is stored
+    fn finish_upload(&mut self, upload: Upload, res: Option<Error>) -> Option<Error> {
+        self.is_uploading = false;
+
+        if res.is_some() {
+            // the changes are part of a snapshot stored meanwhile already
+            if self.compactions != upload.compactions {
+                return res;
+            }
+
+            let mut pending = upload.pending;
+            pending.append(&mut self.pending);
+            self.pending = pending;
+
+            if let Contents::Snapshot(_) = upload.contents {
+                self.is_stale = true;
+            }
+            return res;
+        }
+
+        if let Some(record) = upload.settings {
+            self.stored_settings = Some(record);
+        }
+
+        if let Contents::Nothing = upload.contents {
+            return None;
+        }
+
+        // a snapshot stored meanwhile (e.g. by /restore) may have been overwritten by this upload,
+        // the next one has to store it again
+        if self.compactions != upload.compactions {
+            self.is_stale = true;
+            return None;
+        }
+
+        match upload.contents {
+            Contents::Nothing => (),
+            Contents::Snapshot(_) => {
+                self.log.clear();
+                self.compactions += 1;
+            }
+            Contents::Log(lines) => self.log = lines,
+        }
+
+        self.last_saved = Some(SystemTime::now());
+        None
+    }
+
+    // sends the changes made since the last flush to the storage backend
+    async fn serialize_to_storage(&mut self) -> Option<Error> {
+        match self.prepare_upload(true) {
+            None => None,
+            Some(mut upload) => {
+                let res = upload.store().await;
+                self.finish_upload(upload, res)
+            }
+        }
+    }
+
+    // downloads a binary blob from the storage backend and populates the current object
+    async fn deserialize_from_storage(chat_id: i64) -> Result<Option<ChainInfo>, Error> {
+        if !storage::is_persistent() {
+            return Ok(None);
+        }
+
+        match storage::download_cached(&ChainInfo::snapshot_name(chat_id)).await {
+            Err(e) => Err(e),
+            Ok(buf) => match buf {
+                None => Ok(None),
+                Some(v_u8) => match ChainInfo::load_blob(chat_id, v_u8).await {
+                    Err(e) => Err(e),
+                    Ok(mut c) => {
+                        // replay the lines learnt since the last compaction
+                        let log = ChainInfo::download_log(chat_id).await?;
+                        for (user_id, line) in log.iter() {
+                            c.remember(*user_id, line);
+                        }
+                        c.log = log;
+                        Ok(Some(c))
+                    }
+                },
+            },
+        }
+    }
+
+    // creates a new ChainInfo
+    pub async fn new(chat_id: i64) -> Result<ChainInfo, Error> {
+        let mut chain_info = match ChainInfo::deserialize_from_storage(chat_id).await {
+            Err(e) => return Err(e),
+            Ok(obj) => match obj {
+                // ChainInfo exists for the given chat
+                Some(mut chain_info) => {
+                    chain_info.last_accessed = SystemTime::now();
+                    chain_info
+                }
+                // ChainInfo does not exist
+                None => ChainInfo {
+                    chain: Chain::of_order(DEFAULT_ORDER),
+                    reverse: Chain::of_order(DEFAULT_ORDER),
+                    chat_id,
+                    is_learning: true,
+                    last_accessed: SystemTime::now(),
+                    contributions: HashMap::new(),
+                    usernames: HashMap::new(),
+                    settings: Settings::default(),
+                    stickers: HashMap::new(),
+                    rare_transitions: None,
+                    log: Vec::new(),
+                    pending: Vec::new(),
+                    is_stale: true,
+                    minor_changes_since: None,
+                    last_saved: None,
+                    logged_until: 0,
+                    is_uploading: false,
+                    compactions: 0,
+                    recent: VecDeque::new(),
+                    seen: VecDeque::new(),
+                    last_messages: HashMap::new(),
+                    learnt_messages: VecDeque::new(),
+                    context: VecDeque::new(),
+                    last_generated: HashMap::new(),
+                    activity: HashMap::new(),
+                    muted: HashMap::new(),
+                    // default settings don't need a file of their own
+                    stored_settings: Some(settings::to_record(
+                        true,
+                        &Settings::default(),
+                        None,
+                        None,
+                    )),
+                    learnt_since: None,
+                    last_used: None,
+                },
+            },
+        };
+
+        if storage::is_persistent() {
+            if let Some(err) = chain_info.load_settings().await {
+                return Err(err);
+            }
+        }
+
+        // data learnt before its age was tracked is treated as learnt just now
+        if chain_info.learnt_since.is_none() && !chain_info.chain.is_empty() {
+            chain_info.learnt_since = Some(utils::unix_now());
+        }
+
+        Ok(chain_info)
+    }
+
+    // estimates the memory taken by the object, in bytes
+    // besides the chains, counts what's kept per user, sticker and message, as it grows with the chat
+    fn estimated_size(&self) -> usize {
+        // a String (or Vec) header plus a hash table slot, as in Chain::estimated_size
+        const ENTRY_OVERHEAD: usize = 24 + 16;
+
+        let lines =
+            |lines: &[String]| -> usize { lines.iter().map(|line| line.len() + 24).sum::<usize>() };
+        let authored = |lines: &[(i64, String)]| -> usize {
+            lines.iter().map(|(_, line)| line.len() + 32).sum::<usize>()
+        };
+
+        let contributions: usize = self
+            .contributions
+            .values()
+            .map(|learnt| lines(learnt) + ENTRY_OVERHEAD + 8)
+            .sum();
+        let usernames: usize = self
+            .usernames
+            .keys()
+            .map(|name| name.len() + ENTRY_OVERHEAD + 8)
+            .sum();
+        let stickers: usize = self
+            .stickers
+            .keys()
+            .map(|file_id| file_id.len() + ENTRY_OVERHEAD + 4)
+            .sum();
+        let rare = self
+            .rare_transitions
+            .as_ref()
+            .map_or(0, |(_, rare)| rare.len() * 16);
+
+        // the messages recently received, learnt and generated
+        let recent = self.recent.len() * 8
+            + self.seen.len() * 24
+            + self.last_messages.len() * 32
+            + self
+                .learnt_messages
+                .iter()
+                .map(|(_, _, learnt)| lines(learnt) + 40)
+                .sum::<usize>()
+            + self
+                .context
+                .iter()
+                .map(|line| line.len() + 24)
+                .sum::<usize>()
+            + self.settings.generations.len() * (ENTRY_OVERHEAD + 64)
+            + self.settings.duels.len() * (ENTRY_OVERHEAD + 128)
+            + self.last_generated.len() * 40
+            + self
+                .activity
+                .values()
+                .map(|messages| messages.len() * 32 + ENTRY_OVERHEAD)
+                .sum::<usize>()
+            + self.muted.len() * 40;
+
+        self.chain.estimated_size()
+            + self.reverse.estimated_size()
+            + contributions
+            + usernames
+            + stickers
+            + rare
+            + recent
+            + authored(&self.log)
+            + authored(&self.pending)
+    }
+
+    // updates the last_accessed property, along with the time the chat was last used
+    // the latter only changes once a day, so that the settings file isn't uploaded on every flush
+    fn touch(&mut self) {
+        self.last_accessed = SystemTime::now();
+
+        let now = utils::unix_now();
+        if self
+            .last_used
+            .is_none_or(|last_used| now.saturating_sub(last_used) >= LAST_USED_PRECISION)
+        {
+            self.last_used = Some(now);
+        }
+    }
+
+    // feeds the Markov chain a single line and attributes it to its author
+    fn remember(&mut self, user_id: i64, line: &str) {
+        if self.learnt_since.is_none() {
+            self.learnt_since = Some(utils::unix_now());
+        }
+
+        self.chain.feed_str(line);
+        self.reverse.feed_str_reversed(line);
+
+        if self.recent.len() == ECHO_MEMORY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(ChainInfo::line_hash(line));
+
+        self.contributions
+            .entry(user_id)
+            .or_default()
+            .push(line.to_string());
+    }
+
+    // hashes a line regardless of its whitespace
+    fn line_hash(line: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for word in tokenizer::tokenize(line) {
+            word.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    // checks if a phrase repeats one of the recently learnt lines word for word
+    fn is_echo(&self, phrase: &str) -> bool {
+        self.recent.contains(&ChainInfo::line_hash(phrase))
+    }
+
+    // checks if a message repeats the sender's previous one or floods the chat
+    // records the message either way
+    fn is_repeated(&mut self, user_id: i64, msg: &str) -> bool {
+        let hash = ChainInfo::line_hash(msg);
+        let now = SystemTime::now();
+
+        while let Some((time, _)) = self.seen.front() {
+            match now.duration_since(*time) {
+                Ok(elapsed) if elapsed > REPEAT_WINDOW => {
+                    self.seen.pop_front();
+                }
+                _ => break,
+            }
+        }
+
+        let count = self.seen.iter().filter(|(_, h)| *h == hash).count();
+        self.seen.push_back((now, hash));
+
+        let is_same = self.last_messages.insert(user_id, hash) == Some(hash);
+        is_same || count >= MAX_REPEATS
+    }
+
+    // hashes a message ignoring everything but its letters
+    // messages differing only in numbers, punctuation or spacing (e.g. spam links) collide
+    fn fuzzy_hash(msg: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for c in msg.chars().filter(|c| c.is_alphabetic()) {
+            c.to_lowercase().for_each(|c| c.hash(&mut hasher));
+        }
+        hasher.finish()
+    }
+
+    // checks if a user is flooding the chat, recording the message
+    // a user caught flooding is muted and what they've just taught the bot is unlearnt
+    fn is_flooding(&mut self, user_id: i64, message_id: i64, msg: &str) -> bool {
+        let now = SystemTime::now();
+
+        self.muted.retain(|_, until| *until > now);
+        if self.muted.contains_key(&user_id) {
+            return true;
+        }
+
+        let hash = ChainInfo::fuzzy_hash(msg);
+        let activity = self.activity.entry(user_id).or_default();
+
+        while let Some((time, _, _)) = activity.front() {
+            match now.duration_since(*time) {
+                Ok(elapsed) if elapsed > FLOOD_WINDOW => {
+                    activity.pop_front();
+                }
+                _ => break,
+            }
+        }
+        activity.push_back((now, hash, message_id));
+
+        let similar = activity.iter().filter(|(_, h, _)| *h == hash).count();
+        if activity.len() <= FLOOD_MAX_MESSAGES && similar < FLOOD_MAX_SIMILAR {
+            return false;
+        }
+
+        let burst: Vec<i64> = activity.drain(..).map(|(_, _, id)| id).collect();
+        self.muted.insert(user_id, now + FLOOD_MUTE);
+
+        for message_id in burst {
+            self.unlearn_message(user_id, message_id);
+        }
+
+        true
+    }
+
+    // unlearns the lines of a recently learnt message
+    // does nothing if the message is no longer tracked
+    fn unlearn_message(&mut self, user_id: i64, message_id: i64) {
+        let index = self
+            .learnt_messages
+            .iter()
+            .position(|(id, author, _)| *id == message_id && *author == user_id);
+
+        if let Some((_, _, lines)) = index.and_then(|i| self.learnt_messages.remove(i)) {
+            for line in lines.iter() {
+                self.chain.unfeed_str(line);
+                self.reverse.unfeed_str_reversed(line);
+
+                if let Some(contributed) = self.contributions.get_mut(&user_id) {
+                    if let Some(i) = contributed.iter().rposition(|l| l == line) {
+                        contributed.remove(i);
+                    }
+                }
+            }
+
+            // unlearnt lines can't be expressed by the append log
+            self.is_stale = true;
+        }
+    }
+
+    // feeds the Markov chain a single line without logging it
+    fn learn(&mut self, user_id: i64, line: &str) {
+        self.remember(user_id, line);
+        self.pending.push((user_id, line.to_string()));
+    }
+
+    // feeds the Markov chain a new string sent by a specified user
+    // the message has to be in the write-ahead log already (see batch::feed)
+    // returns the learnt lines
+    pub fn feed(
+        &mut self,
+        user_id: i64,
+        username: &str,
+        message_id: i64,
+        is_forwarded: bool,
+        msg: &str,
+    ) -> Vec<String> {
+        self.touch();
+        self.remember_username(user_id, username);
+
+        if !self.settings.is_learnt_from(user_id) {
+            return Vec::new();
+        }
+
+        // flooding users are tracked even if the bot isn't learning at the moment
+        if self.is_flooding(user_id, message_id, msg) {
+            return Vec::new();
+        }
+
+        if is_forwarded && self.settings.skip_forwarded {
+            return Vec::new();
+        }
+
+        if username.is_empty() && self.settings.skip_anonymous {
+            return Vec::new();
+        }
+
+        if !self.settings.is_blacklisted(msg) {
+            self.remember_context(msg);
+        }
+
+        if self.is_learning && !self.settings.is_blacklisted(msg) && !self.is_repeated(user_id, msg)
+        {
+            let msg = preprocess::clean(msg, &self.settings);
+            let lines = preprocess::filter(&msg, &self.settings);
+
+            for line in lines.iter() {
+                self.learn(user_id, line);
+            }
+
+            if !lines.is_empty() {
+                if self.learnt_messages.len() == EDIT_MEMORY {
+                    self.learnt_messages.pop_front();
+                }
+                self.learnt_messages
+                    .push_back((message_id, user_id, lines.clone()));
+            }
+
+            self.enforce_size_limit();
+            lines
+        } else {
+            Vec::new()
+        }
+    }
+
+    // keeps a message among the recent ones /speak picks a word from
+    fn remember_context(&mut self, msg: &str) {
+        if self.context.len() == CONTEXT_MEMORY {
+            self.context.pop_front();
+        }
+        self.context
+            .push_back(preprocess::clean(msg, &self.settings));
+    }
+
+    // feeds the Markov chain a message waiting in a batch, see feed
+    // an edited message has what was learnt from it replaced with the new text
+    // messages that are no longer tracked only have the new text learnt
+    fn feed_incoming(&mut self, msg: &batch::Incoming) -> Vec<String> {
+        if msg.is_edit {
+            self.unlearn_message(msg.user_id, msg.message_id);
+        }
+
+        self.feed(
+            msg.user_id,
+            &msg.username,
+            msg.message_id,
+            msg.is_forwarded,
+            &msg.text,
+        )
+    }
+
+    // counts a sticker sent in the chat
+    // returns a sticker to reply with, if the chat has asked for that
+    pub fn feed_sticker<R: Rng>(&mut self, file_id: &str, rng: &mut R) -> Option<String> {
+        self.touch();
+
+        if self.is_learning {
+            if !self.stickers.contains_key(file_id) && self.stickers.len() >= STICKER_MEMORY {
+                let least_used = self
+                    .stickers
+                    .iter()
+                    .min_by_key(|(_, count)| **count)
+                    .map(|(id, _)| id.clone());
+
+                if let Some(id) = least_used {
+                    self.stickers.remove(&id);
+                }
+            }
+
+            let count = self.stickers.entry(file_id.to_string()).or_insert(0);
+            *count = count.saturating_add(1);
+
+            // sticker counts can't be expressed by the append log
+            self.note_minor_change();
+        }
+
+        if rng.gen_range(0, MAX_STICKER_CHANCE) < self.settings.sticker_chance {
+            self.pick_sticker(rng)
+        } else {
+            None
+        }
+    }
+
+    // picks one of the chat's stickers at random, the popular ones being more likely
+    pub fn pick_sticker<R: Rng>(&mut self, rng: &mut R) -> Option<String> {
+        self.touch();
+
+        let total: u64 = self.stickers.values().map(|count| u64::from(*count)).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut roll = rng.gen_range(0, total);
+        for (id, count) in self.stickers.iter() {
+            let count = u64::from(*count);
+            if roll < count {
+                return Some(id.clone());
+            }
+            roll -= count;
+        }
+
+        None
+    }
+
+    // sets the chance (in percent) of replying to a sticker with another one
+    pub fn set_sticker_chance(&mut self, chance: u32) {
+        self.touch();
+        self.settings.sticker_chance = chance;
+        self.is_stale = true;
+    }
+
+    // returns the maximum number of states of the Markov chain (0 meaning no limit)
+    fn max_states(&self) -> usize {
+        self.settings.max_states.unwrap_or(config(|c| c.max_states))
+    }
+
+    // evicts the least frequently used states once the chain grows past its size limit
+    // makes some room at once, so that eviction doesn't run after every message
+    fn enforce_size_limit(&mut self) -> usize {
+        let max_states = self.max_states();
+        if max_states == 0 || self.chain.state_count() <= max_states {
+            return 0;
+        }
+
+        // evicted states can't be expressed by the append log
+        self.is_stale = true;
+        let evicted = self.chain.evict(max_states - max_states / 10);
+        self.reverse = self.chain.reversed();
+        evicted
+    }
+
+    // sets the maximum number of states of the Markov chain (0 meaning no limit)
+    // returns the number of evicted states
+    pub fn set_max_states(&mut self, max_states: usize) -> usize {
+        self.touch();
+        self.settings.max_states = Some(max_states);
+        self.is_stale = true;
+        self.enforce_size_limit()
+    }
+
+    // removes the transitions learnt only once that haven't been learnt again for max_age
+    // the transitions learnt only once are noted, and the ones still learnt once next time are removed
+    // returns the number of removed transitions, or the time (as a UNIX timestamp) removal is due
+    fn prune_rare(&mut self, max_age: Duration) -> Result<usize, u64> {
+        let now = utils::unix_now();
+
+        let mut removed = 0;
+        if let Some((noted_at, fingerprints)) = &self.rare_transitions {
+            let due = noted_at + max_age.as_secs();
+            if now < due {
+                return Err(due);
+            }
+
+            removed = self.chain.remove_single_transitions(fingerprints);
+            if removed > 0 {
+                self.reverse = self.chain.reversed();
+            }
+        }
+
+        self.rare_transitions = Some((now, self.chain.single_transitions()));
+        self.is_stale = true;
+        Ok(removed)
+    }
+
+    // returns the number of transitions waiting to be removed by prune_rare
+    fn rare_transition_count(&self) -> usize {
+        self.rare_transitions
+            .as_ref()
+            .map_or(0, |(_, fingerprints)| fingerprints.len())
+    }
+
+    // keeps track of the user ID behind a username
+    // usernames can change, so an outdated entry is replaced
+    // users without a username (or an author) have nothing to keep track of
+    fn remember_username(&mut self, user_id: i64, username: &str) {
+        let username = username.trim_start_matches('@').to_lowercase();
+        if username.is_empty() || user_id == UNKNOWN_USER {
+            return;
+        }
+
+        if self.usernames.get(&username) != Some(&user_id) {
+            self.usernames.retain(|_, id| *id != user_id);
+            self.usernames.insert(username, user_id);
+            self.note_minor_change();
+        }
+    }
+
+    // notes a change that can wait for the next snapshot, see MINOR_CHANGES_DELAY
+    fn note_minor_change(&mut self) {
+        if self.minor_changes_since.is_none() {
+            self.minor_changes_since = Some(SystemTime::now());
+        }
+    }
+
+    // returns the user ID behind a username, if it was seen in the chat
+    fn find_user(&self, username: &str) -> Option<i64> {
+        let username = username.trim_start_matches('@').to_lowercase();
+        self.usernames.get(&username).copied()
+    }
+
+    // applies a change requested through the /settings menu, keeping values within their bounds
+    fn apply_menu_action(&mut self, action: &Action) {
+        let min_words = self.settings.min_output_words;
+        let max_words = self.settings.max_output_words;
+
+        match action {
+            Action::ToggleLearning => {
+                self.toggle_learning();
+            }
+            Action::ToggleFilter(name) => {
+                self.toggle_filter(name);
+            }
+            Action::MinWords(is_increase) => {
+                let min_words = menu::step_min_words(min_words, *is_increase)
+                    .max(1)
+                    .min(max_words);
+                self.set_output_length(min_words, max_words);
+            }
+            Action::MaxWords(is_increase) => {
+                let max_words = menu::step_max_words(max_words, *is_increase)
+                    .min(chain::MAX_WORDS)
+                    .max(min_words);
+                self.set_output_length(min_words, max_words);
+            }
+            Action::Temperature(is_increase) => {
+                let temperature = menu::step_temperature(self.settings.temperature, *is_increase)
+                    .clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+                self.set_temperature(temperature);
+            }
+            Action::StickerChance(is_increase) => {
+                let chance = menu::step_sticker_chance(self.settings.sticker_chance, *is_increase)
+                    .min(MAX_STICKER_CHANCE);
+                self.set_sticker_chance(chance);
+            }
+            Action::Done => (),
+        }
+    }
+
+    // returns the number of learnt phrases that can't be relearnt from the kept lines
+    // those are the merged dumps and the data learnt before the lines were kept
+    fn unkept_phrase_count(&self) -> u64 {
+        let kept: usize = self.contributions.values().map(|lines| lines.len()).sum();
+        self.chain.phrase_count().saturating_sub(kept as u64)
+    }
+
+    // changes the order of the Markov chain
+    // the chain is rebuilt from the kept lines, see unkept_phrase_count
+    // returns the number of relearnt lines
+    fn set_order(&mut self, order: usize) -> usize {
+        self.touch();
+
+        let mut chain = Chain::of_order(order);
+        let mut count = 0;
+        for line in self.contributions.values().flatten() {
+            chain.feed_str(line);
+            count += 1;
+        }
+
+        self.reverse = chain.reversed();
+        self.chain = chain;
+        self.is_stale = true;
+        count
+    }
+
+    // toggles learning of new words
+    pub fn toggle_learning(&mut self) -> String {
+        self.touch();
+        self.is_stale = true;
+
+        if self.is_learning {
+            self.is_learning = false;
+            String::from("[learning disabled]")
+        } else {
+            self.is_learning = true;
+            String::from("[learning enabled]")
+        }
+    }
+
+    // describes what the Markov chain has learnt so far
+    pub fn stats(&mut self) -> String {
+        self.touch();
+
+        let last_saved = match self.last_saved {
+            Some(time) => {
+                let timestamp = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+                utils::format_timestamp(timestamp)
+            }
+            None if self.is_dirty() => String::from("not yet"),
+            None => String::from("up to date"),
+        };
+
+        format!(
+            "[chain statistics]\nlearnt phrases: {}\ndistinct words: {}\n\
+             states: {}\ntransitions: {}\norder: {}\nsize in memory: {} (estimated)\n\
+             learning: {}\nlast saved: {}",
+            self.chain.phrase_count(),
+            self.chain.token_count(),
+            self.chain.state_count(),
+            self.chain.transition_count(),
+            self.chain.order(),
+            utils::format_size(self.estimated_size() as u64),
+            if self.is_learning {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            last_saved
+        )
+    }
+
+    // lists the n most common words, merging their forms with different case or punctuation
+    // stopwords and censored words are left out
+    pub fn top_words(&mut self, n: usize) -> String {
+        self.touch();
+
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for (token, count) in self.chain.frequencies() {
+            let word = token
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            if count == 0
+                || word.is_empty()
+                || stopwords::is_stopword(&word)
+                || self.settings.is_censored(&word)
+            {
+                continue;
+            }
+            *counts.entry(word).or_insert(0) += u64::from(count);
+        }
+
+        if counts.is_empty() {
+            return String::from("[no words learnt]");
+        }
+
+        let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+        counts.sort_unstable_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+
+        let lines: Vec<String> = counts
+            .iter()
+            .take(n)
+            .enumerate()
+            .map(|(i, (word, count))| format!("{}. {} ({})", i + 1, word, count))
+            .collect();
+        format!("[top words]\n{}", lines.join("\n"))
+    }
+
+    // describes a word: how many times it was learnt and which words follow it the most
+    pub fn word_info(&mut self, word: &str) -> String {
+        self.touch();
+
+        let count = self.chain.frequency(word);
+        if count == 0 {
+            return format!("[\"{}\" hasn't been learnt]", word);
+        }
+
+        let mut followers = self.chain.followers(word);
+        followers.sort_unstable_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+        let total: u64 = followers.iter().map(|(_, weight)| weight).sum();
+
+        let lines: Vec<String> = followers
+            .iter()
+            .take(WORD_INFO_FOLLOWERS)
+            .enumerate()
+            .map(|(i, (next, weight))| {
+                let next = match next {
+                    Some(next) => format!("\"{}\"", next),
+                    None => String::from("(end of phrase)"),
+                };
+                let share = *weight as f64 * 100.0 / total.max(1) as f64;
+                format!("{}. {} - {} ({:.1}%)", i + 1, next, weight, share)
+            })
+            .collect();
+
+        format!(
+            "[word info: \"{}\"]\nlearnt: {} times\ndistinct next words: {}\n\
+             most common next words:\n{}",
+            word,
+            count,
+            followers.len(),
+            lines.join("\n")
+        )
+    }
+
+    // sets whether learnt lines are also fed into the global chain
+    pub fn set_share_globally(&mut self, is_shared: bool) {
+        self.touch();
+        self.settings.share_globally = is_shared;
+        self.is_stale = true;
+    }
+
+    // checks if the chat's retention period has run out
+    fn is_expired(&self) -> bool {
+        self.settings
+            .is_expired(self.learnt_since, utils::unix_now())
+    }
+
+    // forgets everything learnt in the chat
+    // unlike clear_data, its stored copies are deleted as well (see ChainInfo::delete_copies)
+    fn wipe(&mut self) {
+        self.chain = Chain::of_order(self.chain.order());
+        self.reverse = Chain::of_order(self.chain.order());
+        self.contributions.clear();
+        self.usernames.clear();
+        self.stickers.clear();
+        self.recent.clear();
+        self.context.clear();
+        self.learnt_messages.clear();
+        self.pending.clear();
+        self.learnt_since = None;
+        self.is_stale = true;
+    }
+
+    // deletes the backups, the trash and the named snapshots of a chat
+    // nothing can be restored afterwards
+    async fn delete_copies(chat_id: i64) -> Option<Error> {
+        if !storage::is_persistent() {
+            return None;
+        }
+
+        match ChainInfo::list_backups(chat_id).await {
+            Err(e) => return Some(e),
+            Ok(timestamps) => {
+                for timestamp in timestamps {
+                    let name = ChainInfo::backup_name(chat_id, timestamp);
+                    if let Some(err) = storage::delete_file(&name).await {
+                        return Some(err);
+                    }
+                }
+            }
+        }
+
+        match ChainInfo::list_trash(chat_id).await {
+            Err(e) => return Some(e),
+            Ok(timestamps) => {
+                for timestamp in timestamps {
+                    let name = ChainInfo::trash_name(chat_id, timestamp);
+                    if let Some(err) = storage::delete_file(&name).await {
+                        return Some(err);
+                    }
+                }
+            }
+        }
+
+        match ChainInfo::list_named_snapshots(chat_id).await {
+            Err(e) => return Some(e),
+            Ok(names) => {
+                for name in names {
+                    let name = ChainInfo::named_snapshot_name(chat_id, &name);
+                    if let Some(err) = storage::delete_file(&name).await {
+                        return Some(err);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    // checks if the object has changes that haven't been sent to the storage backend
+    // settings count too, as commands move the time the chat was last used
+    fn is_dirty(&self) -> bool {
+        self.is_stale
+            || self.minor_changes_since.is_some()
+            || !self.pending.is_empty()
+            || self.stored_settings.as_ref()
+                != Some(&settings::to_record(
+                    self.is_learning,
+                    &self.settings,
+                    self.learnt_since,
+                    self.last_used,
+                ))
+    }
+
+    // serializes the object to the storage backend before it is freed from memory
+    // replaces a Drop implementation, as destructors cannot await
+    pub async fn save(mut self) -> Option<Error> {
+        self.serialize_to_storage().await
+    }
+}
+
+// forgets the write-ahead log entries of stored chains (see wal::forget) on a blocking thread
+// the whole log is rewritten, which would otherwise stall every other chat
+async fn forget_logged(marks: Vec<(i64, u64)>) {
+    utils::run_blocking(move || wal::forget(&marks)).await
+}
+
+// parses a number taken from std::env, naming the variable if it's not one
+fn parse_var<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("{} is not a number", name))
+}
+
+// extracts LOG_COMPACTION_THRESHOLD from std::env
+// defaults to 500 lines if the variable is not set
+fn get_log_compaction_threshold() -> Result<usize, String> {
+    match env::var("LOG_COMPACTION_THRESHOLD") {
+        Err(_) => Ok(500),
+        Ok(lines) => parse_var("LOG_COMPACTION_THRESHOLD", &lines),
+    }
+}
+
+// extracts BACKUP_COUNT from std::env
+// defaults to 5 backups if the variable is not set
+fn get_backup_count() -> Result<usize, String> {
+    match env::var("BACKUP_COUNT") {
+        Err(_) => Ok(5),
+        Ok(count) => parse_var("BACKUP_COUNT", &count),
+    }
+}
+
+// extracts WARM_START_CHATS from std::env
+// defaults to 10 chats if the variable is not set
+fn get_warm_start_chats() -> Result<usize, String> {
+    match env::var("WARM_START_CHATS") {
+        Err(_) => Ok(10),
+        Ok(chats) => parse_var("WARM_START_CHATS", &chats),
+    }
+}
+
+// extracts MAX_STATES from std::env
+// defaults to 0 (no limit) if the variable is not set
+fn get_max_states() -> Result<usize, String> {
+    match env::var("MAX_STATES") {
+        Err(_) => Ok(0),
+        Ok(states) => parse_var("MAX_STATES", &states),
+    }
+}
+
+// extracts ALLOWED_CHATS (a comma-separated list of chat IDs) from std::env
+// returns None (meaning all chats are allowed) if the variable is not set or empty
+fn get_allowed_chats() -> Result<Option<HashSet<i64>>, String> {
+    let chats = match env::var("ALLOWED_CHATS") {
+        Ok(chats) if !chats.trim().is_empty() => chats,
+        _ => return Ok(None),
+    };
+
+    chats
+        .split(',')
+        .map(|id| {
+            id.trim()
+                .parse::<i64>()
+                .map_err(|_| String::from("ALLOWED_CHATS is not a list of chat IDs"))
+        })
+        .collect::<Result<HashSet<i64>, String>>()
+        .map(Some)
+}
+
+// extracts a number of days from std::env and returns a Duration
+// returns None if the variable is not set or set to 0
+fn get_days(name: &str) -> Result<Option<Duration>, String> {
+    let days = match env::var(name) {
+        Ok(days) if !days.trim().is_empty() => parse_var::<u64>(name, &days)?,
+        _ => return Ok(None),
+    };
+
+    if days == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Duration::from_secs(days * 24 * 60 * 60)))
+    }
+}
+
+// extracts MEMORY_BUDGET (in megabytes) from std::env and returns it in bytes
+// returns None (meaning only MAX_TIMEDELTA limits loaded chains) if the variable is not set or set to 0
+fn get_memory_budget() -> Result<Option<usize>, String> {
+    let megabytes = match env::var("MEMORY_BUDGET") {
+        Ok(megabytes) if !megabytes.trim().is_empty() => {
+            parse_var::<usize>("MEMORY_BUDGET", &megabytes)?
+        }
+        _ => return Ok(None),
+    };
+
+    if megabytes == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(megabytes * 1024 * 1024))
+    }
+}
+
+// extracts IDLE_DELETION_DAYS from std::env and returns a Duration
+// returns None (meaning idle chats are kept) if the variable is not set or set to 0
+fn get_idle_deletion() -> Result<Option<Duration>, String> {
+    get_days("IDLE_DELETION_DAYS")
+}
+
+// extracts RARE_TRANSITION_DAYS from std::env and returns a Duration
+// returns None (meaning rare transitions are only pruned on request) if the variable is not set or set to 0
+fn get_rare_transition_age() -> Result<Option<Duration>, String> {
+    get_days("RARE_TRANSITION_DAYS")
+}
+
+// extracts BACKUP_INTERVAL from std::env and returns a Duration
+// defaults to 24 hours if the variable is not set
+fn get_backup_interval() -> Result<Duration, String> {
+    let hours = match env::var("BACKUP_INTERVAL") {
+        Err(_) => 24,
+        Ok(hours) => parse_var::<u64>("BACKUP_INTERVAL", &hours)?,
+    };
+
+    Ok(Duration::from_secs(hours * 60 * 60))
+}
+
+// extracts MAX_TIMEDELTA from std::env and returns a Duration
+fn get_max_timedelta() -> Result<Duration, String> {
+    let minutes = env::var("MAX_TIMEDELTA").map_err(|_| String::from("MAX_TIMEDELTA not set"))?;
+    let minutes = parse_var::<u64>("MAX_TIMEDELTA", &minutes)?;
+
+    Ok(Duration::from_secs(minutes * 60))
+}
+
+// the part of the configuration that can be changed without a restart (see reload_config)
+struct Config {
+    // the maximum duration a chat can stay idle without getting dropped from memory
+    max_timedelta: Duration,
+    // the number of logged lines that triggers a full snapshot upload
+    log_compaction_threshold: usize,
+    // the number of backups kept for each chat
+    backup_count: usize,
+    // the minimum time between two automatic backups of a chat
+    backup_interval: Duration,
+    // the default maximum number of states of a chain
+    max_states: usize,
+    // the only chats the bot works in, if limited
+    allowed_chats: Option<HashSet<i64>>,
+    // the estimated memory all loaded chains may take together, if limited
+    memory_budget: Option<usize>,
+    // the time after which the files of a chat nobody talks in are deleted, if ever
+    idle_deletion: Option<Duration>,
+    // the time after which transitions learnt only once are pruned automatically, if ever
+    rare_transition_age: Option<Duration>,
+    // the number of the most recently used chats loaded on startup
+    warm_start_chats: usize,
+}
+
+impl Config {
+    // reads the configuration from std::env
+    // fails if any of the variables is invalid
+    fn from_env() -> Result<Config, String> {
+        Ok(Config {
+            max_timedelta: get_max_timedelta()?,
+            log_compaction_threshold: get_log_compaction_threshold()?,
+            backup_count: get_backup_count()?,
+            backup_interval: get_backup_interval()?,
+            max_states: get_max_states()?,
+            allowed_chats: get_allowed_chats()?,
+            memory_budget: get_memory_budget()?,
+            idle_deletion: get_idle_deletion()?,
+            rare_transition_age: get_rare_transition_age()?,
+            warm_start_chats: get_warm_start_chats()?,
+        })
+    }
+}
+
+lazy_static! {
+    // an invalid configuration on startup is fatal
+    static ref CONFIG: RwLock<Config> =
+        RwLock::new(Config::from_env().unwrap_or_else(|e| panic!("{}", e)));
+    // held while the schedules of phrases of the day are being saved
+    static ref SCHEDULE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// the reply to a command that couldn't be carried out, e.g. because storage failed
+pub const COMMAND_FAILED: &str = "[command failed, please try again later]";
+
+// reads a value from the current configuration
+// the lock is released right away, so that it's never held across an await
+fn config<T, F: FnOnce(&Config) -> T>(read: F) -> T {
+    read(&CONFIG.read().unwrap())
+}
+
+// re-reads the configuration from std::env, leaving loaded chains as they are
+// an invalid configuration is rejected as a whole and the current one is kept
+pub fn reload_config() -> Option<String> {
+    match Config::from_env() {
+        Ok(new_config) => {
+            *CONFIG.write().unwrap() = new_config;
+            None
+        }
+        Err(e) => Some(format!(
+            "Reloading the configuration failed, the previous one is kept: {}",
+            e
+        )),
+    }
+}
+
+// checks if the bot may work in a chat
+pub fn is_chat_allowed(chat_id: i64) -> bool {
+    // the global chain is only fed by allowed chats
+    if chat_id == chat_key::GLOBAL {
+        return true;
+    }
+
+    config(|c| match &c.allowed_chats {
+        Some(chats) => chats.contains(&chat_id),
+        None => true,
+    })
+}
+
+// a wrapper for ChainInfo
+pub struct ChainWrapper {
+    chains: HashMap<i64, ChainInfo>,
+    // "phrase of the day" schedules of all chats, kept in memory at all times
+    schedules: HashMap<i64, Daily>,
+    // the latest round of /quiz in each chat, a new round ends the previous one
+    quizzes: HashMap<i64, Quiz>,
+    // chats where /clear_data awaits confirmation, along with the requesting user and the time
+    clear_requests: HashMap<i64, (i64, SystemTime)>,
+    // the contents of the activity file as of the last upload, used to skip redundant ones
+    stored_activity: Vec<i64>,
+    // chats whose stored chain can't be read, along with the reason
+    // they're left alone until a restart, so that the blob is neither overwritten nor downloaded again
+    quarantined: HashMap<i64, String>,
+    // locks held while chains are being loaded, see ChainWrapper::load
+    loading: HashMap<i64, Arc<Mutex<()>>>,
+}
+
+// the outcome of ChainWrapper::save_chain
+enum Saved {
+    // the chain isn't loaded, or another upload of it is still in flight
+    Skipped,
+    // the chain is stored, up to the given write-ahead log entry
+    Done(u64),
+    Failed(Error),
+}
+
+impl Default for ChainWrapper {
+    fn default() -> ChainWrapper {
+        ChainWrapper::new()
+    }
+}
+
+impl ChainWrapper {
+    // creates a new ChainWrapper
+    pub fn new() -> ChainWrapper {
+        ChainWrapper {
+            chains: HashMap::new(),
+            schedules: HashMap::new(),
+            quizzes: HashMap::new(),
+            clear_requests: HashMap::new(),
+            stored_activity: Vec::new(),
+            quarantined: HashMap::new(),
+            loading: HashMap::new(),
+        }
+    }
+
+    // downloads the "phrase of the day" schedules of all chats
+    pub async fn load_schedules(&mut self) {
+        match daily::load().await {
+            Ok(schedules) => self.schedules = schedules,
+            Err(e) => {
+                dbg!(e);
+            }
+        }
+    }
+
+    // uploads the schedules of phrases of the day (see daily::save) without holding the lock
+    // saves run one at a time, each taking the schedules once it starts,
+    // so that an older copy never overwrites a newer one
+    async fn save_schedules(wrapper: &Mutex<ChainWrapper>) -> Option<Error> {
+        let _guard = SCHEDULE_LOCK.lock().await;
+        let schedules = wrapper.lock().await.schedules.clone();
+        daily::save(&schedules).await
+    }
+
+    // sets, shows or disables the "phrase of the day" of a specified chat
+    pub async fn set_daily(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        args: &str,
+    ) -> Result<String, Error> {
+        let args = args.trim();
+
+        let msg = {
+            let mut wrapper = wrapper.lock().await;
+            if args.is_empty() {
+                return Ok(match wrapper.schedules.get(&chat_id) {
+                    Some(daily) => format!("[phrase of the day posted at {}]", daily.describe()),
+                    None => String::from("[usage: /daily HH:MM [UTC offset], /daily off]"),
+                });
+            }
+
+            if args == "off" {
+                if wrapper.schedules.remove(&chat_id).is_none() {
+                    return Ok(String::from("[no phrase of the day scheduled]"));
+                }
+                String::from("[phrase of the day disabled]")
+            } else {
+                match Daily::parse(args) {
+                    Some(daily) => {
+                        wrapper.schedules.insert(chat_id, daily);
+                        format!("[phrase of the day posted at {}]", daily.describe())
+                    }
+                    None => {
+                        return Ok(String::from(
+                            "[usage: /daily HH:MM [UTC offset], /daily off]",
+                        ))
+                    }
+                }
+            }
+        };
+
+        match ChainWrapper::save_schedules(wrapper).await {
+            Some(err) => Err(err),
+            None => Ok(msg),
+        }
+    }
+
+    // generates the phrases of the day due at a given minute since the Unix epoch
+    // returns the IDs of the chats along with their phrases
+    pub async fn due_daily(wrapper: &Mutex<ChainWrapper>, epoch_minute: i64) -> Vec<(i64, String)> {
+        let due: Vec<i64> = wrapper
+            .lock()
+            .await
+            .schedules
+            .iter()
+            .filter(|(_, daily)| daily.is_due(epoch_minute))
+            .map(|(chat_id, _)| *chat_id)
+            .collect();
+
+        let mut phrases = Vec::new();
+        for chat_id in due {
+            let mut wrapper = ChainWrapper::lock_loaded(wrapper, &[chat_id]).await;
+            match wrapper.get_chain(chat_id) {
+                Ok(chain) => {
+                    if let Some(phrase) = chain.generate("", &mut rand::thread_rng()) {
+                        phrases.push((chat_id, phrase));
+                    }
+                }
+                Err(e) => {
+                    dbg!(e);
+                }
+            }
+        }
+
+        phrases
+    }
+
+    // returns the number of chains kept in memory
+    pub fn chain_count(&self) -> usize {
+        self.chains.len()
+    }
+
+    // estimates the memory taken by all the loaded chains, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.chains.values().map(|x| x.estimated_size()).sum()
+    }
+
+    // reports the estimated memory taken by the loaded chains, and which of them are the largest
+    pub fn memory_report(&self) -> String {
+        let mut sizes: Vec<(i64, usize)> = self
+            .chains
+            .iter()
+            .map(|(id, x)| (*id, x.estimated_size()))
+            .collect();
+        sizes.sort_unstable_by_key(|(_, size)| Reverse(*size));
+
+        let total: usize = sizes.iter().map(|(_, size)| size).sum();
+        let budget = match config(|c| c.memory_budget) {
+            Some(budget) => format!(
+                "{} ({}% used)",
+                utils::format_size(budget as u64),
+                total * 100 / budget
+            ),
+            None => String::from("none"),
+        };
+
+        let mut msg = format!(
+            "[memory]\nloaded chains: {}\nestimated size: {}\nbudget: {}",
+            sizes.len(),
+            utils::format_size(total as u64),
+            budget
+        );
+
+        if !sizes.is_empty() {
+            msg.push_str("\n\nlargest chains:");
+            for (chat, size) in sizes.iter().take(REPORT_TOP_CHATS) {
+                msg.push_str(&format!(
+                    "\n{} - {}",
+                    chat,
+                    utils::format_size(*size as u64)
+                ));
+            }
+        }
+
+        msg
+    }
+
+    // prunes chains right away if they take more than EARLY_PRUNE_THRESHOLD of MEMORY_BUDGET
+    // chains grow as they learn, so the budget may be exceeded long before the next prune
+    pub async fn prune_if_needed(wrapper: &Mutex<ChainWrapper>) {
+        let budget = match config(|c| c.memory_budget) {
+            Some(budget) => budget,
+            None => return,
+        };
+
+        let usage = wrapper.lock().await.memory_usage();
+        if usage > budget / 100 * EARLY_PRUNE_THRESHOLD {
+            ChainWrapper::prune(wrapper).await;
+        }
+    }
+
+    // returns an error message string
+    fn err_msg() -> String {
+        String::from(COMMAND_FAILED)
+    }
+
+    // checks if a chain may be used: its chat is on the allow-list and isn't quarantined
+    fn is_loadable(&self, chat_id: i64) -> bool {
+        is_chat_allowed(chat_id) && !self.quarantined.contains_key(&chat_id)
+    }
+
+    // returns the lock taken while a specified chain is being loaded
+    fn loading_lock(&mut self, chat_id: i64) -> Arc<Mutex<()>> {
+        // locks nobody holds or waits for aren't needed anymore
+        self.loading.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+        Arc::clone(
+            self.loading
+                .entry(chat_id)
+                .or_insert_with(|| Arc::new(Mutex::new(()))),
+        )
+    }
+
+    // loads the specified ChainInfo object unless it's loaded already, creating a new one if necessary
+    // the chain is downloaded without holding the lock, so that other chats aren't kept waiting
+    // chains are never created for chats outside of the allow-list
+    // a chain that can't be deserialized gets its chat quarantined, other errors are retried next time
+    async fn load(wrapper: &Mutex<ChainWrapper>, chat_id: i64) -> Result<(), Error> {
+        let lock = {
+            let mut wrapper = wrapper.lock().await;
+            if wrapper.chains.contains_key(&chat_id) || !wrapper.is_loadable(chat_id) {
+                return Ok(());
+            }
+            wrapper.loading_lock(chat_id)
+        };
+
+        // a chain asked for by many commands at once is downloaded only once
+        let _guard = lock.lock().await;
+        if wrapper.lock().await.chains.contains_key(&chat_id) {
+            return Ok(());
+        }
+
+        let chain = ChainInfo::new(chat_id).await;
+
+        let evicted = {
+            let mut wrapper = wrapper.lock().await;
+            match chain {
+                Ok(chain) => {
+                    // make room for the new chain first
+                    let evicted = wrapper.over_budget(chain.estimated_size());
+                    wrapper.chains.insert(chat_id, chain);
+                    evicted
+                }
+                Err(Error::Deserialization(reason)) => {
+                    alerts::report(&format!(
+                        "Quarantined {} until a restart, its stored chain can't be read",
+                        chat_id
+                    ));
+                    wrapper.quarantined.insert(chat_id, reason.clone());
+                    return Err(Error::Deserialization(reason));
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        ChainWrapper::evict(wrapper, &evicted).await;
+        Ok(())
+    }
+
+    // loads the specified chains (see ChainWrapper::load) and locks the wrapper
+    // the chains that couldn't be loaded are reported by the commands using them
+    pub async fn lock_loaded<'a>(
+        wrapper: &'a Mutex<ChainWrapper>,
+        chat_ids: &[i64],
+    ) -> MutexGuard<'a, ChainWrapper> {
+        loop {
+            let mut is_complete = true;
+            for chat_id in chat_ids {
+                if let Err(e) = ChainWrapper::load(wrapper, *chat_id).await {
+                    dbg!(e);
+                    is_complete = false;
+                }
+            }
+
+            // a chain may have been evicted before the lock was taken again, it's loaded once more
+            let guard = wrapper.lock().await;
+            if !is_complete
+                || chat_ids
+                    .iter()
+                    .all(|id| guard.chains.contains_key(id) || !guard.is_loadable(*id))
+            {
+                return guard;
+            }
+        }
+    }
+
+    // returns the specified ChainInfo object, which has to be loaded first (see ChainWrapper::lock_loaded)
+    fn get_chain(&mut self, chat_id: i64) -> Result<&mut ChainInfo, Error> {
+        if !is_chat_allowed(chat_id) {
+            return Err(Error::Unavailable(format!(
+                "{} is not on the allow-list",
+                chat_id
+            )));
+        }
+
+        if let Some(reason) = self.quarantined.get(&chat_id) {
+            return Err(Error::Deserialization(format!(
+                "{} is quarantined: {}",
+                chat_id, reason
+            )));
+        }
+
+        match self.chains.get_mut(&chat_id) {
+            Some(chain) => Ok(chain),
+            None => Err(Error::Unavailable(format!("{} is not loaded", chat_id))),
+        }
+    }
+
+    // makes sure a specified chain can be used, loading it if needed
+    // used by commands that only work on its stored copies, without holding the lock
+    async fn check_chain(wrapper: &Mutex<ChainWrapper>, chat_id: i64) -> Result<(), Error> {
+        ChainWrapper::lock_loaded(wrapper, &[chat_id])
+            .await
+            .get_chain(chat_id)
+            .map(|_| ())
+    }
+
+    // takes a serialized copy of a specified chain (see ChainInfo::to_bincode) to be stored aside
+    // returns None if the chain hasn't learnt anything, there's nothing worth keeping then
+    async fn serialize_learnt(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let mut wrapper = ChainWrapper::lock_loaded(wrapper, &[chat_id]).await;
+        let chain = wrapper.get_chain(chat_id)?;
+        chain.touch();
+
+        if chain.chain.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(chain.to_bincode()))
+        }
+    }
+
+    // feeds the specified Markov chain a batch of messages, in the order they were sent
+    // returns the learnt lines (along with their authors) if the chat shares them with the global chain
+    // returns the messages back if the chain couldn't be loaded, so that they're fed with the next batch
+    // rather than having a later save forget their write-ahead log entries
+    // messages of chats that can't be loaded until a restart are left to the write-ahead log
+    pub fn feed_batch(
+        &mut self,
+        chat_id: i64,
+        messages: batch::Batch,
+    ) -> Result<Vec<(i64, String)>, batch::Batch> {
+        let mut shared = Vec::new();
+
+        match self.get_chain(chat_id) {
+            Ok(chain) => {
+                for (seq, msg) in messages {
+                    chain.logged_until = chain.logged_until.max(seq);
+                    let lines = chain.feed_incoming(&msg);
+
+                    // the global chain can't unlearn the original text, so edits aren't shared
+                    if chain.settings.share_globally && chat_id != chat_key::GLOBAL && !msg.is_edit
+                    {
+                        shared.extend(lines.into_iter().map(|line| (msg.user_id, line)));
+                    }
+                }
+            }
+            Err(e) => {
+                dbg!(e);
+                if self.is_loadable(chat_id) {
+                    return Err(messages);
+                }
+            }
+        }
+
+        Ok(shared)
+    }
+
+    // feeds the global chain lines already learnt (and filtered) by one of the sharing chats
+    // every line comes with the sequence number of its write-ahead log entry (see batch::flush)
+    // the lines stay attributed to their authors, so that /forget_me reaches them as well
+    // returns the lines back if the chain couldn't be loaded, see feed_batch
+    pub fn feed_global(
+        &mut self,
+        lines: batch::SharedLines,
+    ) -> Result<(), (Error, batch::SharedLines)> {
+        let is_loadable = self.is_loadable(chat_key::GLOBAL);
+        match self.get_chain(chat_key::GLOBAL) {
+            Ok(chain) => {
+                chain.touch();
+                for (seq, user_id, line) in lines.iter() {
+                    chain.logged_until = chain.logged_until.max(*seq);
+                    chain.learn(*user_id, line);
+                }
+                chain.enforce_size_limit();
+                Ok(())
+            }
+            Err(e) if is_loadable => Err((e, lines)),
+            Err(e) => {
+                dbg!(e);
+                Ok(())
+            }
+        }
+    }
+
+    // sets whether a specified chat feeds the global chain
+    pub fn set_share_globally(&mut self, chat_id: i64, is_shared: bool) -> Result<String, Error> {
+        if chat_id == chat_key::GLOBAL {
+            return Ok(ChainWrapper::err_msg());
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        chain.set_share_globally(is_shared);
+        Ok(if is_shared {
+            String::from(
+                "[from now on, what the bot learns here is also shared with other chats \
+                 through /speak_global]",
+            )
+        } else {
+            String::from(
+                "[the bot no longer shares what it learns here, \
+                 lines shared so far stay in the global chain]",
+            )
+        })
+    }
+
+    // counts a sticker sent in a specified chat
+    // returns a sticker to reply with, if any
+    pub fn feed_sticker(&mut self, chat_id: i64, file_id: &str) -> Option<String> {
+        match self.get_chain(chat_id) {
+            Ok(chain) => chain.feed_sticker(file_id, &mut rand::thread_rng()),
+            Err(e) => {
+                dbg!(e);
+                None
+            }
+        }
+    }
+
+    // picks a popular sticker of a specified chat
+    // returns the message to send instead if the chat has no stickers
+    pub fn sticker(&mut self, chat_id: i64) -> Result<Result<String, String>, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(match chain.pick_sticker(&mut rand::thread_rng()) {
+            Some(file_id) => Ok(file_id),
+            None => Err(String::from("[no stickers learnt]")),
+        })
+    }
+
+    // sets the chance of replying to stickers in a specified chat
+    pub fn set_sticker_chance(&mut self, chat_id: i64, chance: u32) -> Result<String, Error> {
+        if chance > MAX_STICKER_CHANCE {
+            return Ok(format!(
+                "[the chance has to be between 0 and {}%]",
+                MAX_STICKER_CHANCE
+            ));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        chain.set_sticker_chance(chance);
+        Ok(format!("[the bot will reply to {}% of stickers]", chance))
+    }
+
+    // changes the order of a specified Markov chain
+    // phrases that can't be relearnt are only dropped once the change is confirmed
+    // the current state is backed up first, so the operation can be undone
+    pub async fn set_order(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        order: usize,
+        is_confirmed: bool,
+    ) -> Result<String, Error> {
+        if !(1..=MAX_ORDER).contains(&order) {
+            return Ok(format!("[the order has to be between 1 and {}]", MAX_ORDER));
+        }
+
+        // the chain is checked once before the backup and once more before it's rebuilt,
+        // as it may have changed while the lock was released
+        let mut is_backed_up = false;
+        loop {
+            let mut guard = ChainWrapper::lock_loaded(wrapper, &[chat_id]).await;
+            let chain = guard.get_chain(chat_id)?;
+            if chain.chain.order() == order {
+                return Ok(format!("[the order is already {}]", order));
+            }
+
+            let lost = chain.unkept_phrase_count();
+            if lost > 0 && !is_confirmed {
+                return Ok(format!(
+                    "[{} of {} learnt phrases (imported dumps and old data) can't be relearnt \
+                     with a new order and will be lost. send /set_order {} confirm if you're sure, \
+                     /restore can bring them back afterwards]",
+                    lost,
+                    chain.chain.phrase_count(),
+                    order
+                ));
+            }
+
+            if !is_backed_up {
+                drop(guard);
+                if let Some(err) = ChainWrapper::backup_chain(wrapper, chat_id).await {
+                    return Err(err);
+                }
+                is_backed_up = true;
+                continue;
+            }
+
+            let count = chain.set_order(order);
+            drop(guard);
+
+            // lines kept in the append log were learnt with the old order, replace it right away
+            if let Some(err) = ChainWrapper::persist(wrapper, chat_id).await {
+                return Err(err);
+            }
+
+            return Ok(if lost > 0 {
+                format!(
+                    "[order set to {}, {} lines relearnt, {} phrases dropped]",
+                    order, count, lost
+                )
+            } else {
+                format!("[order set to {}, {} lines relearnt]", order, count)
+            });
+        }
+    }
+
+    // describes a specified Markov chain
+    pub fn stats(&mut self, chat_id: i64) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(chain.stats())
+    }
+
+    // lists the most common words of a specified Markov chain
+    pub fn top_words(&mut self, chat_id: i64, n: Option<usize>) -> Result<String, Error> {
+        let n = n.unwrap_or(DEFAULT_TOP_WORDS);
+        if !(1..=MAX_TOP_WORDS).contains(&n) {
+            return Ok(format!(
+                "[the number of words has to be between 1 and {}]",
+                MAX_TOP_WORDS
+            ));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        Ok(chain.top_words(n))
+    }
+
+    // describes a word of a specified Markov chain
+    pub fn word_info(&mut self, chat_id: i64, word: &str) -> Result<String, Error> {
+        if word.is_empty() || tokenizer::count(word) != 1 {
+            return Ok(String::from("[usage: /word_info <word>]"));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        Ok(chain.word_info(word))
+    }
+
+    // toggles learning of new words for a specified Markov chain
+    pub fn toggle_learning(&mut self, chat_id: i64) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(chain.toggle_learning())
+    }
+
+    // sets the maximum number of states of a specified Markov chain
+    pub fn set_max_states(&mut self, chat_id: i64, max_states: usize) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        let evicted = chain.set_max_states(max_states);
+        Ok(if max_states == 0 {
+            String::from("[chain size is no longer limited]")
+        } else {
+            format!(
+                "[chain size limited to {} states, {} evicted]",
+                max_states, evicted
+            )
+        })
+    }
+
+    // removes the transitions of a specified Markov chain learnt only once more than days ago
+    // defaults to RARE_TRANSITION_DAYS, or DEFAULT_RARE_TRANSITION_DAYS if it's not set
+    pub fn prune_rare(&mut self, chat_id: i64, days: Option<u64>) -> Result<String, Error> {
+        let max_age = match days {
+            Some(0) => return Ok(String::from("[the number of days has to be at least 1]")),
+            Some(days) => Duration::from_secs(days * 24 * 60 * 60),
+            None => config(|c| c.rare_transition_age).unwrap_or(Duration::from_secs(
+                DEFAULT_RARE_TRANSITION_DAYS * 24 * 60 * 60,
+            )),
+        };
+
+        let chain = self.get_chain(chat_id)?;
+        Ok(match chain.prune_rare(max_age) {
+            Ok(removed) => format!(
+                "[{} rare transitions removed, {} others will be unless learnt again within {} days]",
+                removed,
+                chain.rare_transition_count(),
+                max_age.as_secs() / (24 * 60 * 60)
+            ),
+            Err(due) => format!(
+                "[rare transitions can't be removed until {}]",
+                utils::format_timestamp(due)
+            ),
+        })
+    }
+
+    // removes the transitions learnt only once more than RARE_TRANSITION_DAYS ago from loaded chains
+    // does nothing if the variable is not set
+    pub fn prune_rare_all(&mut self) {
+        let max_age = match config(|c| c.rare_transition_age) {
+            Some(max_age) => max_age,
+            None => return,
+        };
+
+        for (chat_id, chain) in self.chains.iter_mut() {
+            if let Ok(removed) = chain.prune_rare(max_age) {
+                if removed > 0 {
+                    dbg!(chat_id, removed);
+                }
+            }
+        }
+    }
+
+    // returns the text and buttons of the /settings menu of a specified chat
+    pub fn settings_menu(&mut self, chat_id: i64) -> Result<(String, menu::Buttons), Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(menu::render(chain.is_learning, &chain.settings))
+    }
+
+    // applies a change requested through the /settings menu of a specified chat
+    // returns the updated menu, or None once the menu has been closed
+    // returns the notification to show instead if the option is unknown
+    pub fn menu_action(
+        &mut self,
+        chat_id: i64,
+        data: &str,
+    ) -> Result<Result<Option<(String, menu::Buttons)>, String>, Error> {
+        let action = match Action::parse(data) {
+            Some(action) => action,
+            None => return Ok(Err(String::from("[unknown option]"))),
+        };
+
+        let chain = self.get_chain(chat_id)?;
+        chain.apply_menu_action(&action);
+        Ok(Ok(match action {
+            Action::Done => None,
+            _ => Some(menu::render(chain.is_learning, &chain.settings)),
+        }))
+    }
+
+    // returns the IDs of stored chats whose retention period has run out
+    // only their small settings files are downloaded, chains stay in storage
+    pub async fn expired_chats() -> Result<Vec<i64>, Error> {
+        let now = utils::unix_now();
+        let mut expired = Vec::new();
+
+        for name in storage::list_files("").await? {
+            if !name.ends_with(".settings") {
+                continue;
+            }
+
+            let chat_id = match name.trim_end_matches(".settings").parse::<i64>() {
+                Ok(chat_id) => chat_id,
+                Err(_) => continue,
+            };
+
+            if let Some(bytes) = storage::download_file(&name).await? {
+                match settings::from_record(&bytes) {
+                    Ok(record) if record.settings.is_expired(record.learnt_since, now) => {
+                        expired.push(chat_id)
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        dbg!(e);
+                    }
+                }
+            }
+        }
+
+        Ok(expired)
+    }
+
+    // returns the IDs of stored chats that haven't been used for IDLE_DELETION_DAYS
+    // the time a chat was last used is kept in its settings file, so chains stay in storage
+    // chats stored before that was tracked fall back to the newest modification time of their files
+    // returns nothing if idle chats are kept
+    pub async fn idle_chats() -> Result<Vec<i64>, Error> {
+        let idle_deletion = match config(|c| c.idle_deletion) {
+            Some(idle_deletion) => idle_deletion.as_secs(),
+            None => return Ok(Vec::new()),
+        };
+
+        // the newest modification time of each chat's files
+        let mut modified: HashMap<i64, u64> = HashMap::new();
+        let mut has_settings = HashSet::new();
+        for (name, _, time) in storage::list_files_with_details("").await? {
+            let chat_id = match name.split('.').next().map(str::parse::<i64>) {
+                Some(Ok(chat_id)) => chat_id,
+                _ => continue,
+            };
+
+            if name == ChainInfo::settings_name(chat_id) {
+                has_settings.insert(chat_id);
+            }
+
+            // files without a known modification time are never considered idle
+            let time = time.unwrap_or(u64::MAX);
+            let newest = modified.entry(chat_id).or_insert(0);
+            *newest = (*newest).max(time);
+        }
+
+        let now = utils::unix_now();
+        let mut idle = Vec::new();
+
+        for (chat_id, modified) in modified {
+            let last_used = if has_settings.contains(&chat_id) {
+                match storage::download_file(&ChainInfo::settings_name(chat_id)).await? {
+                    Some(bytes) => match settings::from_record(&bytes) {
+                        Ok(record) => record.last_used.unwrap_or(modified),
+                        Err(e) => {
+                            dbg!(e);
+                            continue;
+                        }
+                    },
+                    None => modified,
+                }
+            } else {
+                modified
+            };
+
+            if now.saturating_sub(last_used) > idle_deletion {
+                idle.push(chat_id);
+            }
+        }
+
+        Ok(idle)
+    }
+
+    // moves everything learnt in a chat to its new ID, as happens when a group becomes a supergroup
+    // backups and snapshots move along, the files left under the old ID are deleted
+    // a chat that has already learnt something under the new ID is left alone
+    pub async fn migrate_chat(
+        wrapper: &Mutex<ChainWrapper>,
+        old_id: i64,
+        new_id: i64,
+    ) -> Option<Error> {
+        let loaded = wrapper
+            .lock()
+            .await
+            .chains
+            .get(&new_id)
+            .map(|chain| !chain.chain.is_empty());
+
+        let is_taken = match loaded {
+            Some(is_taken) => is_taken,
+            None if storage::is_persistent() => {
+                match storage::list_files(&ChainInfo::snapshot_name(new_id)).await {
+                    Ok(names) => names.contains(&ChainInfo::snapshot_name(new_id)),
+                    Err(e) => return Some(e),
+                }
+            }
+            None => false,
+        };
+        if is_taken {
+            return Some(Error::Unavailable(format!(
+                "Not migrating {} to {}, the new chat already has a chain",
+                old_id, new_id
+            )));
+        }
+
+        {
+            let mut wrapper = ChainWrapper::lock_loaded(wrapper, &[old_id]).await;
+            if let Err(e) = wrapper.get_chain(old_id) {
+                return Some(e);
+            }
+            let mut chain = match wrapper.chains.remove(&old_id) {
+                Some(chain) => chain,
+                None => panic!("HashMap changed mid-extraction"),
+            };
+
+            // an upload in flight is still stored under the old ID, the chain is stored anew below
+            chain.chat_id = new_id;
+            chain.stored_settings = None;
+            chain.is_stale = true;
+            chain.is_uploading = false;
+            wrapper.chains.insert(new_id, chain);
+        }
+
+        if let Some(err) = ChainWrapper::persist(wrapper, new_id).await {
+            let mut wrapper = wrapper.lock().await;
+            if let Some(mut chain) = wrapper.chains.remove(&new_id) {
+                chain.chat_id = old_id;
+                wrapper.chains.insert(old_id, chain);
+            }
+            return Some(err);
+        }
+        utils::run_blocking(move || wal::forget_chat(old_id)).await;
+
+        let is_scheduled = {
+            let mut wrapper = wrapper.lock().await;
+            match wrapper.schedules.remove(&old_id) {
+                Some(daily) => {
+                    wrapper.schedules.insert(new_id, daily);
+                    true
+                }
+                None => false,
+            }
+        };
+
+        if is_scheduled {
+            if let Some(err) = ChainWrapper::save_schedules(wrapper).await {
+                dbg!(err);
+            }
+        }
+
+        if !storage::is_persistent() {
+            return None;
+        }
+
+        // the snapshot, the log and the settings have just been stored under the new ID
+        let current = [
+            ChainInfo::snapshot_name(old_id),
+            ChainInfo::log_name(old_id),
+            ChainInfo::settings_name(old_id),
+        ];
+
+        let mut names = Vec::new();
+        for prefix in [old_id.to_string(), ChainInfo::trash_prefix(old_id)].iter() {
+            match storage::list_files(prefix).await {
+                Ok(found) => names.extend(found),
+                Err(e) => return Some(e),
+            }
+        }
+
+        // the prefix also matches longer IDs, e.g. 12 matches 123
+        let (old, new) = (format!("{}.", old_id), format!("{}.", new_id));
+        let mut res = None;
+        for name in names {
+            let renamed = if name == old_id.to_string() {
+                new_id.to_string()
+            } else if name.starts_with(&old) {
+                name.replacen(&old, &new, 1)
+            } else if name.starts_with(&ChainInfo::trash_prefix(old_id)) {
+                name.replacen(
+                    &ChainInfo::trash_prefix(old_id),
+                    &ChainInfo::trash_prefix(new_id),
+                    1,
+                )
+            } else {
+                continue;
+            };
+
+            if !current.contains(&name) {
+                let copied = match storage::download_file(&name).await {
+                    Ok(Some(bytes)) => storage::update_or_create_file(&bytes, &renamed).await,
+                    Ok(None) => None,
+                    Err(e) => Some(e),
+                };
+
+                // a file that couldn't be copied is kept under the old ID
+                if let Some(err) = copied {
+                    res = res.or(Some(err));
+                    continue;
+                }
+            }
+
+            if let Some(err) = storage::delete_file(&name).await {
+                res = res.or(Some(err));
+            }
+        }
+
+        res
+    }
+
+    // deletes all files (backups included) of chats nobody has talked in for a long time
+    // chats loaded in memory have just been used, so they're skipped
+    pub async fn delete_idle(wrapper: &Mutex<ChainWrapper>, chats: Vec<i64>) {
+        let mut deleted = Vec::new();
+
+        for chat_id in chats {
+            if wrapper.lock().await.chains.contains_key(&chat_id) {
+                continue;
+            }
+
+            let names = match storage::list_files(&chat_id.to_string()).await {
+                Ok(names) => names,
+                Err(e) => {
+                    dbg!(e);
+                    continue;
+                }
+            };
+
+            // the prefix also matches longer IDs, e.g. 12 matches 123
+            let prefix = format!("{}.", chat_id);
+            let names = names
+                .into_iter()
+                .filter(|name| *name == chat_id.to_string() || name.starts_with(&prefix));
+
+            let mut res = None;
+            for name in names {
+                res = res.or(storage::delete_file(&name).await);
+            }
+
+            match res {
+                Some(err) => {
+                    alerts::report(&format!("Deleting idle chat {} failed: {}", chat_id, err));
+                    dbg!(err);
+                }
+                None => deleted.push(chat_id),
+            }
+        }
+
+        if deleted.is_empty() {
+            return;
+        }
+
+        alerts::report(&format!("Deleted idle chats: {:?}", deleted));
+
+        // phrases of the day would bring the chats back
+        let is_scheduled = {
+            let mut wrapper = wrapper.lock().await;
+            let count = wrapper.schedules.len();
+            wrapper
+                .schedules
+                .retain(|chat_id, _| !deleted.contains(chat_id));
+            wrapper.schedules.len() != count
+        };
+
+        if is_scheduled {
+            if let Some(err) = ChainWrapper::save_schedules(wrapper).await {
+                dbg!(err);
+            }
+        }
+    }
+
+    // wipes chats whose retention period has run out, along with the lines they shared globally
+    // checks the chains in memory along with the given stored ones, taking the lock for each separately
+    pub async fn wipe_expired(wrapper: &Mutex<ChainWrapper>, stored: Vec<i64>) {
+        let mut chats: Vec<i64> = wrapper.lock().await.chains.keys().cloned().collect();
+        chats.extend(stored);
+        chats.sort_unstable();
+        chats.dedup();
+
+        for chat_id in chats {
+            // the number of lines the chat shared with the global chain, which are forgotten first
+            let forgotten = {
+                let mut wrapper =
+                    ChainWrapper::lock_loaded(wrapper, &[chat_id, chat_key::GLOBAL]).await;
+                let contributions = match wrapper.get_chain(chat_id) {
+                    Ok(chain) if chain.is_expired() => chain.contributions.clone(),
+                    Ok(_) => continue,
+                    Err(e) => {
+                        dbg!(e);
+                        continue;
+                    }
+                };
+
+                if chat_id == chat_key::GLOBAL || contributions.is_empty() {
+                    Ok(0)
+                } else {
+                    wrapper
+                        .get_chain(chat_key::GLOBAL)
+                        .map(|global| global.forget_lines(&contributions))
+                }
+            };
+
+            // the append log may still hold the shared lines, it's replaced right away
+            // if that fails, the chat is left for the next check
+            let res = match forgotten {
+                Ok(0) => None,
+                Ok(_) => ChainWrapper::persist(wrapper, chat_key::GLOBAL).await,
+                Err(e) => Some(e),
+            };
+
+            if let Some(err) = res {
+                alerts::report(&format!(
+                    "Wiping the global lines of {} failed: {}",
+                    chat_id, err
+                ));
+                dbg!(err);
+                continue;
+            }
+
+            match ChainWrapper::lock_loaded(wrapper, &[chat_id])
+                .await
+                .get_chain(chat_id)
+            {
+                Ok(chain) => chain.wipe(),
+                Err(_) => continue,
+            }
+
+            let res = match ChainInfo::delete_copies(chat_id).await {
+                Some(err) => Some(err),
+                None => ChainWrapper::persist(wrapper, chat_id).await,
+            };
+
+            if let Some(err) = res {
+                alerts::report(&format!("Wiping {} failed: {}", chat_id, err));
+                dbg!(err);
+            }
+        }
+    }
+
+    // returns the IDs of all chats with a persisted chain
+    pub async fn stored_chats() -> Result<Vec<i64>, Error> {
+        let files = storage::list_files("").await?;
+
+        // snapshots, logs and backups of a chat all start with its ID
+        let mut chats: Vec<i64> = files
+            .iter()
+            .filter_map(|name| name.split('.').next()?.parse::<i64>().ok())
+            .collect();
+
+        chats.sort_unstable();
+        chats.dedup();
+        Ok(chats)
+    }
+
+    // checks if the stored chain of a specified chat (along with its append log) can be read
+    pub async fn verify(chat_id: i64) -> Option<Error> {
+        ChainInfo::deserialize_from_storage(chat_id).await.err()
+    }
+
+    // reports the space taken by persisted chats and the remaining quota
+    pub async fn storage_report(wrapper: &Mutex<ChainWrapper>) -> Result<String, Error> {
+        let loaded = wrapper.lock().await.chains.len();
+        let files = storage::list_files_with_sizes("").await?;
+
+        // group the snapshots, logs and backups of each chat together
+        let mut sizes: HashMap<&str, u64> = HashMap::new();
+        for (name, size) in files.iter() {
+            let chat = name.split('.').next().unwrap_or(name);
+
+            // skip files shared by all chats
+            if chat.parse::<i64>().is_err() {
+                continue;
+            }
+
+            *sizes.entry(chat).or_insert(0) += size;
+        }
+
+        let total: u64 = sizes.values().sum();
+        let mut largest: Vec<(&str, u64)> = sizes.into_iter().collect();
+        largest.sort_unstable_by_key(|(_, size)| Reverse(*size));
+
+        let mut msg = format!(
+            "[storage usage]\nchats stored: {}\nchats in memory: {}\ntotal size: {}",
+            largest.len(),
+            loaded,
+            utils::format_size(total)
+        );
+
+        match storage::get_quota().await {
+            Ok((used, Some(limit))) => msg.push_str(&format!(
+                "\nquota: {} of {} used ({} left)",
+                utils::format_size(used),
+                utils::format_size(limit),
+                utils::format_size(limit.saturating_sub(used))
+            )),
+            Ok((used, None)) => msg.push_str(&format!(
+                "\nquota: {} used (unlimited)",
+                utils::format_size(used)
+            )),
+            Err(e) => {
+                dbg!(e);
+            }
+        }
+
+        if !largest.is_empty() {
+            msg.push_str("\n\nlargest chats:");
+            for (chat, size) in largest.iter().take(REPORT_TOP_CHATS) {
+                msg.push_str(&format!("\n{} - {}", chat, utils::format_size(*size)));
+            }
+        }
+
+        Ok(msg)
+    }
+
+    // saves a specified chain, holding the lock only while taking its changes and applying the outcome
+    // the upload itself runs in between, so that other chats aren't kept waiting
+    // an evicted chain is dropped once it's saved, unless it's been used in the meantime
+    async fn save_chain(wrapper: &Mutex<ChainWrapper>, chat_id: i64, is_evicted: bool) -> Saved {
+        loop {
+            let mut upload = {
+                let mut wrapper = wrapper.lock().await;
+                let chain = match wrapper.chains.get_mut(&chat_id) {
+                    Some(chain) if !chain.is_uploading => chain,
+                    _ => return Saved::Skipped,
+                };
+
+                match chain.prepare_upload(is_evicted) {
+                    Some(upload) => upload,
+                    None => {
+                        let mark = chain.logged_until;
+                        if is_evicted {
+                            wrapper.chains.remove(&chat_id);
+                        }
+                        return Saved::Done(mark);
+                    }
+                }
+            };
+
+            let res = upload.store().await;
+
+            let mut wrapper = wrapper.lock().await;
+            let (mark, last_accessed) = (upload.logged_until, upload.last_accessed);
+
+            let chain = match wrapper.chains.get_mut(&chat_id) {
+                Some(chain) => chain,
+                // the chain was moved or replaced meanwhile
+                None => {
+                    return match res {
+                        Some(err) => Saved::Failed(err),
+                        None => Saved::Done(mark),
+                    }
+                }
+            };
+
+            // a snapshot stored meanwhile may have been overwritten, it's stored again right away
+            let is_overtaken = match upload.contents {
+                Contents::Nothing => false,
+                _ => chain.compactions != upload.compactions,
+            };
+
+            if let Some(err) = chain.finish_upload(upload, res) {
+                return Saved::Failed(err);
+            }
+
+            if is_overtaken {
+                continue;
+            }
+
+            if is_evicted && chain.last_accessed == last_accessed && !chain.is_dirty() {
+                wrapper.chains.remove(&chat_id);
+            }
+            return Saved::Done(mark);
+        }
+    }
+
+    // stores a full snapshot of a specified chain right away, see ChainInfo::prepare_snapshot
+    // used by commands that change the chain in a way the append log can't express
+    // like save_chain, the lock is held only while taking the snapshot and applying the outcome
+    async fn persist(wrapper: &Mutex<ChainWrapper>, chat_id: i64) -> Option<Error> {
+        if !storage::is_persistent() {
+            return None;
+        }
+
+        loop {
+            let upload = {
+                let mut wrapper = wrapper.lock().await;
+                match wrapper.chains.get_mut(&chat_id) {
+                    None => return None,
+                    // the snapshot has to be taken once the upload in flight is done
+                    Some(chain) if chain.is_uploading => None,
+                    Some(chain) => Some(chain.prepare_snapshot()),
+                }
+            };
+
+            let mut upload = match upload {
+                Some(upload) => upload,
+                None => {
+                    tokio::time::delay_for(UPLOAD_POLL).await;
+                    continue;
+                }
+            };
+
+            let res = upload.store().await;
+
+            let mut wrapper = wrapper.lock().await;
+            let mark = upload.logged_until;
+            let chain = match wrapper.chains.get_mut(&chat_id) {
+                Some(chain) => chain,
+                None => return res,
+            };
+
+            let is_overtaken = chain.compactions != upload.compactions;
+            if let Some(err) = chain.finish_upload(upload, res) {
+                return Some(err);
+            }
+
+            if !is_overtaken {
+                drop(wrapper);
+                // the snapshot already contains the lines kept in the write-ahead log
+                forget_logged(vec![(chat_id, mark)]).await;
+                return None;
+            }
+        }
+    }
+
+    // sends pending changes of all the ChainInfo objects to the storage backend
+    // returns the numbers of saved chains and of the ones that failed to save
+    pub async fn flush_all(wrapper: &Mutex<ChainWrapper>) -> (usize, usize) {
+        let dirty: Vec<i64> = wrapper
+            .lock()
+            .await
+            .chains
+            .iter()
+            .filter(|(_, x)| x.is_dirty())
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut saved = Vec::new();
+        let mut failed = 0;
+
+        for id in dirty {
+            match ChainWrapper::save_chain(wrapper, id, false).await {
+                Saved::Done(mark) => saved.push((id, mark)),
+                Saved::Failed(err) => {
+                    alerts::report(&format!("Saving {} failed: {}", id, err));
+                    dbg!(err);
+                    failed += 1;
+                }
+                Saved::Skipped => (),
+            }
+        }
+
+        let count = saved.len();
+        forget_logged(saved).await;
+
+        let activity = wrapper.lock().await.activity();
+        if let Some(ids) = activity {
+            match ChainWrapper::store_activity(&ids).await {
+                Some(err) => {
+                    dbg!(err);
+                }
+                None => wrapper.lock().await.stored_activity = ids,
+            }
+        }
+
+        (count, failed)
+    }
+
+    // returns the IDs of the most recently used chains, most recent first
+    // they're loaded on the next start, sparing their first commands the download
+    // returns None if they're stored already
+    fn activity(&self) -> Option<Vec<i64>> {
+        let mut chats: Vec<(SystemTime, i64)> = self
+            .chains
+            .iter()
+            .map(|(id, x)| (x.last_accessed, *id))
+            .collect();
+        chats.sort_unstable_by(|a, b| b.cmp(a));
+
+        let ids: Vec<i64> = chats
+            .into_iter()
+            .take(config(|c| c.warm_start_chats))
+            .map(|(_, id)| id)
+            .collect();
+
+        // an empty list would only mean nothing happened since the start, keep the previous one
+        if ids.is_empty() || ids == self.stored_activity {
+            None
+        } else {
+            Some(ids)
+        }
+    }
+
+    // stores the IDs of the most recently used chains (see ChainWrapper::activity)
+    async fn store_activity(ids: &[i64]) -> Option<Error> {
+        let bytes = serde_json::to_vec(ids).expect("Serialization failed");
+        storage::update_or_create_file(&bytes, ACTIVITY_FILE).await
+    }
+
+    // returns the IDs of the chats used the most recently before the last shutdown, most recent first
+    pub async fn recently_active() -> Result<Vec<i64>, Error> {
+        match storage::download_file(ACTIVITY_FILE).await? {
+            Some(bytes) => {
+                serde_json::from_slice(&bytes).map_err(|e| Error::Deserialization(e.to_string()))
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // loads a specified chain ahead of time, unless it's already loaded
+    pub async fn preload(wrapper: &Mutex<ChainWrapper>, chat_id: i64) {
+        if let Err(e) = ChainWrapper::load(wrapper, chat_id).await {
+            dbg!(e);
+        }
+    }
+
+    // checks if a specified chain is loaded in memory
+    pub fn is_loaded(&self, chat_id: i64) -> bool {
+        self.chains.contains_key(&chat_id)
+    }
+
+    // saves and drops all the ChainInfo objects, SHUTDOWN_CONCURRENCY at a time
+    // gives up on the chains that aren't saved within a given time, leaving them in the write-ahead log
+    // a clean shutdown leaves the write-ahead log empty
+    // returns the numbers of saved chains, of the ones that failed to save and of the ones given up on
+    pub async fn drop_all(&mut self, timeout: Duration) -> (usize, usize, usize) {
+        if let Some(ids) = self.activity() {
+            if let Some(err) = ChainWrapper::store_activity(&ids).await {
+                dbg!(err);
+            }
+        }
+
+        // chains whose upload is still in flight are given up on, the upload may not be done in time
+        let total = self.chains.len();
+        let chains: Vec<(i64, ChainInfo)> = self
+            .chains
+            .drain()
+            .filter(|(_, x)| !x.is_uploading)
+            .collect();
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        let mut saves = Box::pin(
+            stream::iter(chains)
+                .map(|(id, chain)| async move {
+                    let mark = chain.logged_until;
+                    (id, mark, chain.save().await)
+                })
+                .buffer_unordered(SHUTDOWN_CONCURRENCY),
+        );
+
+        let mut saved = Vec::new();
+        let mut failed = 0;
+
+        loop {
+            match tokio::time::timeout_at(deadline, saves.next()).await {
+                Ok(Some((id, mark, None))) => saved.push((id, mark)),
+                Ok(Some((id, _, Some(err)))) => {
+                    alerts::report(&format!("Saving {} failed: {}", id, err));
+                    dbg!(err);
+                    failed += 1;
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    dbg!(err);
+                    break;
+                }
+            }
+        }
+
+        let count = saved.len();
+        forget_logged(saved).await;
+        (count, failed, total - count - failed)
+    }
+
+    // feeds the lines left in the write-ahead log after an unclean shutdown
+    // they're left for a start with working storage if there's none, as they couldn't be saved anyway
+    pub async fn replay_wal(wrapper: &Mutex<ChainWrapper>) {
+        if !storage::is_persistent() {
+            return;
+        }
+
+        let entries = wal::read_entries();
+        let mut chat_ids: Vec<i64> = entries.iter().map(|(_, chat_id, _)| *chat_id).collect();
+        chat_ids.sort_unstable();
+        chat_ids.dedup();
+
+        let mut wrapper = ChainWrapper::lock_loaded(wrapper, &chat_ids).await;
+        for (seq, chat_id, entry) in entries {
+            match wrapper.get_chain(chat_id) {
+                Ok(chain) => {
+                    chain.logged_until = chain.logged_until.max(seq);
+                    match entry {
+                        wal::Entry::Line(user_id, line) => chain.learn(user_id, &line),
+                        // the lines shared with the global chain have entries of their own
+                        wal::Entry::Message(msg) => {
+                            chain.feed_incoming(&msg);
+                        }
+                    }
+                }
+                Err(e) => {
+                    dbg!(e);
+                }
+            }
+        }
+    }
+
+    // checks if the ChainInfo is old enough to be dropped
+    fn is_old(elem: &ChainInfo) -> bool {
+        elem.last_accessed.elapsed().unwrap() > config(|c| c.max_timedelta)
+    }
+
+    // returns the IDs of the least recently used chains that have to be dropped
+    // for the rest (and an incoming chain of a given size) to fit into MEMORY_BUDGET
+    fn over_budget(&self, incoming: usize) -> Vec<i64> {
+        let budget = match config(|c| c.memory_budget) {
+            Some(budget) => budget,
+            None => return Vec::new(),
+        };
+
+        let mut chains: Vec<(SystemTime, i64, usize)> = self
+            .chains
+            .iter()
+            .map(|(id, x)| (x.last_accessed, *id, x.estimated_size()))
+            .collect();
+        chains.sort_unstable();
+
+        let mut total: usize = incoming + chains.iter().map(|(_, _, size)| size).sum::<usize>();
+        let mut evicted = Vec::new();
+
+        for (_, id, size) in chains {
+            if total <= budget {
+                break;
+            }
+            total -= size;
+            evicted.push(id);
+        }
+
+        evicted
+    }
+
+    // saves and drops the specified ChainInfo objects, see ChainWrapper::save_chain
+    // chains that fail to save are kept, so that what they've learnt isn't lost
+    async fn evict(wrapper: &Mutex<ChainWrapper>, ids: &[i64]) {
+        let mut saved = Vec::new();
+
+        for id in ids {
+            match ChainWrapper::save_chain(wrapper, *id, true).await {
+                Saved::Done(mark) => saved.push((*id, mark)),
+                Saved::Failed(err) => {
+                    alerts::report(&format!("Saving {} failed: {}", id, err));
+                    dbg!(err);
+                }
+                Saved::Skipped => (),
+            }
+        }
+
+        forget_logged(saved).await;
+    }
+
+    // saves and prunes all the old ChainInfo objects from memory
+    // then drops the least recently used ones until the rest fit into MEMORY_BUDGET
+    pub async fn prune(wrapper: &Mutex<ChainWrapper>) {
+        let old_ids: Vec<i64> = wrapper
+            .lock()
+            .await
+            .chains
+            .iter()
+            .filter(|(_, x)| ChainWrapper::is_old(x))
+            .map(|(id, _)| *id)
+            .collect();
+
+        ChainWrapper::evict(wrapper, &old_ids).await;
+
+        let evicted = wrapper.lock().await.over_budget(0);
+        ChainWrapper::evict(wrapper, &evicted).await;
+    }
+}
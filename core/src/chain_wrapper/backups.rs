@@ -0,0 +1,487 @@
+// copies of chains kept in storage: backups, named snapshots and chains deleted with /clear_data
+
+use super::{config, ChainInfo, ChainWrapper, TRASH_PREFIX, TRASH_TTL};
+use crate::chain::Chain;
+use crate::error::Error;
+use crate::{storage, utils};
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Mutex;
+
+// the number of named snapshots a chat can keep, and the longest name one can be given
+const MAX_SNAPSHOTS: usize = 5;
+
+const MAX_SNAPSHOT_NAME: usize = 32;
+
+// the time a user has to confirm /clear_data in
+const CLEAR_CONFIRMATION_WINDOW: Duration = Duration::from_secs(30);
+
+impl ChainInfo {
+    // returns names of the chat's snapshots, sorted alphabetically
+    pub(super) async fn list_named_snapshots(chat_id: i64) -> Result<Vec<String>, Error> {
+        let prefix = ChainInfo::named_snapshot_prefix(chat_id);
+
+        let mut names: Vec<String> = storage::list_files(&prefix)
+            .await?
+            .iter()
+            .map(|name| name[prefix.len()..].to_string())
+            .collect();
+        names.sort_unstable();
+        Ok(names)
+    }
+
+    // returns timestamps of the chat's backups, newest first
+    pub(super) async fn list_backups(chat_id: i64) -> Result<Vec<u64>, Error> {
+        ChainInfo::list_timestamped(&ChainInfo::backup_prefix(chat_id)).await
+    }
+
+    // returns timestamps of the chat's cleared chains, newest first
+    pub(super) async fn list_trash(chat_id: i64) -> Result<Vec<u64>, Error> {
+        ChainInfo::list_timestamped(&ChainInfo::trash_prefix(chat_id)).await
+    }
+
+    // returns timestamps of the files named with a given prefix followed by one, newest first
+    async fn list_timestamped(prefix: &str) -> Result<Vec<u64>, Error> {
+        match storage::list_files(prefix).await {
+            Err(e) => Err(e),
+            Ok(names) => {
+                let mut timestamps: Vec<u64> = names
+                    .iter()
+                    .filter_map(|name| name[prefix.len()..].parse::<u64>().ok())
+                    .collect();
+                timestamps.sort_unstable_by(|a, b| b.cmp(a));
+                Ok(timestamps)
+            }
+        }
+    }
+
+    // uploads a packed copy of a chat's chain as a new backup
+    // removes the oldest backups so that at most BACKUP_COUNT remain
+    async fn backup(chat_id: i64, packed: &[u8]) -> Option<Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let name = ChainInfo::backup_name(chat_id, now);
+        if let Some(err) = storage::update_or_create_file(packed, &name).await {
+            return Some(err);
+        }
+
+        match ChainInfo::list_backups(chat_id).await {
+            Err(e) => Some(e),
+            Ok(timestamps) => {
+                for timestamp in timestamps.into_iter().skip(config(|c| c.backup_count)) {
+                    let name = ChainInfo::backup_name(chat_id, timestamp);
+                    if let Some(err) = storage::delete_file(&name).await {
+                        return Some(err);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    // backs up a packed copy of a chat's chain if the newest backup is older than BACKUP_INTERVAL
+    pub(super) async fn backup_if_due(chat_id: i64, packed: &[u8]) -> Option<Error> {
+        match ChainInfo::list_backups(chat_id).await {
+            Err(e) => Some(e),
+            Ok(timestamps) => {
+                let is_due = match timestamps.first() {
+                    None => true,
+                    Some(newest) => {
+                        let newest = UNIX_EPOCH + Duration::from_secs(*newest);
+                        newest.elapsed().unwrap_or_default() > config(|c| c.backup_interval)
+                    }
+                };
+
+                if is_due {
+                    ChainInfo::backup(chat_id, packed).await
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // downloads a copy of a chat's chain kept under a given name
+    // returns None if there's no such file
+    async fn download_copy(chat_id: i64, name: &str) -> Result<Option<ChainInfo>, Error> {
+        match storage::download_file(name).await? {
+            Some(v_u8) => Ok(Some(ChainInfo::load_blob(chat_id, v_u8).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // replaces what the chain has learnt with the contents of another object
+    fn replace_data(&mut self, restored: ChainInfo) {
+        self.chain = restored.chain;
+        self.reverse = restored.reverse;
+        self.stickers = restored.stickers;
+        self.is_learning = restored.is_learning;
+        self.contributions = restored.contributions;
+        self.usernames = restored.usernames;
+        self.touch();
+    }
+
+    // deletes the Markov chain data
+    fn clear(&mut self) {
+        self.chain = Chain::of_order(self.chain.order());
+        self.reverse = Chain::of_order(self.chain.order());
+        self.is_learning = true;
+        self.contributions.clear();
+        self.usernames.clear();
+        self.stickers.clear();
+        self.recent.clear();
+        self.context.clear();
+        self.touch();
+    }
+}
+
+impl ChainWrapper {
+    // backs up a specified chain before a change that replaces what it has learnt
+    // the chain is serialized under the lock, packing and uploading it happen without holding it
+    pub(super) async fn backup_chain(wrapper: &Mutex<ChainWrapper>, chat_id: i64) -> Option<Error> {
+        if !storage::is_persistent() {
+            return None;
+        }
+
+        match ChainWrapper::serialize_learnt(wrapper, chat_id).await {
+            Err(e) => Some(e),
+            Ok(None) => None,
+            Ok(Some(binc)) => ChainInfo::backup(chat_id, &ChainInfo::pack(binc).await).await,
+        }
+    }
+
+    // replaces what a specified chain has learnt with a restored copy and stores it right away
+    async fn replace_chain(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        restored: ChainInfo,
+    ) -> Result<(), Error> {
+        ChainWrapper::lock_loaded(wrapper, &[chat_id])
+            .await
+            .get_chain(chat_id)?
+            .replace_data(restored);
+
+        match ChainWrapper::persist(wrapper, chat_id).await {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // deletes the specified Markov chain data
+    // the first call only asks for confirmation, which has to be sent by the same user in time
+    // the current state is moved to the trash first, so it can be brought back within TRASH_TTL
+    pub async fn clear_data(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        user_id: i64,
+        args: &str,
+    ) -> Result<String, Error> {
+        {
+            let mut wrapper = wrapper.lock().await;
+            let now = SystemTime::now();
+            wrapper
+                .clear_requests
+                .retain(|_, (_, time)| match now.duration_since(*time) {
+                    Ok(elapsed) => elapsed <= CLEAR_CONFIRMATION_WINDOW,
+                    Err(_) => true,
+                });
+
+            if args.trim() != "confirm" {
+                wrapper.clear_requests.insert(chat_id, (user_id, now));
+                return Ok(format!(
+                    "[this deletes EVERYTHING the bot has learnt in this chat. \
+                     send /clear_data confirm within {} seconds if you're sure]",
+                    CLEAR_CONFIRMATION_WINDOW.as_secs()
+                ));
+            }
+
+            match wrapper.clear_requests.get(&chat_id) {
+                Some((requester, _)) if *requester == user_id => {
+                    wrapper.clear_requests.remove(&chat_id);
+                }
+                _ => return Ok(String::from("[nothing to confirm, send /clear_data first]")),
+            }
+        }
+
+        if storage::is_persistent() {
+            if let Some(binc) = ChainWrapper::serialize_learnt(wrapper, chat_id).await? {
+                let name = ChainInfo::trash_name(chat_id, utils::unix_now());
+                if let Some(err) =
+                    storage::update_or_create_file(&ChainInfo::pack(binc).await, &name).await
+                {
+                    return Err(err);
+                }
+            }
+        }
+
+        ChainWrapper::lock_loaded(wrapper, &[chat_id])
+            .await
+            .get_chain(chat_id)?
+            .clear();
+
+        // clear the binary blob and the append log
+        match ChainWrapper::persist(wrapper, chat_id).await {
+            Some(err) => Err(err),
+            None => Ok(String::from(
+                "[database cleared, use /undo_clear within 24 hours to undo]",
+            )),
+        }
+    }
+
+    // lists the backups of a specified Markov chain
+    pub async fn list_backups(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+    ) -> Result<String, Error> {
+        ChainWrapper::check_chain(wrapper, chat_id).await?;
+
+        let backups: Vec<String> = ChainInfo::list_backups(chat_id)
+            .await?
+            .into_iter()
+            .map(utils::format_timestamp)
+            .collect();
+
+        Ok(if backups.is_empty() {
+            String::from("[no backups found]")
+        } else {
+            let list: Vec<String> = backups
+                .iter()
+                .enumerate()
+                .map(|(i, date)| format!("{}. {}", i + 1, date))
+                .collect();
+            format!(
+                "[available backups]\n{}\n\nUse /restore <number> to revert to one.",
+                list.join("\n")
+            )
+        })
+    }
+
+    // checks if a snapshot name is valid, returning an error message if it's not
+    // names are kept to letters, digits, '-' and '_' as they become a part of the file name
+    fn check_snapshot_name(name: &str) -> Option<String> {
+        let is_valid = !name.is_empty()
+            && name.chars().count() <= MAX_SNAPSHOT_NAME
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if is_valid {
+            None
+        } else {
+            Some(format!(
+                "[snapshot names can only contain letters, digits, '-' and '_', \
+                 and be up to {} characters long]",
+                MAX_SNAPSHOT_NAME
+            ))
+        }
+    }
+
+    // lists the named snapshots of a specified chat
+    pub async fn list_snapshots(chat_id: i64) -> Result<String, Error> {
+        if !storage::is_persistent() {
+            return Ok(String::from(
+                "[snapshots need a persistent storage backend]",
+            ));
+        }
+
+        let names = ChainInfo::list_named_snapshots(chat_id).await?;
+        Ok(if names.is_empty() {
+            String::from("[no snapshots found]")
+        } else {
+            format!(
+                "[available snapshots]\n{}\n\nUse /rollback <name> to revert to one.",
+                names.join("\n")
+            )
+        })
+    }
+
+    // saves a named copy of the Markov chain of a specified chat,
+    // replacing a snapshot of the same name
+    pub async fn snapshot(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        name: &str,
+    ) -> Result<String, Error> {
+        if !storage::is_persistent() {
+            return Ok(String::from(
+                "[snapshots need a persistent storage backend]",
+            ));
+        }
+
+        if let Some(msg) = ChainWrapper::check_snapshot_name(name) {
+            return Ok(msg);
+        }
+
+        let names = ChainInfo::list_named_snapshots(chat_id).await?;
+        if names.len() >= MAX_SNAPSHOTS && !names.iter().any(|n| n == name) {
+            return Ok(format!(
+                "[a chat can keep up to {} snapshots, \
+                 delete one with /snapshot delete <name> first]",
+                MAX_SNAPSHOTS
+            ));
+        }
+
+        let binc = {
+            let mut wrapper = ChainWrapper::lock_loaded(wrapper, &[chat_id]).await;
+            let chain = wrapper.get_chain(chat_id)?;
+            chain.touch();
+            chain.to_bincode()
+        };
+
+        let snapshot_name = ChainInfo::named_snapshot_name(chat_id, name);
+        match storage::update_or_create_file(&ChainInfo::pack(binc).await, &snapshot_name).await {
+            Some(err) => Err(err),
+            None => Ok(format!("[snapshot {} saved]", name)),
+        }
+    }
+
+    // deletes a named snapshot of a specified chat
+    pub async fn delete_snapshot(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        name: &str,
+    ) -> Result<String, Error> {
+        if !storage::is_persistent() {
+            return Ok(String::from(
+                "[snapshots need a persistent storage backend]",
+            ));
+        }
+
+        ChainWrapper::check_chain(wrapper, chat_id).await?;
+
+        let names = ChainInfo::list_named_snapshots(chat_id).await?;
+        if !names.iter().any(|n| n == name) {
+            return Ok(String::from("[no such snapshot]"));
+        }
+
+        match storage::delete_file(&ChainInfo::named_snapshot_name(chat_id, name)).await {
+            Some(err) => Err(err),
+            None => Ok(format!("[snapshot {} deleted]", name)),
+        }
+    }
+
+    // reverts the Markov chain of a specified chat to a named snapshot
+    // the current state is backed up first, so the operation can be undone with /restore
+    pub async fn rollback(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        name: &str,
+    ) -> Result<String, Error> {
+        if !storage::is_persistent() {
+            return Ok(String::from(
+                "[snapshots need a persistent storage backend]",
+            ));
+        }
+
+        if ChainWrapper::check_snapshot_name(name).is_some() {
+            return Ok(String::from("[no such snapshot]"));
+        }
+
+        ChainWrapper::check_chain(wrapper, chat_id).await?;
+
+        let snapshot_name = ChainInfo::named_snapshot_name(chat_id, name);
+        let restored = match ChainInfo::download_copy(chat_id, &snapshot_name).await? {
+            Some(restored) => restored,
+            None => return Ok(String::from("[no such snapshot]")),
+        };
+
+        if let Some(err) = ChainWrapper::backup_chain(wrapper, chat_id).await {
+            return Err(err);
+        }
+
+        ChainWrapper::replace_chain(wrapper, chat_id, restored).await?;
+        Ok(format!("[chain rolled back to snapshot {}]", name))
+    }
+
+    // brings back the chain of a specified chat deleted by the last /clear_data,
+    // if it's been less than TRASH_TTL
+    pub async fn undo_clear(wrapper: &Mutex<ChainWrapper>, chat_id: i64) -> Result<String, Error> {
+        ChainWrapper::check_chain(wrapper, chat_id).await?;
+
+        let timestamp = match ChainInfo::list_trash(chat_id).await?.first() {
+            Some(timestamp) if utils::unix_now().saturating_sub(*timestamp) < TRASH_TTL => {
+                *timestamp
+            }
+            _ => return Ok(String::from("[nothing to bring back]")),
+        };
+
+        let name = ChainInfo::trash_name(chat_id, timestamp);
+        let restored = match ChainInfo::download_copy(chat_id, &name).await? {
+            Some(restored) => restored,
+            None => return Ok(String::from("[nothing to bring back]")),
+        };
+
+        ChainWrapper::replace_chain(wrapper, chat_id, restored).await?;
+
+        if let Some(err) = storage::delete_file(&name).await {
+            dbg!(err);
+        }
+
+        Ok(format!(
+            "[chain deleted on {} brought back]",
+            utils::format_timestamp(timestamp)
+        ))
+    }
+
+    // deletes chains that have been in the trash for longer than TRASH_TTL
+    pub async fn purge_trash() -> Option<Error> {
+        let names = match storage::list_files(TRASH_PREFIX).await {
+            Ok(names) => names,
+            Err(e) => return Some(e),
+        };
+
+        let now = utils::unix_now();
+        for name in names {
+            let timestamp = name.rsplit('.').next().and_then(|t| t.parse::<u64>().ok());
+            match timestamp {
+                Some(timestamp) if now.saturating_sub(timestamp) >= TRASH_TTL => {
+                    if let Some(err) = storage::delete_file(&name).await {
+                        return Some(err);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    // reverts a specified Markov chain to one of its backups (numbered from 1)
+    // the current state is backed up first, so the operation can be undone
+    pub async fn restore(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        number: usize,
+    ) -> Result<String, Error> {
+        if number == 0 {
+            return Ok(String::from("[no such backup]"));
+        }
+
+        ChainWrapper::check_chain(wrapper, chat_id).await?;
+
+        let timestamp = match ChainInfo::list_backups(chat_id).await?.get(number - 1) {
+            Some(timestamp) => *timestamp,
+            None => return Ok(String::from("[no such backup]")),
+        };
+
+        let name = ChainInfo::backup_name(chat_id, timestamp);
+        let restored = match ChainInfo::download_copy(chat_id, &name).await? {
+            Some(restored) => restored,
+            None => return Ok(String::from("[no such backup]")),
+        };
+
+        if let Some(err) = ChainWrapper::backup_chain(wrapper, chat_id).await {
+            return Err(err);
+        }
+
+        ChainWrapper::replace_chain(wrapper, chat_id, restored).await?;
+        Ok(format!(
+            "[chain restored from {}]",
+            utils::format_timestamp(timestamp)
+        ))
+    }
+}
@@ -0,0 +1,651 @@
+// generating phrases: /speak and its variants, the output settings and the cooldown between them
+
+use super::{ChainInfo, ChainWrapper, MAX_TEMPERATURE, MIN_TEMPERATURE, UNKNOWN_USER};
+use crate::chain::{self, Chain};
+use crate::error::Error;
+use crate::feedback;
+use crate::{chat_key, stopwords, tokenizer};
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+// the number of phrases generated in search of one that fits the length bounds
+const GENERATION_ATTEMPTS: usize = 20;
+
+// the number of sentences in a /story, by default and at most
+const DEFAULT_STORY_LENGTH: usize = 3;
+
+const MAX_STORY_LENGTH: usize = 10;
+
+// the number of members credited by /speak_credits
+const CREDITED_USERS: usize = 3;
+
+// the longest cooldown between generating commands that can be set with /speak_cooldown (in seconds)
+const MAX_SPEAK_COOLDOWN: u64 = 3600;
+
+// the number of words of a message tried as the subject of a reply to it
+const REPLY_SEED_WORDS: usize = 5;
+
+// a word that generated messages have to contain
+#[derive(Clone, Copy)]
+enum Prompt<'a> {
+    Any,
+    StartingWith(&'a str),
+    Containing(&'a str),
+    EndingWith(&'a str),
+}
+
+impl ChainInfo {
+    // picks a word from the recent messages that the chain knows
+    // stopwords and censored words are left out, as they say nothing about the topic
+    fn context_word<R: Rng>(&self, rng: &mut R) -> Option<String> {
+        let words: Vec<String> = self
+            .context
+            .iter()
+            .flat_map(|msg| tokenizer::tokenize(msg))
+            .filter(|token| {
+                let word = token
+                    .trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase();
+                !word.is_empty()
+                    && !stopwords::is_stopword(&word)
+                    && !self.settings.is_censored(&word)
+                    && self.chain.frequency(token) > 0
+            })
+            .collect();
+
+        words.choose(rng).cloned()
+    }
+
+    // sets the bounds (in words) of generated messages
+    pub fn set_output_length(&mut self, min_words: usize, max_words: usize) {
+        self.touch();
+        self.settings.min_output_words = min_words;
+        self.settings.max_output_words = max_words;
+        self.is_stale = true;
+    }
+
+    // sets the sampling temperature of generated messages
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.touch();
+        self.settings.temperature = temperature;
+        self.is_stale = true;
+    }
+
+    // sets the time a user has to wait between generating commands
+    pub fn set_speak_cooldown(&mut self, seconds: u64) {
+        self.touch();
+        self.settings.speak_cooldown = seconds;
+        self.is_stale = true;
+    }
+
+    // checks if a user has to wait before making the bot generate another message
+    // returns the number of seconds left, records the request otherwise
+    fn cooldown_left(&mut self, user_id: i64) -> Option<u64> {
+        let cooldown = Duration::from_secs(self.settings.speak_cooldown);
+        if cooldown == Duration::from_secs(0) {
+            return None;
+        }
+
+        let now = SystemTime::now();
+        if let Some(last) = self.last_generated.get(&user_id) {
+            if let Ok(elapsed) = now.duration_since(*last) {
+                if elapsed < cooldown {
+                    // round up, so that users aren't told to wait 0 seconds
+                    let left = cooldown - elapsed;
+                    return Some(left.as_secs() + u64::from(left.subsec_nanos() > 0));
+                }
+            }
+        }
+
+        // entries of users who may generate again aren't needed anymore
+        self.last_generated
+            .retain(|_, last| match now.duration_since(*last) {
+                Ok(elapsed) => elapsed < cooldown,
+                Err(_) => true,
+            });
+        self.last_generated.insert(user_id, now);
+        None
+    }
+
+    // generates messages from a Markov chain until one fits the chat's length bounds
+    // phrases start with or contain a given word if possible
+    // returns the candidate closest to the bounds (shortened if too long) if none of them fits
+    // copies of recently learnt lines are only returned if nothing else can be generated
+    // phrases with censored words come last, with the words masked
+    // chain-generated messages can be of length 0, None is returned if all of them are
+    fn gen_loop<R: Rng>(
+        &self,
+        chain: &Chain,
+        reverse: &Chain,
+        prompt: Prompt,
+        rng: &mut R,
+    ) -> Option<String> {
+        let min_words = self.settings.min_output_words;
+        let max_words = self.settings.max_output_words;
+        let temperature = self.settings.temperature;
+        let mut best: Option<(usize, String)> = None;
+        let mut echo: Option<String> = None;
+        let mut censored: Option<String> = None;
+
+        for _ in 0..GENERATION_ATTEMPTS {
+            let phrase = match prompt {
+                Prompt::Any => chain.generate_str(temperature, rng),
+                Prompt::StartingWith(token) => {
+                    chain.generate_str_from_token(token, temperature, rng)
+                }
+                Prompt::Containing(token) => {
+                    chain.generate_str_containing(reverse, token, temperature, rng)
+                }
+                Prompt::EndingWith(token) => {
+                    reverse.generate_str_ending_with(token, temperature, rng)
+                }
+            };
+
+            let words = tokenizer::count(&phrase);
+            if words == 0 {
+                continue;
+            }
+
+            if self.settings.is_censored(&phrase) {
+                censored.get_or_insert(phrase);
+                continue;
+            }
+
+            if self.is_echo(&phrase) {
+                echo.get_or_insert(phrase);
+                continue;
+            }
+
+            let distance = if words < min_words {
+                min_words - words
+            } else {
+                words.saturating_sub(max_words)
+            };
+
+            if distance == 0 {
+                return Some(phrase);
+            } else if best.as_ref().is_none_or(|(d, _)| distance < *d) {
+                best = Some((distance, phrase));
+            }
+        }
+
+        best.map(|(_, phrase)| {
+            let words: Vec<String> = tokenizer::tokenize(&phrase)
+                .into_iter()
+                .take(max_words)
+                .collect();
+            tokenizer::join(&words)
+        })
+        .or(echo)
+        .or_else(|| censored.map(|phrase| self.settings.censor(&phrase)))
+    }
+
+    // generates a message from a Markov chain using a given source of randomness
+    // returns None if the chain has nothing to say
+    pub fn generate<R: Rng>(&mut self, token: &str, rng: &mut R) -> Option<String> {
+        self.touch();
+
+        match tokenizer::tokenize(token).first() {
+            // no words were provided after /speak
+            None => self.gen_rated(rng),
+            // some words were provided after /speak
+            Some(token) => self
+                .gen_loop(&self.chain, &self.reverse, Prompt::StartingWith(token), rng)
+                // no message beginning with the given word can be generated
+                .or_else(|| {
+                    self.gen_loop(&self.chain, &self.reverse, Prompt::Containing(token), rng)
+                })
+                // the word is unknown
+                .or_else(|| self.gen_loop(&self.chain, &self.reverse, Prompt::Any, rng)),
+        }
+    }
+
+    // generates any message, leaning towards the ones members rated well
+    // a message starting with a disliked word gets one more try
+    pub(super) fn gen_rated<R: Rng>(&self, rng: &mut R) -> Option<String> {
+        let scores = &self.settings.start_scores;
+        let liked = feedback::pick_start(scores, rng).and_then(|word| {
+            self.gen_loop(&self.chain, &self.reverse, Prompt::StartingWith(&word), rng)
+        });
+        if liked.is_some() {
+            return liked;
+        }
+
+        let phrase = self.gen_loop(&self.chain, &self.reverse, Prompt::Any, rng)?;
+        if feedback::is_disliked(scores, &phrase) {
+            return self
+                .gen_loop(&self.chain, &self.reverse, Prompt::Any, rng)
+                .or(Some(phrase));
+        }
+        Some(phrase)
+    }
+
+    // generates a message about a word picked from the recent messages, so that it stays on topic
+    // generates any message if there's no such word
+    pub fn generate_in_context<R: Rng>(&mut self, rng: &mut R) -> Option<String> {
+        let word = self.context_word(rng).unwrap_or_default();
+        self.generate(&word, rng)
+    }
+
+    // generates a message containing a given word anywhere
+    // returns None if no such message can be generated
+    pub fn speak_about<R: Rng>(&mut self, token: &str, rng: &mut R) -> Option<String> {
+        self.touch();
+        self.gen_loop(&self.chain, &self.reverse, Prompt::Containing(token), rng)
+    }
+
+    // generates a number of sentences forming a single message
+    // every sentence after the first one contains the last word of the previous one if possible
+    pub fn story<R: Rng>(&mut self, sentences: usize, rng: &mut R) -> Option<String> {
+        self.touch();
+
+        let mut story: Vec<String> = Vec::with_capacity(sentences);
+        for _ in 0..sentences {
+            let tail = story
+                .last()
+                .and_then(|sentence| tokenizer::tokenize(sentence).pop())
+                .map(|word| {
+                    word.trim_end_matches(|c: char| c.is_ascii_punctuation())
+                        .to_string()
+                })
+                .filter(|word| !word.is_empty());
+
+            let sentence = match &tail {
+                Some(tail) => self
+                    .gen_loop(&self.chain, &self.reverse, Prompt::Containing(tail), rng)
+                    .or_else(|| self.gen_loop(&self.chain, &self.reverse, Prompt::Any, rng)),
+                None => self.gen_loop(&self.chain, &self.reverse, Prompt::Any, rng),
+            }?;
+
+            story.push(sentence);
+        }
+
+        let story: Vec<String> = story
+            .into_iter()
+            .map(|mut sentence| {
+                if !sentence.ends_with(|c: char| ".!?…".contains(c)) {
+                    sentence.push('.');
+                }
+                sentence
+            })
+            .collect();
+
+        Some(story.join(" "))
+    }
+
+    // generates a reply to a message, containing one of its words if possible
+    // returns None if nothing has been learnt yet
+    pub fn reply<R: Rng>(&mut self, msg: &str, rng: &mut R) -> Option<String> {
+        self.touch();
+
+        if self.chain.is_empty() {
+            return None;
+        }
+
+        let mut words: Vec<String> = tokenizer::tokenize(msg)
+            .into_iter()
+            .filter(|word| !word.starts_with('@') && !word.starts_with('/'))
+            .collect();
+        words.shuffle(rng);
+
+        words
+            .iter()
+            .take(REPLY_SEED_WORDS)
+            .find_map(|word| {
+                self.gen_loop(&self.chain, &self.reverse, Prompt::Containing(word), rng)
+            })
+            .or_else(|| self.gen_loop(&self.chain, &self.reverse, Prompt::Any, rng))
+    }
+
+    // generates a message ending with a given word
+    // returns None if no such message can be generated
+    pub fn speak_ending<R: Rng>(&mut self, token: &str, rng: &mut R) -> Option<String> {
+        self.touch();
+        self.gen_loop(&self.chain, &self.reverse, Prompt::EndingWith(token), rng)
+    }
+
+    // generates a message in the style of a specified user
+    // builds a chain out of the user's lines only
+    pub fn speak_like<R: Rng>(&mut self, user_id: i64, rng: &mut R) -> Option<String> {
+        self.touch();
+
+        match self.contributions.get(&user_id) {
+            Some(lines) if !lines.is_empty() => {
+                let mut chain = Chain::of_order(self.chain.order());
+                let mut reverse = Chain::of_order(self.chain.order());
+                for line in lines.iter() {
+                    chain.feed_str(line);
+                    reverse.feed_str_reversed(line);
+                }
+
+                self.gen_loop(&chain, &reverse, Prompt::Any, rng)
+            }
+            _ => None,
+        }
+    }
+
+    // works out whose lines the pairs of adjacent words in a phrase were learnt from
+    // every pair counts the same, split between users by how many times each of them used it
+    // returns user IDs along with their share (between 0 and 1), the largest first
+    fn credits(&self, phrase: &str) -> Vec<(i64, f64)> {
+        let words = tokenizer::tokenize(phrase);
+        if words.len() < 2 {
+            return Vec::new();
+        }
+
+        // every distinct pair gets an index, looked up by its first and second word
+        // pairs[i] counts the uses of a pair by each user, occurrences lists the phrase's pairs in order
+        let mut index: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+        let mut pairs: Vec<HashMap<i64, u64>> = Vec::new();
+        let mut occurrences = Vec::new();
+        for pair in words.windows(2) {
+            let next = pairs.len();
+            let i = *index
+                .entry(pair[0].as_str())
+                .or_default()
+                .entry(pair[1].as_str())
+                .or_insert(next);
+            if i == next {
+                pairs.push(HashMap::new());
+            }
+            occurrences.push(i);
+        }
+
+        // the number of times each user used each pair
+        // lines of unknown authorship have nobody to credit
+        for (user_id, lines) in self.contributions.iter() {
+            if *user_id == UNKNOWN_USER {
+                continue;
+            }
+
+            for line in lines.iter() {
+                let tokens = tokenizer::tokenize(line);
+                for pair in tokens.windows(2) {
+                    let i = index
+                        .get(pair[0].as_str())
+                        .and_then(|followers| followers.get(pair[1].as_str()));
+                    if let Some(i) = i {
+                        *pairs[*i].entry(*user_id).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let mut shares: HashMap<i64, f64> = HashMap::new();
+        for i in occurrences.iter() {
+            let users = &pairs[*i];
+            let total: u64 = users.values().sum();
+            for (user_id, count) in users.iter() {
+                *shares.entry(*user_id).or_insert(0.0) +=
+                    *count as f64 / total as f64 / occurrences.len() as f64;
+            }
+        }
+
+        let mut shares: Vec<(i64, f64)> = shares.into_iter().collect();
+        shares.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        shares
+    }
+
+    // generates a message and lists the members whose lines it was learnt from the most
+    pub fn speak_credits<R: Rng>(&mut self, token: &str, rng: &mut R) -> Option<String> {
+        if self.chain.is_empty() {
+            return Some(String::from("[no phrases learnt]"));
+        }
+
+        let phrase = if token.trim().is_empty() {
+            self.generate_in_context(rng)
+        } else {
+            self.generate(token, rng)
+        }?;
+
+        let credits: Vec<String> = self
+            .credits(&phrase)
+            .into_iter()
+            .take(CREDITED_USERS)
+            .map(|(user_id, share)| {
+                let name = self
+                    .username_of(user_id)
+                    .unwrap_or_else(|| String::from("someone without a username"));
+                format!("{} ({:.0}%)", name, share * 100.0)
+            })
+            .collect();
+
+        if credits.is_empty() {
+            Some(format!("{}\n\n[nobody to blame]", phrase))
+        } else {
+            Some(format!("{}\n\n[blame: {}]", phrase, credits.join(", ")))
+        }
+    }
+
+    // returns the name a user is known by in the chat, if they have a username
+    pub(super) fn username_of(&self, user_id: i64) -> Option<String> {
+        self.usernames
+            .iter()
+            .find(|(_, id)| **id == user_id)
+            .map(|(username, _)| format!("@{}", username))
+    }
+}
+
+impl ChainWrapper {
+    // generates a message from the chain shared by all chats that opted into it
+    pub fn speak_global(&mut self, token: &str) -> Result<String, Error> {
+        self.generate(chat_key::GLOBAL, token, None)
+    }
+
+    // generates a message from a specified Markov chain
+    // without a prompt or a seed, the message is about a word from the chat's recent messages
+    // the same seed always yields the same message (as long as the chain doesn't change)
+    pub fn generate(
+        &mut self,
+        chat_id: i64,
+        token: &str,
+        seed: Option<u64>,
+    ) -> Result<String, Error> {
+        Ok(self
+            .try_generate(chat_id, token, seed)?
+            .unwrap_or_else(|msg| msg))
+    }
+
+    // works like generate, but tells a generated phrase apart from the message explaining why there's none
+    pub fn try_generate(
+        &mut self,
+        chat_id: i64,
+        token: &str,
+        seed: Option<u64>,
+    ) -> Result<Result<String, String>, Error> {
+        let chain = self.get_chain(chat_id)?;
+        if chain.chain.is_empty() {
+            return Ok(Err(String::from("[no phrases learnt]")));
+        }
+
+        let res = match seed {
+            Some(seed) => chain.generate(token, &mut StdRng::seed_from_u64(seed)),
+            None if token.trim().is_empty() => chain.generate_in_context(&mut rand::thread_rng()),
+            None => chain.generate(token, &mut rand::thread_rng()),
+        };
+
+        Ok(res
+            .filter(|s| !s.trim().is_empty())
+            .ok_or_else(ChainWrapper::err_msg))
+    }
+
+    // generates a phrase from a specified Markov chain to be used outside of the chat
+    // works like generate, but returns None if the chain has nothing to say
+    pub fn phrase(
+        &mut self,
+        chat_id: i64,
+        token: &str,
+        seed: Option<u64>,
+    ) -> Result<Option<String>, Error> {
+        let chain = self.get_chain(chat_id)?;
+        let res = match seed {
+            Some(seed) => chain.generate(token, &mut StdRng::seed_from_u64(seed)),
+            None => chain.generate(token, &mut rand::thread_rng()),
+        };
+        Ok(res.filter(|s| !s.trim().is_empty()))
+    }
+
+    // generates a reply to a message sent in a specified chat
+    pub fn reply(&mut self, chat_id: i64, msg: &str) -> Option<String> {
+        match self.get_chain(chat_id) {
+            Ok(chain) => chain.reply(msg, &mut rand::thread_rng()),
+            Err(e) => {
+                dbg!(e);
+                None
+            }
+        }
+    }
+
+    // generates a message containing a given word
+    pub fn speak_about(&mut self, chat_id: i64, token: &str) -> Result<String, Error> {
+        let token = match tokenizer::tokenize(token).into_iter().next() {
+            Some(token) => token,
+            None => return Ok(String::from("[usage: /speak_about word]")),
+        };
+
+        let chain = self.get_chain(chat_id)?;
+        Ok(match chain.speak_about(&token, &mut rand::thread_rng()) {
+            Some(s) => s,
+            None => String::from("[no phrases with that word learnt]"),
+        })
+    }
+
+    // generates a message ending with a given word
+    pub fn speak_ending(&mut self, chat_id: i64, token: &str) -> Result<String, Error> {
+        let token = match tokenizer::tokenize(token).into_iter().next() {
+            Some(token) => token,
+            None => return Ok(String::from("[usage: /speak_ending word]")),
+        };
+
+        let chain = self.get_chain(chat_id)?;
+        Ok(match chain.speak_ending(&token, &mut rand::thread_rng()) {
+            Some(s) => s,
+            None => String::from("[no phrases ending with that word learnt]"),
+        })
+    }
+
+    // generates a story of a given number of sentences
+    pub fn story(&mut self, chat_id: i64, sentences: Option<usize>) -> Result<String, Error> {
+        let sentences = sentences.unwrap_or(DEFAULT_STORY_LENGTH);
+        if !(2..=MAX_STORY_LENGTH).contains(&sentences) {
+            return Ok(format!(
+                "[a story has to be between 2 and {} sentences long]",
+                MAX_STORY_LENGTH
+            ));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        if chain.chain.is_empty() {
+            return Ok(String::from("[no phrases learnt]"));
+        }
+
+        Ok(match chain.story(sentences, &mut rand::thread_rng()) {
+            Some(s) => s,
+            None => ChainWrapper::err_msg(),
+        })
+    }
+
+    // generates a message in the style of a user, identified by their ID or username
+    pub fn speak_like(
+        &mut self,
+        chat_id: i64,
+        user_id: Option<i64>,
+        username: &str,
+    ) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(match user_id.or_else(|| chain.find_user(username)) {
+            Some(user_id) => match chain.speak_like(user_id, &mut rand::thread_rng()) {
+                Some(s) => s,
+                None => String::from("[no phrases learnt from that user]"),
+            },
+            None => String::from("[unknown user]"),
+        })
+    }
+
+    // generates a message from a specified Markov chain, crediting the members it was learnt from
+    pub fn speak_credits(&mut self, chat_id: i64, token: &str) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(match chain.speak_credits(token, &mut rand::thread_rng()) {
+            Some(s) if !s.trim().is_empty() => s,
+            _ => ChainWrapper::err_msg(),
+        })
+    }
+
+    // sets the bounds of messages generated by a specified Markov chain
+    pub fn set_output_length(
+        &mut self,
+        chat_id: i64,
+        min_words: usize,
+        max_words: usize,
+    ) -> Result<String, Error> {
+        if min_words == 0 || min_words > max_words || max_words > chain::MAX_WORDS {
+            return Ok(format!(
+                "[the length has to satisfy 1 <= min <= max <= {}]",
+                chain::MAX_WORDS
+            ));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        chain.set_output_length(min_words, max_words);
+        Ok(format!(
+            "[generated messages will be {} to {} words long]",
+            min_words, max_words
+        ))
+    }
+
+    // sets the sampling temperature of a specified Markov chain
+    pub fn set_temperature(&mut self, chat_id: i64, temperature: f64) -> Result<String, Error> {
+        if !(MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&temperature) {
+            return Ok(format!(
+                "[the temperature has to be between {} and {}]",
+                MIN_TEMPERATURE, MAX_TEMPERATURE
+            ));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        chain.set_temperature(temperature);
+        Ok(format!("[temperature set to {}]", temperature))
+    }
+
+    // sets the cooldown between generating commands in a specified chat
+    pub fn set_speak_cooldown(&mut self, chat_id: i64, seconds: u64) -> Result<String, Error> {
+        if seconds > MAX_SPEAK_COOLDOWN {
+            return Ok(format!(
+                "[the cooldown has to be between 0 and {} seconds]",
+                MAX_SPEAK_COOLDOWN
+            ));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        chain.set_speak_cooldown(seconds);
+        Ok(if seconds == 0 {
+            String::from("[cooldown disabled]")
+        } else {
+            format!("[cooldown set to {} seconds]", seconds)
+        })
+    }
+
+    // checks if a user may make the bot generate a message in a specified chat
+    // returns None if they may, or a message asking them to wait otherwise
+    pub fn check_cooldown(&mut self, chat_id: i64, user_id: i64) -> Option<String> {
+        match self.get_chain(chat_id) {
+            Ok(chain) => chain.cooldown_left(user_id).map(|seconds| {
+                format!(
+                    "[slow down, please - try again in {} second{}]",
+                    seconds,
+                    if seconds == 1 { "" } else { "s" }
+                )
+            }),
+            Err(e) => {
+                dbg!(e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// moving what a chain has learnt in and out of the bot: exports, graphs, imports and merges
+
+use super::{ChainInfo, ChainWrapper, UNKNOWN_USER};
+use crate::error::Error;
+use crate::{dump, graphviz, history, preprocess, tokenizer};
+
+use tokio::sync::Mutex;
+
+impl ChainInfo {
+    // serializes the Markov chain to a portable JSON dump
+    pub fn export(&mut self) -> Vec<u8> {
+        self.touch();
+        dump::export(self.chat_id, &self.chain)
+    }
+
+    // renders the chain as a DOT graph of words, see graphviz::render
+    pub fn graph(&mut self, min_weight: u64) -> String {
+        self.touch();
+        graphviz::render(&self.chain, min_weight)
+    }
+
+    // merges a JSON dump or feeds a list of lines into the chain
+    // returns a human-readable summary of the import, or why it failed
+    fn import(&mut self, data: dump::Import) -> Result<String, String> {
+        self.touch();
+
+        let summary = match data {
+            dump::Import::Dump(dump) => match dump::merge(&mut self.chain, dump) {
+                Ok(count) => {
+                    self.reverse = self.chain.reversed();
+                    format!("[{} transitions imported]", count)
+                }
+                Err(e) => return Err(format!("[import failed: {}]", e)),
+            },
+            dump::Import::Lines(lines) => {
+                // the lines are kept like learnt ones, so that /set_order can relearn them
+                for line in lines.iter() {
+                    self.remember(UNKNOWN_USER, line);
+                }
+
+                format!("[{} lines imported]", lines.len())
+            }
+        };
+
+        self.enforce_size_limit();
+        Ok(summary)
+    }
+}
+
+impl ChainWrapper {
+    // feeds a specified Markov chain a line typed in a REPL
+    // the line is cleaned and filtered like a message, but skips the spam checks
+    // returns the learnt lines, split into tokens
+    pub fn learn_offline(&mut self, chat_id: i64, msg: &str) -> Result<Vec<Vec<String>>, Error> {
+        let chain = self.get_chain(chat_id)?;
+        let msg = preprocess::clean(msg, &chain.settings);
+        let lines = preprocess::filter(&msg, &chain.settings);
+
+        for line in lines.iter() {
+            chain.learn(UNKNOWN_USER, line);
+        }
+
+        Ok(lines.iter().map(|line| tokenizer::tokenize(line)).collect())
+    }
+
+    // exports a specified Markov chain as a JSON dump
+    // returns the message to show instead if the chain is empty
+    pub fn export(&mut self, chat_id: i64) -> Result<Result<Vec<u8>, String>, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(if chain.chain.is_empty() {
+            Err(String::from("[no phrases learnt]"))
+        } else {
+            Ok(chain.export())
+        })
+    }
+
+    // renders a specified Markov chain as a DOT graph, see graphviz::render
+    // returns the message to show instead if the chain is empty
+    pub fn graph(
+        &mut self,
+        chat_id: i64,
+        min_weight: u64,
+    ) -> Result<Result<String, String>, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(if chain.chain.is_empty() {
+            Err(String::from("[no phrases learnt]"))
+        } else {
+            Ok(chain.graph(min_weight))
+        })
+    }
+
+    // imports parsed data into a specified Markov chain
+    // the current state is backed up first, so the operation can be undone
+    async fn import_data(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        data: Result<dump::Import, String>,
+    ) -> Result<String, Error> {
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => return Ok(format!("[invalid file: {}]", e)),
+        };
+
+        if let Some(err) = ChainWrapper::backup_chain(wrapper, chat_id).await {
+            return Err(err);
+        }
+
+        let imported = ChainWrapper::lock_loaded(wrapper, &[chat_id])
+            .await
+            .get_chain(chat_id)?
+            .import(data);
+
+        match imported {
+            Ok(summary) => {
+                // the imported data isn't logged, persist it right away
+                match ChainWrapper::persist(wrapper, chat_id).await {
+                    Some(err) => Err(err),
+                    None => Ok(summary),
+                }
+            }
+            Err(msg) => Ok(msg),
+        }
+    }
+
+    // imports a JSON dump or a plain-text corpus into a specified Markov chain
+    pub async fn import(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        bytes: &[u8],
+    ) -> Result<String, Error> {
+        ChainWrapper::import_data(wrapper, chat_id, dump::parse_import(bytes)).await
+    }
+
+    // imports a Telegram Desktop chat export into a specified Markov chain
+    // messages sent by the bot itself are skipped
+    pub async fn import_history(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        bytes: &[u8],
+        bot_id: Option<i64>,
+    ) -> Result<String, Error> {
+        ChainWrapper::import_data(wrapper, chat_id, history::parse(bytes, bot_id)).await
+    }
+
+    // merges the transitions of another chat's Markov chain into a specified one
+    pub async fn merge_from(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        source_id: i64,
+    ) -> Result<String, Error> {
+        if chat_id == source_id {
+            return Ok(String::from("[a chat can't be merged with itself]"));
+        }
+
+        // prefer the in-memory copy, as it may contain unsaved lines
+        let loaded = wrapper
+            .lock()
+            .await
+            .chains
+            .get(&source_id)
+            .map(|source| dump::to_dump(source_id, &source.chain));
+
+        let source = match loaded {
+            Some(source) => Some(source),
+            None => ChainInfo::deserialize_from_storage(source_id)
+                .await?
+                .map(|source| dump::to_dump(source_id, &source.chain)),
+        };
+
+        match source {
+            Some(source) => {
+                ChainWrapper::import_data(wrapper, chat_id, Ok(dump::Import::Dump(source))).await
+            }
+            None => Ok(String::from("[no phrases learnt in that chat]")),
+        }
+    }
+}
@@ -0,0 +1,513 @@
+// what a chat learns from and keeps: filters, ignored members, consent, /forget and retention
+
+use super::{ChainInfo, ChainWrapper};
+use crate::error::Error;
+use crate::{chat_key, utils};
+
+use std::collections::HashMap;
+
+use tokio::sync::Mutex;
+
+// the longest retention period that can be set with /retention (in days)
+const MAX_RETENTION_DAYS: u64 = 3650;
+
+impl ChainInfo {
+    // adds a word or phrase to the blacklist
+    // attributed lines containing it are removed from the Markov chain
+    // returns the number of removed lines, or None if it was blacklisted already
+    fn blacklist_add(&mut self, phrase: &str) -> Option<usize> {
+        self.touch();
+
+        let phrase = phrase.to_lowercase();
+        if self.settings.blacklist.contains(&phrase) {
+            return None;
+        }
+
+        self.settings.blacklist.push(phrase.clone());
+        self.is_stale = true;
+
+        let mut removed = Vec::new();
+        for lines in self.contributions.values_mut() {
+            lines.retain(|line| {
+                if line.to_lowercase().contains(&phrase) {
+                    removed.push(line.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        for line in removed.iter() {
+            self.chain.unfeed_str(line);
+            self.reverse.unfeed_str_reversed(line);
+        }
+
+        Some(removed.len())
+    }
+
+    // adds a word to the list of words the bot mustn't say
+    // returns false if it was censored already
+    pub fn censor_add(&mut self, word: &str) -> bool {
+        self.touch();
+
+        let word = word.to_lowercase();
+        if self.settings.censored.contains(&word) {
+            return false;
+        }
+
+        self.settings.censored.push(word);
+        self.is_stale = true;
+        true
+    }
+
+    // adds a user to the list of users whose messages are never learnt, or removes them from it
+    // returns false if the user was (or wasn't) ignored already
+    pub fn set_ignored(&mut self, user_id: i64, is_ignored: bool) -> bool {
+        self.touch();
+
+        if self.settings.ignored.contains(&user_id) == is_ignored {
+            return false;
+        }
+
+        if is_ignored {
+            self.settings.ignored.push(user_id);
+        } else {
+            self.settings.ignored.retain(|id| *id != user_id);
+        }
+        self.is_stale = true;
+        true
+    }
+
+    // records that a user agreed (or no longer agrees) to have their messages learnt
+    // returns false if the user had already done so
+    pub fn set_consent(&mut self, user_id: i64, is_given: bool) -> bool {
+        self.touch();
+
+        let (added, removed) = if is_given {
+            (&mut self.settings.opted_in, &mut self.settings.opted_out)
+        } else {
+            (&mut self.settings.opted_out, &mut self.settings.opted_in)
+        };
+
+        removed.retain(|id| *id != user_id);
+        if added.contains(&user_id) {
+            return false;
+        }
+
+        added.push(user_id);
+        self.is_stale = true;
+        true
+    }
+
+    // sets whether only the messages of users who sent /optin are learnt
+    pub fn set_opt_in_only(&mut self, opt_in_only: bool) {
+        self.touch();
+        self.settings.opt_in_only = opt_in_only;
+        self.is_stale = true;
+    }
+
+    // removes a word from the list of words the bot mustn't say
+    // returns false if it wasn't censored
+    pub fn censor_remove(&mut self, word: &str) -> bool {
+        self.touch();
+
+        let word = word.to_lowercase();
+        let len = self.settings.censored.len();
+        self.settings.censored.retain(|w| *w != word);
+
+        let is_removed = self.settings.censored.len() != len;
+        if is_removed {
+            self.is_stale = true;
+        }
+
+        is_removed
+    }
+
+    // toggles a text filter given its name
+    // returns the new state of the filter, or None if there's no such filter
+    pub fn toggle_filter(&mut self, name: &str) -> Option<bool> {
+        self.touch();
+
+        let filter = self.settings.filter_mut(name)?;
+        *filter = !*filter;
+        let state = *filter;
+
+        self.is_stale = true;
+        Some(state)
+    }
+
+    // sets the minimum length (in words) of learnt messages
+    pub fn set_min_words(&mut self, min_words: usize) {
+        self.touch();
+        self.settings.min_words = min_words;
+        self.is_stale = true;
+    }
+
+    // removes a word or phrase from the blacklist
+    // returns false if it wasn't blacklisted
+    pub fn blacklist_remove(&mut self, phrase: &str) -> bool {
+        self.touch();
+
+        let phrase = phrase.to_lowercase();
+        let len = self.settings.blacklist.len();
+        self.settings.blacklist.retain(|p| *p != phrase);
+
+        let is_removed = self.settings.blacklist.len() != len;
+        if is_removed {
+            self.is_stale = true;
+        }
+
+        is_removed
+    }
+
+    // removes the lines learnt from a specified user from the Markov chain
+    // returns the number of forgotten lines
+    fn forget(&mut self, user_id: i64) -> usize {
+        self.touch();
+
+        self.usernames.retain(|_, id| *id != user_id);
+
+        let lines = match self.contributions.remove(&user_id) {
+            Some(lines) => lines,
+            None => return 0,
+        };
+
+        for line in lines.iter() {
+            self.chain.unfeed_str(line);
+            self.reverse.unfeed_str_reversed(line);
+        }
+        self.is_stale = true;
+        lines.len()
+    }
+
+    // removes lines another chat shared with this one (see feed_global) from the Markov chain
+    // a line goes only if its author's contributions still hold it, once for every time it was shared
+    // returns the number of forgotten lines
+    pub(super) fn forget_lines(&mut self, lines: &HashMap<i64, Vec<String>>) -> usize {
+        let mut forgotten = 0;
+        for (user_id, user_lines) in lines.iter() {
+            let contributions = match self.contributions.get_mut(user_id) {
+                Some(contributions) => contributions,
+                None => continue,
+            };
+
+            for line in user_lines.iter() {
+                if let Some(i) = contributions.iter().position(|learnt| learnt == line) {
+                    contributions.remove(i);
+                    self.chain.unfeed_str(line);
+                    self.reverse.unfeed_str_reversed(line);
+                    forgotten += 1;
+                }
+            }
+
+            if contributions.is_empty() {
+                self.contributions.remove(user_id);
+            }
+        }
+
+        if forgotten > 0 {
+            self.touch();
+            self.is_stale = true;
+        }
+
+        forgotten
+    }
+
+    // sets the number of days after which everything learnt is wiped
+    pub fn set_retention(&mut self, days: u64) {
+        self.touch();
+        self.settings.retention_days = days;
+        self.is_stale = true;
+    }
+}
+
+impl ChainWrapper {
+    // stops (or resumes) learning the messages of a user given by ID or by username
+    pub fn set_ignored(
+        &mut self,
+        chat_id: i64,
+        user_id: Option<i64>,
+        username: &str,
+        is_ignored: bool,
+    ) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        Ok(match user_id.or_else(|| chain.find_user(username)) {
+            Some(user_id) => match (chain.set_ignored(user_id, is_ignored), is_ignored) {
+                (true, true) => String::from(
+                    "[messages from that user will no longer be learnt, \
+                     they can remove what they've taught the bot so far with /forget_me]",
+                ),
+                (true, false) => String::from("[messages from that user will be learnt again]"),
+                (false, true) => String::from("[that user is already ignored]"),
+                (false, false) => String::from("[that user isn't ignored]"),
+            },
+            None => String::from("[unknown user]"),
+        })
+    }
+
+    // records that a user agreed to have their messages learnt in a specified chat
+    pub fn opt_in(&mut self, chat_id: i64, user_id: i64) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        let is_new = chain.set_consent(user_id, true);
+        Ok(match (is_new, chain.settings.is_learnt_from(user_id)) {
+            (_, false) => String::from(
+                "[consent noted, but an admin has asked the bot to ignore your messages]",
+            ),
+            (true, true) => String::from("[your messages will be learnt from now on]"),
+            (false, true) => String::from("[you've already opted in]"),
+        })
+    }
+
+    // records that a user no longer agrees to have their messages learnt in a specified chat
+    // what the bot has learnt from them so far is forgotten as well
+    pub async fn opt_out(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<String, Error> {
+        ChainWrapper::lock_loaded(wrapper, &[chat_id])
+            .await
+            .get_chain(chat_id)?
+            .set_consent(user_id, false);
+
+        let forgotten = ChainWrapper::forget_user(wrapper, chat_id, user_id).await?;
+        Ok(format!(
+            "[your messages will no longer be learnt]\n{}",
+            forgotten
+        ))
+    }
+
+    // sets whether only the messages of users who sent /optin are learnt in a specified chat
+    pub fn set_opt_in_only(&mut self, chat_id: i64, opt_in_only: bool) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        chain.set_opt_in_only(opt_in_only);
+        Ok(if opt_in_only {
+            format!(
+                "[only messages from members who sent /optin will be learnt \
+                 ({} so far), what's been learnt before is kept]",
+                chain.settings.opted_in.len()
+            )
+        } else {
+            String::from(
+                "[messages from everyone will be learnt, except for members who sent /optout]",
+            )
+        })
+    }
+
+    // manages the blacklist of a specified Markov chain
+    // action is one of "add", "remove" or "list"
+    pub async fn blacklist(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        action: &str,
+        phrase: &str,
+    ) -> Result<String, Error> {
+        let phrase = phrase.trim();
+        if action != "list" && phrase.is_empty() {
+            return Ok(String::from(
+                "[usage: /blacklist add|remove phrase, /blacklist list]",
+            ));
+        }
+
+        let mut guard = ChainWrapper::lock_loaded(wrapper, &[chat_id]).await;
+        let chain = guard.get_chain(chat_id)?;
+        Ok(match action {
+            "add" => match chain.blacklist_add(phrase) {
+                Some(0) => String::from("[phrase blacklisted]"),
+                Some(count) => {
+                    drop(guard);
+
+                    // the append log may still hold the removed lines, replace it right away
+                    if let Some(err) = ChainWrapper::persist(wrapper, chat_id).await {
+                        return Err(err);
+                    }
+
+                    format!("[phrase blacklisted, {} learnt lines removed]", count)
+                }
+                None => String::from("[phrase already blacklisted]"),
+            },
+            "remove" => {
+                if chain.blacklist_remove(phrase) {
+                    String::from("[phrase removed from the blacklist]")
+                } else {
+                    String::from("[phrase not blacklisted]")
+                }
+            }
+            "list" => {
+                if chain.settings.blacklist.is_empty() {
+                    String::from("[the blacklist is empty]")
+                } else {
+                    format!("[blacklist]\n{}", chain.settings.blacklist.join("\n"))
+                }
+            }
+            _ => String::from("[usage: /blacklist add|remove phrase, /blacklist list]"),
+        })
+    }
+
+    // manages the words a specified Markov chain mustn't say
+    pub fn censor(&mut self, chat_id: i64, action: &str, word: &str) -> Result<String, Error> {
+        let usage = "[usage: /censor add|remove word, /censor list]";
+
+        let word = word.trim();
+        if action != "list" && (word.is_empty() || word.contains(char::is_whitespace)) {
+            return Ok(String::from(usage));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        Ok(match action {
+            "add" => {
+                if chain.censor_add(word) {
+                    String::from("[word censored]")
+                } else {
+                    String::from("[word already censored]")
+                }
+            }
+            "remove" => {
+                if chain.censor_remove(word) {
+                    String::from("[word no longer censored]")
+                } else {
+                    String::from("[word not censored]")
+                }
+            }
+            "list" => {
+                if chain.settings.censored.is_empty() {
+                    String::from("[no words are censored]")
+                } else {
+                    format!("[censored words]\n{}", chain.settings.censored.join("\n"))
+                }
+            }
+            _ => String::from(usage),
+        })
+    }
+
+    // toggles a text filter of a specified Markov chain, or lists the filters
+    pub fn toggle_filter(&mut self, chat_id: i64, name: &str) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        if name.is_empty() {
+            return Ok(format!(
+                "[text filters]\n{}",
+                chain.settings.describe_filters()
+            ));
+        }
+
+        Ok(match chain.toggle_filter(name) {
+            Some(true) => format!("[{} will be stripped from learnt messages]", name),
+            Some(false) => format!("[{} will be kept in learnt messages]", name),
+            None => String::from(
+                "[usage: /strip urls|mentions|hashtags|commands|symbols|forwarded|anonymous]",
+            ),
+        })
+    }
+
+    // sets the minimum length of messages learnt by a specified Markov chain
+    pub fn set_min_words(&mut self, chat_id: i64, min_words: usize) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        chain.set_min_words(min_words);
+        Ok(if min_words <= 1 {
+            String::from("[messages of any length will be learnt]")
+        } else {
+            format!(
+                "[messages shorter than {} words will be ignored]",
+                min_words
+            )
+        })
+    }
+
+    // sets the retention period of a specified chat
+    pub fn set_retention(&mut self, chat_id: i64, days: u64) -> Result<String, Error> {
+        if days > MAX_RETENTION_DAYS {
+            return Ok(format!(
+                "[the retention period can be at most {} days]",
+                MAX_RETENTION_DAYS
+            ));
+        }
+
+        let chain = self.get_chain(chat_id)?;
+        chain.set_retention(days);
+        Ok(if days == 0 {
+            String::from("[learnt data will be kept until deleted with /clear_data]")
+        } else {
+            format!(
+                "[everything learnt will be wiped (backups and globally shared lines included) once it's {} days old]",
+                days
+            )
+        })
+    }
+
+    // describes what the bot stores about a specified chat
+    pub fn privacy(&mut self, chat_id: i64) -> String {
+        let mut msg = String::from(
+            "[privacy]\nfor every chat, the bot stores:\n\
+             - the lines it learnt (after applying the /strip filters), each with the ID of its author\n\
+             - usernames and IDs of members whose messages were learnt\n\
+             - IDs of stickers sent in the chat and how often they were sent\n\
+             - the chat's settings, including the IDs of members who rated its last 100 phrases with /feedback on\n\
+             - with /share_global on, another copy of the learnt lines in a chain shared with other chats\n\
+             - if whoever runs the bot set up speech-to-text, the transcripts of voice messages \
+             (the audio itself is only sent to their transcription server)\n\
+             media, message history from before the bot joined and messages it ignores are never stored. \
+             the data is uploaded to the storage service chosen by whoever runs the bot, \
+             along with a few backups, and is kept in a local log on the server until it's uploaded.\n\n\
+             /forget_me removes what the bot learnt from you (/optout also stops it from learning more), \
+             /clear_data removes everything \
+             and /retention days makes the bot wipe everything on its own",
+        );
+
+        match self.get_chain(chat_id) {
+            Ok(chain) => {
+                if chain.settings.opt_in_only {
+                    msg.push_str(&format!(
+                        "\n\nlearning: only from members who sent /optin ({} so far)",
+                        chain.settings.opted_in.len()
+                    ));
+                }
+
+                msg.push_str("\n\nretention: ");
+                match (chain.settings.retention_days, chain.learnt_since) {
+                    (0, _) => msg.push_str("none, data is kept until deleted"),
+                    (days, Some(since)) => msg.push_str(&format!(
+                        "{} days, the oldest data was learnt on {}",
+                        days,
+                        utils::format_timestamp(since)
+                    )),
+                    (days, None) => msg.push_str(&format!("{} days, nothing learnt yet", days)),
+                }
+            }
+            Err(e) => {
+                dbg!(e);
+            }
+        }
+
+        msg
+    }
+
+    // removes a specified user's lines from a specified Markov chain
+    // the user's lines shared with the global chain are forgotten as well
+    pub async fn forget_user(
+        wrapper: &Mutex<ChainWrapper>,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<String, Error> {
+        let (global_count, count) = {
+            let mut wrapper =
+                ChainWrapper::lock_loaded(wrapper, &[chat_id, chat_key::GLOBAL]).await;
+            let global_count = wrapper.get_chain(chat_key::GLOBAL)?.forget(user_id);
+            (global_count, wrapper.get_chain(chat_id)?.forget(user_id))
+        };
+
+        // the append logs may still hold the user's lines, they're replaced right away
+        for (id, forgotten) in [(chat_key::GLOBAL, global_count), (chat_id, count)].iter() {
+            if *forgotten > 0 {
+                if let Some(err) = ChainWrapper::persist(wrapper, *id).await {
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(match count {
+            0 => String::from("[nothing to forget]"),
+            count => format!("[{} of your lines forgotten]", count),
+        })
+    }
+}
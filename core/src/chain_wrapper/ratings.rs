@@ -0,0 +1,346 @@
+// feedback on generated phrases: rating buttons, /speak2 duels and /quiz
+
+use super::{ChainInfo, ChainWrapper};
+use crate::error::Error;
+use crate::feedback::{self, Ballot, Duel, Vote};
+use crate::menu;
+use crate::quiz::{self, Guess, Quiz};
+use crate::{tokenizer, utils};
+
+use std::cmp::Reverse;
+
+use rand::{seq::SliceRandom, Rng};
+
+// the number of phrases generated in search of one different from the first /speak2 candidate
+const DUEL_ATTEMPTS: usize = 10;
+
+// the number of members listed by /quiz_scores
+const QUIZ_LEADERBOARD: usize = 10;
+
+// a line picked for /quiz, its author and the members to choose the author from
+type QuizLine = (String, i64, Vec<(i64, String)>);
+
+// the round of a /speak2 duel along with its phrases and their buttons
+pub type DuelRound = (u32, [String; 2], [menu::Buttons; 2]);
+
+impl ChainInfo {
+    // picks a line for /quiz along with its author and the members to choose the author from
+    // only members with a username take part, as the choices would be nameless otherwise
+    // returns None if fewer than two members have said something long enough
+    fn quiz_line<R: Rng>(&mut self, rng: &mut R) -> Option<QuizLine> {
+        self.touch();
+
+        let mut candidates: Vec<(i64, String, Vec<&String>)> = self
+            .contributions
+            .iter()
+            .filter_map(|(user_id, lines)| {
+                let name = self.username_of(*user_id)?;
+                let lines: Vec<&String> = lines
+                    .iter()
+                    .filter(|line| tokenizer::tokenize(line).len() >= quiz::MIN_WORDS)
+                    .collect();
+                if lines.is_empty() {
+                    None
+                } else {
+                    Some((*user_id, name, lines))
+                }
+            })
+            .collect();
+
+        if candidates.len() < 2 {
+            return None;
+        }
+
+        candidates.shuffle(rng);
+        let (author, _, lines) = &candidates[0];
+        let line = lines.choose(rng)?.to_string();
+
+        let options = candidates
+            .iter()
+            .take(quiz::OPTIONS)
+            .map(|(user_id, name, _)| (*user_id, name.clone()))
+            .collect();
+        Some((line, *author, options))
+    }
+
+    // gives a user a point for guessing the author in /quiz
+    // returns the user's points
+    fn add_quiz_point(&mut self, user_id: i64) -> u32 {
+        self.touch();
+
+        let points = self.settings.quiz_scores.entry(user_id).or_insert(0);
+        *points += 1;
+        *points
+    }
+
+    // starts tracking a message about to be sent, so that members can rate it
+    // returns the rating buttons to attach to it, None if it can't be rated
+    // the settings file holds the tracked messages, so a snapshot isn't needed
+    fn track_generation<R: Rng>(&mut self, phrase: &str, rng: &mut R) -> Option<menu::Buttons> {
+        feedback::track(&mut self.settings.generations, phrase, rng)
+    }
+
+    // records a member's rating of a generated message
+    fn rate_generation(&mut self, id: u32, user_id: i64, change: i32) -> String {
+        match feedback::vote(&mut self.settings.generations, id, user_id, change) {
+            Vote::Counted(word, change) => {
+                self.touch();
+                feedback::apply(&mut self.settings.start_scores, &word, change);
+                String::from("[thanks for the feedback]")
+            }
+            Vote::Repeated => String::from("[you've already rated this message]"),
+            Vote::Expired => String::from("[this message can no longer be rated]"),
+        }
+    }
+
+    // generates two different phrases for members to choose from in /speak2
+    fn duel_candidates<R: Rng>(&mut self, rng: &mut R) -> Option<[String; 2]> {
+        self.touch();
+
+        let first = self.gen_rated(rng)?;
+        for _ in 0..DUEL_ATTEMPTS {
+            let second = self.gen_rated(rng)?;
+            if second != first {
+                return Some([first, second]);
+            }
+        }
+
+        None
+    }
+
+    // starts a /speak2 duel between two phrases
+    // returns the round of the duel along with the buttons of each phrase
+    fn start_duel<R: Rng>(
+        &mut self,
+        candidates: &[String; 2],
+        rng: &mut R,
+    ) -> (u32, [menu::Buttons; 2]) {
+        let now = utils::unix_now();
+        self.settle_abandoned_duels(now);
+
+        let (duel, buttons) = Duel::new(candidates, now, rng);
+        let round = duel.round();
+        self.settings.duels.push(duel);
+        (round, buttons)
+    }
+
+    // records a vote for one of the phrases of a /speak2 duel
+    fn vote_duel(&mut self, round: u32, user_id: i64, choice: usize) -> Ballot {
+        match self
+            .settings
+            .duels
+            .iter_mut()
+            .find(|duel| duel.round() == round)
+        {
+            Some(duel) => duel.vote(user_id, choice, utils::unix_now()),
+            None => Ballot::Expired,
+        }
+    }
+
+    // ends a /speak2 duel, letting its outcome affect the ratings of starting words
+    // returns the index of the winning phrase, None if the duel is unknown
+    fn settle_duel(&mut self, round: u32) -> Option<usize> {
+        let index = self
+            .settings
+            .duels
+            .iter()
+            .position(|duel| duel.round() == round)?;
+        let duel = self.settings.duels.remove(index);
+        Some(duel.settle(&mut self.settings.start_scores))
+    }
+
+    // settles the duels whose timers were lost, e.g. to a restart
+    fn settle_abandoned_duels(&mut self, now: u64) {
+        let abandoned: Vec<u32> = self
+            .settings
+            .duels
+            .iter()
+            .filter(|duel| duel.is_abandoned(now))
+            .map(Duel::round)
+            .collect();
+
+        for round in abandoned {
+            self.settle_duel(round);
+        }
+    }
+
+    // sets whether generated messages come with buttons for rating them
+    pub fn set_feedback(&mut self, is_enabled: bool) {
+        self.touch();
+        self.settings.feedback = is_enabled;
+        self.is_stale = true;
+    }
+}
+
+impl ChainWrapper {
+    // starts a round of "guess who said it" in a specified chat
+    // returns the question along with the buttons to answer it with
+    pub fn quiz(&mut self, chat_id: i64) -> Result<Result<(String, menu::Buttons), String>, Error> {
+        let mut rng = rand::thread_rng();
+
+        let (line, author, options) = match self.get_chain(chat_id)?.quiz_line(&mut rng) {
+            Some(question) => question,
+            None => {
+                return Ok(Err(String::from(
+                    "[not enough members with a username have said something yet]",
+                )))
+            }
+        };
+
+        let (quiz, text, buttons) = Quiz::new(&line, author, options, &mut rng);
+        self.quizzes.insert(chat_id, quiz);
+        Ok(Ok((text, buttons)))
+    }
+
+    // records a guess made by pressing one of the buttons of a quiz in a specified chat
+    // returns a notification for the person who pressed it
+    pub fn answer_quiz(&mut self, chat_id: i64, user_id: i64, data: &str) -> Result<String, Error> {
+        let (round, author) = match quiz::parse(data) {
+            Some(guess) => guess,
+            None => return Ok(String::from("[unknown option]")),
+        };
+
+        let guess = match self.quizzes.get_mut(&chat_id) {
+            Some(quiz) => quiz.guess(user_id, round, author),
+            None => Guess::Expired,
+        };
+
+        Ok(match guess {
+            Guess::Correct => format!(
+                "[correct! you have {} points]",
+                self.get_chain(chat_id)?.add_quiz_point(user_id)
+            ),
+            Guess::Wrong => String::from("[wrong, better luck next time]"),
+            Guess::Repeated => String::from("[you've already guessed]"),
+            Guess::Expired => String::from("[this quiz is over]"),
+        })
+    }
+
+    // starts tracking a message about to be sent in a specified chat if it has rating buttons enabled
+    // returns the buttons to attach to the message
+    pub fn feedback_buttons(&mut self, chat_id: i64, phrase: &str) -> Option<menu::Buttons> {
+        match self.get_chain(chat_id) {
+            Ok(chain) if chain.settings.feedback => {
+                chain.track_generation(phrase, &mut rand::thread_rng())
+            }
+            Ok(_) => None,
+            Err(e) => {
+                dbg!(e);
+                None
+            }
+        }
+    }
+
+    // records a member's rating of a message generated in a specified chat
+    // returns the notification to show to the member
+    pub fn rate_generation(
+        &mut self,
+        chat_id: i64,
+        user_id: i64,
+        data: &str,
+    ) -> Result<String, Error> {
+        let (id, change) = match feedback::parse(data) {
+            Some(rating) => rating,
+            None => return Ok(String::from("[this message can no longer be rated]")),
+        };
+
+        let chain = self.get_chain(chat_id)?;
+        Ok(chain.rate_generation(id, user_id, change))
+    }
+
+    // generates two phrases for members of a specified chat to choose from
+    // returns the round of the duel along with the phrases and their buttons
+    pub fn speak2(&mut self, chat_id: i64) -> Result<Result<DuelRound, String>, Error> {
+        let mut rng = rand::thread_rng();
+        let chain = self.get_chain(chat_id)?;
+
+        let candidates = match chain.duel_candidates(&mut rng) {
+            Some(candidates) => candidates,
+            None => {
+                return Ok(Err(String::from(
+                    "[not enough phrases learnt to choose from]",
+                )))
+            }
+        };
+
+        let (round, buttons) = chain.start_duel(&candidates, &mut rng);
+        Ok(Ok((round, candidates, buttons)))
+    }
+
+    // records a vote cast by pressing the button under a /speak2 candidate in a specified chat
+    // returns a notification for the person who pressed it
+    pub fn vote_duel(&mut self, chat_id: i64, user_id: i64, data: &str) -> String {
+        let (round, choice) = match feedback::parse_duel(data) {
+            Some(vote) => vote,
+            None => return String::from("[unknown option]"),
+        };
+
+        let ballot = match self.get_chain(chat_id) {
+            Ok(chain) => chain.vote_duel(round, user_id, choice),
+            Err(e) => {
+                dbg!(e);
+                Ballot::Expired
+            }
+        };
+
+        match ballot {
+            Ballot::Cast => String::from("[vote counted]"),
+            Ballot::Repeated => String::from("[you've already voted]"),
+            Ballot::Expired => String::from("[the voting is over]"),
+        }
+    }
+
+    // ends a /speak2 duel in a specified chat
+    // returns the index of the phrase that stays, None if the duel is unknown
+    pub fn settle_duel(&mut self, chat_id: i64, round: u32) -> Option<usize> {
+        match self.get_chain(chat_id) {
+            Ok(chain) => chain.settle_duel(round),
+            Err(e) => {
+                dbg!(e);
+                None
+            }
+        }
+    }
+
+    // sets whether messages generated in a specified chat come with buttons for rating them
+    pub fn set_feedback(&mut self, chat_id: i64, is_enabled: bool) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        chain.set_feedback(is_enabled);
+        Ok(if is_enabled {
+            String::from(
+                "[from now on, /speak comes with buttons for rating the phrase, \
+                 the bot will lean towards what you like]",
+            )
+        } else {
+            String::from("[rating buttons disabled, past ratings are still used]")
+        })
+    }
+
+    // lists the members of a specified chat with the most /quiz points
+    pub fn quiz_scores(&mut self, chat_id: i64) -> Result<String, Error> {
+        let chain = self.get_chain(chat_id)?;
+        let mut scores: Vec<(i64, u32)> = chain
+            .settings
+            .quiz_scores
+            .iter()
+            .map(|(user_id, points)| (*user_id, *points))
+            .collect();
+        if scores.is_empty() {
+            return Ok(String::from("[nobody has scored yet, start with /quiz]"));
+        }
+
+        scores.sort_unstable_by_key(|(_, score)| Reverse(*score));
+        let list: Vec<String> = scores
+            .into_iter()
+            .take(QUIZ_LEADERBOARD)
+            .enumerate()
+            .map(|(i, (user_id, points))| {
+                let name = chain
+                    .username_of(user_id)
+                    .unwrap_or_else(|| String::from("someone without a username"));
+                format!("{}. {} - {}", i + 1, name, points)
+            })
+            .collect();
+        Ok(format!("[quiz scores]\n{}", list.join("\n")))
+    }
+}
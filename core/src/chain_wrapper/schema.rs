@@ -0,0 +1,405 @@
+// the layouts ChainInfo had in earlier schema versions and the steps converting them to the current one
+
+use super::{ChainInfo, DEFAULT_ORDER, SCHEMA_VERSION};
+use crate::chain::{Chain, LegacyChain};
+use crate::settings::{self, Settings};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::SystemTime,
+};
+
+use serde::Deserialize;
+#[cfg(test)]
+use serde::Serialize;
+
+// the layout of ChainInfo used by schema versions 0 and 1
+// legacy layouts are kept frozen, so that older blobs can still be read
+// each of them can be converted to the following one
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+pub(super) struct ChainInfoV1 {
+    chain: LegacyChain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+}
+
+// the layout of ChainInfo used by schema version 2
+#[derive(Deserialize)]
+pub(super) struct ChainInfoV2 {
+    chain: LegacyChain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    contributions: HashMap<i64, Vec<String>>,
+}
+
+impl From<ChainInfoV1> for ChainInfoV2 {
+    fn from(old: ChainInfoV1) -> ChainInfoV2 {
+        ChainInfoV2 {
+            chain: old.chain,
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: HashMap::new(),
+        }
+    }
+}
+
+impl From<ChainInfoV2> for ChainInfoV3 {
+    fn from(old: ChainInfoV2) -> ChainInfoV3 {
+        ChainInfoV3 {
+            chain: old.chain,
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: old.contributions,
+            usernames: HashMap::new(),
+        }
+    }
+}
+
+// the layout of ChainInfo used by schema version 3
+#[derive(Deserialize)]
+pub(super) struct ChainInfoV3 {
+    chain: LegacyChain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    contributions: HashMap<i64, Vec<String>>,
+    usernames: HashMap<String, i64>,
+}
+
+impl From<ChainInfoV3> for ChainInfoV4 {
+    fn from(old: ChainInfoV3) -> ChainInfoV4 {
+        ChainInfoV4 {
+            chain: old.chain,
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: old.contributions,
+            usernames: old.usernames,
+            // chains used to be created with the crate's default order
+            order: DEFAULT_ORDER,
+        }
+    }
+}
+
+// the layout of ChainInfo used by schema version 4
+// the last one relying on the markov crate
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+pub(super) struct ChainInfoV4 {
+    chain: LegacyChain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    contributions: HashMap<i64, Vec<String>>,
+    usernames: HashMap<String, i64>,
+    // the order is stored in the chain itself as well
+    #[allow(dead_code)]
+    order: usize,
+}
+
+impl From<ChainInfoV4> for ChainInfoV5 {
+    fn from(old: ChainInfoV4) -> ChainInfoV5 {
+        ChainInfoV5 {
+            chain: Chain::from(old.chain),
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: old.contributions,
+            usernames: old.usernames,
+        }
+    }
+}
+
+// the layout of ChainInfo used by schema version 5
+#[derive(Deserialize)]
+pub(super) struct ChainInfoV5 {
+    chain: Chain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    contributions: HashMap<i64, Vec<String>>,
+    usernames: HashMap<String, i64>,
+}
+
+impl From<ChainInfoV5> for ChainInfoV6 {
+    fn from(old: ChainInfoV5) -> ChainInfoV6 {
+        ChainInfoV6 {
+            chain: old.chain,
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: old.contributions,
+            usernames: old.usernames,
+            settings: Settings::default(),
+        }
+    }
+}
+
+// the layout of ChainInfo used by schema version 6
+#[derive(Deserialize)]
+pub(super) struct ChainInfoV6 {
+    chain: Chain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    contributions: HashMap<i64, Vec<String>>,
+    usernames: HashMap<String, i64>,
+    #[serde(with = "settings::as_json")]
+    settings: Settings,
+}
+
+impl From<ChainInfoV6> for ChainInfoV7 {
+    fn from(old: ChainInfoV6) -> ChainInfoV7 {
+        ChainInfoV7 {
+            reverse: old.chain.reversed(),
+            chain: old.chain,
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: old.contributions,
+            usernames: old.usernames,
+            settings: old.settings,
+        }
+    }
+}
+
+// the layout of ChainInfo used by schema version 7
+#[derive(Deserialize)]
+pub(super) struct ChainInfoV7 {
+    chain: Chain,
+    reverse: Chain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    contributions: HashMap<i64, Vec<String>>,
+    usernames: HashMap<String, i64>,
+    #[serde(with = "settings::as_json")]
+    settings: Settings,
+}
+
+impl From<ChainInfoV7> for ChainInfoV8 {
+    fn from(old: ChainInfoV7) -> ChainInfoV8 {
+        ChainInfoV8 {
+            chain: old.chain,
+            reverse: old.reverse,
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: old.contributions,
+            usernames: old.usernames,
+            settings: old.settings,
+            stickers: HashMap::new(),
+        }
+    }
+}
+
+// the layout of ChainInfo used by schema version 8
+#[derive(Deserialize)]
+pub(super) struct ChainInfoV8 {
+    chain: Chain,
+    reverse: Chain,
+    chat_id: i64,
+    is_learning: bool,
+    last_accessed: SystemTime,
+    contributions: HashMap<i64, Vec<String>>,
+    usernames: HashMap<String, i64>,
+    #[serde(with = "settings::as_json")]
+    settings: Settings,
+    stickers: HashMap<String, u32>,
+}
+
+impl From<ChainInfoV8> for ChainInfo {
+    fn from(old: ChainInfoV8) -> ChainInfo {
+        ChainInfo {
+            chain: old.chain,
+            reverse: old.reverse,
+            chat_id: old.chat_id,
+            is_learning: old.is_learning,
+            last_accessed: old.last_accessed,
+            contributions: old.contributions,
+            usernames: old.usernames,
+            settings: old.settings,
+            stickers: old.stickers,
+            rare_transitions: None,
+            log: Vec::new(),
+            pending: Vec::new(),
+            // store the object in the current format
+            is_stale: true,
+            minor_changes_since: None,
+            last_saved: None,
+            logged_until: 0,
+            is_uploading: false,
+            compactions: 0,
+            recent: VecDeque::new(),
+            seen: VecDeque::new(),
+            last_messages: HashMap::new(),
+            learnt_messages: VecDeque::new(),
+            context: VecDeque::new(),
+            last_generated: HashMap::new(),
+            activity: HashMap::new(),
+            muted: HashMap::new(),
+            stored_settings: None,
+            learnt_since: None,
+            last_used: None,
+        }
+    }
+}
+
+// a deserialized ChainInfo in any of the layouts it had over time
+// adding a schema version takes a variant, an arm in decode and one in upgrade
+pub(super) enum Layout {
+    V1(ChainInfoV1),
+    V2(ChainInfoV2),
+    V3(ChainInfoV3),
+    V4(ChainInfoV4),
+    V5(ChainInfoV5),
+    V6(ChainInfoV6),
+    V7(ChainInfoV7),
+    V8(ChainInfoV8),
+    // boxed, as the current layout is much larger than the legacy ones
+    Current(Box<ChainInfo>),
+}
+
+impl Layout {
+    // deserializes a blob of a given schema version
+    pub(super) fn decode(version: u32, binc: &[u8]) -> Result<Layout, String> {
+        fn read<'a, T: Deserialize<'a>>(binc: &'a [u8]) -> Result<T, String> {
+            bincode::deserialize(binc).map_err(|e| e.to_string())
+        }
+
+        Ok(match version {
+            // unversioned blobs share their layout with version 1
+            0 | 1 => Layout::V1(read(binc)?),
+            2 => Layout::V2(read(binc)?),
+            3 => Layout::V3(read(binc)?),
+            4 => Layout::V4(read(binc)?),
+            5 => Layout::V5(read(binc)?),
+            6 => Layout::V6(read(binc)?),
+            7 => Layout::V7(read(binc)?),
+            8 => Layout::V8(read(binc)?),
+            SCHEMA_VERSION => Layout::Current(Box::new(read(binc)?)),
+            _ => return Err(format!("unsupported schema version {}", version)),
+        })
+    }
+
+    // converts the object to the layout of the following schema version
+    pub(super) fn upgrade(self) -> Layout {
+        match self {
+            Layout::V1(old) => Layout::V2(old.into()),
+            Layout::V2(old) => Layout::V3(old.into()),
+            Layout::V3(old) => Layout::V4(old.into()),
+            Layout::V4(old) => Layout::V5(old.into()),
+            Layout::V5(old) => Layout::V6(old.into()),
+            Layout::V6(old) => Layout::V7(old.into()),
+            Layout::V7(old) => Layout::V8(old.into()),
+            Layout::V8(old) => Layout::Current(Box::new(old.into())),
+            Layout::Current(chain) => Layout::Current(chain),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn version_1_blobs_are_migrated() {
+        let blob = ChainInfoV1 {
+            chain: LegacyChain::of_line("hello there"),
+            chat_id: 42,
+            is_learning: false,
+            last_accessed: UNIX_EPOCH,
+        };
+        let binc = bincode::serialize(&blob).unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        // unversioned blobs share the layout
+        for version in 0..=1 {
+            let chain = ChainInfo::migrate(version, &binc).unwrap();
+            assert_eq!(chain.chat_id, 42);
+            assert!(!chain.is_learning);
+            assert_eq!(chain.last_accessed, UNIX_EPOCH);
+            assert_eq!(chain.chain.order(), DEFAULT_ORDER);
+            assert_eq!(chain.chain.generate_str(1.0, &mut rng), "hello there");
+            assert_eq!(
+                chain
+                    .reverse
+                    .generate_str_ending_with("there", 1.0, &mut rng),
+                "hello there"
+            );
+            assert!(chain.contributions.is_empty());
+            assert!(chain.usernames.is_empty());
+            assert!(chain.stickers.is_empty());
+            // migrated chains are stored in the current layout
+            assert!(chain.is_stale);
+        }
+    }
+
+    #[test]
+    fn later_versions_keep_their_data() {
+        let mut contributions = HashMap::new();
+        contributions.insert(7, vec![String::from("hello there")]);
+        let mut usernames = HashMap::new();
+        usernames.insert(String::from("someone"), 7);
+
+        let blob = ChainInfoV4 {
+            chain: LegacyChain::of_line("hello there"),
+            chat_id: -42,
+            is_learning: true,
+            last_accessed: UNIX_EPOCH,
+            contributions,
+            usernames,
+            order: 1,
+        };
+        let binc = bincode::serialize(&blob).unwrap();
+
+        let chain = ChainInfo::migrate(4, &binc).unwrap();
+        assert_eq!(chain.chat_id, -42);
+        assert_eq!(chain.contributions[&7], vec![String::from("hello there")]);
+        assert_eq!(chain.usernames["someone"], 7);
+        assert_eq!(chain.chain.frequency("there"), 1);
+    }
+
+    #[test]
+    fn current_blobs_round_trip() {
+        let blob = ChainInfoV1 {
+            chain: LegacyChain::of_line("round trip"),
+            chat_id: 42,
+            is_learning: true,
+            last_accessed: UNIX_EPOCH,
+        };
+        let old = ChainInfo::migrate(1, &bincode::serialize(&blob).unwrap()).unwrap();
+        let binc = bincode::serialize(&old).unwrap();
+
+        let chain = ChainInfo::migrate(SCHEMA_VERSION, &binc).unwrap();
+        assert_eq!(chain.chat_id, 42);
+        assert_eq!(
+            chain.chain.generate_str(1.0, &mut StdRng::seed_from_u64(7)),
+            "round trip"
+        );
+        assert!(!chain.is_stale);
+    }
+
+    #[test]
+    fn unreadable_blobs_are_rejected() {
+        let blob = ChainInfoV1 {
+            chain: LegacyChain::of_line("hello"),
+            chat_id: 42,
+            is_learning: true,
+            last_accessed: UNIX_EPOCH,
+        };
+        let binc = bincode::serialize(&blob).unwrap();
+
+        assert!(ChainInfo::migrate(1, &binc[..binc.len() / 2]).is_err());
+        assert!(ChainInfo::migrate(SCHEMA_VERSION + 1, &binc).is_err());
+    }
+}
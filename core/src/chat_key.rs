@@ -0,0 +1,94 @@
+// chains are keyed by i64 chat IDs, whichever platform the chat lives on
+// Telegram IDs are used as they are, IDs from other platforms are mapped outside of their range
+// so that chats from different platforms never share a chain by accident
+
+use sha2::{Digest, Sha256};
+
+// Telegram guarantees that chat IDs have at most 52 significant bits
+const TELEGRAM_LIMIT: i64 = 1 << 53;
+
+// the key of the chain shared by all chats that opted into it (see /share_global)
+// Matrix IDs are hashed below it, so it can't be taken by a real chat
+pub const GLOBAL: i64 = i64::MAX;
+
+// maps a Discord channel ID (a snowflake) to a chain key
+// snowflakes start with a timestamp, so every real one is far above TELEGRAM_LIMIT
+// returns None for IDs that can't be mapped without colliding with Telegram chats
+pub fn discord(channel_id: u64) -> Option<i64> {
+    if channel_id < TELEGRAM_LIMIT as u64 || channel_id > i64::MAX as u64 {
+        return None;
+    }
+
+    Some(-(channel_id as i64))
+}
+
+// maps a Matrix ID (of a room or a user) to a chain key
+// Matrix IDs are strings, so they're hashed into the positive range above TELEGRAM_LIMIT
+pub fn matrix(id: &str) -> i64 {
+    let digest = Sha256::digest(id.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    let hash = u64::from_be_bytes(bytes) % (i64::MAX - TELEGRAM_LIMIT) as u64;
+
+    TELEGRAM_LIMIT + hash as i64
+}
+
+// checks if a chain key is a Telegram chat ID
+pub fn is_telegram(key: i64) -> bool {
+    key > -TELEGRAM_LIMIT && key < TELEGRAM_LIMIT
+}
+
+// checks if a chain key was mapped from a Discord channel ID
+pub fn is_discord(key: i64) -> bool {
+    key <= -TELEGRAM_LIMIT
+}
+
+// checks if a chain key was mapped from a Matrix ID
+pub fn is_matrix(key: i64) -> bool {
+    key >= TELEGRAM_LIMIT && key != GLOBAL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telegram_ids_are_kept() {
+        for id in &[-1_001_234_567_890, -42, 42, TELEGRAM_LIMIT - 1] {
+            assert!(is_telegram(*id));
+            assert!(!is_discord(*id));
+            assert!(!is_matrix(*id));
+        }
+    }
+
+    #[test]
+    fn discord_ids_are_mapped_below_telegram() {
+        let key = discord(175_928_847_299_117_063).unwrap();
+        assert!(is_discord(key));
+        assert!(!is_telegram(key));
+        assert_eq!(discord(175_928_847_299_117_064), Some(key - 1));
+    }
+
+    #[test]
+    fn colliding_discord_ids_are_refused() {
+        assert_eq!(discord(42), None);
+        assert_eq!(discord(u64::MAX), None);
+    }
+
+    #[test]
+    fn matrix_ids_are_hashed_above_telegram() {
+        let room = matrix("!room:example.org");
+        assert_eq!(room, matrix("!room:example.org"));
+        assert_ne!(room, matrix("!other:example.org"));
+        assert!(is_matrix(room));
+        assert!(!is_telegram(room));
+        assert!(room < GLOBAL);
+    }
+
+    #[test]
+    fn the_global_key_belongs_to_no_platform() {
+        assert!(!is_telegram(GLOBAL));
+        assert!(!is_discord(GLOBAL));
+        assert!(!is_matrix(GLOBAL));
+    }
+}
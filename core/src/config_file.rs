@@ -0,0 +1,229 @@
+use std::{collections::HashSet, env, fs, path::PathBuf};
+
+use lazy_static::lazy_static;
+
+// the file read if no other one is given with --config
+const DEFAULT_PATH: &str = "config.toml";
+
+// the file dotenv reads on startup
+const DOTENV_FILE: &str = ".env";
+
+// extracts the path given with --config (or --config=path) from the command line
+// returns the default path if the option is not given, along with whether it was given
+fn get_path() -> (String, bool) {
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return (args.next().expect("--config needs a path"), true);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return (path.to_string(), true);
+        }
+    }
+
+    (DEFAULT_PATH.to_string(), false)
+}
+
+lazy_static! {
+    // the path of the configuration file and whether it was given explicitly
+    static ref PATH: (String, bool) = get_path();
+    // the variables set before the file was first read (e.g. by the shell or the .env file)
+    // they take precedence over the file, also when it's read again on SIGHUP
+    static ref OVERRIDES: HashSet<String> = env::vars().map(|(key, _)| key).collect();
+    // the variables set by the shell, before the .env file was first read
+    // reading the .env file again on SIGHUP leaves them alone
+    static ref ENVIRONMENT: HashSet<String> = env::vars().map(|(key, _)| key).collect();
+}
+
+// remembers which variables were set by the shell, has to be called before the .env file is read
+pub fn capture_environment() {
+    lazy_static::initialize(&ENVIRONMENT);
+}
+
+// finds the .env file the way dotenv does on startup, in the working directory or one of its parents
+fn find_dotenv() -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+    dir.ancestors()
+        .map(|dir| dir.join(DOTENV_FILE))
+        .find(|path| path.is_file())
+}
+
+// parses a line of a .env file into a variable and its value
+// returns None for blank lines and comments
+// follows the format dotenv reads: an optional "export" prefix, single-quoted values kept as they are,
+// double-quoted ones with \\, \", \n and \$ escapes, unquoted ones ending at a comment
+// unlike dotenv, references to other variables (e.g. $HOME) aren't substituted
+fn parse_dotenv_line(line: &str) -> Result<Option<(String, String)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let line = match line.strip_prefix("export ") {
+        Some(rest) if rest.contains('=') => rest.trim_start(),
+        _ => line,
+    };
+
+    let (key, value) = match line.split_once('=') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => return Err(format!("expected KEY=VALUE in \"{}\"", line)),
+    };
+
+    let is_valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !is_valid_key {
+        return Err(format!("invalid variable name \"{}\"", key));
+    }
+
+    let value = if let Some(rest) = value.strip_prefix('\'') {
+        match rest.find('\'') {
+            Some(end) => rest[..end].to_string(),
+            None => return Err(format!("unterminated quote in \"{}\"", line)),
+        }
+    } else if let Some(rest) = value.strip_prefix('"') {
+        let mut unquoted = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => unquoted.push('\n'),
+                    Some(c @ '\\') | Some(c @ '"') | Some(c @ '$') => unquoted.push(c),
+                    _ => return Err(format!("invalid escape in \"{}\"", line)),
+                },
+                Some(c) => unquoted.push(c),
+                None => return Err(format!("unterminated quote in \"{}\"", line)),
+            }
+        }
+        unquoted
+    } else {
+        match value.find(" #") {
+            Some(comment) => value[..comment].trim_end().to_string(),
+            None => value.to_string(),
+        }
+    };
+
+    Ok(Some((key.to_string(), value)))
+}
+
+// reads the .env file again and sets the variables it defines
+// variables set by the shell take precedence, as on startup
+// a missing file is not an error
+pub fn reload_dotenv() -> Option<String> {
+    let path = find_dotenv()?;
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => return Some(format!("Failed to read .env: {}", e)),
+    };
+
+    let mut parsed = Vec::new();
+    for line in contents.lines() {
+        match parse_dotenv_line(line) {
+            Ok(Some(var)) => parsed.push(var),
+            Ok(None) => (),
+            Err(e) => return Some(format!("Failed to parse .env: {}", e)),
+        }
+    }
+
+    for (key, value) in parsed {
+        if !ENVIRONMENT.contains(&key) {
+            env::set_var(key, value);
+        }
+    }
+
+    None
+}
+
+// converts a TOML value to the form it takes in an environment variable
+// arrays become comma-separated lists (e.g. allowed_chats = [1, 2])
+fn to_env_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(to_env_value)
+            .collect::<Option<Vec<String>>>()
+            .map(|values| values.join(",")),
+        _ => None,
+    }
+}
+
+// reads the configuration file and sets the environment variables it defines
+// keys are the names of the variables in any case (e.g. max_timedelta = 15 sets MAX_TIMEDELTA)
+// variables set in the environment or the .env file override the ones from the file
+// a missing file is only an error if it was given with --config
+// nothing is set if the file is invalid
+pub fn apply() -> Option<String> {
+    lazy_static::initialize(&OVERRIDES);
+    let (path, is_explicit) = &*PATH;
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) if !is_explicit => return None,
+        Err(e) => return Some(format!("Failed to read {}: {}", path, e)),
+    };
+
+    let table = match toml::from_str::<toml::value::Table>(&contents) {
+        Ok(table) => table,
+        Err(e) => return Some(format!("Failed to parse {}: {}", path, e)),
+    };
+
+    let mut vars = Vec::with_capacity(table.len());
+    for (key, value) in table.iter() {
+        match to_env_value(value) {
+            Some(value) => vars.push((key.to_uppercase(), value)),
+            None => return Some(format!("Unsupported value of {} in {}", key, path)),
+        }
+    }
+
+    for (key, value) in vars {
+        if !OVERRIDES.contains(&key) {
+            env::set_var(key, value);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(line: &str) -> Option<(String, String)> {
+        parse_dotenv_line(line).unwrap()
+    }
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        assert_eq!(parsed("   "), None);
+        assert_eq!(parsed("# BOT_TOKEN=123"), None);
+    }
+
+    #[test]
+    fn reads_values() {
+        let var = |key: &str, value: &str| Some((key.to_string(), value.to_string()));
+
+        assert_eq!(parsed("BOT_TOKEN=123:abc"), var("BOT_TOKEN", "123:abc"));
+        assert_eq!(parsed("export ADMIN_ID = 42"), var("ADMIN_ID", "42"));
+        assert_eq!(parsed("MAX_WORDS=30 # per phrase"), var("MAX_WORDS", "30"));
+        assert_eq!(parsed("PREFIX='a # b'"), var("PREFIX", "a # b"));
+        assert_eq!(
+            parsed(r#"GREETING="hi\n\"there\"""#),
+            var("GREETING", "hi\n\"there\"")
+        );
+        assert_eq!(parsed("EMPTY="), var("EMPTY", ""));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse_dotenv_line("BOT_TOKEN").is_err());
+        assert!(parse_dotenv_line("1TOKEN=x").is_err());
+        assert!(parse_dotenv_line("TOKEN=\"unterminated").is_err());
+    }
+}
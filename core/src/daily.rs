@@ -0,0 +1,105 @@
+use crate::{error::Error, storage};
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+// the name of the file holding the schedules of all chats
+const SCHEDULE_FILE: &str = "daily";
+
+// the number of minutes in a day
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+// the time of a chat's "phrase of the day"
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Daily {
+    // minutes past local midnight
+    minute: i64,
+    // the difference between local time and UTC, in minutes
+    utc_offset: i64,
+}
+
+// parses "HH:MM" into minutes past midnight
+fn parse_time(time: &str) -> Option<i64> {
+    let mut parts = time.splitn(2, ':');
+    let hours = parts.next()?.parse::<i64>().ok()?;
+    let minutes = parts.next().unwrap_or("0").parse::<i64>().ok()?;
+
+    if (0..24).contains(&hours) && (0..60).contains(&minutes) {
+        Some(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+// parses a UTC offset such as "+2", "-5:30" or "UTC+1" into minutes
+fn parse_offset(offset: &str) -> Option<i64> {
+    let offset = offset.trim_start_matches("UTC").trim_start_matches("utc");
+    let (sign, offset) = match offset.chars().next()? {
+        '+' => (1, &offset[1..]),
+        '-' => (-1, &offset[1..]),
+        _ => (1, offset),
+    };
+
+    let minutes = parse_time(offset)?;
+    if minutes <= 14 * 60 {
+        Some(sign * minutes)
+    } else {
+        None
+    }
+}
+
+// formats minutes as "HH:MM"
+fn format_time(minutes: i64) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+impl Daily {
+    // parses "HH:MM [UTC offset]", the offset defaulting to 0
+    pub fn parse(args: &str) -> Option<Daily> {
+        let mut args = args.split_whitespace();
+        let minute = parse_time(args.next()?)?;
+        let utc_offset = match args.next() {
+            Some(offset) => parse_offset(offset)?,
+            None => 0,
+        };
+
+        if args.next().is_some() {
+            return None;
+        }
+
+        Some(Daily { minute, utc_offset })
+    }
+
+    // checks if the phrase is due at a given minute since the Unix epoch
+    pub fn is_due(&self, epoch_minute: i64) -> bool {
+        (epoch_minute + self.utc_offset).rem_euclid(MINUTES_PER_DAY) == self.minute
+    }
+
+    // describes the schedule in a human-readable form
+    pub fn describe(&self) -> String {
+        let sign = if self.utc_offset < 0 { '-' } else { '+' };
+        format!(
+            "{} (UTC{}{})",
+            format_time(self.minute),
+            sign,
+            format_time(self.utc_offset.abs())
+        )
+    }
+}
+
+// downloads the schedules of all chats
+pub async fn load() -> Result<HashMap<i64, Daily>, Error> {
+    match storage::download_file(SCHEDULE_FILE).await? {
+        Some(bytes) => {
+            serde_json::from_slice(&bytes).map_err(|e| Error::Deserialization(e.to_string()))
+        }
+        None => Ok(HashMap::new()),
+    }
+}
+
+// uploads the schedules of all chats
+pub async fn save(schedules: &HashMap<i64, Daily>) -> Option<Error> {
+    let bytes = serde_json::to_vec(schedules).expect("Serialization failed");
+    storage::update_or_create_file(&bytes, SCHEDULE_FILE).await
+}
@@ -0,0 +1,305 @@
+use crate::{
+    error::Error,
+    utils::{self, exponential_retry_async},
+};
+
+use std::env;
+
+use lazy_static::lazy_static;
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+
+// Dropbox API endpoints
+const UPLOAD_URL: &str = "https://content.dropboxapi.com/2/files/upload";
+const DOWNLOAD_URL: &str = "https://content.dropboxapi.com/2/files/download";
+const LIST_REVISIONS_URL: &str = "https://api.dropboxapi.com/2/files/list_revisions";
+const LIST_FOLDER_URL: &str = "https://api.dropboxapi.com/2/files/list_folder";
+const LIST_FOLDER_CONTINUE_URL: &str = "https://api.dropboxapi.com/2/files/list_folder/continue";
+const DELETE_URL: &str = "https://api.dropboxapi.com/2/files/delete_v2";
+const SPACE_USAGE_URL: &str = "https://api.dropboxapi.com/2/users/get_space_usage";
+
+// a header carrying the JSON-encoded arguments of a content request
+const API_ARG: &str = "Dropbox-API-Arg";
+
+// a single revision of a Dropbox file
+#[derive(Deserialize)]
+struct Revision {
+    rev: String,
+}
+
+// a response to a Dropbox revision listing
+#[derive(Deserialize)]
+struct RevisionList {
+    entries: Vec<Revision>,
+}
+
+// a single entry of a Dropbox folder listing
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    // absent for folders
+    size: Option<u64>,
+    server_modified: Option<String>,
+}
+
+// a single page of a Dropbox folder listing
+#[derive(Deserialize)]
+struct EntryList {
+    entries: Vec<Entry>,
+    cursor: String,
+    has_more: bool,
+}
+
+// space allocated to a Dropbox account
+#[derive(Deserialize)]
+struct SpaceAllocation {
+    allocated: Option<u64>,
+}
+
+// a response to a Dropbox space usage request
+#[derive(Deserialize)]
+struct SpaceUsage {
+    used: u64,
+    allocation: SpaceAllocation,
+}
+
+lazy_static! {
+    // an HTTPS client used for all Dropbox requests
+    static ref CLIENT: Client = Client::new();
+    // the access token of a Dropbox app, empty if not set (see initialize)
+    static ref TOKEN: String = env::var("DROPBOX_TOKEN").unwrap_or_default();
+    // the path of a chaindump folder, "/" if CHAINDUMP_DIR is not set (see initialize)
+    static ref PARENT: String = get_folder_path();
+}
+
+// returns Dropbox path of a chaindump directory
+fn get_folder_path() -> String {
+    let chaindump_dir = env::var("CHAINDUMP_DIR").unwrap_or_default();
+    format!("/{}", chaindump_dir.trim_matches('/'))
+}
+
+// returns Dropbox path of a specified file
+fn get_file_path(name: &str) -> String {
+    format!("{}/{}", *PARENT, name)
+}
+
+// uploads a file to Dropbox using the given write mode
+async fn upload_file(bytes: &[u8], name: &str, mode: &str) -> Option<Error> {
+    let arg = json!({
+        "path": get_file_path(name),
+        "mode": mode,
+        "mute": true,
+    })
+    .to_string();
+
+    let req = exponential_retry_async(|| async {
+        let res = CLIENT
+            .post(UPLOAD_URL)
+            .bearer_auth(&*TOKEN)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .header(API_ARG, arg.as_str())
+            .body(bytes.to_vec())
+            .send()
+            .await?;
+
+        let status = res.status();
+        if status == StatusCode::OK {
+            Ok(())
+        } else {
+            let body = res.text().await.unwrap_or_default();
+            Err(Error::from_status(status, format!("{}: {}", status, body)).into())
+        }
+    })
+    .await;
+
+    match req {
+        Ok(_) => None,
+        Err(e) => Some(Error::from_request("upload_file failed", e)),
+    }
+}
+
+// replaces contents of a specified Dropbox file
+async fn replace_file(bytes: &[u8], name: &str) -> Option<Error> {
+    upload_file(bytes, name, "overwrite").await
+}
+
+// downloads a file from a specified Dropbox path
+async fn download_path(path: &str) -> Result<Option<Vec<u8>>, Error> {
+    let arg = json!({ "path": path }).to_string();
+
+    let req = exponential_retry_async(|| async {
+        let res = CLIENT
+            .post(DOWNLOAD_URL)
+            .bearer_auth(&*TOKEN)
+            .header(API_ARG, arg.as_str())
+            .send()
+            .await?;
+
+        let status = res.status();
+        let content = res.bytes().await?;
+
+        match status {
+            StatusCode::OK => Ok(Some(content.to_vec())),
+            // the API reports a missing file as a path lookup conflict
+            StatusCode::CONFLICT if String::from_utf8_lossy(&content).contains("not_found") => {
+                Ok(None)
+            }
+            status => Err(Error::from_status(
+                status,
+                format!("{}: {}", status, String::from_utf8_lossy(&content)),
+            )
+            .into()),
+        }
+    })
+    .await;
+
+    match req {
+        Ok(value) => Ok(value),
+        Err(e) => Err(Error::from_request("Failed to download file", e)),
+    }
+}
+
+// sends a JSON request to a Dropbox RPC endpoint and parses the response
+async fn rpc<T>(url: &str, arg: &serde_json::Value) -> Result<T, failure::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    exponential_retry_async(|| async {
+        let res = CLIENT
+            .post(url)
+            .bearer_auth(&*TOKEN)
+            .json(arg)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<T>()
+            .await?;
+
+        Ok(res)
+    })
+    .await
+}
+
+// initializes lazy_static fields
+// fails if the token or the folder is missing, so that the bot doesn't panic on the first upload
+pub fn initialize() -> Result<(), Error> {
+    lazy_static::initialize(&CLIENT);
+
+    if TOKEN.trim().is_empty() {
+        return Err(Error::Storage(String::from("DROPBOX_TOKEN not set")));
+    }
+    if PARENT.as_str() == "/" {
+        return Err(Error::Storage(String::from("CHAINDUMP_DIR not set")));
+    }
+
+    Ok(())
+}
+
+// replaces contents of a specified Dropbox file
+// creates a new file if one does not exist
+pub async fn update_or_create_file(bytes: &[u8], name: &str) -> Option<Error> {
+    replace_file(bytes, name).await
+}
+
+// downloads a specified Dropbox file
+pub async fn download_file(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    download_path(&get_file_path(name)).await
+}
+
+// returns revision IDs of a specified Dropbox file, newest first
+pub async fn list_revisions(name: &str) -> Result<Vec<String>, Error> {
+    let arg = json!({
+        "path": get_file_path(name),
+        "limit": 10,
+    });
+
+    match rpc::<RevisionList>(LIST_REVISIONS_URL, &arg).await {
+        Ok(list) => Ok(list.entries.into_iter().map(|entry| entry.rev).collect()),
+        Err(e) => Err(Error::from_request("list_revisions failed", e)),
+    }
+}
+
+// downloads a specified revision of a Dropbox file
+pub async fn download_revision(name: &str, revision: &str) -> Result<Vec<u8>, Error> {
+    match download_path(&format!("rev:{}", revision)).await {
+        Err(e) => Err(e),
+        Ok(None) => Err(Error::NotFound(format!(
+            "Revision {} of {} not found",
+            revision, name
+        ))),
+        Ok(Some(content)) => Ok(content),
+    }
+}
+
+// returns names, sizes and modification times of all files in the chaindump folder
+// starting with a given prefix
+pub async fn list_files(prefix: &str) -> Result<Vec<(String, u64, Option<u64>)>, Error> {
+    let mut names = Vec::new();
+    let mut req = rpc::<EntryList>(LIST_FOLDER_URL, &json!({ "path": *PARENT })).await;
+
+    loop {
+        match req {
+            Err(e) => return Err(Error::from_request("list_files failed", e)),
+            Ok(list) => {
+                names.extend(
+                    list.entries
+                        .into_iter()
+                        .filter(|entry| entry.name.starts_with(prefix))
+                        .map(|entry| {
+                            let modified = entry
+                                .server_modified
+                                .and_then(|time| utils::parse_timestamp(&time));
+                            (entry.name, entry.size.unwrap_or(0), modified)
+                        }),
+                );
+
+                if !list.has_more {
+                    return Ok(names);
+                }
+
+                let arg = json!({ "cursor": list.cursor });
+                req = rpc::<EntryList>(LIST_FOLDER_CONTINUE_URL, &arg).await;
+            }
+        }
+    }
+}
+
+// deletes a specified Dropbox file
+// a file that doesn't exist counts as deleted, like on Google Drive
+pub async fn delete_file(name: &str) -> Option<Error> {
+    let arg = json!({ "path": get_file_path(name) });
+
+    let req = exponential_retry_async(|| async {
+        let res = CLIENT
+            .post(DELETE_URL)
+            .bearer_auth(&*TOKEN)
+            .json(&arg)
+            .send()
+            .await?;
+
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+
+        match status {
+            StatusCode::OK => Ok(()),
+            // the API reports a missing file as a path lookup conflict
+            StatusCode::CONFLICT if body.contains("path_lookup/not_found") => Ok(()),
+            status => Err(Error::from_status(status, format!("{}: {}", status, body)).into()),
+        }
+    })
+    .await;
+
+    match req {
+        Ok(_) => None,
+        Err(e) => Some(Error::from_request("delete_file failed", e)),
+    }
+}
+
+// returns the used and total storage of the Dropbox account in bytes
+pub async fn get_quota() -> Result<(u64, Option<u64>), Error> {
+    match rpc::<SpaceUsage>(SPACE_USAGE_URL, &serde_json::Value::Null).await {
+        Ok(usage) => Ok((usage.used, usage.allocation.allocated)),
+        Err(e) => Err(Error::from_request("get_quota failed", e)),
+    }
+}
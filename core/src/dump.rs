@@ -0,0 +1,115 @@
+use crate::chain::Chain;
+
+use serde::{Deserialize, Serialize};
+
+// the version of the portable chain dump format
+const DUMP_VERSION: u32 = 1;
+
+// a single transition of a Markov chain
+// None marks the start or the end of a phrase
+#[derive(Serialize, Deserialize)]
+struct Transition {
+    from: Vec<Option<String>>,
+    to: Option<String>,
+    count: usize,
+}
+
+// a portable, human-readable representation of a chat's chain
+#[derive(Serialize, Deserialize)]
+pub struct ChainDump {
+    version: u32,
+    chat_id: i64,
+    order: usize,
+    transitions: Vec<Transition>,
+}
+
+// lists the transitions of a chat's chain
+pub fn to_dump(chat_id: i64, chain: &Chain) -> ChainDump {
+    let mut transitions: Vec<Transition> = chain
+        .transitions()
+        .into_iter()
+        .map(|(from, to, count)| Transition {
+            from,
+            to,
+            count: count as usize,
+        })
+        .collect();
+
+    // keep the output stable between exports
+    transitions.sort_unstable_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+
+    ChainDump {
+        version: DUMP_VERSION,
+        chat_id,
+        order: chain.order(),
+        transitions,
+    }
+}
+
+// serializes a chat's chain to pretty-printed JSON
+pub fn export(chat_id: i64, chain: &Chain) -> Vec<u8> {
+    serde_json::to_vec_pretty(&to_dump(chat_id, chain)).expect("Serialization failed")
+}
+
+// data that can be merged into a chain
+pub enum Import {
+    Dump(ChainDump),
+    Lines(Vec<String>),
+}
+
+// splits a text into trimmed, non-empty lines
+pub fn to_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+// parses a JSON dump produced by export
+fn parse(bytes: &[u8]) -> Result<ChainDump, String> {
+    let dump: ChainDump = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+
+    if dump.version > DUMP_VERSION {
+        Err(format!("unsupported dump version {}", dump.version))
+    } else {
+        Ok(dump)
+    }
+}
+
+// parses a JSON dump or a plain-text corpus (one sentence per line)
+pub fn parse_import(bytes: &[u8]) -> Result<Import, String> {
+    let text = String::from_utf8_lossy(bytes);
+
+    if text.trim_start().starts_with('{') {
+        parse(bytes).map(Import::Dump)
+    } else {
+        Ok(Import::Lines(to_lines(&text)))
+    }
+}
+
+// adds the transitions of a dump to a Markov chain
+// returns the number of merged transitions
+pub fn merge(chain: &mut Chain, dump: ChainDump) -> Result<usize, String> {
+    if dump.order == 0 || dump.transitions.iter().any(|t| t.from.len() != dump.order) {
+        return Err(String::from("malformed transitions"));
+    }
+
+    // an empty chain can take over the order of the dump
+    if chain.is_empty() {
+        *chain = Chain::of_order(dump.order);
+    } else if chain.order() != dump.order {
+        return Err(format!(
+            "chain order mismatch ({} vs {})",
+            chain.order(),
+            dump.order
+        ));
+    }
+
+    for transition in dump.transitions.iter() {
+        let count = transition.count.min(u32::MAX as usize) as u32;
+        chain.add_transition(&transition.from, &transition.to, count);
+    }
+
+    Ok(dump.transitions.len())
+}
@@ -0,0 +1,70 @@
+use failure::Fail;
+use reqwest::StatusCode;
+
+// an error raised while storing or loading chains
+// the kind tells callers whether to retry, quarantine the blob or alert the admin
+#[derive(Debug, Fail)]
+pub enum Error {
+    // the backend failed or couldn't be reached, the request may succeed later
+    #[fail(display = "{}", _0)]
+    Storage(String),
+    // the backend throttled the requests, they should be retried after a while
+    #[fail(display = "{}", _0)]
+    RateLimited(String),
+    // the requested file doesn't exist
+    #[fail(display = "{}", _0)]
+    NotFound(String),
+    // the file was downloaded but its contents can't be read, retrying won't help
+    #[fail(display = "{}", _0)]
+    Deserialization(String),
+    // the chat can't be used, e.g. it's not on the allow-list or its chain isn't loaded
+    #[fail(display = "{}", _0)]
+    Unavailable(String),
+}
+
+impl Error {
+    // classifies an error response of a storage backend by its HTTP status
+    pub fn from_status(status: StatusCode, msg: String) -> Error {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => Error::RateLimited(msg),
+            StatusCode::NOT_FOUND => Error::NotFound(msg),
+            _ => Error::Storage(msg),
+        }
+    }
+
+    // classifies a request that failed despite exponential_retry_async
+    // describes the failed operation in front of the original message
+    pub fn from_request(operation: &str, e: failure::Error) -> Error {
+        let e = match e.downcast::<Error>() {
+            Ok(err) => return err.prefixed(operation),
+            Err(e) => e,
+        };
+
+        let msg = format!("{}: {}", operation, e);
+        match e.downcast_ref::<reqwest::Error>().and_then(|e| e.status()) {
+            Some(status) => Error::from_status(status, msg),
+            None => Error::Storage(msg),
+        }
+    }
+
+    // prepends a description of the failed operation, keeping the kind of the error
+    pub fn prefixed(self, operation: &str) -> Error {
+        match self {
+            Error::Storage(msg) => Error::Storage(format!("{}: {}", operation, msg)),
+            Error::RateLimited(msg) => Error::RateLimited(format!("{}: {}", operation, msg)),
+            Error::NotFound(msg) => Error::NotFound(format!("{}: {}", operation, msg)),
+            Error::Deserialization(msg) => {
+                Error::Deserialization(format!("{}: {}", operation, msg))
+            }
+            Error::Unavailable(msg) => Error::Unavailable(format!("{}: {}", operation, msg)),
+        }
+    }
+
+    // checks if repeating the operation later may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Storage(_) | Error::RateLimited(_) => true,
+            Error::NotFound(_) | Error::Deserialization(_) | Error::Unavailable(_) => false,
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::menu::Buttons;
+use crate::tokenizer;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    time::Duration,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// the prefix of the callback data of rating buttons, telling them apart from the /settings menu
+const CALLBACK_PREFIX: &str = "feedback:";
+
+// the prefix of the callback data of the buttons under /speak2 candidates
+const DUEL_PREFIX: &str = "speak2:";
+
+// the time members have to pick the better /speak2 candidate
+pub const DUEL_TIME: Duration = Duration::from_secs(60);
+
+// the number of recently generated messages that can still be rated
+const TRACKED_MESSAGES: usize = 100;
+
+// the chance (in percent) of a phrase without a prompt starting with a well-received word
+const BIAS_CHANCE: u32 = 30;
+
+// the score a single starting word can reach either way, so that no word takes over
+const MAX_SCORE: i32 = 20;
+
+// a message generated by the bot that members can rate
+// kept in the chat's settings, so that messages can still be rated after a restart
+#[derive(Serialize, Deserialize)]
+pub struct Generation {
+    // tells the buttons of this message apart from the ones of other messages
+    // known before the message is sent, unlike its message ID
+    id: u32,
+    // the word the phrase started with
+    start: String,
+    // IDs of users who have rated the message already, everyone gets one vote
+    // ordered, so that unchanged settings always serialize the same way
+    voters: BTreeSet<i64>,
+}
+
+// the outcome of pressing one of the rating buttons
+pub enum Vote {
+    // the score of the phrase's starting word changes by the given amount
+    Counted(String, i32),
+    // the user has rated the message already
+    Repeated,
+    // the message is no longer tracked
+    Expired,
+}
+
+// two phrases generated by /speak2, waiting for members to pick the better one
+// kept in the chat's settings, so that a restart doesn't lose the votes cast so far
+#[derive(Serialize, Deserialize)]
+pub struct Duel {
+    // tells the buttons of this duel apart from the ones of other duels
+    round: u32,
+    // the time (as a UNIX timestamp) the duel started
+    started: u64,
+    // the words the candidates started with
+    starts: [Option<String>; 2],
+    // IDs of users who voted for each of the candidates, everyone gets one vote
+    // ordered, so that unchanged settings always serialize the same way
+    votes: [BTreeSet<i64>; 2],
+}
+
+// the outcome of pressing the button under a /speak2 candidate
+pub enum Ballot {
+    Cast,
+    // the user has voted already
+    Repeated,
+    // the voting is over
+    Expired,
+}
+
+impl Duel {
+    // starts a duel between two phrases at a given time
+    // returns the duel along with the buttons of each candidate
+    pub fn new<R: Rng>(candidates: &[String; 2], now: u64, rng: &mut R) -> (Duel, [Buttons; 2]) {
+        let round = rng.gen();
+        let button = |choice: usize| {
+            vec![vec![(
+                String::from("👍 this one"),
+                format!("{}{}:{}", DUEL_PREFIX, round, choice),
+            )]]
+        };
+
+        let duel = Duel {
+            round,
+            started: now,
+            starts: [first_word(&candidates[0]), first_word(&candidates[1])],
+            votes: [BTreeSet::new(), BTreeSet::new()],
+        };
+        (duel, [button(0), button(1)])
+    }
+
+    // identifies the duel among the others of the chat
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    // checks if the time to vote is up
+    pub fn is_over(&self, now: u64) -> bool {
+        now >= self.started + DUEL_TIME.as_secs()
+    }
+
+    // checks if the duel should have been settled long ago
+    // the timer settling a duel only lives in memory, a restart leaves its duel to be settled later
+    pub fn is_abandoned(&self, now: u64) -> bool {
+        now >= self.started + 2 * DUEL_TIME.as_secs()
+    }
+
+    // records a user's vote for one of the candidates at a given time
+    pub fn vote(&mut self, user_id: i64, choice: usize, now: u64) -> Ballot {
+        if choice > 1 || self.is_over(now) {
+            return Ballot::Expired;
+        }
+
+        if self.votes.iter().any(|voters| voters.contains(&user_id)) {
+            return Ballot::Repeated;
+        }
+
+        self.votes[choice].insert(user_id);
+        Ballot::Cast
+    }
+
+    // ends the duel, rewarding the starting word of the winner and penalizing the other one
+    // returns the index of the winner, the first candidate wins ties
+    pub fn settle(self, scores: &mut BTreeMap<String, i32>) -> usize {
+        let winner = if self.votes[1].len() > self.votes[0].len() {
+            1
+        } else {
+            0
+        };
+
+        if self.votes[0].len() != self.votes[1].len() {
+            if let (Some(first), Some(second)) = (&self.starts[0], &self.starts[1]) {
+                if first != second {
+                    let (won, lost) = if winner == 0 {
+                        (first, second)
+                    } else {
+                        (second, first)
+                    };
+                    apply(scores, won, 1);
+                    apply(scores, lost, -1);
+                }
+            }
+        }
+
+        winner
+    }
+}
+
+// returns the word a phrase starts with, as the chain knows it
+fn first_word(phrase: &str) -> Option<String> {
+    tokenizer::tokenize(phrase).into_iter().next()
+}
+
+// the rating buttons attached to a generated message
+fn buttons(id: u32) -> Buttons {
+    vec![vec![
+        (String::from("👍"), format!("{}{}:+", CALLBACK_PREFIX, id)),
+        (String::from("👎"), format!("{}{}:-", CALLBACK_PREFIX, id)),
+    ]]
+}
+
+// checks if callback data belongs to one of the rating buttons
+pub fn is_feedback_data(data: &str) -> bool {
+    data.starts_with(CALLBACK_PREFIX)
+}
+
+// checks if callback data belongs to one of the buttons under /speak2 candidates
+pub fn is_duel_data(data: &str) -> bool {
+    data.starts_with(DUEL_PREFIX)
+}
+
+// parses the callback data of a /speak2 button into the round and the chosen candidate
+pub fn parse_duel(data: &str) -> Option<(u32, usize)> {
+    let mut parts = data.trim_start_matches(DUEL_PREFIX).splitn(2, ':');
+    let round = parts.next()?.parse::<u32>().ok()?;
+    let choice = parts.next()?.parse::<usize>().ok()?;
+    Some((round, choice))
+}
+
+// parses the callback data of a rating button into the ID of the message and the change of the score
+pub fn parse(data: &str) -> Option<(u32, i32)> {
+    let mut parts = data.trim_start_matches(CALLBACK_PREFIX).splitn(2, ':');
+    let id = parts.next()?.parse::<u32>().ok()?;
+    let change = match parts.next()? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    Some((id, change))
+}
+
+// starts tracking a message about to be sent, forgetting the oldest one if there are too many
+// the message is tracked before it's sent, so that it can be rated as soon as it shows up
+// returns the rating buttons to attach to it, None if the phrase has no word to rate
+pub fn track<R: Rng>(
+    generations: &mut VecDeque<Generation>,
+    phrase: &str,
+    rng: &mut R,
+) -> Option<Buttons> {
+    let start = first_word(phrase)?;
+
+    if generations.len() >= TRACKED_MESSAGES {
+        generations.pop_front();
+    }
+
+    let id = rng.gen();
+    generations.push_back(Generation {
+        id,
+        start,
+        voters: BTreeSet::new(),
+    });
+    Some(buttons(id))
+}
+
+// records a user's vote on a tracked message
+pub fn vote(generations: &mut VecDeque<Generation>, id: u32, user_id: i64, change: i32) -> Vote {
+    let generation = match generations.iter_mut().find(|g| g.id == id) {
+        Some(generation) => generation,
+        None => return Vote::Expired,
+    };
+
+    if !generation.voters.insert(user_id) {
+        return Vote::Repeated;
+    }
+
+    Vote::Counted(generation.start.clone(), change)
+}
+
+// changes the score of a starting word, keeping it within MAX_SCORE
+// words whose score drops back to 0 are forgotten
+pub fn apply(scores: &mut BTreeMap<String, i32>, word: &str, change: i32) {
+    let score = scores.entry(word.to_string()).or_insert(0);
+    *score = (*score + change).clamp(-MAX_SCORE, MAX_SCORE);
+
+    if *score == 0 {
+        scores.remove(word);
+    }
+}
+
+// picks a starting word for a phrase without a prompt, proportionally to the positive scores
+// returns None most of the time, so that phrases don't all start the same way
+pub fn pick_start<R: Rng>(scores: &BTreeMap<String, i32>, rng: &mut R) -> Option<String> {
+    if rng.gen_range(0, 100) >= BIAS_CHANCE {
+        return None;
+    }
+
+    let total: i32 = scores.values().filter(|score| **score > 0).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut roll = rng.gen_range(0, total);
+    for (word, score) in scores.iter().filter(|(_, score)| **score > 0) {
+        if roll < *score {
+            return Some(word.clone());
+        }
+        roll -= score;
+    }
+
+    None
+}
+
+// checks if a phrase starts with a word members didn't like
+pub fn is_disliked(scores: &BTreeMap<String, i32>, phrase: &str) -> bool {
+    match first_word(phrase) {
+        Some(word) => scores.get(&word).is_some_and(|score| *score < 0),
+        None => false,
+    }
+}
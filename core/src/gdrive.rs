@@ -0,0 +1,621 @@
+use crate::{
+    error::Error,
+    utils::{self, exponential_retry_async},
+};
+
+use std::{
+    env,
+    sync::{Arc, RwLock},
+};
+
+use failure::{format_err, Fail};
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use lazy_static::lazy_static;
+use rand::Rng;
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::Deserialize;
+use serde_json::json;
+use yup_oauth2::{
+    authenticator::Authenticator, InstalledFlowAuthenticator, InstalledFlowReturnMethod,
+    ServiceAccountAuthenticator,
+};
+
+// Google Drive API endpoints
+const FILES_URL: &str = "https://www.googleapis.com/drive/v3/files";
+const UPLOAD_URL: &str = "https://www.googleapis.com/upload/drive/v3/files";
+const ABOUT_URL: &str = "https://www.googleapis.com/drive/v3/about";
+
+// the MIME type Drive uses for folders
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+// query parameters allowing requests to access Shared Drives
+const ALL_DRIVES: &[(&str, &str)] = &[("supportsAllDrives", "true")];
+
+// the OAuth scope required to read and write Drive files
+const SCOPES: &[&str] = &["https://www.googleapis.com/auth/drive"];
+
+// shorthands for complex types
+type MyAuth = Authenticator<HttpsConnector<HttpConnector>>;
+type MyArcHub = Arc<Hub>;
+
+// a Google Drive hub holding everything needed to issue requests
+struct Hub {
+    auth: MyAuth,
+    client: Client,
+    // the ID of a chaindump folder
+    parent: String,
+}
+
+// a single entry of a Drive file listing
+#[derive(Deserialize)]
+struct File {
+    id: String,
+    name: String,
+    // Drive reports sizes as strings
+    size: Option<String>,
+    #[serde(rename = "modifiedTime")]
+    modified_time: Option<String>,
+}
+
+// a single page of a Drive file listing
+#[derive(Deserialize)]
+struct FileList {
+    files: Vec<File>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+// a single revision of a Drive file
+#[derive(Deserialize)]
+struct Revision {
+    id: String,
+}
+
+// a single page of a Drive revision listing
+#[derive(Deserialize)]
+struct RevisionList {
+    revisions: Vec<Revision>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+// an error raised while locating the chaindump folder
+#[derive(Debug, Fail)]
+pub enum FolderError {
+    #[fail(display = "CHAINDUMP_DIR not set")]
+    MissingName,
+    #[fail(display = "Failed to search for the chaindump folder: {}", _0)]
+    Search(Error),
+    #[fail(display = "Failed to create the chaindump folder: {}", _0)]
+    Create(Error),
+}
+
+// storage quota of a Drive account
+#[derive(Deserialize)]
+struct StorageQuota {
+    // absent for accounts with unlimited storage
+    limit: Option<String>,
+    usage: String,
+}
+
+// a response to a Drive account information request
+#[derive(Deserialize)]
+struct About {
+    #[serde(rename = "storageQuota")]
+    storage_quota: StorageQuota,
+}
+
+lazy_static! {
+    // a Google Drive hub service worker object, populated by initialize()
+    static ref HUB: RwLock<Option<MyArcHub>> = RwLock::new(None);
+    // the ID of a Shared Drive holding the chaindump folder, if any
+    static ref DRIVE_ID: Option<String> = env::var("GDRIVE_DRIVE_ID").ok().filter(|id| !id.is_empty());
+}
+
+// returns the Google Drive hub
+fn get_hub() -> MyArcHub {
+    HUB.read()
+        .unwrap()
+        .clone()
+        .expect("Google Drive hub not initialized")
+}
+
+// creates a Google Drive authenticator acting as a service account
+async fn create_service_account_auth() -> Result<MyAuth, Error> {
+    let secret = yup_oauth2::read_service_account_key("./credentials.json")
+        .await
+        .map_err(|e| Error::Storage(format!("Failed to read credentials.json: {}", e)))?;
+
+    ServiceAccountAuthenticator::builder(secret)
+        .build()
+        .await
+        .map_err(|e| {
+            Error::Storage(format!(
+                "Failed to create a service account authenticator: {}",
+                e
+            ))
+        })
+}
+
+// creates a Google Drive authenticator acting as a regular user
+// asks for consent on the first run, then reuses the cached tokens
+async fn create_installed_app_auth() -> Result<MyAuth, Error> {
+    let secret = yup_oauth2::read_application_secret("./credentials.json")
+        .await
+        .map_err(|e| Error::Storage(format!("Failed to read credentials.json: {}", e)))?;
+
+    InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::Interactive)
+        .persist_tokens_to_disk("./tokencache.json")
+        .build()
+        .await
+        .map_err(|e| {
+            Error::Storage(format!(
+                "Failed to create an installed app authenticator: {}",
+                e
+            ))
+        })
+}
+
+// creates a Google Drive authenticator of the kind selected by GDRIVE_AUTH
+async fn create_auth() -> Result<MyAuth, Error> {
+    match env::var("GDRIVE_AUTH") {
+        Err(_) => create_service_account_auth().await,
+        Ok(kind) => match kind.trim().to_lowercase().as_str() {
+            "" | "service_account" => create_service_account_auth().await,
+            "oauth" => create_installed_app_auth().await,
+            other => Err(Error::Storage(format!(
+                "Unknown GDRIVE_AUTH method: {}",
+                other
+            ))),
+        },
+    }
+}
+
+// returns a fresh access token
+async fn get_token(auth: &MyAuth) -> Result<String, failure::Error> {
+    let token = auth.token(SCOPES).await.map_err(|e| format_err!("{}", e))?;
+
+    Ok(token.as_str().to_string())
+}
+
+// returns all files matching a specified Drive query
+async fn query_files(auth: &MyAuth, client: &Client, query: &str) -> Result<Vec<File>, Error> {
+    let mut files = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let req = exponential_retry_async(|| async {
+            let token = get_token(auth).await?;
+            let mut params = vec![
+                ("q", query),
+                (
+                    "fields",
+                    "nextPageToken, files(id, name, size, modifiedTime)",
+                ),
+                ("pageSize", "1000"),
+                ("supportsAllDrives", "true"),
+                ("includeItemsFromAllDrives", "true"),
+            ];
+            if let Some(drive_id) = DRIVE_ID.as_ref() {
+                params.push(("corpora", "drive"));
+                params.push(("driveId", drive_id.as_str()));
+            }
+            if let Some(page) = page_token.as_ref() {
+                params.push(("pageToken", page.as_str()));
+            }
+
+            let res = client
+                .get(FILES_URL)
+                .bearer_auth(&token)
+                .query(&params)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<FileList>()
+                .await?;
+
+            Ok(res)
+        })
+        .await;
+
+        match req {
+            Err(e) => return Err(Error::from_request("query_files failed", e)),
+            Ok(list) => {
+                files.extend(list.files);
+                match list.next_page_token {
+                    Some(next) => page_token = Some(next),
+                    None => return Ok(files),
+                }
+            }
+        }
+    }
+}
+
+// escapes a value put between single quotes in a Drive query
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+// returns Google Drive file ID from the name of a file
+async fn get_id_by_name(hub: &Hub, name: &str) -> Result<Option<String>, Error> {
+    let query = format!(
+        "name = '{}' and '{}' in parents and trashed = false",
+        escape(name),
+        escape(&hub.parent)
+    );
+
+    match query_files(&hub.auth, &hub.client, &query).await {
+        Err(e) => Err(e),
+        Ok(files) => Ok(files.into_iter().next().map(|file| file.id)),
+    }
+}
+
+// replaces contents of a specified Google Drive file
+async fn replace_file_by_id(hub: &Hub, bytes: &[u8], id: &str) -> Option<Error> {
+    let req = exponential_retry_async(|| async {
+        let token = get_token(&hub.auth).await?;
+        hub.client
+            .patch(&format!("{}/{}", UPLOAD_URL, id))
+            .bearer_auth(&token)
+            .query(ALL_DRIVES)
+            .query(&[("uploadType", "media")])
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(bytes.to_vec())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    })
+    .await;
+
+    match req {
+        Ok(_) => None,
+        Err(e) => Some(Error::from_request("replace_file_by_id failed", e)),
+    }
+}
+
+// uploads a file to Google Drive
+// the metadata and the contents are sent in a single request, so a failed upload leaves no empty file
+async fn upload_file(hub: &Hub, bytes: &[u8], name: &str) -> Option<Error> {
+    let metadata = json!({
+        "name": name,
+        "parents": [hub.parent],
+    });
+
+    // a random boundary can't turn up in the (compressed) contents by accident
+    let boundary = format!("markov_bot_{:016x}", rand::thread_rng().gen::<u64>());
+    let mut body = format!(
+        "--{}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n\
+         --{}\r\nContent-Type: application/octet-stream\r\n\r\n",
+        boundary, metadata, boundary
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let content_type = format!("multipart/related; boundary={}", boundary);
+
+    let req = exponential_retry_async(|| async {
+        let token = get_token(&hub.auth).await?;
+        hub.client
+            .post(UPLOAD_URL)
+            .bearer_auth(&token)
+            .query(ALL_DRIVES)
+            .query(&[("uploadType", "multipart")])
+            .header(CONTENT_TYPE, content_type.as_str())
+            .body(body.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    })
+    .await;
+
+    match req {
+        Ok(_) => None,
+        Err(e) => Some(Error::from_request("upload_file failed", e)),
+    }
+}
+
+// creates a Google Drive folder and returns its ID
+async fn create_folder(auth: &MyAuth, client: &Client, name: &str) -> Result<String, Error> {
+    // folders in Shared Drives need to be placed in the drive's root explicitly
+    let metadata = match DRIVE_ID.as_ref() {
+        Some(drive_id) => json!({
+            "name": name,
+            "mimeType": FOLDER_MIME_TYPE,
+            "parents": [drive_id],
+        }),
+        None => json!({
+            "name": name,
+            "mimeType": FOLDER_MIME_TYPE,
+        }),
+    };
+
+    let req = exponential_retry_async(|| async {
+        let token = get_token(auth).await?;
+        let res = client
+            .post(FILES_URL)
+            .bearer_auth(&token)
+            .query(ALL_DRIVES)
+            .json(&metadata)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<File>()
+            .await?;
+
+        Ok(res)
+    })
+    .await;
+
+    match req {
+        Ok(file) => Ok(file.id),
+        Err(e) => Err(Error::from_request("create_folder failed", e)),
+    }
+}
+
+// returns Google Drive folder ID of a chaindump directory
+// creates the folder if it does not exist
+async fn get_or_create_folder(auth: &MyAuth, client: &Client) -> Result<String, FolderError> {
+    let chaindump_dir = env::var("CHAINDUMP_DIR").map_err(|_| FolderError::MissingName)?;
+
+    let query = format!(
+        "name = '{}' and mimeType = '{}' and trashed = false",
+        escape(&chaindump_dir),
+        FOLDER_MIME_TYPE
+    );
+
+    let files = match query_files(auth, client, &query).await {
+        Ok(elem) => elem,
+        Err(e) => return Err(FolderError::Search(e)),
+    };
+
+    match files.into_iter().next() {
+        Some(headers) => Ok(headers.id),
+        None => create_folder(auth, client, &chaindump_dir)
+            .await
+            .map_err(FolderError::Create),
+    }
+}
+
+// downloads contents of a Drive URL
+async fn download_url(hub: &Hub, url: &str, params: &[(&str, &str)]) -> Result<Vec<u8>, Error> {
+    let req = exponential_retry_async(|| async {
+        let token = get_token(&hub.auth).await?;
+        let res = hub
+            .client
+            .get(url)
+            .bearer_auth(&token)
+            .query(params)
+            .query(&[("alt", "media")])
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        Ok(res)
+    })
+    .await;
+
+    match req {
+        Ok(content) => Ok(content.to_vec()),
+        Err(e) => Err(Error::from_request("Failed to download file", e)),
+    }
+}
+
+// creates a connection to Google Drive
+pub async fn initialize() -> Result<(), Error> {
+    let auth = create_auth().await?;
+    let client = Client::new();
+    let parent = get_or_create_folder(&auth, &client)
+        .await
+        .map_err(|e| Error::Storage(e.to_string()))?;
+
+    let hub = Hub {
+        auth,
+        client,
+        parent,
+    };
+
+    *HUB.write().unwrap() = Some(Arc::new(hub));
+    Ok(())
+}
+
+// replaces contents of a specified Google Drive file
+// creates a new file if one does not exist
+pub async fn update_or_create_file(bytes: &[u8], name: &str) -> Option<Error> {
+    let hub = get_hub();
+
+    // a failed lookup mustn't create a second file with the same name
+    match get_id_by_name(&hub, name).await {
+        Err(e) => Some(e),
+        Ok(Some(id)) => replace_file_by_id(&hub, bytes, &id).await,
+        Ok(None) => upload_file(&hub, bytes, name).await,
+    }
+}
+
+// downloads a specified Google Drive file
+pub async fn download_file(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let hub = get_hub();
+
+    match get_id_by_name(&hub, name).await {
+        Err(e) => Err(e),
+        Ok(value) => match value {
+            // no file found
+            None => Ok(None),
+            // some file found
+            Some(file_id) => {
+                let url = format!("{}/{}", FILES_URL, file_id);
+                download_url(&hub, &url, ALL_DRIVES).await.map(Some)
+            }
+        },
+    }
+}
+
+// returns revision IDs of a specified Google Drive file, newest first
+pub async fn list_revisions(name: &str) -> Result<Vec<String>, Error> {
+    let hub = get_hub();
+
+    let file_id = match get_id_by_name(&hub, name).await {
+        Err(e) => return Err(e),
+        Ok(None) => return Ok(Vec::new()),
+        Ok(Some(file_id)) => file_id,
+    };
+
+    // Drive lists revisions oldest first
+    let mut revisions = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let req = exponential_retry_async(|| async {
+            let token = get_token(&hub.auth).await?;
+            let mut params = vec![
+                ("fields", "nextPageToken, revisions(id)"),
+                ("pageSize", "1000"),
+            ];
+            if let Some(page) = page_token.as_ref() {
+                params.push(("pageToken", page.as_str()));
+            }
+
+            let res = hub
+                .client
+                .get(&format!("{}/{}/revisions", FILES_URL, file_id))
+                .bearer_auth(&token)
+                .query(&params)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<RevisionList>()
+                .await?;
+
+            Ok(res)
+        })
+        .await;
+
+        match req {
+            Err(e) => return Err(Error::from_request("list_revisions failed", e)),
+            Ok(list) => {
+                revisions.extend(list.revisions.into_iter().map(|rev| rev.id));
+                match list.next_page_token {
+                    Some(next) => page_token = Some(next),
+                    None => break,
+                }
+            }
+        }
+    }
+
+    revisions.reverse();
+    Ok(revisions)
+}
+
+// downloads a specified revision of a Google Drive file
+pub async fn download_revision(name: &str, revision: &str) -> Result<Vec<u8>, Error> {
+    let hub = get_hub();
+
+    match get_id_by_name(&hub, name).await {
+        Err(e) => Err(e),
+        Ok(None) => Err(Error::NotFound(format!("File not found: {}", name))),
+        Ok(Some(file_id)) => {
+            let url = format!("{}/{}/revisions/{}", FILES_URL, file_id, revision);
+            download_url(&hub, &url, &[]).await
+        }
+    }
+}
+
+// returns names, sizes and modification times of all files in the chaindump folder
+// starting with a given prefix
+pub async fn list_files(prefix: &str) -> Result<Vec<(String, u64, Option<u64>)>, Error> {
+    let hub = get_hub();
+
+    let query = if prefix.is_empty() {
+        format!("'{}' in parents and trashed = false", escape(&hub.parent))
+    } else {
+        format!(
+            "name contains '{}' and '{}' in parents and trashed = false",
+            escape(prefix),
+            escape(&hub.parent)
+        )
+    };
+
+    match query_files(&hub.auth, &hub.client, &query).await {
+        Err(e) => Err(e),
+        // "contains" matches word prefixes, so the results need to be filtered again
+        Ok(files) => Ok(files
+            .into_iter()
+            .filter(|file| file.name.starts_with(prefix))
+            .map(|file| {
+                let size = file.size.and_then(|size| size.parse().ok()).unwrap_or(0);
+                let modified = file
+                    .modified_time
+                    .and_then(|time| utils::parse_timestamp(&time));
+                (file.name, size, modified)
+            })
+            .collect()),
+    }
+}
+
+// returns the used and total storage of the Drive account in bytes
+pub async fn get_quota() -> Result<(u64, Option<u64>), Error> {
+    let hub = get_hub();
+
+    let req = exponential_retry_async(|| async {
+        let token = get_token(&hub.auth).await?;
+        let res = hub
+            .client
+            .get(ABOUT_URL)
+            .bearer_auth(&token)
+            .query(&[("fields", "storageQuota")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<About>()
+            .await?;
+
+        Ok(res)
+    })
+    .await;
+
+    match req {
+        Err(e) => Err(Error::from_request("get_quota failed", e)),
+        Ok(about) => {
+            let quota = about.storage_quota;
+            let usage = quota.usage.parse().unwrap_or(0);
+            let limit = quota.limit.and_then(|limit| limit.parse().ok());
+            Ok((usage, limit))
+        }
+    }
+}
+
+// deletes a specified Google Drive file
+pub async fn delete_file(name: &str) -> Option<Error> {
+    let hub = get_hub();
+
+    let file_id = match get_id_by_name(&hub, name).await {
+        Err(e) => return Some(e),
+        Ok(None) => return None,
+        Ok(Some(file_id)) => file_id,
+    };
+
+    let req = exponential_retry_async(|| async {
+        let token = get_token(&hub.auth).await?;
+        hub.client
+            .delete(&format!("{}/{}", FILES_URL, file_id))
+            .bearer_auth(&token)
+            .query(ALL_DRIVES)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    })
+    .await;
+
+    match req {
+        Ok(_) => None,
+        Err(e) => Some(Error::from_request("delete_file failed", e)),
+    }
+}
@@ -0,0 +1,61 @@
+use crate::chain::Chain;
+
+use std::collections::HashSet;
+
+// the names of the nodes standing for the start and the end of phrases
+// words never contain whitespace, so these can't be taken for one
+const START_NODE: &str = "phrase start";
+const END_NODE: &str = "phrase end";
+
+// the widest an edge can be drawn, reached by the heaviest transition
+const MAX_PEN_WIDTH: f64 = 8.0;
+
+// quotes a word so that it can be used as a node ID
+fn quote(word: &str) -> String {
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// renders the transitions between words as a directed graph in the DOT language
+// transitions learnt fewer than min_weight times are left out, along with the words only they use
+pub fn render(chain: &Chain, min_weight: u64) -> String {
+    let mut transitions: Vec<(String, String, u64)> = chain
+        .word_transitions()
+        .into_iter()
+        .filter(|(_, _, weight)| *weight >= min_weight)
+        .map(|(from, to, weight)| {
+            let from = from.unwrap_or_else(|| String::from(START_NODE));
+            let to = to.unwrap_or_else(|| String::from(END_NODE));
+            (from, to, weight)
+        })
+        .collect();
+    transitions.sort_unstable();
+
+    let max_weight = transitions.iter().map(|(_, _, w)| *w).max().unwrap_or(1);
+
+    let mut out = String::from("digraph chain {\n    node [shape=box, style=rounded];\n");
+
+    // the boundaries are drawn differently, so that phrases are easy to follow
+    let words: HashSet<&str> = transitions
+        .iter()
+        .flat_map(|(from, to, _)| vec![from.as_str(), to.as_str()])
+        .collect();
+    for node in [START_NODE, END_NODE].iter() {
+        if words.contains(node) {
+            out.push_str(&format!("    {} [shape=ellipse];\n", quote(node)));
+        }
+    }
+
+    for (from, to, weight) in transitions.iter() {
+        let width = 1.0 + (MAX_PEN_WIDTH - 1.0) * *weight as f64 / max_weight as f64;
+        out.push_str(&format!(
+            "    {} -> {} [label={}, penwidth={:.1}];\n",
+            quote(from),
+            quote(to),
+            weight,
+            width
+        ));
+    }
+
+    out.push_str("}\n");
+    out
+}
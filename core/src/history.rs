@@ -0,0 +1,67 @@
+use crate::dump::{to_lines, Import};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+// a single entry of a Telegram Desktop chat export
+#[derive(Deserialize)]
+struct Message {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    from_id: Value,
+    #[serde(default)]
+    via_bot: Option<String>,
+    #[serde(default)]
+    text: Value,
+}
+
+// the result.json file produced by Telegram Desktop's "Export chat history"
+#[derive(Deserialize)]
+struct ChatExport {
+    messages: Vec<Message>,
+}
+
+// extracts the numeric ID of a message's sender
+// newer exports store it as "user123456", older ones as a plain number
+fn sender_id(from_id: &Value) -> Option<i64> {
+    match from_id {
+        Value::Number(id) => id.as_i64(),
+        Value::String(id) => id.trim_start_matches(char::is_alphabetic).parse().ok(),
+        _ => None,
+    }
+}
+
+// flattens a message's text, which is either a string or a list of formatted fragments
+fn plain_text(text: &Value) -> String {
+    match text {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .map(|part| match part {
+                Value::String(text) => text.as_str(),
+                Value::Object(entity) => entity.get("text").and_then(Value::as_str).unwrap_or(""),
+                _ => "",
+            })
+            .collect(),
+        _ => String::new(),
+    }
+}
+
+// parses a Telegram Desktop chat export into a list of lines
+// skips service messages, commands, inline bot results and messages sent by bot_id
+pub fn parse(bytes: &[u8], bot_id: Option<i64>) -> Result<Import, String> {
+    let export: ChatExport = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+
+    let lines = export
+        .messages
+        .iter()
+        .filter(|msg| msg.kind == "message" && msg.via_bot.is_none())
+        .filter(|msg| bot_id.is_none() || sender_id(&msg.from_id) != bot_id)
+        .map(|msg| plain_text(&msg.text))
+        .filter(|text| !text.trim_start().starts_with('/'))
+        .flat_map(|text| to_lines(&text))
+        .collect();
+
+    Ok(Import::Lines(lines))
+}
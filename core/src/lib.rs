@@ -0,0 +1,34 @@
+// the chain engine behind markov_bot: Markov chains, the text pipeline and storage backends
+// everything is configured through environment variables (see config_file)
+// the Telegram bot is a separate binary built on top of this crate
+
+// failure_derive wraps the impls it generates in a const block
+#![allow(non_local_definitions)]
+
+pub mod alerts;
+pub mod batch;
+mod blob;
+mod cache;
+pub mod chain;
+pub mod chain_wrapper;
+pub mod chat_key;
+pub mod config_file;
+pub mod daily;
+mod dropbox;
+pub mod dump;
+pub mod error;
+pub mod feedback;
+mod gdrive;
+pub mod graphviz;
+pub mod history;
+pub mod menu;
+pub mod preprocess;
+pub mod quiz;
+pub mod settings;
+mod spool;
+mod stopwords;
+pub mod storage;
+pub mod tasks;
+pub mod tokenizer;
+pub mod utils;
+mod wal;
@@ -0,0 +1,148 @@
+use crate::settings::{Settings, FILTERS};
+
+// the amounts a single press of a /settings button changes a value by
+const MIN_WORDS_STEP: usize = 1;
+const MAX_WORDS_STEP: usize = 5;
+const TEMPERATURE_STEP: f64 = 0.1;
+const STICKER_CHANCE_STEP: u32 = 5;
+
+// the labels and callback data of the menu's buttons, row by row
+pub type Buttons = Vec<Vec<(String, String)>>;
+
+// a change requested by pressing a button of the /settings menu
+pub enum Action {
+    ToggleLearning,
+    ToggleFilter(String),
+    // the first field is true for an increase, false for a decrease
+    MinWords(bool),
+    MaxWords(bool),
+    Temperature(bool),
+    StickerChance(bool),
+    // closes the menu, leaving the settings as they are
+    Done,
+}
+
+impl Action {
+    // parses the callback data of a button
+    pub fn parse(data: &str) -> Option<Action> {
+        let mut parts = data.splitn(2, ':');
+        let name = parts.next()?;
+        let arg = parts.next().unwrap_or("");
+
+        let is_increase = match arg {
+            "+" => Some(true),
+            "-" => Some(false),
+            _ => None,
+        };
+
+        match name {
+            "learning" => Some(Action::ToggleLearning),
+            "filter" if FILTERS.contains(&arg) => Some(Action::ToggleFilter(arg.to_string())),
+            "min" => is_increase.map(Action::MinWords),
+            "max" => is_increase.map(Action::MaxWords),
+            "temp" => is_increase.map(Action::Temperature),
+            "sticker" => is_increase.map(Action::StickerChance),
+            "done" => Some(Action::Done),
+            _ => None,
+        }
+    }
+}
+
+// the step functions below return the value a setting should have after a button was pressed
+// results may fall out of the setting's bounds, callers are expected to clamp them
+
+// increases or decreases a word count by a given step
+fn step_words(value: usize, is_increase: bool, step: usize) -> usize {
+    if is_increase {
+        value.saturating_add(step)
+    } else {
+        value.saturating_sub(step)
+    }
+}
+
+// returns the new minimum length of generated messages
+pub fn step_min_words(value: usize, is_increase: bool) -> usize {
+    step_words(value, is_increase, MIN_WORDS_STEP)
+}
+
+// returns the new maximum length of generated messages
+pub fn step_max_words(value: usize, is_increase: bool) -> usize {
+    step_words(value, is_increase, MAX_WORDS_STEP)
+}
+
+// returns the new temperature
+// rounded to a single decimal place, so that repeated steps don't accumulate errors
+pub fn step_temperature(value: f64, is_increase: bool) -> f64 {
+    let value = if is_increase {
+        value + TEMPERATURE_STEP
+    } else {
+        value - TEMPERATURE_STEP
+    };
+    (value * 10.0).round() / 10.0
+}
+
+// returns the new chance (in percent) of replying to stickers
+pub fn step_sticker_chance(value: u32, is_increase: bool) -> u32 {
+    if is_increase {
+        value.saturating_add(STICKER_CHANCE_STEP)
+    } else {
+        value.saturating_sub(STICKER_CHANCE_STEP)
+    }
+}
+
+// describes a chat's configuration and lays out the buttons changing it
+pub fn render(is_learning: bool, settings: &Settings) -> (String, Buttons) {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    let button = |label: String, data: &str| (label, data.to_string());
+
+    // every change shows in the text, which lets callers tell if the menu needs redrawing
+    let text = format!(
+        "[settings]\nlearning: {}\ngenerated messages: {} to {} words\n\
+         temperature: {}\nsticker replies: {}%\n\n[text filters]\n{}",
+        on_off(is_learning),
+        settings.min_output_words,
+        settings.max_output_words,
+        settings.temperature,
+        settings.sticker_chance,
+        settings.describe_filters()
+    );
+
+    let mut buttons = vec![vec![button(
+        format!("learning: {}", on_off(is_learning)),
+        "learning",
+    )]];
+
+    for pair in FILTERS.chunks(2) {
+        buttons.push(
+            pair.iter()
+                .map(|name| {
+                    let state = settings.filter(name).unwrap_or(false);
+                    button(
+                        format!("{}: {}", name, on_off(state)),
+                        &format!("filter:{}", name),
+                    )
+                })
+                .collect(),
+        );
+    }
+
+    buttons.push(vec![
+        button(String::from("- min words"), "min:-"),
+        button(String::from("+ min words"), "min:+"),
+    ]);
+    buttons.push(vec![
+        button(String::from("- max words"), "max:-"),
+        button(String::from("+ max words"), "max:+"),
+    ]);
+    buttons.push(vec![
+        button(String::from("- temperature"), "temp:-"),
+        button(String::from("+ temperature"), "temp:+"),
+    ]);
+    buttons.push(vec![
+        button(String::from("- sticker replies"), "sticker:-"),
+        button(String::from("+ sticker replies"), "sticker:+"),
+    ]);
+    buttons.push(vec![button(String::from("done"), "done")]);
+
+    (text, buttons)
+}
@@ -0,0 +1,128 @@
+use crate::settings::Settings;
+use crate::tokenizer;
+
+// checks if a word looks like a link
+fn is_url(word: &str) -> bool {
+    let word = word.to_lowercase();
+    word.contains("://") || word.starts_with("www.") || word.starts_with("t.me/")
+}
+
+// checks if a word starts with a given sigil followed by a name
+// a lone sigil (e.g. "#" or "@") is left alone
+fn is_tagged(word: &str, sigil: char) -> bool {
+    let mut chars = word.chars();
+    chars.next() == Some(sigil)
+        && chars
+            .next()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+// checks if a word should be removed according to the chat's settings
+fn is_filtered(word: &str, settings: &Settings) -> bool {
+    (settings.strip_urls && is_url(word))
+        || (settings.strip_mentions && is_tagged(word, '@'))
+        || (settings.strip_hashtags && is_tagged(word, '#'))
+        || (settings.strip_commands && is_tagged(word, '/'))
+}
+
+// removes links, mentions, hashtags and commands from a message
+// each line is processed separately, so that line breaks are kept
+pub fn clean(msg: &str, settings: &Settings) -> String {
+    msg.lines()
+        .map(|line| {
+            line.split_whitespace()
+                .filter(|word| !is_filtered(word, settings))
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// checks if a line consists of emoji and punctuation only
+fn is_symbols_only(line: &str) -> bool {
+    !line.chars().any(char::is_alphanumeric)
+}
+
+// drops lines that aren't worth learning and messages that are too short
+// returns the lines that should be learnt
+pub fn filter(msg: &str, settings: &Settings) -> Vec<String> {
+    let lines: Vec<String> = msg
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .filter(|line| !(settings.skip_symbols_only && is_symbols_only(line)))
+        .map(|line| line.to_string())
+        .collect();
+
+    let words: usize = lines.iter().map(|line| tokenizer::count(line)).sum();
+    if words < settings.min_words {
+        Vec::new()
+    } else {
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_mentions_hashtags_and_commands_are_removed() {
+        let settings = Settings::default();
+        assert_eq!(
+            clean(
+                "/start@bot see https://example.com and www.example.com, @friend #news",
+                &settings
+            ),
+            "see and"
+        );
+    }
+
+    #[test]
+    fn lone_sigils_and_line_breaks_are_kept() {
+        let settings = Settings::default();
+        assert_eq!(clean("a # b @\nc / d", &settings), "a # b @\nc / d");
+    }
+
+    #[test]
+    fn filters_follow_the_settings() {
+        let settings = Settings {
+            strip_urls: false,
+            strip_hashtags: false,
+            ..Settings::default()
+        };
+        assert_eq!(
+            clean("https://example.com #news @friend", &settings),
+            "https://example.com #news"
+        );
+    }
+
+    #[test]
+    fn symbol_only_lines_are_dropped() {
+        let settings = Settings::default();
+        assert_eq!(
+            filter("hello\n  \n😀!!\nworld", &settings),
+            vec!["hello", "world"]
+        );
+
+        let settings = Settings {
+            skip_symbols_only: false,
+            ..Settings::default()
+        };
+        assert_eq!(filter("hello\n😀!!", &settings), vec!["hello", "😀!!"]);
+    }
+
+    #[test]
+    fn short_messages_are_dropped() {
+        let settings = Settings {
+            min_words: 3,
+            ..Settings::default()
+        };
+        assert!(filter("one two", &settings).is_empty());
+        assert_eq!(
+            filter("one\ntwo three", &settings),
+            vec!["one", "two three"]
+        );
+    }
+}
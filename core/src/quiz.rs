@@ -0,0 +1,94 @@
+use crate::menu::Buttons;
+
+use std::collections::HashSet;
+
+use rand::{seq::SliceRandom, Rng};
+
+// the prefix of the callback data of quiz buttons, telling them apart from the /settings menu
+const CALLBACK_PREFIX: &str = "quiz:";
+
+// the number of members to choose from, the author included
+pub const OPTIONS: usize = 4;
+
+// the minimum length (in words) of a line worth guessing the author of
+pub const MIN_WORDS: usize = 4;
+
+// a round of "guess who said it" in a chat
+pub struct Quiz {
+    // tells the buttons of this round apart from the ones of earlier rounds
+    round: u32,
+    // the user ID of the line's author
+    author: i64,
+    // IDs of users who have guessed already, everyone gets one guess
+    guessed: HashSet<i64>,
+}
+
+// the outcome of pressing one of the buttons of a quiz
+pub enum Guess {
+    Correct,
+    Wrong,
+    // the user has guessed already
+    Repeated,
+    // the button belongs to an earlier round
+    Expired,
+}
+
+impl Quiz {
+    // starts a round of guessing the author of a line
+    // options are user IDs along with their names, the author's among them
+    // returns the round along with its question and buttons
+    pub fn new<R: Rng>(
+        line: &str,
+        author: i64,
+        mut options: Vec<(i64, String)>,
+        rng: &mut R,
+    ) -> (Quiz, String, Buttons) {
+        options.shuffle(rng);
+        let round = rng.gen();
+
+        let buttons = options
+            .into_iter()
+            .map(|(user_id, name)| {
+                vec![(name, format!("{}{}:{}", CALLBACK_PREFIX, round, user_id))]
+            })
+            .collect();
+        let text = format!("[who said it?]\n\n{}", line);
+
+        let quiz = Quiz {
+            round,
+            author,
+            guessed: HashSet::new(),
+        };
+        (quiz, text, buttons)
+    }
+
+    // records a user's guess given the callback data of the pressed button
+    pub fn guess(&mut self, user_id: i64, round: u32, author: i64) -> Guess {
+        if round != self.round {
+            return Guess::Expired;
+        }
+
+        if !self.guessed.insert(user_id) {
+            return Guess::Repeated;
+        }
+
+        if author == self.author {
+            Guess::Correct
+        } else {
+            Guess::Wrong
+        }
+    }
+}
+
+// checks if callback data belongs to one of the buttons of a quiz
+pub fn is_quiz_data(data: &str) -> bool {
+    data.starts_with(CALLBACK_PREFIX)
+}
+
+// parses the callback data of a quiz button into the round and the guessed user ID
+pub fn parse(data: &str) -> Option<(u32, i64)> {
+    let mut parts = data.trim_start_matches(CALLBACK_PREFIX).splitn(2, ':');
+    let round = parts.next()?.parse::<u32>().ok()?;
+    let author = parts.next()?.parse::<i64>().ok()?;
+    Some((round, author))
+}
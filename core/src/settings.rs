@@ -0,0 +1,285 @@
+use crate::chain::MAX_WORDS;
+use crate::feedback::{Duel, Generation};
+use crate::tokenizer;
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// the names of text filters, as used by /strip and the /settings menu
+pub const FILTERS: [&str; 7] = [
+    "urls",
+    "mentions",
+    "hashtags",
+    "commands",
+    "symbols",
+    "forwarded",
+    "anonymous",
+];
+
+// per-chat configuration
+// stored as JSON, so that new options can be added without a schema migration
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // lowercase words and phrases that keep a message from being learnt
+    pub blacklist: Vec<String>,
+    // links removed from messages before they're learnt
+    pub strip_urls: bool,
+    // @usernames removed from messages before they're learnt
+    pub strip_mentions: bool,
+    // #hashtags removed from messages before they're learnt
+    pub strip_hashtags: bool,
+    // /commands removed from messages before they're learnt
+    pub strip_commands: bool,
+    // messages shorter than that (in words) aren't learnt
+    pub min_words: usize,
+    // lines without a single letter or digit (emoji, punctuation) aren't learnt
+    pub skip_symbols_only: bool,
+    // forwarded messages aren't learnt
+    pub skip_forwarded: bool,
+    // messages from users without a username and anonymous admins aren't learnt
+    pub skip_anonymous: bool,
+    // the maximum number of chain states, overrides MAX_STATES (0 meaning no limit)
+    pub max_states: Option<usize>,
+    // the preferred minimum length (in words) of generated messages
+    pub min_output_words: usize,
+    // the maximum length (in words) of generated messages
+    pub max_output_words: usize,
+    // flattens (above 1) or sharpens (below 1) the distribution of the next word
+    pub temperature: f64,
+    // lowercase words the bot mustn't say, even though it may learn them
+    pub censored: Vec<String>,
+    // the chance (in percent) of replying to a sticker with one of the chat's popular stickers
+    pub sticker_chance: u32,
+    // the time (in seconds) a user has to wait between generating commands (0 meaning no limit)
+    pub speak_cooldown: u64,
+    // the number of days after which everything learnt is wiped (0 meaning never)
+    pub retention_days: u64,
+    // learnt lines are also fed into the chain shared by all chats that opted into it
+    pub share_globally: bool,
+    // IDs of users whose messages are never learnt
+    pub ignored: Vec<i64>,
+    // only the messages of users who sent /optin are learnt
+    pub opt_in_only: bool,
+    // IDs of users who sent /optin
+    pub opted_in: Vec<i64>,
+    // IDs of users who sent /optout, their messages are never learnt
+    pub opted_out: Vec<i64>,
+    // the points each user has scored in /quiz
+    // ordered, so that unchanged settings always serialize the same way
+    pub quiz_scores: BTreeMap<i64, u32>,
+    // generated messages come with buttons for rating them
+    pub feedback: bool,
+    // the ratings of the words generated messages started with, see feedback::apply
+    pub start_scores: BTreeMap<String, i32>,
+    // the messages recently generated with rating buttons, see feedback::track
+    pub generations: VecDeque<Generation>,
+    // the /speak2 duels still being voted on, see feedback::Duel
+    pub duels: Vec<Duel>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            blacklist: Vec::new(),
+            strip_urls: true,
+            strip_mentions: true,
+            strip_hashtags: true,
+            strip_commands: true,
+            min_words: 1,
+            skip_symbols_only: true,
+            skip_forwarded: false,
+            skip_anonymous: false,
+            max_states: None,
+            min_output_words: 1,
+            max_output_words: MAX_WORDS,
+            temperature: 1.0,
+            censored: Vec::new(),
+            sticker_chance: 0,
+            speak_cooldown: 0,
+            retention_days: 0,
+            share_globally: false,
+            ignored: Vec::new(),
+            opt_in_only: false,
+            opted_in: Vec::new(),
+            opted_out: Vec::new(),
+            quiz_scores: BTreeMap::new(),
+            feedback: false,
+            start_scores: BTreeMap::new(),
+            generations: VecDeque::new(),
+            duels: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    // checks if a message contains any of the blacklisted words or phrases
+    pub fn is_blacklisted(&self, msg: &str) -> bool {
+        if self.blacklist.is_empty() {
+            return false;
+        }
+
+        let msg = msg.to_lowercase();
+        self.blacklist.iter().any(|phrase| msg.contains(phrase))
+    }
+
+    // checks if a user's messages may be learnt
+    pub fn is_learnt_from(&self, user_id: i64) -> bool {
+        !self.ignored.contains(&user_id)
+            && !self.opted_out.contains(&user_id)
+            && (!self.opt_in_only || self.opted_in.contains(&user_id))
+    }
+
+    // checks if a word is one of the censored ones, ignoring case and surrounding punctuation
+    fn is_censored_word(&self, word: &str) -> bool {
+        let word = word
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        self.censored.contains(&word)
+    }
+
+    // checks if a generated phrase contains any of the censored words
+    pub fn is_censored(&self, phrase: &str) -> bool {
+        !self.censored.is_empty()
+            && tokenizer::tokenize(phrase)
+                .iter()
+                .any(|w| self.is_censored_word(w))
+    }
+
+    // masks censored words of a phrase with asterisks, leaving their first letter
+    pub fn censor(&self, phrase: &str) -> String {
+        let words: Vec<String> = tokenizer::tokenize(phrase)
+            .into_iter()
+            .map(|word| {
+                if self.is_censored_word(&word) {
+                    word.chars()
+                        .enumerate()
+                        .map(|(i, c)| {
+                            if i == 0 || !c.is_alphanumeric() {
+                                c
+                            } else {
+                                '*'
+                            }
+                        })
+                        .collect()
+                } else {
+                    word
+                }
+            })
+            .collect();
+
+        tokenizer::join(&words)
+    }
+
+    // checks if the retention period has run out for data learnt since a given UNIX timestamp
+    pub fn is_expired(&self, learnt_since: Option<u64>, now: u64) -> bool {
+        match learnt_since {
+            Some(since) if self.retention_days > 0 => {
+                now.saturating_sub(since) >= self.retention_days * 24 * 60 * 60
+            }
+            _ => false,
+        }
+    }
+
+    // returns the state of a text filter given its name
+    pub fn filter(&self, name: &str) -> Option<bool> {
+        match name {
+            "urls" => Some(self.strip_urls),
+            "mentions" => Some(self.strip_mentions),
+            "hashtags" => Some(self.strip_hashtags),
+            "commands" => Some(self.strip_commands),
+            "symbols" => Some(self.skip_symbols_only),
+            "forwarded" => Some(self.skip_forwarded),
+            "anonymous" => Some(self.skip_anonymous),
+            _ => None,
+        }
+    }
+
+    // returns a mutable reference to a text filter given its name
+    pub fn filter_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "urls" => Some(&mut self.strip_urls),
+            "mentions" => Some(&mut self.strip_mentions),
+            "hashtags" => Some(&mut self.strip_hashtags),
+            "commands" => Some(&mut self.strip_commands),
+            "symbols" => Some(&mut self.skip_symbols_only),
+            "forwarded" => Some(&mut self.skip_forwarded),
+            "anonymous" => Some(&mut self.skip_anonymous),
+            _ => None,
+        }
+    }
+
+    // lists text filters along with their states
+    pub fn describe_filters(&self) -> String {
+        let state = |enabled: bool| if enabled { "stripped" } else { "kept" };
+
+        format!(
+            "urls - {}\nmentions - {}\nhashtags - {}\ncommands - {}\n\
+             symbols (emoji / punctuation-only lines) - {}\nforwarded messages - {}\n\
+             anonymous messages (no username / anonymous admins) - {}\n\
+             minimum message length - {} words",
+            state(self.strip_urls),
+            state(self.strip_mentions),
+            state(self.strip_hashtags),
+            state(self.strip_commands),
+            state(self.skip_symbols_only),
+            state(self.skip_forwarded),
+            state(self.skip_anonymous),
+            self.min_words
+        )
+    }
+}
+
+// the layout of a chat's settings file, stored apart from the chain
+// keeps the configuration of chats whose chain is empty (and thus never stored)
+#[derive(Serialize, Deserialize)]
+pub struct Record<S> {
+    pub is_learning: bool,
+    pub settings: S,
+    // the time (as a UNIX timestamp) the oldest data still kept was learnt
+    // used to enforce the retention period without loading the chain
+    #[serde(default)]
+    pub learnt_since: Option<u64>,
+    // the time (as a UNIX timestamp) the chat was last used, to the day
+    // used to find idle chats without loading the chain
+    #[serde(default)]
+    pub last_used: Option<u64>,
+}
+
+// serializes a chat's configuration for its settings file
+pub fn to_record(
+    is_learning: bool,
+    settings: &Settings,
+    learnt_since: Option<u64>,
+    last_used: Option<u64>,
+) -> Vec<u8> {
+    let record = Record {
+        is_learning,
+        settings,
+        learnt_since,
+        last_used,
+    };
+    serde_json::to_vec(&record).expect("Serialization failed")
+}
+
+// deserializes a chat's configuration from its settings file
+pub fn from_record(bytes: &[u8]) -> Result<Record<Settings>, String> {
+    serde_json::from_slice(bytes).map_err(|e| e.to_string())
+}
+
+// (de)serializes Settings as a JSON string
+// used with #[serde(with = "settings::as_json")] inside binary formats
+pub mod as_json {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(settings: &Settings, serializer: S) -> Result<S::Ok, S::Error> {
+        let json = serde_json::to_string(settings).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&json)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Settings, D::Error> {
+        let json = String::deserialize(deserializer)?;
+        serde_json::from_str(&json).map_err(serde::de::Error::custom)
+    }
+}
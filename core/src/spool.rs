@@ -0,0 +1,73 @@
+use crate::utils;
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+
+// extracts SPOOL_DIR from std::env
+// defaults to ./spool if the variable is not set
+fn get_spool_dir() -> String {
+    env::var("SPOOL_DIR").unwrap_or_else(|_| String::from("./spool"))
+}
+
+lazy_static! {
+    // the directory holding files that couldn't be uploaded yet
+    static ref SPOOL_DIR: String = get_spool_dir();
+    // guards spooled files against concurrent writes
+    static ref SPOOL_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// returns the path a file is spooled at
+// names are hex-encoded, as they may contain characters that aren't allowed in paths
+fn path(name: &str) -> PathBuf {
+    Path::new(&*SPOOL_DIR).join(utils::hex_encode(name))
+}
+
+// keeps a file that couldn't be uploaded, replacing an older spooled version of it
+pub fn store(name: &str, bytes: &[u8]) -> Option<String> {
+    let _guard = SPOOL_LOCK.lock().unwrap();
+
+    let res = fs::create_dir_all(&*SPOOL_DIR).and_then(|_| fs::write(path(name), bytes));
+
+    match res {
+        Ok(_) => None,
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+// returns the contents of a spooled file, if there is one
+// a spooled file is always newer than the uploaded one
+pub fn load(name: &str) -> Option<Vec<u8>> {
+    let _guard = SPOOL_LOCK.lock().unwrap();
+
+    fs::read(path(name)).ok()
+}
+
+// forgets a spooled file, once it's been uploaded or deleted
+pub fn remove(name: &str) {
+    let _guard = SPOOL_LOCK.lock().unwrap();
+
+    let path = path(name);
+    if path.exists() {
+        if let Err(err) = fs::remove_file(path) {
+            dbg!(err);
+        }
+    }
+}
+
+// returns the names of all spooled files
+pub fn names() -> Vec<String> {
+    let _guard = SPOOL_LOCK.lock().unwrap();
+
+    match fs::read_dir(&*SPOOL_DIR) {
+        Err(_) => Vec::new(),
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| utils::hex_decode(&entry.file_name().to_string_lossy()))
+            .collect(),
+    }
+}
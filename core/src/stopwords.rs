@@ -0,0 +1,32 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+
+// common words that carry no meaning on their own, left out of word statistics
+// English and Polish, the languages the bot is used in the most
+const STOPWORDS: &[&str] = &[
+    // English
+    "a", "about", "after", "again", "all", "also", "am", "an", "and", "any", "are", "as", "at",
+    "be", "because", "been", "before", "being", "but", "by", "can", "could", "did", "do", "does",
+    "doing", "don't", "for", "from", "had", "has", "have", "having", "he", "her", "here", "hers",
+    "him", "his", "how", "i", "i'm", "if", "in", "into", "is", "it", "it's", "its", "just", "me",
+    "more", "my", "no", "not", "now", "of", "off", "on", "once", "only", "or", "other", "our",
+    "out", "over", "she", "so", "some", "such", "than", "that", "the", "their", "them", "then",
+    "there", "these", "they", "this", "those", "to", "too", "up", "very", "was", "we", "were",
+    "what", "when", "where", "which", "who", "why", "will", "with", "would", "you", "your",
+    // Polish
+    "a", "aby", "ale", "bo", "by", "być", "był", "była", "było", "czy", "dla", "do", "gdy", "go",
+    "i", "ich", "im", "jak", "jako", "je", "jego", "jej", "jest", "jestem", "już", "ja", "mi",
+    "mnie", "na", "nas", "nie", "no", "o", "od", "on", "ona", "oni", "po", "pod", "przez", "przy",
+    "sie", "się", "są", "ta", "tak", "tam", "te", "tego", "to", "tu", "ty", "tylko", "w", "we",
+    "z", "za", "ze", "że", "żeby",
+];
+
+lazy_static! {
+    static ref STOPWORD_SET: HashSet<&'static str> = STOPWORDS.iter().copied().collect();
+}
+
+// checks if a lowercase word is a stopword
+pub fn is_stopword(word: &str) -> bool {
+    STOPWORD_SET.contains(word)
+}
@@ -0,0 +1,372 @@
+use crate::{alerts, blob, cache, dropbox, error::Error, gdrive, spool, utils};
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use lazy_static::lazy_static;
+use tokio::sync::Mutex as AsyncMutex;
+
+// a service used to persist chains
+enum Backend {
+    GDrive,
+    Dropbox,
+    // chains are kept in memory only and never persisted
+    Memory,
+}
+
+// extracts STORAGE from std::env and returns a Backend
+// defaults to Google Drive if the variable is not set
+fn get_backend() -> Backend {
+    match env::var("STORAGE") {
+        Err(_) => Backend::GDrive,
+        Ok(name) => match name.trim().to_lowercase().as_str() {
+            "gdrive" => Backend::GDrive,
+            "dropbox" => Backend::Dropbox,
+            "memory" => Backend::Memory,
+            other => panic!("Unknown STORAGE backend: {}", other),
+        },
+    }
+}
+
+// set if the selected backend failed to initialize or was detached
+static IS_DEGRADED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // the backend selected for this deployment
+    static ref BACKEND: Backend = get_backend();
+    // a prefix prepended to the names of all files of this deployment
+    static ref PREFIX: String = env::var("STORAGE_PREFIX").unwrap_or_default();
+    // locks of the files being uploaded or deleted, so that a retried spooled file never overwrites
+    // a newer one or recreates a deleted one
+    static ref UPLOAD_LOCKS: Mutex<HashMap<String, Arc<AsyncMutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+// returns the lock guarding the uploads of a specified file
+fn upload_lock(name: &str) -> Arc<AsyncMutex<()>> {
+    let mut locks = UPLOAD_LOCKS.lock().unwrap();
+
+    // locks nobody holds or waits for aren't needed anymore
+    locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+    Arc::clone(
+        locks
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(AsyncMutex::new(()))),
+    )
+}
+
+// returns the name a file is stored under in this deployment's namespace
+fn namespaced(name: &str) -> String {
+    format!("{}{}", *PREFIX, name)
+}
+
+// returns the backend requests should go to
+// a backend that failed to initialize is replaced with memory, so that the bot keeps working
+fn backend() -> &'static Backend {
+    if is_degraded() {
+        &Backend::Memory
+    } else {
+        &BACKEND
+    }
+}
+
+// creates a connection to the selected backend
+// if that fails, the bot runs without persistence (see backend) and the error is reported
+pub async fn initialize() {
+    let res = match *BACKEND {
+        Backend::GDrive => {
+            async {
+                utils::parse_credentials().map_err(Error::Storage)?;
+                utils::parse_token_cache().map_err(Error::Storage)?;
+                gdrive::initialize().await
+            }
+            .await
+        }
+        Backend::Dropbox => dropbox::initialize(),
+        Backend::Memory => Ok(()),
+    };
+
+    if let Err(e) = res {
+        IS_DEGRADED.store(true, Ordering::SeqCst);
+
+        let err = format!(
+            "Storage unavailable, running without persistence until a restart: {}",
+            e
+        );
+        alerts::report(&err);
+        dbg!(err);
+    }
+}
+
+// stops sending requests to the backend, as if it failed to initialize
+// chains loaded so far stay in memory, but nothing is written back
+// used by offline tools that mustn't change the stored chains
+pub fn detach() {
+    IS_DEGRADED.store(true, Ordering::SeqCst);
+}
+
+// checks if the selected backend failed to initialize or was detached
+// requests go to memory meanwhile, so nothing is persisted
+pub fn is_degraded() -> bool {
+    IS_DEGRADED.load(Ordering::SeqCst)
+}
+
+// checks if chains outlive the process
+pub fn is_persistent() -> bool {
+    !matches!(backend(), Backend::Memory)
+}
+
+// returns revision IDs of a specified file, newest first
+async fn list_revisions(name: &str) -> Result<Vec<String>, Error> {
+    let name = &namespaced(name);
+
+    match backend() {
+        Backend::GDrive => gdrive::list_revisions(name).await,
+        Backend::Dropbox => dropbox::list_revisions(name).await,
+        Backend::Memory => Ok(Vec::new()),
+    }
+}
+
+// downloads a specified revision of a file
+async fn download_revision(name: &str, revision: &str) -> Result<Vec<u8>, Error> {
+    let name = &namespaced(name);
+
+    match backend() {
+        Backend::GDrive => gdrive::download_revision(name, revision).await,
+        Backend::Dropbox => dropbox::download_revision(name, revision).await,
+        Backend::Memory => Err(Error::NotFound(String::from(
+            "Revisions are not stored in memory mode",
+        ))),
+    }
+}
+
+// downloads the newest revision of a file that passes checksum verification
+// skips the current revision, which is assumed to be corrupted
+async fn download_previous_revision(name: &str) -> Result<Vec<u8>, Error> {
+    let revisions = list_revisions(name).await?;
+
+    for revision in revisions.iter().skip(1) {
+        match download_revision(name, revision).await {
+            Ok(bytes) => {
+                if let Ok(contents) = blob::verify_checksum(bytes) {
+                    return Ok(contents);
+                }
+            }
+            Err(e) => {
+                dbg!(e);
+            }
+        }
+    }
+
+    Err(Error::Deserialization(format!(
+        "No intact revision found for {}",
+        name
+    )))
+}
+
+// uploads a file under its full name, replacing the previous contents
+async fn upload(bytes: &[u8], name: &str) -> Option<Error> {
+    match backend() {
+        Backend::GDrive => gdrive::update_or_create_file(bytes, name).await,
+        Backend::Dropbox => dropbox::update_or_create_file(bytes, name).await,
+        Backend::Memory => None,
+    }
+}
+
+// replaces contents of a specified file
+// creates a new file if one does not exist
+// a file that can't be uploaded for now is spooled locally and retried later (see retry_spooled)
+pub async fn update_or_create_file(bytes: &[u8], name: &str) -> Option<Error> {
+    let bytes = blob::add_checksum(bytes);
+    let name = &namespaced(name);
+
+    let lock = upload_lock(name);
+    let _guard = lock.lock().await;
+
+    match upload(&bytes, name).await {
+        None => {
+            // an older version waiting for a retry mustn't overwrite this one
+            spool::remove(name);
+            None
+        }
+        Some(err) if !err.is_retryable() => Some(err),
+        Some(err) => match spool::store(name, &bytes) {
+            None => {
+                let err = format!("Uploading {} failed, spooled for a retry: {}", name, err);
+                alerts::report(&err);
+                dbg!(err);
+                None
+            }
+            Some(spool_err) => Some(err.prefixed(&format!("spooling failed: {}", spool_err))),
+        },
+    }
+}
+
+// uploads the spooled files, stopping at the first failure (as the backend is likely still down)
+// every file is locked while it's being uploaded, a newer upload of the same file waits for it
+// and one that finished in the meantime has already removed the file from the spool
+// returns the number of files still waiting
+pub async fn retry_spooled() -> usize {
+    let names = spool::names();
+    let mut remaining = names.len();
+
+    for name in names {
+        let lock = upload_lock(&name);
+        let _guard = lock.lock().await;
+
+        let bytes = match spool::load(&name) {
+            Some(bytes) => bytes,
+            None => {
+                remaining -= 1;
+                continue;
+            }
+        };
+
+        match upload(&bytes, &name).await {
+            None => {
+                spool::remove(&name);
+                remaining -= 1;
+            }
+            Some(err) => {
+                dbg!(err);
+                break;
+            }
+        }
+    }
+
+    remaining
+}
+
+// downloads a specified file and verifies its checksum
+// a spooled version, which is newer than the uploaded one, takes precedence
+// falls back to previous revisions if the file is corrupted
+pub async fn download_file(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let res = match spool::load(&namespaced(name)) {
+        Some(bytes) => Ok(Some(bytes)),
+        None => match backend() {
+            Backend::GDrive => gdrive::download_file(&namespaced(name)).await,
+            Backend::Dropbox => dropbox::download_file(&namespaced(name)).await,
+            Backend::Memory => Ok(None),
+        },
+    };
+
+    match res {
+        Err(e) => Err(e),
+        Ok(None) => Ok(None),
+        Ok(Some(bytes)) => match blob::verify_checksum(bytes) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) => {
+                let err = format!("{} for {}, trying previous revisions", e, name);
+                alerts::report(&err);
+                dbg!(err);
+                download_previous_revision(name).await.map(Some)
+            }
+        },
+    }
+}
+
+// like download_file, but keeps a copy of the file on local disk (see BLOB_CACHE_DIR)
+// the copy is used for as long as the stored file's newest revision stays the same,
+// which only takes listing the revisions rather than downloading the whole file
+pub async fn download_cached(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let full_name = namespaced(name);
+    if !cache::is_enabled() || spool::load(&full_name).is_some() {
+        return download_file(name).await;
+    }
+
+    let revision = match list_revisions(name).await {
+        Ok(revisions) => match revisions.into_iter().next() {
+            Some(revision) => revision,
+            None => return download_file(name).await,
+        },
+        Err(e) => {
+            dbg!(e);
+            return download_file(name).await;
+        }
+    };
+
+    if let Some(bytes) = cache::load(&full_name, &revision) {
+        if let Ok(contents) = blob::verify_checksum(bytes) {
+            return Ok(Some(contents));
+        }
+    }
+
+    let res = download_file(name).await;
+    if let Ok(Some(contents)) = &res {
+        cache::store(&full_name, &revision, &blob::add_checksum(contents));
+    }
+
+    res
+}
+
+// returns names, sizes (in bytes) and modification times (as UNIX timestamps, if known)
+// of all stored files starting with a given prefix
+// names are returned without this deployment's prefix
+pub async fn list_files_with_details(
+    prefix: &str,
+) -> Result<Vec<(String, u64, Option<u64>)>, Error> {
+    let prefix = &namespaced(prefix);
+
+    let res = match backend() {
+        Backend::GDrive => gdrive::list_files(prefix).await,
+        Backend::Dropbox => dropbox::list_files(prefix).await,
+        Backend::Memory => Ok(Vec::new()),
+    };
+
+    res.map(|files| {
+        files
+            .into_iter()
+            .map(|(name, size, modified)| (name[PREFIX.len()..].to_string(), size, modified))
+            .collect()
+    })
+}
+
+// returns names and sizes (in bytes) of all stored files starting with a given prefix
+pub async fn list_files_with_sizes(prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+    list_files_with_details(prefix).await.map(|files| {
+        files
+            .into_iter()
+            .map(|(name, size, _)| (name, size))
+            .collect()
+    })
+}
+
+// returns names of all stored files starting with a given prefix
+pub async fn list_files(prefix: &str) -> Result<Vec<String>, Error> {
+    list_files_with_sizes(prefix)
+        .await
+        .map(|files| files.into_iter().map(|(name, _)| name).collect())
+}
+
+// returns the used and total (if limited) space of the backend in bytes
+pub async fn get_quota() -> Result<(u64, Option<u64>), Error> {
+    match backend() {
+        Backend::GDrive => gdrive::get_quota().await,
+        Backend::Dropbox => dropbox::get_quota().await,
+        Backend::Memory => Ok((0, None)),
+    }
+}
+
+// deletes a specified file
+// waits for an upload of the file in progress, so that a retried spooled copy can't recreate it
+pub async fn delete_file(name: &str) -> Option<Error> {
+    let name = &namespaced(name);
+
+    let lock = upload_lock(name);
+    let _guard = lock.lock().await;
+
+    spool::remove(name);
+    cache::remove(name);
+
+    match backend() {
+        Backend::GDrive => gdrive::delete_file(name).await,
+        Backend::Dropbox => dropbox::delete_file(name).await,
+        Backend::Memory => None,
+    }
+}
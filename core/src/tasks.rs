@@ -0,0 +1,204 @@
+use crate::alerts;
+use crate::batch;
+use crate::chain_wrapper::{self, ChainWrapper};
+use crate::config_file;
+use crate::storage;
+
+use std::{env, sync::Arc, time::Duration};
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Mutex,
+};
+
+// the time between two attempts to upload the files spooled during a storage outage
+const SPOOL_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// the time between two checks of the memory taken by the loaded chains
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// the time between two checks for chats whose retention period has run out (or that are idle)
+// cleared chains are purged from the trash at the same time
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// the time between two removals of rare transitions from the loaded chains
+const RARE_PRUNING_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// extracts FLUSH_FREQUENCY from std::env and returns a Duration
+// defaults to 10 minutes if the variable is not set
+fn get_flush_frequency() -> Duration {
+    let minutes = match env::var("FLUSH_FREQUENCY") {
+        Err(_) => 10,
+        Ok(minutes) => minutes
+            .parse::<u64>()
+            .expect("FLUSH_FREQUENCY is not a number"),
+    };
+
+    Duration::from_secs(minutes * 60)
+}
+
+// extracts UPDATE_FREQUENCY from std::env and returns a Duration
+fn get_update_frequency() -> Duration {
+    let minutes = env::var("UPDATE_FREQUENCY")
+        .expect("UPDATE_FREQUENCY not set")
+        .parse::<u64>()
+        .expect("UPDATE_FREQUENCY is not a number");
+
+    Duration::from_secs(minutes * 60)
+}
+
+// spawns a task that periodically saves and frees chains nobody has used for a while
+// runs on its own schedule, so that chains are freed even when no updates arrive
+pub fn spawn_prune(chain: Arc<Mutex<ChainWrapper>>) {
+    let mut interval = tokio::time::interval(get_update_frequency());
+
+    tokio::spawn(async move {
+        // the first tick completes immediately, there's nothing to prune yet
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            ChainWrapper::prune(&chain).await;
+        }
+    });
+}
+
+// spawns a task that loads the chats used the most recently before the last shutdown
+// every chat is loaded separately, so that updates are handled in the meantime
+pub fn spawn_warm_start(chain: Arc<Mutex<ChainWrapper>>) {
+    tokio::spawn(async move {
+        let chats = match ChainWrapper::recently_active().await {
+            Ok(chats) => chats,
+            Err(e) => {
+                dbg!(e);
+                return;
+            }
+        };
+
+        // the most recently used chat is loaded last, so that it's the last one to be evicted
+        for chat_id in chats.into_iter().rev() {
+            ChainWrapper::preload(&chain, chat_id).await;
+        }
+    });
+}
+
+// spawns a task that prunes chains early once they take up most of MEMORY_BUDGET
+pub fn spawn_memory_check(chain: Arc<Mutex<ChainWrapper>>) {
+    let mut interval = tokio::time::interval(MEMORY_CHECK_INTERVAL);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            ChainWrapper::prune_if_needed(&chain).await;
+        }
+    });
+}
+
+// spawns a task that periodically sends unsaved changes to the storage backend
+pub fn spawn_flush(chain: Arc<Mutex<ChainWrapper>>) {
+    let mut interval = tokio::time::interval(get_flush_frequency());
+
+    tokio::spawn(async move {
+        // the first tick completes immediately, there's nothing to flush yet
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            batch::flush(&chain).await;
+            ChainWrapper::flush_all(&chain).await;
+        }
+    });
+}
+
+// spawns a task that uploads the files that couldn't be uploaded before, starting right away
+// the chains aren't locked meanwhile, storage keeps the retries from overwriting newer uploads
+pub fn spawn_spool_retry() {
+    let mut interval = tokio::time::interval(SPOOL_RETRY_INTERVAL);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let remaining = storage::retry_spooled().await;
+            if remaining > 0 {
+                dbg!(remaining);
+            }
+        }
+    });
+}
+
+// spawns a task that wipes chats whose retention period has run out
+// deletes the files of chats that have been idle for too long and purges the old trash
+// stored chats are checked without holding the lock, as that takes a while
+pub fn spawn_retention(chain: Arc<Mutex<ChainWrapper>>) {
+    let mut interval = tokio::time::interval(RETENTION_CHECK_INTERVAL);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let stored = match ChainWrapper::expired_chats().await {
+                Ok(stored) => stored,
+                Err(e) => {
+                    dbg!(e);
+                    Vec::new()
+                }
+            };
+
+            ChainWrapper::wipe_expired(&chain, stored).await;
+
+            match ChainWrapper::idle_chats().await {
+                Ok(idle) => ChainWrapper::delete_idle(&chain, idle).await,
+                Err(e) => {
+                    dbg!(e);
+                }
+            }
+
+            if let Some(err) = ChainWrapper::purge_trash().await {
+                dbg!(err);
+            }
+        }
+    });
+}
+
+// spawns a task that re-reads the configuration on SIGHUP
+// values from the configuration file and the .env file override the ones read before,
+// so that edits to them take effect, while variables set by the shell are left alone
+pub fn spawn_reload() {
+    let mut sighup = signal(SignalKind::hangup()).expect("Failed to register SIGHUP handler");
+
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            if let Some(err) = config_file::apply() {
+                alerts::report(&err);
+                dbg!(err);
+            }
+
+            if let Some(err) = config_file::reload_dotenv() {
+                alerts::report(&err);
+                dbg!(err);
+            }
+
+            match chain_wrapper::reload_config() {
+                Some(err) => {
+                    alerts::report(&err);
+                    dbg!(err);
+                }
+                None => println!("Configuration reloaded"),
+            }
+        }
+    });
+}
+
+// spawns a task that removes transitions learnt only once more than RARE_TRANSITION_DAYS ago
+// only the loaded chains are checked, the rest are checked once somebody uses them
+pub fn spawn_rare_pruning(chain: Arc<Mutex<ChainWrapper>>) {
+    let mut interval = tokio::time::interval(RARE_PRUNING_INTERVAL);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            chain.lock().await.prune_rare_all();
+        }
+    });
+}
@@ -0,0 +1,111 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+// checks if a character belongs to a script that doesn't separate words with spaces
+// (Chinese, Japanese) or is an emoji, which tends to be glued to other emoji
+fn is_unspaced(c: char) -> bool {
+    match c as u32 {
+        // CJK symbols and punctuation, hiragana, katakana
+        0x3000..=0x30FF | 0x31F0..=0x31FF => true,
+        // CJK unified ideographs (with extension A) and compatibility ideographs
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => true,
+        // fullwidth punctuation and halfwidth katakana
+        0xFF01..=0xFF0F | 0xFF1A..=0xFF20 | 0xFF5B..=0xFF9F => true,
+        // CJK unified ideographs, extensions B and beyond
+        0x20000..=0x3134F => true,
+        // emoji and pictographs
+        0x2600..=0x27BF | 0x1F000..=0x1FAFF => true,
+        _ => false,
+    }
+}
+
+// splits a line into tokens
+// words are separated by whitespace, except for the scripts that don't use it,
+// which are split on Unicode word boundaries (single ideographs, kana runs, emoji sequences)
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+
+    for word in line.split_whitespace() {
+        if !word.chars().any(is_unspaced) {
+            tokens.push(word.to_string());
+            continue;
+        }
+
+        // keep the parts written in other scripts (and their punctuation) together
+        let mut spaced = String::new();
+        for segment in word.split_word_bounds() {
+            if segment.chars().any(is_unspaced) {
+                if !spaced.is_empty() {
+                    tokens.push(spaced.split_off(0));
+                }
+                tokens.push(segment.to_string());
+            } else {
+                spaced.push_str(segment);
+            }
+        }
+
+        if !spaced.is_empty() {
+            tokens.push(spaced);
+        }
+    }
+
+    tokens
+}
+
+// returns the number of tokens in a line
+pub fn count(line: &str) -> usize {
+    tokenize(line).len()
+}
+
+// joins tokens into a line
+// tokens of the scripts that don't use spaces are glued together
+pub fn join<S: AsRef<str>>(tokens: &[S]) -> String {
+    let mut line = String::new();
+    let mut prev: Option<char> = None;
+
+    for token in tokens.iter() {
+        let token = token.as_ref();
+
+        let is_glued = match (prev, token.chars().next()) {
+            (Some(last), Some(first)) => is_unspaced(last) && is_unspaced(first),
+            _ => true,
+        };
+
+        if !is_glued {
+            line.push(' ');
+        }
+
+        line.push_str(token);
+        prev = token.chars().last().or(prev);
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_split_on_whitespace() {
+        assert_eq!(tokenize("  hello,\tworld!\n"), vec!["hello,", "world!"]);
+        assert!(tokenize(" \n ").is_empty());
+    }
+
+    #[test]
+    fn unspaced_scripts_are_split_on_word_bounds() {
+        assert_eq!(tokenize("我爱你"), vec!["我", "爱", "你"]);
+        assert_eq!(tokenize("hi😀😀"), vec!["hi", "😀", "😀"]);
+    }
+
+    #[test]
+    fn counts_tokens() {
+        assert_eq!(count("one two 三四"), 4);
+    }
+
+    #[test]
+    fn joining_reverses_tokenizing() {
+        for line in &["hello, world!", "我爱你", "say 你好 please", "hi 😀😀"] {
+            assert_eq!(join(&tokenize(line)), *line);
+        }
+    }
+}
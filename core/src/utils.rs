@@ -0,0 +1,159 @@
+use std::{
+    env, fs,
+    io::prelude::*,
+    path::Path,
+    str,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::decode;
+use chrono::{DateTime, NaiveDateTime};
+use failure::Error;
+use futures::future::Future;
+use retry::delay::{jitter, Exponential};
+
+// runs a CPU-heavy closure on tokio's blocking thread pool and awaits its result
+// keeps the runtime responsive while large blobs are (un)packed
+// a panic inside the closure is propagated to the caller, just as if it ran inline
+pub async fn run_blocking<F, T>(closure: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(closure)
+        .await
+        .expect("A blocking task panicked")
+}
+
+// encodes a string as lowercase hex, making it safe to use as a file name
+pub fn hex_encode(s: &str) -> String {
+    s.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+// decodes a string encoded with hex_encode, returns None if it's not valid
+pub fn hex_decode(encoded: &str) -> Option<String> {
+    if !encoded.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let bytes: Option<Vec<u8>> = (0..encoded.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(encoded.get(i..i + 2)?, 16).ok())
+        .collect();
+
+    String::from_utf8(bytes?).ok()
+}
+
+// returns a Vec of 5 durations with a random jitter
+fn random_durations() -> Vec<Duration> {
+    Exponential::from_millis(2)
+        .map(jitter)
+        .map(|x| x * 100)
+        .take(5)
+        .collect()
+}
+
+// takes a Fn closure that returns a Future resolving to Result<T, Error>
+// awaits the closure until it either returns Ok or fails enough times
+pub async fn exponential_retry_async<C, F, T>(closure: C) -> Result<T, Error>
+where
+    C: Fn() -> F,
+    F: Future<Output = Result<T, Error>>,
+{
+    let mut err = None;
+    for duration in random_durations() {
+        tokio::time::delay_for(duration).await;
+        match closure().await {
+            Ok(result) => return Ok(result),
+            Err(e) => {
+                err = Some(e);
+            }
+        }
+    }
+
+    Err(err.unwrap())
+}
+
+// returns the current time as a UNIX timestamp
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// formats a UNIX timestamp as a human-readable UTC date
+pub fn format_timestamp(timestamp: u64) -> String {
+    NaiveDateTime::from_timestamp(timestamp as i64, 0)
+        .format("%Y-%m-%d %H:%M UTC")
+        .to_string()
+}
+
+// parses an RFC 3339 date (as reported by storage backends) into a UNIX timestamp
+pub fn parse_timestamp(date: &str) -> Option<u64> {
+    DateTime::parse_from_rfc3339(date)
+        .ok()
+        .map(|date| date.timestamp().max(0) as u64)
+}
+
+// formats a number of bytes as a human-readable size
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+// deletes a file from a filesystem
+fn delete_file(path: &str) -> std::io::Result<()> {
+    if Path::new(path).exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// writes a file to a filesystem
+fn bytes_to_file(bytes: &[u8], path: &str) -> Result<(), String> {
+    let res = delete_file(path).and_then(|_| {
+        let mut f = fs::OpenOptions::new()
+            .read(false)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        f.write_all(bytes)?;
+        f.sync_all()
+    });
+
+    res.map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+// decodes base64 string from std::env and saves the result to a filesystem
+pub fn parse_credentials() -> Result<(), String> {
+    let cred_b64 = env::var("GDRIVE_CREDENTIALS").map_err(|_| "GDRIVE_CREDENTIALS not set")?;
+    let v_u8_b64 = decode(&cred_b64).map_err(|e| format!("Failed to decode credentials: {}", e))?;
+    bytes_to_file(&v_u8_b64, "./credentials.json")
+}
+
+// decodes an optional base64 OAuth token cache from std::env and saves it to a filesystem
+pub fn parse_token_cache() -> Result<(), String> {
+    if let Ok(cache_b64) = env::var("GDRIVE_TOKEN_CACHE") {
+        if !cache_b64.trim().is_empty() {
+            let v_u8 = decode(cache_b64.trim())
+                .map_err(|e| format!("Failed to decode the token cache: {}", e))?;
+            bytes_to_file(&v_u8, "./tokencache.json")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use crate::batch::Incoming;
+use crate::storage;
+
+use std::{
+    env, fs,
+    io::{self, prelude::*, BufReader},
+    path::Path,
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+
+// an entry of the write-ahead log
+pub enum Entry {
+    // a line learnt by a chat, along with its author
+    Line(i64, String),
+    // a message received but not learnt yet (see batch::feed)
+    Message(Incoming),
+}
+
+// extracts WAL_PATH from std::env
+// defaults to ./wal.log if the variable is not set
+fn get_wal_path() -> String {
+    env::var("WAL_PATH").unwrap_or_else(|_| String::from("./wal.log"))
+}
+
+lazy_static! {
+    // the path of a write-ahead log holding lines that weren't persisted yet
+    static ref WAL_PATH: String = get_wal_path();
+    // guards the log file against concurrent writes
+    // holds the sequence number of the last entry, which goes on from the entries left by the last run
+    static ref WAL_LOCK: Mutex<u64> = Mutex::new(last_sequence());
+}
+
+// escapes a field, so that it fits on a single line and doesn't contain the separator
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// reverses escape
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// reads the lines of the log, the caller has to hold WAL_LOCK
+fn read_lines() -> Vec<String> {
+    if !Path::new(&*WAL_PATH).exists() {
+        return Vec::new();
+    }
+
+    let f = fs::File::open(&*WAL_PATH).expect("Failed to open the write-ahead log");
+
+    // a read error would repeat forever, so reading stops at the first one
+    BufReader::new(f).lines().map_while(Result::ok).collect()
+}
+
+// parses a line of the log into the entry's sequence number, chat ID and contents
+fn parse(line: &str) -> Option<(u64, i64, Entry)> {
+    let (tag, rest) = line.split_once('\t')?;
+
+    match tag {
+        "L" => {
+            let mut parts = rest.splitn(4, '\t');
+            let seq = parts.next()?.parse::<u64>().ok()?;
+            let chat_id = parts.next()?.parse::<i64>().ok()?;
+            let user_id = parts.next()?.parse::<i64>().ok()?;
+            let text = unescape(parts.next()?);
+            Some((seq, chat_id, Entry::Line(user_id, text)))
+        }
+        "M" => {
+            // the fields are escaped, so every tab separates two of them
+            let fields: Vec<&str> = rest.split('\t').collect();
+            if fields.len() != 8 {
+                return None;
+            }
+
+            let seq = fields[0].parse::<u64>().ok()?;
+            let chat_id = fields[1].parse::<i64>().ok()?;
+            let incoming = Incoming {
+                user_id: fields[2].parse::<i64>().ok()?,
+                message_id: fields[3].parse::<i64>().ok()?,
+                is_forwarded: fields[4] == "1",
+                is_edit: fields[5] == "1",
+                username: unescape(fields[6]),
+                text: unescape(fields[7]),
+            };
+            Some((seq, chat_id, Entry::Message(incoming)))
+        }
+        _ => None,
+    }
+}
+
+// returns the sequence number of the last entry left in the log
+fn last_sequence() -> u64 {
+    read_lines()
+        .iter()
+        .filter_map(|line| parse(line))
+        .map(|(seq, _, _)| seq)
+        .max()
+        .unwrap_or(0)
+}
+
+// appends a line made of the next sequence number to the log
+// returns the sequence number
+fn write<F: FnOnce(u64) -> String>(make_line: F) -> u64 {
+    let mut last = WAL_LOCK.lock().unwrap();
+    *last += 1;
+
+    let line = make_line(*last);
+    let res = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&*WAL_PATH)
+        .and_then(|mut f| writeln!(f, "{}", line));
+
+    if let Err(err) = res {
+        dbg!(err);
+    }
+
+    *last
+}
+
+// appends a learnt line and its author to the write-ahead log
+// returns the sequence number of the entry
+pub fn append(chat_id: i64, user_id: i64, line: &str) -> u64 {
+    write(|seq| format!("L\t{}\t{}\t{}\t{}", seq, chat_id, user_id, escape(line)))
+}
+
+// appends a message that's about to wait in a batch to the write-ahead log
+// returns the sequence number of the entry
+pub fn append_message(chat_id: i64, incoming: &Incoming) -> u64 {
+    write(|seq| {
+        format!(
+            "M\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            seq,
+            chat_id,
+            incoming.user_id,
+            incoming.message_id,
+            if incoming.is_forwarded { 1 } else { 0 },
+            if incoming.is_edit { 1 } else { 0 },
+            escape(&incoming.username),
+            escape(&incoming.text)
+        )
+    })
+}
+
+// returns all the entries of the write-ahead log as (sequence number, chat ID, entry)
+// a non-empty log means the previous shutdown wasn't clean
+pub fn read_entries() -> Vec<(u64, i64, Entry)> {
+    let _guard = WAL_LOCK.lock().unwrap();
+
+    read_lines().iter().filter_map(|line| parse(line)).collect()
+}
+
+// replaces the contents of the log with given lines, the caller has to hold WAL_LOCK
+// the lines are written to a temporary file first, which then takes the place of the log,
+// so that a crash halfway through leaves either the old log or the new one
+fn rewrite(lines: &[String]) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", *WAL_PATH);
+
+    let mut f = fs::File::create(&tmp_path)?;
+    for line in lines {
+        writeln!(f, "{}", line)?;
+    }
+    f.sync_all()?;
+
+    fs::rename(&tmp_path, &*WAL_PATH)
+}
+
+// removes the entries of chats that were successfully persisted
+// every chat comes with the sequence number of the last entry its stored state reflects,
+// so that later ones (e.g. of messages still waiting in a batch) are kept
+// without persistent storage nothing is, so the log is kept for a start with working storage
+pub fn forget(marks: &[(i64, u64)]) {
+    if marks.is_empty() || !storage::is_persistent() {
+        return;
+    }
+
+    let _guard = WAL_LOCK.lock().unwrap();
+
+    let remaining: Vec<String> = read_lines()
+        .into_iter()
+        .filter(|line| match parse(line) {
+            Some((seq, chat_id, _)) => !marks
+                .iter()
+                .any(|(id, mark)| *id == chat_id && seq <= *mark),
+            None => false,
+        })
+        .collect();
+
+    let res = if remaining.is_empty() {
+        if Path::new(&*WAL_PATH).exists() {
+            fs::remove_file(&*WAL_PATH)
+        } else {
+            Ok(())
+        }
+    } else {
+        rewrite(&remaining)
+    };
+
+    if let Err(err) = res {
+        dbg!(err);
+    }
+}
+
+// removes all the entries of a chat, used when what it learnt is replaced or moved as a whole
+pub fn forget_chat(chat_id: i64) {
+    forget(&[(chat_id, u64::MAX)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_escaped() {
+        let text = "tab\there, backslash \\n there";
+        let line = format!("L\t3\t-42\t7\t{}", escape(text));
+
+        match parse(&line) {
+            Some((3, -42, Entry::Line(7, parsed))) => assert_eq!(parsed, text),
+            _ => panic!("Failed to parse {:?}", line),
+        }
+    }
+
+    #[test]
+    fn discord_message_ids_are_kept_whole() {
+        let snowflake = 1_234_567_890_123_456_789;
+        let line = format!("M\t6\t-42\t7\t{}\t0\t1\tsomeone\thello", snowflake);
+
+        match parse(&line) {
+            Some((6, -42, Entry::Message(msg))) => assert_eq!(msg.message_id, snowflake),
+            _ => panic!("Failed to parse {:?}", line),
+        }
+    }
+}
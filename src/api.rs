@@ -0,0 +1,253 @@
+use markov_bot_core::batch;
+use markov_bot_core::chain_wrapper::{self, ChainWrapper};
+
+use std::{convert::Infallible, env, net::SocketAddr, sync::Arc};
+
+use futures::StreamExt;
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_LENGTH},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+// the largest request body accepted
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+// the username messages fed through the API are learnt under, unless a request gives one
+const DEFAULT_USERNAME: &str = "api";
+
+// the body of a /generate request, all fields are optional
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct GenerateRequest {
+    // the word the phrase starts with
+    word: String,
+    // the same seed always yields the same phrase (as long as the chain doesn't change)
+    seed: Option<u64>,
+}
+
+// the body of a /generate response
+#[derive(Serialize)]
+struct GenerateResponse {
+    // None if the chain has nothing to say
+    text: Option<String>,
+}
+
+// the body of a /feed request
+#[derive(Deserialize)]
+struct FeedRequest {
+    text: String,
+    // the author of the message, used by per-user features such as /speak_like and /forget_me
+    #[serde(default)]
+    user_id: i64,
+    #[serde(default)]
+    username: Option<String>,
+}
+
+// extracts API_TOKENS (a comma-separated list of tokens) from std::env
+// every request has to carry one of them as a bearer token
+fn get_tokens() -> Vec<String> {
+    env::var("API_TOKENS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+// compares two strings in constant time, so that tokens can't be guessed one byte at a time
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+// checks if a request carries one of the accepted tokens
+fn is_authorized(req: &Request<Body>, tokens: &[String]) -> bool {
+    let token = match req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => match value.strip_prefix("Bearer ") {
+            Some(token) => token,
+            None => return false,
+        },
+        None => return false,
+    };
+
+    tokens
+        .iter()
+        .any(|accepted| constant_time_eq(accepted, token))
+}
+
+// builds a JSON response with a given status
+fn respond<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("Serialization failed");
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+// builds a JSON response describing an error
+fn error(status: StatusCode, msg: &str) -> Response<Body> {
+    respond(status, &json!({ "error": msg }))
+}
+
+// reads and deserializes the body of a request
+// an empty body is read as an empty JSON object
+async fn read_json<T: DeserializeOwned>(req: Request<Body>) -> Result<T, Response<Body>> {
+    let size = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    if size.is_some_and(|size| size > MAX_BODY_SIZE) {
+        return Err(error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "request body too large",
+        ));
+    }
+
+    // the header may be missing (e.g. with chunked encoding), so the limit is enforced while reading
+    let mut body = req.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        if (bytes.len() + chunk.len()) as u64 > MAX_BODY_SIZE {
+            return Err(error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "request body too large",
+            ));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let bytes: &[u8] = if bytes.is_empty() { b"{}" } else { &bytes };
+
+    serde_json::from_slice(bytes).map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))
+}
+
+// handles POST /chats/{id}/generate
+async fn generate(req: Request<Body>, chain: &Mutex<ChainWrapper>, chat_id: i64) -> Response<Body> {
+    let body = match read_json::<GenerateRequest>(req).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+
+    let text = match ChainWrapper::lock_loaded(chain, &[chat_id]).await.phrase(
+        chat_id,
+        body.word.trim(),
+        body.seed,
+    ) {
+        Ok(text) => text,
+        Err(e) => return error(StatusCode::SERVICE_UNAVAILABLE, &e.to_string()),
+    };
+
+    respond(StatusCode::OK, &GenerateResponse { text })
+}
+
+// handles POST /chats/{id}/feed
+// the message is learnt along with the ones received from the chat, so it may take a moment
+async fn feed(req: Request<Body>, chain: &Mutex<ChainWrapper>, chat_id: i64) -> Response<Body> {
+    let body = match read_json::<FeedRequest>(req).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+
+    if body.text.trim().is_empty() {
+        return error(StatusCode::BAD_REQUEST, "text is empty");
+    }
+
+    let incoming = batch::Incoming {
+        user_id: body.user_id,
+        username: body
+            .username
+            .unwrap_or_else(|| String::from(DEFAULT_USERNAME)),
+        message_id: 0,
+        is_forwarded: false,
+        is_edit: false,
+        text: body.text,
+    };
+    batch::feed(chain, chat_id, incoming).await;
+
+    respond(StatusCode::ACCEPTED, &json!({}))
+}
+
+// answers a single HTTP request
+async fn handle(
+    req: Request<Body>,
+    chain: Arc<Mutex<ChainWrapper>>,
+    tokens: Arc<Vec<String>>,
+) -> Result<Response<Body>, Infallible> {
+    if !is_authorized(&req, &tokens) {
+        return Ok(error(StatusCode::UNAUTHORIZED, "missing or invalid token"));
+    }
+
+    // the only routes are /chats/{id}/generate and /chats/{id}/feed
+    let path: Vec<&str> = req.uri().path().trim_matches('/').split('/').collect();
+    let (chat_id, action) = match path.as_slice() {
+        ["chats", chat_id, action] => match chat_id.parse::<i64>() {
+            Ok(chat_id) => (chat_id, action.to_string()),
+            Err(_) => return Ok(error(StatusCode::NOT_FOUND, "not a chat ID")),
+        },
+        _ => return Ok(error(StatusCode::NOT_FOUND, "no such endpoint")),
+    };
+
+    if req.method() != Method::POST {
+        return Ok(error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only POST is supported",
+        ));
+    }
+
+    if !chain_wrapper::is_chat_allowed(chat_id) {
+        return Ok(error(StatusCode::FORBIDDEN, "chat not allowed"));
+    }
+
+    let response = match action.as_str() {
+        "generate" => generate(req, &chain, chat_id).await,
+        "feed" => feed(req, &chain, chat_id).await,
+        _ => error(StatusCode::NOT_FOUND, "no such endpoint"),
+    };
+
+    Ok(response)
+}
+
+// starts an HTTP server exposing the generation API on API_PORT
+// does nothing if the variable is not set or empty, refuses to start without any API_TOKENS
+pub fn spawn_server(chain: Arc<Mutex<ChainWrapper>>) {
+    let port = match env::var("API_PORT") {
+        Err(_) => return,
+        Ok(port) if port.trim().is_empty() => return,
+        Ok(port) => port.parse::<u16>().expect("API_PORT is not a number"),
+    };
+
+    let tokens = get_tokens();
+    if tokens.is_empty() {
+        panic!("API_PORT is set, but API_TOKENS is empty");
+    }
+    let tokens = Arc::new(tokens);
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let chain = chain.clone();
+        let tokens = tokens.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle(req, chain.clone(), tokens.clone())
+            }))
+        }
+    });
+
+    tokio::spawn(async move {
+        if let Err(err) = Server::bind(&addr).serve(make_service).await {
+            dbg!(err);
+        }
+    });
+}
@@ -1,21 +1,368 @@
-use crate::chain_wrapper;
-use crate::utils::exponential_retry_async;
+use crate::health;
+use crate::messaging;
+use crate::stt;
+use crate::tts;
+use markov_bot_core::alerts;
+use markov_bot_core::batch;
+use markov_bot_core::chain_wrapper::{self, ChainWrapper};
+use markov_bot_core::chat_key;
+use markov_bot_core::error::Error;
+use markov_bot_core::feedback;
+use markov_bot_core::menu;
+use markov_bot_core::quiz;
+use markov_bot_core::utils::exponential_retry_async;
 
 use std::{
-    env,
+    env, fmt,
     sync::{Arc, Mutex},
-    time,
 };
 
+use lazy_static::lazy_static;
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+
 use tbot::prelude::*;
-use tbot::types::{
-    chat::{Id, Kind::*},
-    parameters::Text,
+use tbot::{
+    connectors::Connector,
+    contexts::{self, traits::ChatMethods},
+    types::{
+        callback,
+        chat::{member::Status, Id, Kind::*},
+        input_file::{Document, Sticker, Voice},
+        keyboard::inline::{Button, ButtonKind, Keyboard},
+        message,
+        parameters::Text,
+        user, Chat, Message,
+    },
 };
 
+// extracts OWNER_ID from std::env
+// owner-only commands are disabled if the variable is not set or empty
+fn get_owner_id() -> Option<i64> {
+    env::var("OWNER_ID")
+        .ok()
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| id.parse::<i64>().expect("OWNER_ID is not a number"))
+}
+
+// extracts the bot's own user ID from HTTP_TOKEN
+// tokens have the form "123456789:secret", the part before the colon being the ID
+fn get_bot_id() -> Option<i64> {
+    env::var("HTTP_TOKEN")
+        .ok()
+        .and_then(|token| token.split(':').next()?.parse::<i64>().ok())
+}
+
+lazy_static! {
+    // the Telegram user ID of the person running the bot
+    static ref OWNER_ID: Option<i64> = get_owner_id();
+    // the Telegram user ID of the bot itself
+    static ref BOT_ID: Option<i64> = get_bot_id();
+    // the bot's username (lowercase, without the leading @), fetched on first use
+    static ref BOT_USERNAME: Mutex<Option<String>> = Mutex::new(None);
+}
+
+// returns the bot's username (lowercase, without the leading @)
+// asks Telegram once and caches the answer
+async fn get_bot_username<C: Connector>(context: &contexts::Text<C>) -> Option<String> {
+    let cached = BOT_USERNAME.lock().unwrap().clone();
+    if cached.is_some() {
+        return cached;
+    }
+
+    let call_result =
+        exponential_retry_async(|| async { Ok(context.bot.get_me().call().await?) }).await;
+
+    match call_result {
+        Ok(me) => {
+            let username = me.username?.to_lowercase();
+            *BOT_USERNAME.lock().unwrap() = Some(username.clone());
+            Some(username)
+        }
+        Err(err) => {
+            dbg!(err);
+            None
+        }
+    }
+}
+
+// the account Telegram copies a channel's posts into the channel's discussion group as
+const CHANNEL_COPY_ID: i64 = 777_000;
+
+// the bot account anonymous group admins send messages as
+const ANONYMOUS_ADMIN_ID: i64 = 1_087_968_824;
+
+// returns the user ID and username a message is learnt under, along with whether it's a copy
+// of a channel post in the channel's discussion group (learnt even if forwarded messages aren't)
+// channel posts and their copies have no author, so they're learnt under the channel
+// anonymous admins have no author either, they're learnt without a username like users lacking one
+// (the "anonymous" filter skips them)
+// returns None for messages that aren't learnt, i.e. those sent by other bots
+fn get_author(from: Option<&user::User>, chat: &Chat) -> Option<(i64, String, bool)> {
+    let channel = String::from("channel");
+
+    match (from, &chat.kind) {
+        (Some(usr), _) => {
+            let user::Id(user_id) = usr.id;
+            match user_id {
+                CHANNEL_COPY_ID => Some((chain_wrapper::UNKNOWN_USER, channel, true)),
+                ANONYMOUS_ADMIN_ID => Some((chain_wrapper::UNKNOWN_USER, String::new(), false)),
+                _ if usr.is_bot => None,
+                _ => {
+                    let username = usr.username.clone().unwrap_or_default();
+                    Some((user_id, username, false))
+                }
+            }
+        }
+        (None, Channel { username, .. }) => Some((
+            chain_wrapper::UNKNOWN_USER,
+            username.clone().unwrap_or(channel),
+            false,
+        )),
+        (None, _) => None,
+    }
+}
+
+// checks if a message mentions the bot or replies to one of its messages
+async fn is_addressed_to_bot<C: Connector>(context: &contexts::Text<C>) -> bool {
+    let is_reply_to_bot = context
+        .reply_to
+        .as_ref()
+        .and_then(|msg| msg.from.as_ref())
+        .is_some_and(|usr| {
+            let user::Id(id) = usr.id;
+            Some(id) == *BOT_ID
+        });
+
+    if is_reply_to_bot {
+        return true;
+    }
+
+    if !context.text.value.contains('@') {
+        return false;
+    }
+
+    match get_bot_username(context).await {
+        Some(username) => context
+            .text
+            .value
+            .to_lowercase()
+            .contains(&format!("@{}", username)),
+        None => false,
+    }
+}
+
+// returns the chat member status of a command's sender
+// returns None for private chats and messages sent on behalf of the chat
+async fn get_sender_status<C: Connector>(context: &contexts::Text<C>) -> Option<Status> {
+    if let Private { .. } = &context.chat.kind {
+        return None;
+    }
+
+    match context.from.as_ref() {
+        Some(usr) => {
+            let call_result = exponential_retry_async(|| async {
+                Ok(context.get_chat_member(usr.id).call().await?)
+            })
+            .await;
+
+            match call_result {
+                Ok(member) => Some(member.status),
+                Err(err) => {
+                    dbg!(err);
+                    Some(Status::Left)
+                }
+            }
+        }
+        None => None,
+    }
+}
+
+// checks if a command was received from an admin or a group creator
+// commands received from private chats are always allowed
+async fn is_sender_admin<C: Connector>(context: &contexts::Text<C>) -> bool {
+    match get_sender_status(context).await {
+        Some(status) => status.is_administrator() || status.is_creator(),
+        None => true,
+    }
+}
+
+// checks if a command was received from a group creator
+// commands received from private chats are always allowed
+async fn is_sender_creator<C: Connector>(context: &contexts::Text<C>) -> bool {
+    match get_sender_status(context).await {
+        Some(status) => status.is_creator(),
+        None => true,
+    }
+}
+
+// checks if the sender of a command is the creator of another chat
+async fn is_sender_creator_of<C: Connector>(context: &contexts::Text<C>, chat_id: i64) -> bool {
+    match context.from.as_ref() {
+        Some(usr) => {
+            let call_result = exponential_retry_async(|| async {
+                Ok(context
+                    .bot
+                    .get_chat_member(Id(chat_id), usr.id)
+                    .call()
+                    .await?)
+            })
+            .await;
+
+            match call_result {
+                Ok(member) => member.status.is_creator(),
+                Err(err) => {
+                    dbg!(err);
+                    false
+                }
+            }
+        }
+        None => false,
+    }
+}
+
+// checks if a button was pressed by an admin or the creator of the chat its message is in
+// buttons pressed in private chats are always allowed
+async fn is_presser_admin<C: Connector>(
+    context: &contexts::DataCallback<C>,
+    message: &Message,
+) -> bool {
+    if let Private { .. } = &message.chat.kind {
+        return true;
+    }
+
+    let call_result = exponential_retry_async(|| async {
+        Ok(context
+            .bot
+            .get_chat_member(message.chat.id, context.from.id)
+            .call()
+            .await?)
+    })
+    .await;
+
+    match call_result {
+        Ok(member) => member.status.is_administrator() || member.status.is_creator(),
+        Err(err) => {
+            dbg!(err);
+            false
+        }
+    }
+}
+
+// tells a chat outside of the allow-list that the bot can't work there and leaves it
+// private chats can't be left, the bot just ignores them
+async fn leave_unlisted_chat<C: Connector>(bot: &tbot::Bot<C>, chat: &Chat) {
+    if let Private { .. } = &chat.kind {
+        return;
+    }
+
+    let msg = "[this bot is private and only works in selected chats, goodbye!]";
+    let call_result =
+        exponential_retry_async(|| async { Ok(bot.send_message(chat.id, msg).call().await?) })
+            .await;
+
+    if let Err(err) = call_result {
+        dbg!(err);
+    }
+
+    let call_result =
+        exponential_retry_async(|| async { Ok(bot.leave_chat(chat.id).call().await?) }).await;
+
+    if let Err(err) = call_result {
+        dbg!(err);
+    }
+}
+
+// sends a message to the chat an update came from, retrying if that fails
+// messages that can't be sent even then are reported to ADMIN_CHAT_ID
+async fn reply<'a, C, T>(context: &'a Arc<T>, msg: &'a str)
+where
+    C: Connector,
+    T: ChatMethods<'a, C>,
+{
+    let call_result =
+        exponential_retry_async(|| async { Ok(context.send_message(msg).call().await?) }).await;
+
+    if let Err(err) = call_result {
+        reply_failed(context.chat().id, err);
+    }
+}
+
+// logs a reply that couldn't be sent even after retrying and reports it to ADMIN_CHAT_ID
+fn reply_failed<E: fmt::Debug + fmt::Display>(chat_id: Id, err: E) {
+    alerts::report(&format!("Failed to reply in chat {}: {}", chat_id.0, err));
+    dbg!(err);
+}
+
+// logs an error a command ran into and returns the reply telling the user it failed
+pub fn failed(err: Error) -> String {
+    dbg!(err);
+    String::from(chain_wrapper::COMMAND_FAILED)
+}
+
+// turns the labels and callback data of a menu into inline keyboard buttons
+fn keyboard_rows(buttons: &menu::Buttons) -> Vec<Vec<Button<'_>>> {
+    buttons
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|(label, data)| Button::new(label, ButtonKind::CallbackData(data)))
+                .collect()
+        })
+        .collect()
+}
+
+// checks if a command was received from the bot owner
+fn is_sender_owner<C: Connector>(context: &contexts::Text<C>) -> bool {
+    match (context.from.as_ref(), *OWNER_ID) {
+        (Some(usr), Some(owner_id)) => {
+            let user::Id(id) = usr.id;
+            id == owner_id
+        }
+        _ => false,
+    }
+}
+
+// checks if the sender of a generating command has to wait before using another one
+// returns None if they don't, or the message to reply with otherwise
+async fn check_cooldown<C: Connector>(
+    context: &contexts::Text<C>,
+    chain: &AsyncMutex<ChainWrapper>,
+) -> Option<String> {
+    let usr = context.from.as_ref()?;
+    let Id(id) = context.chat.id;
+    let user::Id(user_id) = usr.id;
+    ChainWrapper::lock_loaded(chain, &[id])
+        .await
+        .check_cooldown(id, user_id)
+}
+
+// downloads the document a command was sent in reply to
+// returns Ok(None) if the command doesn't reply to a document
+async fn download_replied_document<C: Connector>(
+    context: &contexts::Text<C>,
+) -> Result<Option<Vec<u8>>, String> {
+    let document = match context.reply_to.as_ref().map(|msg| &msg.kind) {
+        Some(message::Kind::Document(document, _)) => document.as_ref(),
+        _ => return Ok(None),
+    };
+
+    let call_result = exponential_retry_async(|| async {
+        let file = context.bot.get_file(document).call().await?;
+        Ok(context.bot.download_file(&file).await?)
+    })
+    .await;
+
+    match call_result {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 // creates and returns an event loop for the bot
+// /shutdown sends a message through the given channel
 pub fn create(
-    chain: Arc<Mutex<chain_wrapper::ChainWrapper>>,
+    chain: Arc<AsyncMutex<ChainWrapper>>,
+    shutdown: mpsc::Sender<()>,
 ) -> tbot::EventLoop<impl tbot::connectors::Connector> {
     // create an empty event loop
     let mut bot = tbot::Bot::from_env("HTTP_TOKEN").event_loop();
@@ -25,20 +372,74 @@ pub fn create(
         let msg = "Hi! Add me to a group as an administrator to begin your \
                    Markov adventure.\nWant to know more? Use /help.";
 
-        let call_result =
-            exponential_retry_async(|| async { Ok(context.send_message(msg).call().await?) }).await;
-
-        if let Err(err) = call_result {
-            dbg!(err);
-        }
+        reply(&context, msg).await;
     });
 
     // add a callback for /help
     bot.help(|context| async move {
         let msg = "You can use the following commands:\n\n\
-                   /speak msg - generate a new phrase (starting from msg if possible)\n\
+                   /speak msg - generate a new phrase (starting from msg if possible, \
+                   answering the replied message if sent as a reply)\n\
+                   /speak_about word - generate a phrase containing a word\n\
+                   /speak_ending word - generate a phrase ending with a word\n\
+                   /story [number] - tell a story of 2-10 sentences (3 by default)\n\
+                   /sticker - send one of the chat's popular stickers\n\
+                   /speak_seed number [msg] - like /speak, but the same number always gives \
+                   the same phrase\n\
+                   /speak_like @user - generate a phrase in the style of a chat member \
+                   (or reply to their message)\n\
+                   /speak_credits msg - like /speak, but tells whose words it used the most\n\
+                   /speak_voice msg - like /speak, but sends the phrase as a voice message\n\
+                   /speak2 - generate two phrases and let the chat vote for the better one\n\
+                   /speak_global msg - generate a phrase from what all sharing chats taught the bot\n\
+                   /quiz - guess who said one of the learnt messages\n\
+                   /quiz_scores - show who's the best at /quiz\n\
+                   /stats - show what the bot has learnt so far\n\
+                   /top_words [number] - list the most common words (10 by default)\n\
+                   /word_info word - show how often a word was learnt and what follows it\n\
+                   /settings - change the options below using buttons\n\
                    /toggle_learning - enable / disable learning\n\
-                   /clear_data - delete ALL data (irreversible!)\n\n\
+                   /set_order number - make phrases more coherent (2-3) or more random (1), \
+                   asks for confirmation if imported data would be lost\n\
+                   /blacklist add|remove phrase - stop / resume learning messages containing a phrase\n\
+                   /blacklist list - list blacklisted phrases\n\
+                   /censor add|remove word - stop / resume saying a word\n\
+                   /censor list - list censored words\n\
+                   /ignore @user - never learn a member's messages (or reply to one of them)\n\
+                   /unignore @user - learn a member's messages again\n\
+                   /strip [urls|mentions|hashtags|commands|symbols|forwarded|anonymous] - list / toggle text filters\n\
+                   /min_words number - ignore messages shorter than that\n\
+                   /length min max - set the length (in words) of generated messages\n\
+                   /set_temperature number - make phrases more creative (above 1) \
+                   or more predictable (below 1)\n\
+                   /sticker_chance percent - reply to that many stickers with a sticker\n\
+                   /speak_cooldown seconds - make each member wait that long between \
+                   generated messages (0 to disable)\n\
+                   /daily HH:MM [UTC offset] - post a phrase of the day at that time\n\
+                   /daily off - stop posting the phrase of the day\n\
+                   /clear_data - delete ALL data (asks for confirmation first)\n\
+                   /undo_clear - bring back the data deleted by /clear_data (within 24 hours)\n\
+                   /restore [number] - list backups / revert to one of them\n\
+                   /snapshot [name] - list snapshots / save the chain under that name\n\
+                   /snapshot delete name - delete a snapshot\n\
+                   /rollback name - revert to a snapshot\n\
+                   /export - download everything the bot has learnt as a JSON file\n\
+                   /import - reply to an exported file or a text file to teach the bot its contents\n\
+                   /import_history - reply to a Telegram Desktop chat export (result.json) to learn it\n\
+                   /merge_from chat_id - learn everything the bot knows from another chat you own\n\
+                   /forget_me - remove everything the bot has learnt from you in this chat\n\
+                   /optin - let the bot learn your messages when it asks for consent first\n\
+                   /optout - stop the bot from learning your messages and forget them\n\
+                   /optin_only on|off - only learn from members who sent /optin\n\
+                   /privacy - see what the bot stores about this chat\n\
+                   /prune_rare [days] - forget word pairs seen only once that aren't seen \
+                   again within that many days\n\
+                   /max_states number - limit the size of the chain, evicting the least used \
+                   word sequences (0 removes the limit, bot owner only)\n\
+                   /share_global on|off - share / stop sharing what the bot learns here with \
+                   other chats\n\
+                   /feedback on|off - add / remove buttons for rating what /speak says\n\
+                   /retention days|off - wipe everything learnt once it's that many days old\n\n\
                    Any more questions? Feature suggestions? Contact @Vyaatu or visit \
                    <a href=\"https://github.com/vyatu/markov_bot\">project's GitHub page</a>";
 
@@ -48,26 +449,72 @@ pub fn create(
         .await;
 
         if let Err(err) = call_result {
-            dbg!(err);
+            reply_failed(context.chat.id, err);
         }
     });
 
     {
         let ch = Arc::clone(&chain);
         // add a callback for /speak msg
+        // sent in reply to a message, /speak answers that message using its words
         bot.command("speak", move |context| {
             let chain = ch.clone();
             async move {
                 let Id(id) = context.chat.id;
-                let msg = chain.lock().unwrap().generate(id, &context.text.value);
+
+                let replied = context.reply_to.as_ref().map(|msg| {
+                    let text = match &msg.kind {
+                        message::Kind::Text(text) => text.value.as_str(),
+                        _ => "",
+                    };
+                    (msg.id, text)
+                });
+
+                let phrase = match check_cooldown(&context, &chain).await {
+                    Some(msg) => Err(msg),
+                    None => match replied {
+                        Some((_, text)) if context.text.value.trim().is_empty() => {
+                            ChainWrapper::lock_loaded(&chain, &[id])
+                                .await
+                                .reply(id, text)
+                                .ok_or_else(|| String::from("[no phrases learnt]"))
+                        }
+                        _ => ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .try_generate(id, &context.text.value, None)
+                            .unwrap_or_else(|err| Err(failed(err))),
+                    },
+                };
+
+                // only generated phrases can be rated, not the messages explaining why there's none
+                // the phrase is tracked before it's sent, so that it can be rated as soon as it shows up
+                let buttons = match &phrase {
+                    Ok(msg) => ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .feedback_buttons(id, msg),
+                    Err(_) => None,
+                };
+                let msg = phrase.unwrap_or_else(|msg| msg);
 
                 let call_result = exponential_retry_async(|| async {
-                    Ok(context.send_message(&msg).call().await?)
+                    let mut call = context.send_message(&msg);
+                    if let Some((message_id, _)) = replied {
+                        call = call.reply_to_message_id(message_id);
+                    }
+
+                    match &buttons {
+                        Some(buttons) => {
+                            let rows = keyboard_rows(buttons);
+                            let rows: Vec<&[Button]> = rows.iter().map(Vec::as_slice).collect();
+                            Ok(call.reply_markup(Keyboard::new(&rows)).call().await?)
+                        }
+                        None => Ok(call.call().await?),
+                    }
                 })
                 .await;
 
                 if let Err(err) = call_result {
-                    dbg!(err);
+                    reply_failed(context.chat.id, err);
                 }
             }
         });
@@ -75,85 +522,312 @@ pub fn create(
 
     {
         let ch = Arc::clone(&chain);
-        // add a callback for /toggle_learning
-        bot.command("toggle_learning", move |context| {
+        // add a callback for /speak2
+        bot.command("speak2", move |context| {
             let chain = ch.clone();
             async move {
-                let is_allowed = if let Private { .. } = &context.chat.kind {
-                    // the command was received from a private chat
-                    true
-                } else {
-                    // the command was received from an admin or a group creator
-                    match context.from.as_ref() {
-                        Some(usr) => {
-                            let status =
-                                context.get_chat_member(usr.id).call().await.unwrap().status;
-                            status.is_administrator() || status.is_creator()
-                        }
-                        None => true,
+                let Id(id) = context.chat.id;
+                let duel = match check_cooldown(&context, &chain).await {
+                    Some(msg) => Err(msg),
+                    None => ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .speak2(id)
+                        .unwrap_or_else(|err| Err(failed(err))),
+                };
+
+                let (round, candidates, buttons) = match duel {
+                    Ok(duel) => duel,
+                    Err(msg) => {
+                        reply(&context, &msg).await;
+                        return;
                     }
                 };
 
-                let mut msg = String::new();
+                // every candidate is a message of its own, so that the loser can be deleted
+                // the IDs of the messages are kept by the candidate they belong to
+                let mut sent = [None, None];
+                for (i, (text, buttons)) in candidates.iter().zip(buttons.iter()).enumerate() {
+                    let call_result = exponential_retry_async(|| async {
+                        let rows = keyboard_rows(buttons);
+                        let rows: Vec<&[Button]> = rows.iter().map(Vec::as_slice).collect();
+                        Ok(context
+                            .send_message(text)
+                            .reply_markup(Keyboard::new(&rows))
+                            .call()
+                            .await?)
+                    })
+                    .await;
 
-                // execute or refuse the command
-                if is_allowed {
-                    let Id(id) = context.chat.id;
-                    msg.push_str(&chain.lock().unwrap().toggle_learning(id));
-                } else {
-                    msg.push_str("[only the chat owner and admins can do that]");
+                    match call_result {
+                        Ok(message) => sent[i] = Some(message.id),
+                        Err(err) => {
+                            reply_failed(context.chat.id, err);
+                        }
+                    }
                 }
 
-                let call_result = exponential_retry_async(|| async {
-                    Ok(context.send_message(&msg).call().await?)
-                })
-                .await;
-
-                if let Err(err) = call_result {
-                    dbg!(err);
+                // a duel missing one of its phrases can't be voted on, so it ends right away
+                if sent.iter().any(Option::is_none) {
+                    ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .settle_duel(id, round);
+                    return;
                 }
+
+                // the timer runs in a task of its own, so that the handler returns right away
+                // if the bot restarts before it's up, the duel is settled later (see feedback::Duel::is_abandoned)
+                tokio::spawn(async move {
+                    tokio::time::delay_for(feedback::DUEL_TIME).await;
+
+                    let winner = match ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .settle_duel(id, round)
+                    {
+                        Some(winner) => winner,
+                        None => return,
+                    };
+
+                    if let (Some(won), Some(lost)) = (sent[winner], sent[1 - winner]) {
+                        let call_result = exponential_retry_async(|| async {
+                            Ok(context
+                                .bot
+                                .delete_message(context.chat.id, lost)
+                                .call()
+                                .await?)
+                        })
+                        .await;
+
+                        if let Err(err) = call_result {
+                            reply_failed(context.chat.id, err);
+                        }
+
+                        // the phrase that stays loses its button
+                        let call_result = exponential_retry_async(|| async {
+                            Ok(context
+                                .bot
+                                .edit_message_reply_markup(context.chat.id, won, Keyboard::new(&[]))
+                                .call()
+                                .await?)
+                        })
+                        .await;
+
+                        if let Err(err) = call_result {
+                            reply_failed(context.chat.id, err);
+                        }
+                    }
+                });
             }
         });
     }
 
     {
         let ch = Arc::clone(&chain);
-        // add a callback for /clear_data
-        bot.command("clear_data", move |context| {
+        // add a callback for /speak_seed number [msg]
+        bot.command("speak_seed", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let mut args = context.text.value.trim().splitn(2, char::is_whitespace);
+                let seed = args.next().unwrap_or("").parse::<u64>();
+                let token = args.next().unwrap_or("").trim();
+
+                let msg = match seed {
+                    Ok(seed) => match check_cooldown(&context, &chain).await {
+                        Some(msg) => msg,
+                        None => ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .generate(id, token, Some(seed))
+                            .unwrap_or_else(failed),
+                    },
+                    Err(_) => String::from("[usage: /speak_seed number [msg]]"),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /speak_global msg
+        bot.command("speak_global", move |context| {
+            let chain = ch.clone();
+            async move {
+                let msg = match check_cooldown(&context, &chain).await {
+                    Some(msg) => msg,
+                    None => ChainWrapper::lock_loaded(&chain, &[chat_key::GLOBAL])
+                        .await
+                        .speak_global(&context.text.value)
+                        .unwrap_or_else(failed),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /speak_credits msg
+        bot.command("speak_credits", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let msg = match check_cooldown(&context, &chain).await {
+                    Some(msg) => msg,
+                    None => ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .speak_credits(id, &context.text.value)
+                        .unwrap_or_else(failed),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /speak_voice msg, reading the phrase out loud if TTS_URL is set
+        bot.command("speak_voice", move |context| {
             let chain = ch.clone();
             async move {
-                let is_allowed = if let Private { .. } = &context.chat.kind {
-                    // the command was received from a private chat
-                    true
+                let Id(id) = context.chat.id;
+                let res = if !tts::is_enabled() {
+                    Err(String::from("[voice replies are not set up]"))
                 } else {
-                    // the command was received from a group creator
-                    match context.from.as_ref() {
-                        Some(usr) => {
-                            let status =
-                                context.get_chat_member(usr.id).call().await.unwrap().status;
-                            status.is_creator()
+                    match check_cooldown(&context, &chain).await {
+                        Some(msg) => Err(msg),
+                        None => {
+                            let phrase = ChainWrapper::lock_loaded(&chain, &[id]).await.phrase(
+                                id,
+                                &context.text.value,
+                                None,
+                            );
+
+                            match phrase {
+                                Ok(Some(phrase)) => tts::synthesize(&phrase).await.map_err(|err| {
+                                    dbg!(err);
+                                    String::from("[the phrase couldn't be read out loud]")
+                                }),
+                                Ok(None) => Err(String::from("[no phrases learnt]")),
+                                Err(err) => Err(failed(err)),
+                            }
                         }
-                        None => true,
                     }
                 };
 
-                let mut msg = String::new();
+                match res {
+                    Ok(bytes) => {
+                        let call_result = exponential_retry_async(|| async {
+                            Ok(context.send_voice(Voice::bytes(&bytes)).call().await?)
+                        })
+                        .await;
 
-                // execute or refuse the command
-                if is_allowed {
-                    let Id(id) = context.chat.id;
-                    msg.push_str(&chain.lock().unwrap().clear_data(id));
-                } else {
-                    msg.push_str("[only the chat owner can do that]");
+                        if let Err(err) = call_result {
+                            reply_failed(context.chat.id, err);
+                        }
+                    }
+                    Err(msg) => reply(&context, &msg).await,
                 }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /speak_about word
+        bot.command("speak_about", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let msg = match check_cooldown(&context, &chain).await {
+                    Some(msg) => msg,
+                    None => ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .speak_about(id, &context.text.value)
+                        .unwrap_or_else(failed),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /speak_ending word
+        bot.command("speak_ending", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let msg = match check_cooldown(&context, &chain).await {
+                    Some(msg) => msg,
+                    None => ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .speak_ending(id, &context.text.value)
+                        .unwrap_or_else(failed),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /story [number]
+        bot.command("story", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let arg = context.text.value.trim();
+
+                let sentences = if arg.is_empty() {
+                    Ok(None)
+                } else {
+                    arg.parse::<usize>().map(Some)
+                };
+
+                let msg = match sentences {
+                    Ok(sentences) => match check_cooldown(&context, &chain).await {
+                        Some(msg) => msg,
+                        None => ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .story(id, sentences)
+                            .unwrap_or_else(failed),
+                    },
+                    Err(_) => String::from("[usage: /story [number]]"),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /sticker
+        bot.command("sticker", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let res = ChainWrapper::lock_loaded(&chain, &[id])
+                    .await
+                    .sticker(id)
+                    .unwrap_or_else(|err| Err(failed(err)));
 
                 let call_result = exponential_retry_async(|| async {
-                    Ok(context.send_message(&msg).call().await?)
+                    match &res {
+                        Ok(file_id) => context.send_sticker(Sticker::id(file_id)).call().await?,
+                        Err(msg) => context.send_message(msg).call().await?,
+                    };
+                    Ok(())
                 })
                 .await;
 
                 if let Err(err) = call_result {
-                    dbg!(err);
+                    reply_failed(context.chat.id, err);
                 }
             }
         });
@@ -161,46 +835,1810 @@ pub fn create(
 
     {
         let ch = Arc::clone(&chain);
-        // add a callback for non-command messages
-        bot.text(move |context| {
+        // add a callback for /speak_like @user
+        bot.command("speak_like", move |context| {
             let chain = ch.clone();
             async move {
-                if let Some(from) = &context.from {
-                    if let Some(_) = from.username {
-                        let Id(id) = context.chat.id;
-                        chain.lock().unwrap().feed(id, &context.text.value);
+                let Id(id) = context.chat.id;
+                let username = context.text.value.trim();
+
+                // a reply points to the user directly, otherwise the username is looked up
+                let user_id = context
+                    .reply_to
+                    .as_ref()
+                    .and_then(|msg| msg.from.as_ref())
+                    .map(|usr| {
+                        let user::Id(user_id) = usr.id;
+                        user_id
+                    });
+
+                let msg = if user_id.is_none() && username.is_empty() {
+                    String::from("[usage: /speak_like @user, or reply to someone's message]")
+                } else {
+                    match check_cooldown(&context, &chain).await {
+                        Some(msg) => msg,
+                        None => ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .speak_like(id, user_id, username)
+                            .unwrap_or_else(failed),
                     }
-                }
+                };
+
+                reply(&context, &msg).await;
             }
         });
     }
 
     {
-        // set update frequency
-        let upd_freq = env::var("UPDATE_FREQUENCY")
-            .expect("UPDATE_FREQUENCY not set")
-            .parse::<u64>()
-            .unwrap();
-
         let ch = Arc::clone(&chain);
-        let dur = time::Duration::from_secs(upd_freq * 60);
-        let now = Arc::new(Mutex::new(time::SystemTime::now()));
-
-        // add a callback for periodic serialization
-        bot.after_update(move |_| {
+        // add a callback for /ignore @user
+        // sent in reply to a message, /ignore applies to its author
+        bot.command("ignore", move |context| {
             let chain = ch.clone();
-            let now = now.clone();
             async move {
-                let mut now = now.lock().unwrap();
-                // executes only if the last update was performed sufficiently long ago
-                if now.elapsed().unwrap() > dur {
-                    *now = time::SystemTime::now();
-                    chain.lock().unwrap().prune();
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let username = context.text.value.trim();
+                    let user_id = context
+                        .reply_to
+                        .as_ref()
+                        .and_then(|msg| msg.from.as_ref())
+                        .map(|usr| {
+                            let user::Id(user_id) = usr.id;
+                            user_id
+                        });
+
+                    if user_id.is_none() && username.is_empty() {
+                        msg.push_str("[usage: /ignore @user, or reply to someone's message]");
+                    } else {
+                        msg.push_str(
+                            &ChainWrapper::lock_loaded(&chain, &[id])
+                                .await
+                                .set_ignored(id, user_id, username, true)
+                                .unwrap_or_else(failed),
+                        );
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /unignore @user
+        // sent in reply to a message, /unignore applies to its author
+        bot.command("unignore", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let username = context.text.value.trim();
+                    let user_id = context
+                        .reply_to
+                        .as_ref()
+                        .and_then(|msg| msg.from.as_ref())
+                        .map(|usr| {
+                            let user::Id(user_id) = usr.id;
+                            user_id
+                        });
+
+                    if user_id.is_none() && username.is_empty() {
+                        msg.push_str("[usage: /unignore @user, or reply to someone's message]");
+                    } else {
+                        msg.push_str(
+                            &ChainWrapper::lock_loaded(&chain, &[id])
+                                .await
+                                .set_ignored(id, user_id, username, false)
+                                .unwrap_or_else(failed),
+                        );
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /stats
+        bot.command("stats", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let msg = ChainWrapper::lock_loaded(&chain, &[id])
+                    .await
+                    .stats(id)
+                    .unwrap_or_else(failed);
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /top_words [n]
+        bot.command("top_words", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let arg = context.text.value.trim();
+
+                let msg = if arg.is_empty() {
+                    ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .top_words(id, None)
+                        .unwrap_or_else(failed)
+                } else {
+                    match arg.parse::<usize>() {
+                        Ok(n) => ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .top_words(id, Some(n))
+                            .unwrap_or_else(failed),
+                        Err(_) => String::from("[usage: /top_words [number]]"),
+                    }
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /word_info word
+        bot.command("word_info", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let msg = ChainWrapper::lock_loaded(&chain, &[id])
+                    .await
+                    .word_info(id, context.text.value.trim())
+                    .unwrap_or_else(failed);
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /toggle_learning
+        bot.command("toggle_learning", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    msg.push_str(
+                        &ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .toggle_learning(id)
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /set_order number [confirm]
+        bot.command("set_order", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let mut args = context.text.value.split_whitespace();
+                    let order = args.next().map(str::parse::<usize>);
+                    let is_confirmed = args.next() == Some("confirm");
+
+                    match order {
+                        Some(Ok(order)) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::set_order(&chain, id, order, is_confirmed)
+                                    .await
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        _ => msg.push_str("[usage: /set_order number]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /blacklist add|remove|list [phrase]
+        bot.command("blacklist", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let mut args = context.text.value.trim().splitn(2, char::is_whitespace);
+                    let action = args.next().unwrap_or("");
+                    let phrase = args.next().unwrap_or("");
+
+                    msg.push_str(
+                        &ChainWrapper::blacklist(&chain, id, action, phrase)
+                            .await
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /censor add|remove|list [word]
+        bot.command("censor", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let mut args = context.text.value.trim().splitn(2, char::is_whitespace);
+                    let action = args.next().unwrap_or("");
+                    let word = args.next().unwrap_or("");
+
+                    msg.push_str(
+                        &ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .censor(id, action, word)
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /settings
+        bot.command("settings", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                // execute or refuse the command
+                let menu = if is_allowed {
+                    let Id(id) = context.chat.id;
+                    ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .settings_menu(id)
+                        .map_err(failed)
+                } else {
+                    Err(String::from("[only the chat owner and admins can do that]"))
+                };
+
+                let call_result = exponential_retry_async(|| async {
+                    match &menu {
+                        Ok((text, buttons)) => {
+                            let rows = keyboard_rows(buttons);
+                            let rows: Vec<&[Button]> = rows.iter().map(Vec::as_slice).collect();
+                            context
+                                .send_message(text)
+                                .reply_markup(Keyboard::new(&rows))
+                                .call()
+                                .await?
+                        }
+                        Err(msg) => context.send_message(msg).call().await?,
+                    };
+                    Ok(())
+                })
+                .await;
+
+                if let Err(err) = call_result {
+                    reply_failed(context.chat.id, err);
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for the buttons of the /settings menu and /quiz
+        bot.data_callback(move |context| {
+            let chain = ch.clone();
+            async move {
+                let message = match &context.origin {
+                    callback::Origin::Message(message) => message,
+                    _ => return,
+                };
+
+                // a notification shown to the person pressing the button, if any
+                let mut notification = None;
+
+                // anyone can answer a quiz or vote on a phrase, only admins can change the settings
+                if quiz::is_quiz_data(&context.data) {
+                    let Id(id) = message.chat.id;
+                    let user::Id(user_id) = context.from.id;
+                    notification = Some(
+                        ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .answer_quiz(id, user_id, &context.data)
+                            .unwrap_or_else(failed),
+                    );
+                } else if feedback::is_duel_data(&context.data) {
+                    let Id(id) = message.chat.id;
+                    let user::Id(user_id) = context.from.id;
+                    notification = Some(
+                        ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .vote_duel(id, user_id, &context.data),
+                    );
+                } else if feedback::is_feedback_data(&context.data) {
+                    let Id(id) = message.chat.id;
+                    let user::Id(user_id) = context.from.id;
+                    notification = Some(
+                        ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .rate_generation(id, user_id, &context.data)
+                            .unwrap_or_else(failed),
+                    );
+                } else if is_presser_admin(&context, message).await {
+                    let Id(id) = message.chat.id;
+                    let res = ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .menu_action(id, &context.data)
+                        .unwrap_or_else(|err| Err(failed(err)));
+
+                    // Telegram refuses edits that don't change anything
+                    let current = match &message.kind {
+                        message::Kind::Text(text) => text.value.as_str(),
+                        _ => "",
+                    };
+
+                    let call_result = exponential_retry_async(|| async {
+                        match &res {
+                            Ok(Some((text, buttons))) if text != current => {
+                                let rows = keyboard_rows(buttons);
+                                let rows: Vec<&[Button]> = rows.iter().map(Vec::as_slice).collect();
+                                context
+                                    .bot
+                                    .edit_message_text(message.chat.id, message.id, text)
+                                    .reply_markup(Keyboard::new(&rows))
+                                    .call()
+                                    .await?;
+                            }
+                            Ok(None) => {
+                                context
+                                    .bot
+                                    .edit_message_text(
+                                        message.chat.id,
+                                        message.id,
+                                        "[settings saved]",
+                                    )
+                                    .call()
+                                    .await?;
+                            }
+                            _ => (),
+                        };
+                        Ok(())
+                    })
+                    .await;
+
+                    if let Err(err) = call_result {
+                        dbg!(err);
+                    }
+
+                    if let Err(msg) = res {
+                        notification = Some(msg);
+                    }
+                } else {
+                    notification =
+                        Some(String::from("[only the chat owner and admins can do that]"));
+                }
+
+                // stop the button's loading animation
+                let call_result = exponential_retry_async(|| async {
+                    match &notification {
+                        Some(msg) => context.notify(msg).call().await?,
+                        None => context.ignore().call().await?,
+                    };
+                    Ok(())
+                })
+                .await;
+
+                if let Err(err) = call_result {
+                    dbg!(err);
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /quiz
+        bot.command("quiz", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let quiz = match check_cooldown(&context, &chain).await {
+                    Some(msg) => Err(msg),
+                    None => ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .quiz(id)
+                        .unwrap_or_else(|err| Err(failed(err))),
+                };
+
+                let call_result = exponential_retry_async(|| async {
+                    match &quiz {
+                        Ok((text, buttons)) => {
+                            let rows = keyboard_rows(buttons);
+                            let rows: Vec<&[Button]> = rows.iter().map(Vec::as_slice).collect();
+                            context
+                                .send_message(text)
+                                .reply_markup(Keyboard::new(&rows))
+                                .call()
+                                .await?
+                        }
+                        Err(msg) => context.send_message(msg).call().await?,
+                    };
+                    Ok(())
+                })
+                .await;
+
+                if let Err(err) = call_result {
+                    reply_failed(context.chat.id, err);
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /quiz_scores
+        bot.command("quiz_scores", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let msg = ChainWrapper::lock_loaded(&chain, &[id])
+                    .await
+                    .quiz_scores(id)
+                    .unwrap_or_else(failed);
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /strip [filter]
+        bot.command("strip", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let name = context.text.value.trim().to_lowercase();
+                    msg.push_str(
+                        &ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .toggle_filter(id, &name)
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /min_words number
+        bot.command("min_words", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    match context.text.value.trim().parse::<usize>() {
+                        Ok(min_words) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_min_words(id, min_words)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Err(_) => msg.push_str("[usage: /min_words number]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /length min max
+        bot.command("length", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let args: Vec<Result<usize, _>> = context
+                        .text
+                        .value
+                        .split_whitespace()
+                        .map(|arg| arg.parse::<usize>())
+                        .collect();
+
+                    match args.as_slice() {
+                        [Ok(min_words), Ok(max_words)] => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_output_length(id, *min_words, *max_words)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        _ => msg.push_str("[usage: /length min max]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /set_temperature number
+        bot.command("set_temperature", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    match context.text.value.trim().parse::<f64>() {
+                        Ok(temperature) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_temperature(id, temperature)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Err(_) => msg.push_str("[usage: /set_temperature number]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /speak_cooldown seconds
+        bot.command("speak_cooldown", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    match context.text.value.trim().parse::<u64>() {
+                        Ok(seconds) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_speak_cooldown(id, seconds)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Err(_) => msg.push_str("[usage: /speak_cooldown seconds]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /sticker_chance percent
+        bot.command("sticker_chance", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let arg = context.text.value.trim().trim_end_matches('%');
+                    match arg.parse::<u32>() {
+                        Ok(chance) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_sticker_chance(id, chance)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Err(_) => msg.push_str("[usage: /sticker_chance percent]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /daily HH:MM [UTC offset] | off
+        bot.command("daily", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    msg.push_str(
+                        &ChainWrapper::set_daily(&chain, id, &context.text.value)
+                            .await
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /clear_data [confirm]
+        bot.command("clear_data", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_creator(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let user_id = context.from.as_ref().map_or(0, |usr| {
+                        let user::Id(user_id) = usr.id;
+                        user_id
+                    });
+                    msg.push_str(
+                        &ChainWrapper::clear_data(&chain, id, user_id, &context.text.value)
+                            .await
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the chat owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /undo_clear
+        bot.command("undo_clear", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_creator(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    msg.push_str(
+                        &ChainWrapper::undo_clear(&chain, id)
+                            .await
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the chat owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /restore [number]
+        bot.command("restore", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_creator(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let arg = context.text.value.trim();
+
+                    if arg.is_empty() {
+                        msg.push_str(
+                            &ChainWrapper::list_backups(&chain, id)
+                                .await
+                                .unwrap_or_else(failed),
+                        );
+                    } else {
+                        match arg.parse::<usize>() {
+                            Ok(number) => msg.push_str(
+                                &ChainWrapper::restore(&chain, id, number)
+                                    .await
+                                    .unwrap_or_else(failed),
+                            ),
+                            Err(_) => msg.push_str("[usage: /restore [number]]"),
+                        }
+                    }
+                } else {
+                    msg.push_str("[only the chat owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /snapshot [name | delete name]
+        bot.command("snapshot", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_creator(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let args: Vec<&str> = context.text.value.split_whitespace().collect();
+
+                    match args.as_slice() {
+                        [] => msg.push_str(
+                            &ChainWrapper::list_snapshots(id)
+                                .await
+                                .unwrap_or_else(failed),
+                        ),
+                        ["delete", name] => msg.push_str(
+                            &ChainWrapper::delete_snapshot(&chain, id, name)
+                                .await
+                                .unwrap_or_else(failed),
+                        ),
+                        [name] => msg.push_str(
+                            &ChainWrapper::snapshot(&chain, id, name)
+                                .await
+                                .unwrap_or_else(failed),
+                        ),
+                        _ => msg.push_str("[usage: /snapshot [name | delete name]]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /rollback [name]
+        bot.command("rollback", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_creator(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let arg = context.text.value.trim();
+
+                    if arg.is_empty() {
+                        msg.push_str(
+                            &ChainWrapper::list_snapshots(id)
+                                .await
+                                .unwrap_or_else(failed),
+                        );
+                    } else {
+                        msg.push_str(
+                            &ChainWrapper::rollback(&chain, id, arg)
+                                .await
+                                .unwrap_or_else(failed),
+                        );
+                    }
+                } else {
+                    msg.push_str("[only the chat owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /export
+        bot.command("export", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_owner(&context) || is_sender_creator(&context).await;
+
+                // execute or refuse the command
+                let res = if is_allowed {
+                    let Id(id) = context.chat.id;
+                    ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .export(id)
+                        .unwrap_or_else(|err| Err(failed(err)))
+                } else {
+                    Err(String::from("[only the chat owner can do that]"))
+                };
+
+                match res {
+                    Ok(bytes) => {
+                        let Id(id) = context.chat.id;
+                        let name = format!("chain_{}.json", id);
+
+                        let call_result = exponential_retry_async(|| async {
+                            Ok(context
+                                .send_document(Document::bytes(&name, &bytes))
+                                .call()
+                                .await?)
+                        })
+                        .await;
+
+                        if let Err(err) = call_result {
+                            reply_failed(context.chat.id, err);
+                        }
+                    }
+                    Err(msg) => reply(&context, &msg).await,
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /import
+        bot.command("import", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_owner(&context) || is_sender_creator(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    match download_replied_document(&context).await {
+                        Ok(Some(bytes)) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::import(&chain, id, &bytes)
+                                    .await
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Ok(None) => {
+                            msg.push_str("[reply to an exported chain or a text file with /import]")
+                        }
+                        Err(err) => {
+                            dbg!(err);
+                            msg.push_str("[couldn't download the file, please try again later]");
+                        }
+                    }
+                } else {
+                    msg.push_str("[only the chat owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /import_history
+        bot.command("import_history", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_owner(&context) || is_sender_creator(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    match download_replied_document(&context).await {
+                        Ok(Some(bytes)) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::import_history(&chain, id, &bytes, *BOT_ID)
+                                    .await
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Ok(None) => msg.push_str(
+                            "[reply to a chat export (result.json) with /import_history]",
+                        ),
+                        Err(err) => {
+                            dbg!(err);
+                            msg.push_str("[couldn't download the file, please try again later]");
+                        }
+                    }
+                } else {
+                    msg.push_str("[only the chat owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /merge_from chat_id
+        bot.command("merge_from", move |context| {
+            let chain = ch.clone();
+            async move {
+                let mut msg = String::new();
+
+                match context.text.value.trim().parse::<i64>() {
+                    Ok(source_id) => {
+                        // the sender has to own both chats, unless they run the bot
+                        let is_allowed = is_sender_owner(&context)
+                            || (is_sender_creator(&context).await
+                                && is_sender_creator_of(&context, source_id).await);
+
+                        // execute or refuse the command
+                        if is_allowed {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::merge_from(&chain, id, source_id)
+                                    .await
+                                    .unwrap_or_else(failed),
+                            );
+                        } else {
+                            msg.push_str("[only the owner of both chats can do that]");
+                        }
+                    }
+                    Err(_) => msg.push_str("[usage: /merge_from chat_id]"),
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /forget_me
+        bot.command("forget_me", move |context| {
+            let chain = ch.clone();
+            async move {
+                let msg = match &context.from {
+                    Some(from) => {
+                        let Id(id) = context.chat.id;
+                        let user::Id(user_id) = from.id;
+                        ChainWrapper::forget_user(&chain, id, user_id)
+                            .await
+                            .unwrap_or_else(failed)
+                    }
+                    None => String::from("[anonymous messages can't be forgotten]"),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /optin
+        bot.command("optin", move |context| {
+            let chain = ch.clone();
+            async move {
+                let msg = match &context.from {
+                    Some(from) => {
+                        let Id(id) = context.chat.id;
+                        let user::Id(user_id) = from.id;
+                        ChainWrapper::lock_loaded(&chain, &[id])
+                            .await
+                            .opt_in(id, user_id)
+                            .unwrap_or_else(failed)
+                    }
+                    None => String::from("[anonymous members can't opt in]"),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /optout
+        bot.command("optout", move |context| {
+            let chain = ch.clone();
+            async move {
+                let msg = match &context.from {
+                    Some(from) => {
+                        let Id(id) = context.chat.id;
+                        let user::Id(user_id) = from.id;
+                        ChainWrapper::opt_out(&chain, id, user_id)
+                            .await
+                            .unwrap_or_else(failed)
+                    }
+                    None => String::from("[anonymous members can't opt out]"),
+                };
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /optin_only on|off
+        bot.command("optin_only", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let opt_in_only = match context.text.value.trim() {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        _ => None,
+                    };
+
+                    match opt_in_only {
+                        Some(opt_in_only) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_opt_in_only(id, opt_in_only)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        None => msg.push_str("[usage: /optin_only on|off]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /privacy
+        bot.command("privacy", move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let msg = ChainWrapper::lock_loaded(&chain, &[id]).await.privacy(id);
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /share_global on|off
+        bot.command("share_global", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let is_shared = match context.text.value.trim() {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        _ => None,
+                    };
+
+                    match is_shared {
+                        Some(is_shared) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_share_globally(id, is_shared)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        None => msg.push_str("[usage: /share_global on|off]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /feedback on|off
+        bot.command("feedback", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let is_enabled = match context.text.value.trim() {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        _ => None,
+                    };
+
+                    match is_enabled {
+                        Some(is_enabled) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_feedback(id, is_enabled)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        None => msg.push_str("[usage: /feedback on|off]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /retention days|off
+        bot.command("retention", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let arg = context.text.value.trim();
+                    let days = if arg == "off" {
+                        Ok(0)
+                    } else {
+                        arg.parse::<u64>()
+                    };
+
+                    match days {
+                        Ok(days) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_retention(id, days)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Err(_) => msg.push_str("[usage: /retention days|off]"),
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /storage
+        bot.command("storage", move |context| {
+            let chain = ch.clone();
+            async move {
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_sender_owner(&context) {
+                    msg.push_str(
+                        &ChainWrapper::storage_report(&chain)
+                            .await
+                            .unwrap_or_else(failed),
+                    );
+                } else {
+                    msg.push_str("[only the bot owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /memstats
+        bot.command("memstats", move |context| {
+            let chain = ch.clone();
+            async move {
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_sender_owner(&context) {
+                    msg.push_str(&chain.lock().await.memory_report());
+                } else {
+                    msg.push_str("[only the bot owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /flush
+        bot.command("flush", move |context| {
+            let chain = ch.clone();
+            async move {
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_sender_owner(&context) {
+                    batch::flush(&chain).await;
+                    let (saved, failed) = ChainWrapper::flush_all(&chain).await;
+                    msg.push_str(&format!("[{} chains saved, {} failed]", saved, failed));
+                } else {
+                    msg.push_str("[only the bot owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    // add a callback for /shutdown
+    // all chains are saved by main once receiving updates stops
+    bot.command("shutdown", move |context| {
+        let mut shutdown = shutdown.clone();
+        async move {
+            let is_allowed = is_sender_owner(&context);
+
+            let msg = if is_allowed {
+                "[shutting down, all chains will be saved]"
+            } else {
+                "[only the bot owner can do that]"
+            };
+
+            reply(&context, msg).await;
+
+            if is_allowed {
+                if let Err(err) = shutdown.send(()).await {
+                    dbg!(err);
+                }
+            }
+        }
+    });
+
+    // add a callback for /broadcast msg
+    bot.command("broadcast", |context| async move {
+        let text = context.text.value.trim().to_string();
+
+        // execute or refuse the command
+        let msg = if !is_sender_owner(&context) {
+            String::from("[only the bot owner can do that]")
+        } else if text.is_empty() {
+            String::from("[usage: /broadcast msg]")
+        } else {
+            match ChainWrapper::stored_chats().await {
+                Ok(chats) => {
+                    // chats on other platforms can't be reached through Telegram
+                    let chats: Vec<i64> = chats
+                        .into_iter()
+                        .filter(|id| chat_key::is_telegram(*id))
+                        .collect();
+                    let msg = format!("[broadcasting to {} chats]", chats.len());
+                    let bot = tbot::Bot::clone(&context.bot);
+                    messaging::spawn_broadcast(bot, chats, text, context.chat.id);
+                    msg
+                }
+                Err(e) => {
+                    dbg!(e);
+                    String::from("[failed to list chats]")
+                }
+            }
+        };
+
+        reply(&context, &msg).await;
+    });
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /max_states number
+        bot.command("max_states", move |context| {
+            let chain = ch.clone();
+            async move {
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_sender_owner(&context) {
+                    match context.text.value.trim().parse::<usize>() {
+                        Ok(max_states) => {
+                            let Id(id) = context.chat.id;
+                            msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .set_max_states(id, max_states)
+                                    .unwrap_or_else(failed),
+                            );
+                        }
+                        Err(_) => msg.push_str("[usage: /max_states number]"),
+                    }
+                } else {
+                    msg.push_str("[only the bot owner can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for /prune_rare [days]
+        bot.command("prune_rare", move |context| {
+            let chain = ch.clone();
+            async move {
+                let is_allowed = is_sender_admin(&context).await;
+
+                let mut msg = String::new();
+
+                // execute or refuse the command
+                if is_allowed {
+                    let Id(id) = context.chat.id;
+                    let arg = context.text.value.trim();
+
+                    if arg.is_empty() {
+                        msg.push_str(
+                            &ChainWrapper::lock_loaded(&chain, &[id])
+                                .await
+                                .prune_rare(id, None)
+                                .unwrap_or_else(failed),
+                        );
+                    } else {
+                        match arg.parse::<u64>() {
+                            Ok(days) => msg.push_str(
+                                &ChainWrapper::lock_loaded(&chain, &[id])
+                                    .await
+                                    .prune_rare(id, Some(days))
+                                    .unwrap_or_else(failed),
+                            ),
+                            Err(_) => msg.push_str("[usage: /prune_rare [days]]"),
+                        }
+                    }
+                } else {
+                    msg.push_str("[only the chat owner and admins can do that]");
+                }
+
+                reply(&context, &msg).await;
+            }
+        });
+    }
+
+    // add a callback for new chat members
+    // the bot leaves chats outside of the allow-list as soon as it's added to them
+    bot.new_members(|context| async move {
+        let Id(id) = context.chat.id;
+        let is_bot_added = context.members.iter().any(|usr| {
+            let user::Id(user_id) = usr.id;
+            Some(user_id) == *BOT_ID
+        });
+
+        if is_bot_added && !chain_wrapper::is_chat_allowed(id) {
+            leave_unlisted_chat(&context.bot, &context.chat).await;
+        }
+    });
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for groups upgraded to supergroups, which changes their IDs
+        // the chain moves to the new ID, so that nothing learnt so far is lost
+        bot.migration(move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(old_id) = context.old_id;
+                let Id(new_id) = context.chat.id;
+
+                // messages sent before the upgrade may still wait in the buffer
+                batch::flush(&chain).await;
+
+                if let Some(err) = ChainWrapper::migrate_chat(&chain, old_id, new_id).await {
+                    alerts::report(&format!(
+                        "Migrating {} to {} failed: {}",
+                        old_id, new_id, err
+                    ));
+                    dbg!(err);
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for non-command messages
+        bot.text(move |context| {
+            let chain = ch.clone();
+            async move {
+                // leave chats the bot was added to before they were taken off the allow-list
+                let Id(id) = context.chat.id;
+                if !chain_wrapper::is_chat_allowed(id) {
+                    leave_unlisted_chat(&context.bot, &context.chat).await;
+                    return;
+                }
+
+                if let Some((user_id, username, is_copy)) =
+                    get_author(context.from.as_ref(), &context.chat)
+                {
+                    let message_id = i64::from(context.message_id.0);
+                    let is_forwarded = context.forward.is_some() && !is_copy;
+                    let incoming = batch::Incoming {
+                        user_id,
+                        username,
+                        message_id,
+                        is_forwarded,
+                        is_edit: false,
+                        text: context.text.value.clone(),
+                    };
+                    batch::feed(&chain, id, incoming).await;
+                }
+
+                // answer people talking to the bot
+                if is_addressed_to_bot(&context).await {
+                    let Id(id) = context.chat.id;
+                    let reply = ChainWrapper::lock_loaded(&chain, &[id])
+                        .await
+                        .reply(id, &context.text.value);
+
+                    if let Some(msg) = reply {
+                        let call_result = exponential_retry_async(|| async {
+                            Ok(context.send_message_in_reply(&msg).call().await?)
+                        })
+                        .await;
+
+                        if let Err(err) = call_result {
+                            reply_failed(context.chat.id, err);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for edited messages
+        bot.edited_text(move |context| {
+            let chain = ch.clone();
+            async move {
+                if let Some((user_id, username, _)) =
+                    get_author(context.from.as_ref(), &context.chat)
+                {
+                    let Id(id) = context.chat.id;
+                    // edits go through the buffer as well, so that they're applied after the original message
+                    let incoming = batch::Incoming {
+                        user_id,
+                        username,
+                        message_id: i64::from(context.message_id.0),
+                        is_forwarded: false,
+                        is_edit: true,
+                        text: context.text.value.clone(),
+                    };
+                    batch::feed(&chain, id, incoming).await;
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for photos, learning their captions
+        bot.photo(move |context| {
+            let chain = ch.clone();
+            async move {
+                if let Some((user_id, username, is_copy)) =
+                    get_author(context.from.as_ref(), &context.chat)
+                {
+                    if !context.caption.value.trim().is_empty() {
+                        let Id(id) = context.chat.id;
+                        let message_id = i64::from(context.message_id.0);
+                        let is_forwarded = context.forward.is_some() && !is_copy;
+                        let incoming = batch::Incoming {
+                            user_id,
+                            username,
+                            message_id,
+                            is_forwarded,
+                            is_edit: false,
+                            text: context.caption.value.clone(),
+                        };
+                        batch::feed(&chain, id, incoming).await;
+                    }
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for videos, learning their captions
+        bot.video(move |context| {
+            let chain = ch.clone();
+            async move {
+                if let Some((user_id, username, is_copy)) =
+                    get_author(context.from.as_ref(), &context.chat)
+                {
+                    if !context.caption.value.trim().is_empty() {
+                        let Id(id) = context.chat.id;
+                        let message_id = i64::from(context.message_id.0);
+                        let is_forwarded = context.forward.is_some() && !is_copy;
+                        let incoming = batch::Incoming {
+                            user_id,
+                            username,
+                            message_id,
+                            is_forwarded,
+                            is_edit: false,
+                            text: context.caption.value.clone(),
+                        };
+                        batch::feed(&chain, id, incoming).await;
+                    }
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for animations, learning their captions
+        bot.animation(move |context| {
+            let chain = ch.clone();
+            async move {
+                if let Some((user_id, username, is_copy)) =
+                    get_author(context.from.as_ref(), &context.chat)
+                {
+                    if !context.caption.value.trim().is_empty() {
+                        let Id(id) = context.chat.id;
+                        let message_id = i64::from(context.message_id.0);
+                        let is_forwarded = context.forward.is_some() && !is_copy;
+                        let incoming = batch::Incoming {
+                            user_id,
+                            username,
+                            message_id,
+                            is_forwarded,
+                            is_edit: false,
+                            text: context.caption.value.clone(),
+                        };
+                        batch::feed(&chain, id, incoming).await;
+                    }
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for voice messages, learning their transcripts if STT_URL is set
+        bot.voice(move |context| {
+            let chain = ch.clone();
+            async move {
+                if !stt::accepts(u64::from(context.voice.duration)) {
+                    return;
+                }
+
+                if let Some((user_id, username, is_copy)) =
+                    get_author(context.from.as_ref(), &context.chat)
+                {
+                    let Id(id) = context.chat.id;
+                    if !chain_wrapper::is_chat_allowed(id) {
+                        return;
+                    }
+
+                    let call_result = exponential_retry_async(|| async {
+                        let file = context.bot.get_file(&context.voice).call().await?;
+                        Ok(context.bot.download_file(&file).await?)
+                    })
+                    .await;
+
+                    let audio = match call_result {
+                        Ok(audio) => audio,
+                        Err(err) => {
+                            dbg!(err);
+                            return;
+                        }
+                    };
+
+                    let text = match stt::transcribe(audio).await {
+                        Ok(text) => text,
+                        Err(err) => {
+                            alerts::report(&format!(
+                                "Failed to transcribe a voice message: {}",
+                                err
+                            ));
+                            return;
+                        }
+                    };
+
+                    if !text.is_empty() {
+                        let message_id = i64::from(context.message_id.0);
+                        let is_forwarded = context.forward.is_some() && !is_copy;
+                        let incoming = batch::Incoming {
+                            user_id,
+                            username,
+                            message_id,
+                            is_forwarded,
+                            is_edit: false,
+                            text,
+                        };
+                        batch::feed(&chain, id, incoming).await;
+                    }
+                }
+            }
+        });
+    }
+
+    {
+        let ch = Arc::clone(&chain);
+        // add a callback for stickers
+        bot.sticker(move |context| {
+            let chain = ch.clone();
+            async move {
+                let Id(id) = context.chat.id;
+                let reply = ChainWrapper::lock_loaded(&chain, &[id])
+                    .await
+                    .feed_sticker(id, &context.sticker.file_id.0);
+
+                if let Some(file_id) = reply {
+                    let call_result = exponential_retry_async(|| async {
+                        Ok(context
+                            .send_sticker_in_reply(Sticker::id(&file_id))
+                            .call()
+                            .await?)
+                    })
+                    .await;
+
+                    if let Err(err) = call_result {
+                        reply_failed(context.chat.id, err);
+                    }
                 }
             }
         });
     }
 
+    // keep track of handled updates for the health check
+    bot.after_update(|_| async {
+        health::record_update();
+    });
+
     // return the event loop
     bot
 }
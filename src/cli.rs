@@ -0,0 +1,334 @@
+use markov_bot_core::chain_wrapper::{self, ChainWrapper};
+use markov_bot_core::{storage, tokenizer};
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    time::Duration,
+};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use tokio::sync::Mutex;
+
+// the longest time offline commands wait for the changed chains to be saved
+const SAVE_TIMEOUT: Duration = Duration::from_secs(300);
+
+// the number of times a transition has to be learnt to be drawn by graph, by default
+const DEFAULT_MIN_WEIGHT: u64 = 2;
+
+// the chat ID used by the REPL when no stored chain is loaded
+const REPL_CHAT_ID: i64 = 0;
+
+// the commands understood by the REPL, lines not starting with '/' are learnt
+const REPL_HELP: &str = "[type a line to learn it, or use one of the commands]\n\
+/speak [word] - generate a phrase\n\
+/speak_about <word> - generate a phrase containing a word\n\
+/speak_ending <word> - generate a phrase ending with a word\n\
+/story [number] - generate a few sentences\n\
+/reply <msg> - generate a reply to a message\n\
+/tokens <msg> - show how a message is split into words, without learning it\n\
+/stats - describe the chain\n\
+/quit - exit (changes are never saved)";
+
+// returns the --chat-id argument shared by commands working on a single chat
+fn chat_id_arg() -> Arg<'static, 'static> {
+    Arg::with_name("chat-id")
+        .long("chat-id")
+        .value_name("ID")
+        .help("The ID of the chat")
+        .takes_value(true)
+        .allow_hyphen_values(true)
+        .required(true)
+}
+
+// describes the command line interface
+pub fn app() -> App<'static, 'static> {
+    App::new("markov_bot")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("A Telegram bot that learns to speak like chat members")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Reads the configuration from a TOML file (config.toml by default)")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(SubCommand::with_name("run").about("Runs the bot (the default)"))
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Prints the JSON dump of a chat's chain")
+                .arg(chat_id_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Imports a JSON dump or a list of lines into a chat's chain")
+                .arg(chat_id_arg())
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("The file to import")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("graph")
+                .about("Prints a chat's chain as a Graphviz (DOT) graph of words")
+                .arg(chat_id_arg())
+                .arg(
+                    Arg::with_name("min-weight")
+                        .long("min-weight")
+                        .value_name("N")
+                        .help("Leaves out transitions learnt fewer than N times (2 by default)")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list-chats").about("Lists the chats with a stored chain"),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Checks that the stored chains of all chats can be read"),
+        )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Learns lines typed on stdin and generates phrases on demand")
+                .arg(
+                    Arg::with_name("chat-id")
+                        .long("chat-id")
+                        .value_name("ID")
+                        .help("Starts with the stored chain of a chat")
+                        .takes_value(true)
+                        .allow_hyphen_values(true),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Starts with a JSON dump or a list of lines")
+                        .takes_value(true),
+                ),
+        )
+}
+
+// checks if a command works without the storage backend
+// the REPL only needs it to load a stored chain
+pub fn is_standalone(args: &ArgMatches) -> bool {
+    match args.subcommand() {
+        ("repl", Some(args)) => !args.is_present("chat-id"),
+        _ => false,
+    }
+}
+
+// extracts the chat ID given with --chat-id
+pub fn chat_id(args: &ArgMatches) -> Result<i64, String> {
+    let chat_id = args.value_of("chat-id").unwrap_or_default();
+    chat_id
+        .parse::<i64>()
+        .map_err(|_| format!("{} is not a chat ID", chat_id))
+}
+
+// prints the JSON dump of a specified chat's chain to stdout
+pub async fn export(chat_id: i64) -> Option<String> {
+    let wrapper = Mutex::new(ChainWrapper::new());
+    let mut chain = ChainWrapper::lock_loaded(&wrapper, &[chat_id]).await;
+
+    match chain.export(chat_id) {
+        Ok(Ok(bytes)) => io::stdout().write_all(&bytes).err().map(|e| e.to_string()),
+        Ok(Err(msg)) => Some(msg),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+// extracts the --min-weight argument of graph
+pub fn min_weight(args: &ArgMatches) -> Result<u64, String> {
+    match args.value_of("min-weight") {
+        None => Ok(DEFAULT_MIN_WEIGHT),
+        Some(weight) => weight
+            .parse::<u64>()
+            .map_err(|_| format!("{} is not a number", weight)),
+    }
+}
+
+// prints the DOT graph of a specified chat's chain to stdout
+pub async fn graph(chat_id: i64, min_weight: u64) -> Option<String> {
+    let wrapper = Mutex::new(ChainWrapper::new());
+    let mut chain = ChainWrapper::lock_loaded(&wrapper, &[chat_id]).await;
+
+    match chain.graph(chat_id, min_weight) {
+        Ok(Ok(dot)) => io::stdout()
+            .write_all(dot.as_bytes())
+            .err()
+            .map(|e| e.to_string()),
+        Ok(Err(msg)) => Some(msg),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+// imports a file into a specified chat's chain and saves it
+pub async fn import(chat_id: i64, path: &str) -> Option<String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Some(format!("Failed to read {}: {}", path, e)),
+    };
+
+    let wrapper = Mutex::new(ChainWrapper::new());
+    match ChainWrapper::import(&wrapper, chat_id, &bytes).await {
+        Ok(msg) => println!("{}", msg),
+        Err(e) => return Some(e.to_string()),
+    }
+
+    let saved = wrapper.lock().await.drop_all(SAVE_TIMEOUT).await;
+    match saved {
+        (_, 0, 0) => None,
+        _ => Some(format!("Failed to save the chain of {}", chat_id)),
+    }
+}
+
+// prints the IDs of all chats with a stored chain, one per line
+pub async fn list_chats() -> Option<String> {
+    match ChainWrapper::stored_chats().await {
+        Ok(chats) => {
+            for chat_id in chats {
+                println!("{}", chat_id);
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+// downloads and deserializes the chain of every stored chat, printing the result of each
+pub async fn verify() -> Option<String> {
+    let chats = match ChainWrapper::stored_chats().await {
+        Ok(chats) => chats,
+        Err(e) => return Some(e.to_string()),
+    };
+
+    let mut failed = 0;
+    for chat_id in chats.iter() {
+        match ChainWrapper::verify(*chat_id).await {
+            None => println!("{}: ok", chat_id),
+            Some(err) => {
+                println!("{}: {}", chat_id, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed == 0 {
+        None
+    } else {
+        Some(format!(
+            "{} of {} chains can't be read",
+            failed,
+            chats.len()
+        ))
+    }
+}
+
+// runs a single REPL command, returning its output
+fn repl_command(chain: &mut ChainWrapper, chat_id: i64, line: &str) -> String {
+    let mut parts = line.splitn(2, ' ');
+    let command = parts.next().unwrap_or_default();
+    let args = parts.next().unwrap_or_default().trim();
+
+    let res = match command {
+        "/speak" => chain.generate(chat_id, args, None),
+        "/speak_about" => chain.speak_about(chat_id, args),
+        "/speak_ending" => chain.speak_ending(chat_id, args),
+        "/story" => match args {
+            "" => chain.story(chat_id, None),
+            args => match args.parse::<usize>() {
+                Ok(sentences) => chain.story(chat_id, Some(sentences)),
+                Err(_) => Ok(String::from("[usage: /story [number]]")),
+            },
+        },
+        "/reply" => Ok(chain
+            .reply(chat_id, args)
+            .unwrap_or_else(|| String::from("[no reply]"))),
+        "/tokens" => Ok(format!("[{}]", tokenizer::tokenize(args).join(" | "))),
+        "/stats" => chain.stats(chat_id),
+        _ => Ok(String::from(REPL_HELP)),
+    };
+
+    res.unwrap_or_else(|e| format!("[{}]", e))
+}
+
+// loads a chain from storage and/or a file, then reads lines from stdin
+// lines are learnt, commands (see REPL_HELP) generate phrases
+// the storage backend is detached once the chain is loaded, so nothing is ever written back
+pub async fn repl(chat_id: Option<i64>, path: Option<&str>) -> Option<String> {
+    let wrapper = Mutex::new(ChainWrapper::new());
+    let chat_id = match chat_id {
+        Some(chat_id) => {
+            ChainWrapper::preload(&wrapper, chat_id).await;
+            if !wrapper.lock().await.is_loaded(chat_id) {
+                return Some(format!("Failed to load the chain of {}", chat_id));
+            }
+            chat_id
+        }
+        None => {
+            // the allow-list is meant for real chats, it mustn't lock the REPL out of its own
+            env::remove_var("ALLOWED_CHATS");
+            if let Some(err) = chain_wrapper::reload_config() {
+                return Some(err);
+            }
+            REPL_CHAT_ID
+        }
+    };
+    storage::detach();
+
+    if let Some(path) = path {
+        match fs::read(path) {
+            Ok(bytes) => match ChainWrapper::import(&wrapper, chat_id, &bytes).await {
+                Ok(msg) => println!("{}", msg),
+                Err(e) => return Some(e.to_string()),
+            },
+            Err(e) => return Some(format!("Failed to read {}: {}", path, e)),
+        }
+    }
+
+    // without storage, the REPL's own chain is created empty
+    let mut chain = ChainWrapper::lock_loaded(&wrapper, &[chat_id]).await;
+
+    println!("{}", REPL_HELP);
+
+    loop {
+        print!("> ");
+        if let Err(e) = io::stdout().flush() {
+            return Some(e.to_string());
+        }
+
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            // end of input
+            Ok(0) => return None,
+            Ok(_) => (),
+            Err(e) => return Some(e.to_string()),
+        }
+
+        let line = line.trim();
+        if line == "/quit" {
+            return None;
+        } else if line.starts_with('/') {
+            println!("{}", repl_command(&mut chain, chat_id, line));
+        } else if !line.is_empty() {
+            let lines = match chain.learn_offline(chat_id, line) {
+                Ok(lines) => lines,
+                Err(e) => {
+                    println!("[{}]", e);
+                    continue;
+                }
+            };
+            if lines.is_empty() {
+                println!("[nothing learnt]");
+            }
+            for tokens in lines {
+                println!("[learnt: {}]", tokens.join(" | "));
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::platform::{self, Platform};
+use markov_bot_core::chain_wrapper::ChainWrapper;
+use markov_bot_core::utils::{exponential_retry_async, run_blocking};
+use markov_bot_core::{alerts, batch, chat_key};
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as SyncMutex,
+    },
+};
+
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    future::{select, LocalBoxFuture},
+    StreamExt,
+};
+use serenity::{
+    client::{bridge::gateway::ShardManager, Client, Context, EventHandler},
+    http::Http,
+    model::{
+        channel::Message,
+        gateway::Ready,
+        id::{ChannelId, UserId},
+    },
+    prelude::Mutex as ShardMutex,
+};
+use tokio::sync::Mutex;
+
+// a message received on a gateway thread, handled on the async side
+struct Received {
+    http: Arc<Http>,
+    channel_id: u64,
+    user_id: i64,
+    username: String,
+    // Discord message IDs are 64-bit snowflakes, which always fit in an i64
+    message_id: i64,
+    text: String,
+    is_mention: bool,
+}
+
+// passes messages from the gateway threads run by serenity to the async side
+struct Handler {
+    sender: UnboundedSender<Received>,
+    // the bot's own user ID, known once the gateway is ready
+    bot_id: SyncMutex<Option<UserId>>,
+    // set once the gateway is ready, tells a connection that went down from one that never came up
+    is_connected: Arc<AtomicBool>,
+    // the number of failed connections in a row before this one
+    failures: u32,
+}
+
+impl EventHandler for Handler {
+    fn ready(&self, _: Context, ready: Ready) {
+        *self.bot_id.lock().unwrap() = Some(ready.user.id);
+
+        // serenity reconnects by itself too, so that readiness is only reported once
+        if !self.is_connected.swap(true, Ordering::SeqCst) && self.failures > 0 {
+            alerts::report(&format!(
+                "Reconnected to Discord after {} failed attempts",
+                self.failures
+            ));
+        }
+    }
+
+    fn message(&self, ctx: Context, message: Message) {
+        // ignore other bots (and the bot itself), so that they don't talk to each other forever
+        if message.author.bot {
+            return;
+        }
+
+        let is_mention = match *self.bot_id.lock().unwrap() {
+            Some(bot_id) => message.mentions_user_id(bot_id),
+            None => false,
+        };
+
+        let ChannelId(channel_id) = message.channel_id;
+        let UserId(user_id) = message.author.id;
+        let received = Received {
+            http: Arc::clone(&ctx.http),
+            channel_id,
+            user_id: user_id as i64,
+            username: message.author.name.clone(),
+            message_id: message.id.0 as i64,
+            // mentions are replaced with names, so that generated phrases never ping anybody
+            text: message.content_safe(&ctx.cache),
+            is_mention,
+        };
+
+        if let Err(err) = self.sender.unbounded_send(received) {
+            dbg!(err);
+        }
+    }
+}
+
+// shuts the gateway down once it's dropped (e.g. when the bot is stopped)
+struct Gateway(Arc<ShardMutex<ShardManager>>);
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        self.0.lock().shutdown_all();
+    }
+}
+
+// sends a message to a Discord channel
+async fn send(http: &Arc<Http>, channel_id: u64, msg: &str) {
+    let call_result = exponential_retry_async(|| {
+        let http = Arc::clone(http);
+        let msg = msg.to_string();
+        async move { Ok(run_blocking(move || ChannelId(channel_id).say(&*http, msg)).await?) }
+    })
+    .await;
+
+    if let Err(err) = call_result {
+        dbg!(err);
+    }
+}
+
+// learns a message or runs the command it contains, replying if needed
+async fn handle(
+    chain: Arc<Mutex<ChainWrapper>>,
+    links: Arc<HashMap<u64, i64>>,
+    received: Received,
+) {
+    let chat_id = match links
+        .get(&received.channel_id)
+        .copied()
+        .or_else(|| chat_key::discord(received.channel_id))
+    {
+        Some(chat_id) => chat_id,
+        None => return,
+    };
+
+    let incoming = batch::Incoming {
+        user_id: received.user_id,
+        username: received.username,
+        message_id: received.message_id,
+        is_forwarded: false,
+        is_edit: false,
+        text: received.text,
+    };
+
+    if let Some(msg) = platform::respond(&chain, chat_id, incoming, received.is_mention).await {
+        send(&received.http, received.channel_id, &msg).await;
+    }
+}
+
+// the Discord bot, enabled by setting DISCORD_TOKEN
+pub struct Discord {
+    token: String,
+    links: Arc<HashMap<u64, i64>>,
+}
+
+impl Discord {
+    // extracts the configuration of the Discord bot from std::env
+    // returns None if DISCORD_TOKEN is not set
+    pub fn from_env() -> Option<Discord> {
+        let token = env::var("DISCORD_TOKEN")
+            .ok()
+            .filter(|token| !token.trim().is_empty())?;
+
+        Some(Discord {
+            token,
+            links: Arc::new(platform::get_links("DISCORD_LINKS")),
+        })
+    }
+}
+
+impl Platform for Discord {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    fn run(self: Box<Self>, chain: Arc<Mutex<ChainWrapper>>) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move {
+            let (sender, mut receiver) = mpsc::unbounded();
+
+            // reconnects after every failure, Discord is optional and shouldn't take the bot down
+            let token = self.token;
+            let connect = async move {
+                let mut failures: u32 = 0;
+                loop {
+                    let is_connected = Arc::new(AtomicBool::new(false));
+                    let handler = Handler {
+                        sender: sender.clone(),
+                        bot_id: SyncMutex::new(None),
+                        is_connected: Arc::clone(&is_connected),
+                        failures,
+                    };
+
+                    let res = match Client::new(&token, handler) {
+                        Ok(mut client) => {
+                            let _gateway = Gateway(Arc::clone(&client.shard_manager));
+
+                            // serenity runs the gateway on its own threads and blocks until it's stopped
+                            run_blocking(move || client.start()).await
+                        }
+                        Err(err) => Err(err),
+                    };
+
+                    // a connection that worked for a while starts a new streak of failures
+                    if is_connected.load(Ordering::SeqCst) {
+                        failures = 0;
+                    }
+
+                    let delay = platform::retry_delay(failures);
+                    match res {
+                        // only alert about the first failure in a row, so that an outage doesn't flood the admin chat
+                        Err(err) if failures == 0 => {
+                            alerts::report(&format!(
+                                "Discord gateway stopped, reconnecting: {}",
+                                err
+                            ));
+                            dbg!(err);
+                        }
+                        Err(err) => {
+                            dbg!(err);
+                        }
+                        Ok(()) => (),
+                    }
+                    failures += 1;
+                    tokio::time::delay_for(delay).await;
+                }
+            };
+
+            let links = self.links;
+            let receive = async move {
+                while let Some(received) = receiver.next().await {
+                    tokio::spawn(handle(chain.clone(), links.clone(), received));
+                }
+            };
+
+            select(Box::pin(connect), Box::pin(receive)).await;
+        })
+    }
+}
@@ -0,0 +1,200 @@
+use markov_bot_core::chain_wrapper::ChainWrapper;
+use markov_bot_core::storage;
+
+use std::{
+    convert::Infallible,
+    env,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::Mutex as AsyncMutex;
+
+// the longest time a single check may take before it's considered failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the time the outcome of a storage check is reused for
+// probes may come every few seconds, each of them would be a request to the backend otherwise
+const STORAGE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// extracts HEALTH_MAX_UPDATE_AGE (in seconds) from std::env
+// returns None (meaning quiet periods of any length are fine) if the variable is not set or empty
+fn get_max_update_age() -> Option<u64> {
+    match env::var("HEALTH_MAX_UPDATE_AGE") {
+        Err(_) => None,
+        Ok(age) if age.trim().is_empty() => None,
+        Ok(age) => Some(
+            age.trim()
+                .parse::<u64>()
+                .expect("HEALTH_MAX_UPDATE_AGE is not a number"),
+        ),
+    }
+}
+
+lazy_static! {
+    // the time the last update was received
+    static ref LAST_UPDATE: Mutex<Option<SystemTime>> = Mutex::new(None);
+    // the time the health check started, counts as the last update until one is received
+    static ref STARTED_AT: SystemTime = SystemTime::now();
+    // the bot is reported unhealthy once no update has been received for this many seconds
+    static ref MAX_UPDATE_AGE: Option<u64> = get_max_update_age();
+    // the time of the last storage check along with its outcome, see STORAGE_CHECK_INTERVAL
+    static ref LAST_STORAGE_CHECK: Mutex<Option<(SystemTime, bool)>> = Mutex::new(None);
+}
+
+// set while the bot is polling or listening for webhook requests
+// cleared while polling fails, and set again once an update gets through
+static IS_RECEIVING: AtomicBool = AtomicBool::new(false);
+
+// the body of a /healthz response
+#[derive(Serialize)]
+struct Report {
+    receiving_updates: bool,
+    // None if no update has been received since the start
+    seconds_since_last_update: Option<u64>,
+    storage_reachable: bool,
+    // set if the storage backend failed to initialize and nothing is persisted
+    storage_degraded: bool,
+    // None if the chains were locked for longer than CHECK_TIMEOUT
+    loaded_chains: Option<usize>,
+    // the estimated memory taken by the loaded chains, None under the same conditions as above
+    estimated_memory_bytes: Option<usize>,
+}
+
+// notes that an update has just been received, which means polling works again
+pub fn record_update() {
+    *LAST_UPDATE.lock().unwrap() = Some(SystemTime::now());
+    set_receiving(true);
+}
+
+// notes that the bot has started or stopped receiving updates
+pub fn set_receiving(is_receiving: bool) {
+    IS_RECEIVING.store(is_receiving, Ordering::SeqCst);
+}
+
+// checks if the storage backend answers, reusing the outcome for STORAGE_CHECK_INTERVAL
+// a degraded backend is never reachable, requests only go to memory meanwhile
+async fn is_storage_reachable() -> bool {
+    if storage::is_degraded() {
+        return false;
+    }
+
+    if let Some((checked_at, is_reachable)) = *LAST_STORAGE_CHECK.lock().unwrap() {
+        if let Ok(elapsed) = checked_at.elapsed() {
+            if elapsed < STORAGE_CHECK_INTERVAL {
+                return is_reachable;
+            }
+        }
+    }
+
+    let is_reachable = match tokio::time::timeout(CHECK_TIMEOUT, storage::get_quota()).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            dbg!(e);
+            false
+        }
+        Err(_) => false,
+    };
+
+    *LAST_STORAGE_CHECK.lock().unwrap() = Some((SystemTime::now(), is_reachable));
+    is_reachable
+}
+
+// checks the bot's state
+async fn report(chain: &AsyncMutex<ChainWrapper>) -> Report {
+    let last_update = *LAST_UPDATE.lock().unwrap();
+    let storage_reachable = is_storage_reachable().await;
+
+    let (loaded_chains, estimated_memory_bytes) =
+        match tokio::time::timeout(CHECK_TIMEOUT, chain.lock()).await {
+            Ok(chain) => (Some(chain.chain_count()), Some(chain.memory_usage())),
+            Err(_) => (None, None),
+        };
+
+    Report {
+        receiving_updates: IS_RECEIVING.load(Ordering::SeqCst),
+        seconds_since_last_update: last_update
+            .and_then(|time| time.elapsed().ok())
+            .map(|elapsed| elapsed.as_secs()),
+        storage_reachable,
+        storage_degraded: storage::is_degraded(),
+        loaded_chains,
+        estimated_memory_bytes,
+    }
+}
+
+// checks if an update (or the start, if none came yet) was received within MAX_UPDATE_AGE
+fn is_update_recent() -> bool {
+    let max_age = match *MAX_UPDATE_AGE {
+        Some(max_age) => max_age,
+        None => return true,
+    };
+
+    let last_update = LAST_UPDATE.lock().unwrap().unwrap_or(*STARTED_AT);
+    match last_update.elapsed() {
+        Ok(elapsed) => elapsed.as_secs() <= max_age,
+        // the clock went back, the update can't be that old
+        Err(_) => true,
+    }
+}
+
+// answers a single HTTP request
+async fn handle(
+    req: Request<Body>,
+    chain: Arc<AsyncMutex<ChainWrapper>>,
+) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() != "/healthz" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let report = report(&chain).await;
+    let is_healthy = report.receiving_updates
+        && report.storage_reachable
+        && !report.storage_degraded
+        && report.loaded_chains.is_some()
+        && is_update_recent();
+
+    let body = serde_json::to_vec(&report).expect("Serialization failed");
+    let mut response = Response::new(Body::from(body));
+    if !is_healthy {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    Ok(response)
+}
+
+// starts an HTTP server exposing /healthz on HEALTH_PORT
+// does nothing if the variable is not set or empty
+pub fn spawn_server(chain: Arc<AsyncMutex<ChainWrapper>>) {
+    let port = match env::var("HEALTH_PORT") {
+        Err(_) => return,
+        Ok(port) if port.trim().is_empty() => return,
+        Ok(port) => port.parse::<u16>().expect("HEALTH_PORT is not a number"),
+    };
+    lazy_static::initialize(&STARTED_AT);
+    lazy_static::initialize(&MAX_UPDATE_AGE);
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let chain = chain.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, chain.clone()))) }
+    });
+
+    tokio::spawn(async move {
+        if let Err(err) = Server::bind(&addr).serve(make_service).await {
+            dbg!(err);
+        }
+    });
+}
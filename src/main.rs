@@ -1,38 +1,195 @@
+mod api;
 mod bot;
-mod chain_wrapper;
-mod gdrive;
-mod utils;
+mod cli;
+mod discord;
+mod health;
+mod mastodon;
+mod matrix;
+mod messaging;
+mod platform;
+mod stt;
+mod tts;
+mod webhook;
 
-use std::sync::{Arc, Mutex};
+use crate::platform::Platform;
+use markov_bot_core::{batch, chain_wrapper, config_file, storage, tasks};
+
+use std::{env, process, sync::Arc, time::Duration};
 
 use dotenv::dotenv;
-use futures::future::select;
-use tokio::signal::unix::*;
+use futures::{
+    future::{join_all, pending, select},
+    FutureExt,
+};
+use tokio::{
+    signal::unix::*,
+    sync::{mpsc, Mutex},
+};
+
+// extracts SHUTDOWN_TIMEOUT from std::env and returns a Duration
+// defaults to 60 seconds if the variable is not set
+fn get_shutdown_timeout() -> Duration {
+    let seconds = match env::var("SHUTDOWN_TIMEOUT") {
+        Err(_) => 60,
+        Ok(seconds) => seconds
+            .parse::<u64>()
+            .expect("SHUTDOWN_TIMEOUT is not a number"),
+    };
+
+    Duration::from_secs(seconds)
+}
 
 #[tokio::main]
 async fn main() {
-    // load environment variables
+    let matches = cli::app().get_matches();
+
+    // load environment variables, then the configuration file (which doesn't override them)
+    config_file::capture_environment();
     dotenv().ok();
+    if let Some(err) = config_file::apply() {
+        panic!("{}", err);
+    }
+
+    // create a connection to the storage backend, unless the command doesn't need one
+    if cli::is_standalone(&matches) {
+        storage::detach();
+    } else {
+        storage::initialize().await;
+    }
 
-    // create a connection to Google Drive
-    utils::parse_credentials();
-    gdrive::initialize();
+    let res = match matches.subcommand() {
+        ("run", _) | ("", _) => {
+            run().await;
+            None
+        }
+        ("repl", Some(args)) if cli::is_standalone(&matches) => {
+            cli::repl(None, args.value_of("file")).await
+        }
+        // offline commands are pointless without the stored chains
+        _ if !storage::is_persistent() => Some(String::from("Storage unavailable")),
+        ("export", Some(args)) => match cli::chat_id(args) {
+            Ok(chat_id) => cli::export(chat_id).await,
+            Err(e) => Some(e),
+        },
+        ("import", Some(args)) => match cli::chat_id(args) {
+            Ok(chat_id) => cli::import(chat_id, args.value_of("file").unwrap_or_default()).await,
+            Err(e) => Some(e),
+        },
+        ("graph", Some(args)) => match (cli::chat_id(args), cli::min_weight(args)) {
+            (Ok(chat_id), Ok(min_weight)) => cli::graph(chat_id, min_weight).await,
+            (Err(e), _) | (_, Err(e)) => Some(e),
+        },
+        ("list-chats", _) => cli::list_chats().await,
+        ("verify", _) => cli::verify().await,
+        ("repl", Some(args)) => match cli::chat_id(args) {
+            Ok(chat_id) => cli::repl(Some(chat_id), args.value_of("file")).await,
+            Err(e) => Some(e),
+        },
+        (other, _) => Some(format!("Unknown command: {}", other)),
+    };
 
-    // register a SIGTERM handler
-    let mut sigstream =
-        signal(SignalKind::terminate()).expect("Failed to register SIGTERM handler");
-    let sig = sigstream.recv();
+    if let Some(err) = res {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+// runs the bot until it's stopped with a signal or /shutdown
+async fn run() {
+    // register handlers of the signals that stop the bot (SIGTERM, Ctrl-C and Ctrl-\)
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to register SIGTERM handler");
+    let mut sigint = signal(SignalKind::interrupt()).expect("Failed to register SIGINT handler");
+    let mut sigquit = signal(SignalKind::quit()).expect("Failed to register SIGQUIT handler");
+    let sig = select(
+        Box::pin(sigterm.recv()),
+        select(Box::pin(sigint.recv()), Box::pin(sigquit.recv())),
+    );
 
     // create a container for Markov chains
     let chain = Arc::new(Mutex::new(chain_wrapper::ChainWrapper::new()));
 
-    // create and start the bot
-    let bot = bot::create(chain.clone());
-    let polling = bot.polling().error_handler(|_| async {}).start();
+    // recover lines that weren't persisted before the previous shutdown
+    chain_wrapper::ChainWrapper::replay_wal(&chain).await;
+
+    // load the chats used the most recently before the previous shutdown
+    tasks::spawn_warm_start(chain.clone());
+
+    // upload files that couldn't be uploaded before (e.g. during a storage outage)
+    tasks::spawn_spool_retry();
+
+    // feed incoming messages into chains in batches
+    batch::spawn(chain.clone());
+
+    // periodically save unsaved changes in the background
+    tasks::spawn_flush(chain.clone());
+
+    // free chains nobody has used for a while, or early if they take too much memory
+    tasks::spawn_prune(chain.clone());
+    tasks::spawn_memory_check(chain.clone());
+
+    // re-read the configuration on SIGHUP
+    tasks::spawn_reload();
+
+    // wipe data kept for longer than chats allow and delete long-idle chats
+    tasks::spawn_retention(chain.clone());
+
+    // forget transitions learnt only once that haven't been learnt again for a while
+    tasks::spawn_rare_pruning(chain.clone());
+
+    // a bot handle used to send messages outside of update handlers
+    let api = tbot::Bot::from_env("HTTP_TOKEN");
+
+    // report errors to the admin chat
+    messaging::spawn_alerts(api.clone());
+
+    // post scheduled phrases of the day
+    chain.lock().await.load_schedules().await;
+    messaging::spawn_daily(chain.clone(), api);
+
+    // publish phrases on Mastodon, if an account is configured
+    mastodon::spawn(chain.clone());
+
+    // report the state of the bot to orchestrators
+    health::spawn_server(chain.clone());
+
+    // let other services generate phrases and feed messages over HTTP
+    api::spawn_server(chain.clone());
+
+    // the owner can stop the bot with /shutdown
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
+    let shutdown = shutdown_rx.recv();
+
+    // start the bot on every configured platform, they all share the chains
+    let telegram: Box<dyn Platform> = Box::new(platform::Telegram::new(shutdown_tx));
+    let mut optional: Vec<Box<dyn Platform>> = Vec::new();
+    if let Some(discord) = discord::Discord::from_env() {
+        optional.push(Box::new(discord));
+    }
+    if let Some(matrix) = matrix::Matrix::from_env() {
+        optional.push(Box::new(matrix));
+    }
+
+    println!("Receiving messages from {}", telegram.name());
+    let telegram = telegram.run(chain.clone());
+
+    // the other platforms are optional, one that stops for good doesn't stop the bot
+    let optional = join_all(optional.into_iter().map(|platform| {
+        println!("Receiving messages from {}", platform.name());
+        platform.run(chain.clone())
+    }))
+    .then(|_| pending::<()>());
+    let updates = select(telegram, optional.boxed_local());
 
-    // await SIGTERM and ensure that polling is stopped
-    select(Box::pin(polling), Box::pin(sig)).await;
+    // await a signal, /shutdown or Telegram failing and ensure that receiving updates is stopped
+    let stop = select(Box::pin(sig), Box::pin(shutdown));
+    select(Box::pin(updates), stop).await;
 
-    // write all changes to Google Drive
-    chain.lock().unwrap().drop_all();
+    // write all changes (including messages still waiting to be fed) to the storage backend
+    // chains that aren't saved in time are recovered from the write-ahead log on the next start
+    batch::flush(&chain).await;
+    let (saved, failed, timed_out) = chain.lock().await.drop_all(get_shutdown_timeout()).await;
+    println!(
+        "Shutdown: {} chains saved, {} failed, {} timed out",
+        saved, failed, timed_out
+    );
 }
@@ -0,0 +1,149 @@
+use markov_bot_core::alerts;
+use markov_bot_core::chain_wrapper::ChainWrapper;
+use markov_bot_core::utils::{exponential_retry_async, unix_now};
+
+use std::{env, sync::Arc, time::Duration};
+
+use reqwest::{Client, Url};
+use tokio::{sync::Mutex, time::Instant};
+
+// Mastodon's default limit on the length of a post
+const MAX_LENGTH: usize = 500;
+
+// the number of phrases generated in search of one that fits into MAX_LENGTH
+const ATTEMPTS: usize = 10;
+
+// the auto-posting configuration taken from std::env
+struct Config {
+    // the address of the Mastodon instance, e.g. https://mastodon.social
+    instance: Url,
+    // the access token of the account posts are published on (with the write:statuses scope)
+    token: String,
+    // the chat whose chain generates the posts
+    chat_id: i64,
+    // the time between posts
+    interval: Duration,
+    // who can see the posts (public, unlisted or private)
+    visibility: String,
+}
+
+// extracts the auto-posting configuration from std::env
+// returns None (meaning nothing is posted) if MASTODON_INSTANCE or MASTODON_TOKEN is not set
+fn get_config() -> Option<Config> {
+    let instance = env::var("MASTODON_INSTANCE")
+        .ok()
+        .filter(|url| !url.trim().is_empty())?;
+    let token = env::var("MASTODON_TOKEN")
+        .ok()
+        .filter(|token| !token.trim().is_empty())?;
+
+    let instance = Url::parse(&instance).expect("MASTODON_INSTANCE is not a valid URL");
+
+    let chat_id = env::var("MASTODON_CHAT_ID")
+        .expect("MASTODON_CHAT_ID not set")
+        .parse::<i64>()
+        .expect("MASTODON_CHAT_ID is not a chat ID");
+
+    let hours = match env::var("MASTODON_INTERVAL") {
+        Err(_) => 6,
+        Ok(hours) => hours
+            .parse::<u64>()
+            .expect("MASTODON_INTERVAL is not a number"),
+    };
+    if hours == 0 {
+        panic!("MASTODON_INTERVAL has to be at least 1");
+    }
+
+    let visibility = env::var("MASTODON_VISIBILITY").unwrap_or_else(|_| String::from("public"));
+    match visibility.as_str() {
+        "public" | "unlisted" | "private" => (),
+        _ => panic!("MASTODON_VISIBILITY has to be public, unlisted or private"),
+    }
+
+    Some(Config {
+        instance,
+        token,
+        chat_id,
+        interval: Duration::from_secs(hours * 60 * 60),
+        visibility,
+    })
+}
+
+// publishes a post on the configured account
+async fn post(client: &Client, config: &Config, status: &str) -> Result<(), String> {
+    let url = config
+        .instance
+        .join("api/v1/statuses")
+        .map_err(|e| e.to_string())?;
+
+    // Mastodon ignores repeated requests with the same key, so retries never post twice
+    let idempotency_key = format!("markov_bot.{}.{}", config.chat_id, unix_now());
+    let form = [
+        ("status", status),
+        ("visibility", config.visibility.as_str()),
+    ];
+
+    exponential_retry_async(|| async {
+        Ok(client
+            .post(url.clone())
+            .bearer_auth(&config.token)
+            .header("Idempotency-Key", idempotency_key.as_str())
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?)
+    })
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+// spawns a task that posts a phrase from the configured chat's chain every MASTODON_INTERVAL hours
+// does nothing if MASTODON_INSTANCE or MASTODON_TOKEN is not set
+pub fn spawn(chain: Arc<Mutex<ChainWrapper>>) {
+    let config = match get_config() {
+        Some(config) => config,
+        None => return,
+    };
+
+    // the first post waits for a whole interval, so that restarts don't post right away
+    let mut interval = tokio::time::interval_at(Instant::now() + config.interval, config.interval);
+    let client = Client::new();
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let mut phrase = None;
+            for _ in 0..ATTEMPTS {
+                match ChainWrapper::lock_loaded(&chain, &[config.chat_id])
+                    .await
+                    .phrase(config.chat_id, "", None)
+                {
+                    Ok(Some(s)) if s.chars().count() <= MAX_LENGTH => {
+                        phrase = Some(s);
+                        break;
+                    }
+                    Ok(Some(_)) => continue,
+                    Ok(None) => break,
+                    Err(err) => {
+                        dbg!(err);
+                        break;
+                    }
+                }
+            }
+
+            let phrase = match phrase {
+                Some(phrase) => phrase,
+                None => {
+                    dbg!("No phrase to post on Mastodon");
+                    continue;
+                }
+            };
+
+            if let Err(err) = post(&client, &config, &phrase).await {
+                alerts::report(&format!("Failed to post on Mastodon: {}", err));
+            }
+        }
+    });
+}
@@ -0,0 +1,418 @@
+use crate::platform::{self, Platform};
+use markov_bot_core::chain_wrapper::{self, ChainWrapper};
+use markov_bot_core::utils::{exponential_retry_async, unix_now};
+use markov_bot_core::{alerts, batch, chat_key};
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::future::LocalBoxFuture;
+use reqwest::{Client, Error, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+// how long the homeserver may hold a sync request before answering with no new events
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+// only messages are of interest, everything else is left out of sync responses
+const SYNC_FILTER: &str =
+    r#"{"room":{"timeline":{"types":["m.room.message"]}},"presence":{"types":[]}}"#;
+
+// makes transaction IDs unique within a second
+static TRANSACTION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// the parts of a sync response the bot cares about
+#[derive(Deserialize)]
+struct Sync {
+    next_batch: String,
+    #[serde(default)]
+    rooms: Rooms,
+}
+
+#[derive(Default, Deserialize)]
+struct Rooms {
+    #[serde(default)]
+    join: HashMap<String, JoinedRoom>,
+    #[serde(default)]
+    invite: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct JoinedRoom {
+    #[serde(default)]
+    timeline: Timeline,
+}
+
+#[derive(Default, Deserialize)]
+struct Timeline {
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    sender: String,
+    event_id: String,
+    #[serde(default)]
+    content: Content,
+}
+
+#[derive(Default, Deserialize)]
+struct Content {
+    msgtype: Option<String>,
+    body: Option<String>,
+    // the HTML version of the body, where clients put mentions as links to the user (pills)
+    formatted_body: Option<String>,
+    // the users the sender meant to mention, set by recent clients
+    #[serde(rename = "m.mentions")]
+    mentions: Option<Mentions>,
+}
+
+#[derive(Default, Deserialize)]
+struct Mentions {
+    #[serde(default)]
+    user_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct WhoAmI {
+    user_id: String,
+}
+
+// a client of the Matrix client-server API, authenticated as the bot
+struct Api {
+    client: Client,
+    homeserver: Url,
+    token: String,
+}
+
+impl Api {
+    // builds the URL of a client-server API endpoint, encoding each part of the path
+    fn url(&self, path: &[&str]) -> Url {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("MATRIX_HOMESERVER is not a valid URL")
+            .pop_if_empty()
+            .extend(&["_matrix", "client", "r0"])
+            .extend(path);
+        url
+    }
+
+    // sends a GET request and deserializes the response
+    async fn get<T: DeserializeOwned>(&self, url: Url, query: &[(&str, &str)]) -> Result<T, Error> {
+        self.client
+            .get(url)
+            .bearer_auth(&self.token)
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<T>()
+            .await
+    }
+
+    // sends a request with a JSON body, ignoring the response
+    async fn post(&self, url: Url, body: &serde_json::Value) -> Result<(), Error> {
+        self.client
+            .post(url)
+            .bearer_auth(&self.token)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // returns the user ID of the bot
+    async fn whoami(&self) -> Result<String, Error> {
+        let url = self.url(&["account", "whoami"]);
+        Ok(self.get::<WhoAmI>(url, &[]).await?.user_id)
+    }
+
+    // returns the events since the given batch, waiting up to SYNC_TIMEOUT for new ones
+    // returns right away if there's no batch, which is how the bot skips messages sent before it started
+    async fn sync(&self, since: Option<&str>) -> Result<Sync, Error> {
+        let url = self.url(&["sync"]);
+        match since {
+            Some(since) => {
+                let timeout = SYNC_TIMEOUT.as_millis().to_string();
+                let query = [
+                    ("filter", SYNC_FILTER),
+                    ("since", since),
+                    ("timeout", timeout.as_str()),
+                ];
+                self.get(url, &query).await
+            }
+            None => {
+                self.get(url, &[("filter", SYNC_FILTER), ("timeout", "0")])
+                    .await
+            }
+        }
+    }
+
+    // accepts an invite to a room
+    async fn join(&self, room_id: &str) -> Result<(), Error> {
+        self.post(self.url(&["rooms", room_id, "join"]), &json!({}))
+            .await
+    }
+
+    // rejects an invite to a room, or leaves it
+    async fn leave(&self, room_id: &str) -> Result<(), Error> {
+        self.post(self.url(&["rooms", room_id, "leave"]), &json!({}))
+            .await
+    }
+
+    // sends a message to a room
+    // bots are expected to send notices, which other bots don't respond to
+    async fn send(&self, room_id: &str, msg: &str) {
+        // the transaction ID stays the same across retries, so the message is never sent twice
+        let transaction_id = format!(
+            "{}.{}",
+            unix_now(),
+            TRANSACTION_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = self.url(&["rooms", room_id, "send", "m.room.message", &transaction_id]);
+        let body = json!({ "msgtype": "m.notice", "body": msg });
+
+        let call_result = exponential_retry_async(|| async {
+            Ok(self
+                .client
+                .put(url.clone())
+                .bearer_auth(&self.token)
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?)
+        })
+        .await;
+
+        if let Err(err) = call_result {
+            dbg!(err);
+        }
+    }
+}
+
+// checks if a message mentions the bot, given its lowercase user ID
+// only the full ID counts, names and localparts are too likely to be part of other words
+fn is_mention(content: &Content, bot_id: &str) -> bool {
+    let is_listed = content.mentions.as_ref().is_some_and(|mentions| {
+        mentions
+            .user_ids
+            .iter()
+            .any(|user_id| user_id.to_lowercase() == bot_id)
+    });
+
+    // pills link to https://matrix.to/#/@user:server, sometimes with the @ percent-encoded
+    let encoded_id = bot_id.replacen('@', "%40", 1);
+    let mentions_id = |text: &Option<String>| {
+        text.as_ref().is_some_and(|text| {
+            let text = text.to_lowercase();
+            text.contains(bot_id) || text.contains(&encoded_id)
+        })
+    };
+
+    is_listed || mentions_id(&content.body) || mentions_id(&content.formatted_body)
+}
+
+// returns the chain key of a room, see chat_key
+fn chat_id(links: &HashMap<String, i64>, room_id: &str) -> i64 {
+    links
+        .get(room_id)
+        .copied()
+        .unwrap_or_else(|| chat_key::matrix(room_id))
+}
+
+// joins rooms the bot was invited to, unless they're not allowed
+async fn handle_invite(api: Arc<Api>, links: Arc<HashMap<String, i64>>, room_id: String) {
+    let res = if chain_wrapper::is_chat_allowed(chat_id(&links, &room_id)) {
+        api.join(&room_id).await
+    } else {
+        api.leave(&room_id).await
+    };
+
+    if let Err(err) = res {
+        dbg!(err);
+    }
+}
+
+// learns a message or runs the command it contains, replying if needed
+async fn handle_message(
+    chain: Arc<Mutex<ChainWrapper>>,
+    api: Arc<Api>,
+    links: Arc<HashMap<String, i64>>,
+    bot_id: Arc<String>,
+    room_id: String,
+    event: Event,
+) {
+    // notices are sent by bots (including this one), answering them could go on forever
+    // user IDs are compared lowercase, like the bot's own one
+    if event.content.msgtype.as_deref() != Some("m.text") || event.sender.to_lowercase() == *bot_id
+    {
+        return;
+    }
+
+    let is_mention = is_mention(&event.content, &bot_id);
+    let body = match event.content.body {
+        Some(body) => body,
+        None => return,
+    };
+
+    // Matrix user and event IDs are strings, hashing them is enough to tell them apart
+    let incoming = batch::Incoming {
+        user_id: chat_key::matrix(&event.sender),
+        username: event.sender,
+        message_id: chat_key::matrix(&event.event_id),
+        is_forwarded: false,
+        is_edit: false,
+        text: body,
+    };
+
+    let chat_id = chat_id(&links, &room_id);
+    if let Some(msg) = platform::respond(&chain, chat_id, incoming, is_mention).await {
+        api.send(&room_id, &msg).await;
+    }
+}
+
+// the Matrix bot, enabled by setting MATRIX_HOMESERVER and MATRIX_ACCESS_TOKEN
+pub struct Matrix {
+    api: Arc<Api>,
+    links: Arc<HashMap<String, i64>>,
+}
+
+impl Matrix {
+    // extracts the configuration of the Matrix bot from std::env
+    // returns None if MATRIX_HOMESERVER or MATRIX_ACCESS_TOKEN is not set
+    pub fn from_env() -> Option<Matrix> {
+        let homeserver = env::var("MATRIX_HOMESERVER")
+            .ok()
+            .filter(|url| !url.trim().is_empty())?;
+        let token = env::var("MATRIX_ACCESS_TOKEN")
+            .ok()
+            .filter(|token| !token.trim().is_empty())?;
+
+        let homeserver = Url::parse(&homeserver).expect("MATRIX_HOMESERVER is not a valid URL");
+        let api = Api {
+            client: Client::new(),
+            homeserver,
+            token,
+        };
+
+        Some(Matrix {
+            api: Arc::new(api),
+            links: Arc::new(platform::get_links("MATRIX_LINKS")),
+        })
+    }
+}
+
+impl Platform for Matrix {
+    fn name(&self) -> &'static str {
+        "Matrix"
+    }
+
+    fn run(self: Box<Self>, chain: Arc<Mutex<ChainWrapper>>) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move {
+            // Matrix is optional, so that failures are retried instead of stopping the bot
+            // only a rejected access token stops the Matrix bot, main keeps running without it
+            let mut failures: u32 = 0;
+            let bot_id = loop {
+                match self.api.whoami().await {
+                    Ok(user_id) => break Arc::new(user_id.to_lowercase()),
+                    Err(err) if err.status() == Some(StatusCode::UNAUTHORIZED) => {
+                        alerts::report(&format!("Matrix rejected the access token: {}", err));
+                        dbg!(err);
+                        return;
+                    }
+                    Err(err) => {
+                        if failures == 0 {
+                            alerts::report(&format!(
+                                "Failed to connect to Matrix, retrying: {}",
+                                err
+                            ));
+                        }
+                        dbg!(err);
+                        tokio::time::delay_for(platform::retry_delay(failures)).await;
+                        failures += 1;
+                    }
+                }
+            };
+
+            if failures > 0 {
+                alerts::report(&format!(
+                    "Connected to Matrix after {} failed attempts",
+                    failures
+                ));
+                failures = 0;
+            }
+
+            let mut since: Option<String> = None;
+            loop {
+                let sync = match self.api.sync(since.as_deref()).await {
+                    Ok(sync) => {
+                        if failures > 0 {
+                            alerts::report(&format!(
+                                "Matrix sync recovered after {} failed attempts",
+                                failures
+                            ));
+                            failures = 0;
+                        }
+                        sync
+                    }
+                    // the access token was revoked, there's no point in retrying
+                    Err(err) if err.status() == Some(StatusCode::UNAUTHORIZED) => {
+                        alerts::report(&format!("Matrix sync stopped: {}", err));
+                        dbg!(err);
+                        return;
+                    }
+                    // only the first failure in a row is reported, so that an outage doesn't flood the admin chat
+                    Err(err) => {
+                        if failures == 0 {
+                            alerts::report(&format!("Matrix sync failed, retrying: {}", err));
+                        }
+                        dbg!(err);
+                        tokio::time::delay_for(platform::retry_delay(failures)).await;
+                        failures += 1;
+                        continue;
+                    }
+                };
+
+                for room_id in sync.rooms.invite.keys() {
+                    let api = Arc::clone(&self.api);
+                    let links = Arc::clone(&self.links);
+                    tokio::spawn(handle_invite(api, links, room_id.clone()));
+                }
+
+                // the first sync only marks where the bot starts, older messages are skipped
+                if since.is_some() {
+                    for (room_id, room) in sync.rooms.join {
+                        for event in room.timeline.events {
+                            if event.kind != "m.room.message" {
+                                continue;
+                            }
+
+                            tokio::spawn(handle_message(
+                                chain.clone(),
+                                Arc::clone(&self.api),
+                                Arc::clone(&self.links),
+                                Arc::clone(&bot_id),
+                                room_id.clone(),
+                                event,
+                            ));
+                        }
+                    }
+                }
+
+                since = Some(sync.next_batch);
+            }
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use markov_bot_core::alerts;
+use markov_bot_core::chain_wrapper::ChainWrapper;
+use markov_bot_core::utils::exponential_retry_async;
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tbot::{connectors::Connector, types::chat::Id, Bot};
+use tokio::sync::Mutex;
+
+// the time between messages sent by a broadcast
+// Telegram allows about 30 messages per second in total, this leaves room for regular replies
+const BROADCAST_DELAY: Duration = Duration::from_millis(100);
+
+// the time between messages sent to the admin chat
+// errors reported in the meantime are sent together
+const ALERT_INTERVAL: Duration = Duration::from_secs(30);
+
+// returns the number of minutes since the Unix epoch
+fn epoch_minute() -> i64 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    (secs / 60) as i64
+}
+
+// spawns a task that posts "phrases of the day" at the times chosen by chats
+// every minute since the previous check is checked, so that late ticks don't skip phrases
+pub fn spawn_daily<C: Connector>(chain: Arc<Mutex<ChainWrapper>>, bot: Bot<C>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    tokio::spawn(async move {
+        let mut last = epoch_minute();
+
+        loop {
+            interval.tick().await;
+
+            let now = epoch_minute();
+            for minute in (last + 1)..=now {
+                let phrases = ChainWrapper::due_daily(&chain, minute).await;
+
+                for (chat_id, phrase) in phrases {
+                    let call_result = exponential_retry_async(|| async {
+                        Ok(bot.send_message(Id(chat_id), &phrase).call().await?)
+                    })
+                    .await;
+
+                    if let Err(err) = call_result {
+                        dbg!(err);
+                    }
+                }
+            }
+            last = now.max(last);
+        }
+    });
+}
+
+// spawns a task that sends a message to every given chat, one at a time
+// a summary is sent to the chat the broadcast was requested from once it's done
+pub fn spawn_broadcast<C: Connector>(bot: Bot<C>, chats: Vec<i64>, text: String, origin: Id) {
+    tokio::spawn(async move {
+        let mut failed = 0;
+
+        for chat_id in chats.iter() {
+            tokio::time::delay_for(BROADCAST_DELAY).await;
+
+            let call_result = exponential_retry_async(|| async {
+                Ok(bot.send_message(Id(*chat_id), &text).call().await?)
+            })
+            .await;
+
+            // the bot might have been removed from the chat, keep going
+            if let Err(err) = call_result {
+                failed += 1;
+                dbg!(err);
+            }
+        }
+
+        let msg = format!(
+            "[broadcast sent to {} of {} chats]",
+            chats.len() - failed,
+            chats.len()
+        );
+
+        let call_result =
+            exponential_retry_async(|| async { Ok(bot.send_message(origin, &msg).call().await?) })
+                .await;
+
+        if let Err(err) = call_result {
+            dbg!(err);
+        }
+    });
+}
+
+// spawns a task that periodically sends the errors queued with alerts::report to the admin chat
+// does nothing if ADMIN_CHAT_ID is not set
+pub fn spawn_alerts<C: Connector>(bot: Bot<C>) {
+    let chat_id = match alerts::admin_chat_id() {
+        Some(chat_id) => chat_id,
+        None => return,
+    };
+
+    let mut interval = tokio::time::interval(ALERT_INTERVAL);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let (errors, dropped) = alerts::take();
+            if errors.is_empty() {
+                continue;
+            }
+
+            let mut msg = format!("[errors]\n{}", errors.join("\n"));
+            if dropped > 0 {
+                msg.push_str(&format!("\n(and {} more)", dropped));
+            }
+
+            let call_result = exponential_retry_async(|| async {
+                Ok(bot.send_message(Id(chat_id), &msg).call().await?)
+            })
+            .await;
+
+            if let Err(err) = call_result {
+                dbg!(err);
+            }
+        }
+    });
+}
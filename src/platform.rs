@@ -0,0 +1,170 @@
+use crate::{bot, webhook};
+use markov_bot_core::batch;
+use markov_bot_core::chain_wrapper::{self, ChainWrapper};
+use markov_bot_core::chat_key;
+
+use std::{collections::HashMap, env, hash::Hash, str::FromStr, sync::Arc, time::Duration};
+
+use futures::future::LocalBoxFuture;
+use tokio::sync::{mpsc, Mutex};
+
+// the time an optional platform waits before reconnecting after a failure, doubled with every failure in a row
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// the longest an optional platform waits before reconnecting
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+// platforms without built-in bot commands treat messages starting with this character as such
+const COMMAND_PREFIX: char = '!';
+
+// the commands understood on platforms without built-in bot commands
+const HELP: &str = "[available commands]\n\
+!speak [word] - generate a phrase\n\
+!speak_about <word> - generate a phrase containing a word\n\
+!speak_ending <word> - generate a phrase ending with a word\n\
+!speak_global [word] - generate a phrase from the chain shared by many chats\n\
+!story [number] - generate a few sentences\n\
+!stats - describe the chain\n\
+mention the bot to get a reply";
+
+// a chat platform the bot talks on
+// all platforms share one ChainWrapper, each of them maps its chats to chain keys (see chat_key)
+pub trait Platform {
+    // the name of the platform, used in logs
+    fn name(&self) -> &'static str;
+
+    // receives messages until an unrecoverable error occurs, feeding them into the chains
+    // only Telegram stopping stops the bot, the other platforms reconnect after failures on their own
+    // the future is only ever polled by main, so it doesn't have to be Send
+    fn run(self: Box<Self>, chain: Arc<Mutex<ChainWrapper>>) -> LocalBoxFuture<'static, ()>;
+}
+
+// the Telegram bot, see bot::create
+pub struct Telegram {
+    // /shutdown sends a message through this channel
+    shutdown: mpsc::Sender<()>,
+}
+
+impl Telegram {
+    pub fn new(shutdown: mpsc::Sender<()>) -> Telegram {
+        Telegram { shutdown }
+    }
+}
+
+impl Platform for Telegram {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    fn run(self: Box<Self>, chain: Arc<Mutex<ChainWrapper>>) -> LocalBoxFuture<'static, ()> {
+        let bot = bot::create(chain, self.shutdown);
+        Box::pin(webhook::receive_updates(bot))
+    }
+}
+
+// the time an optional platform waits before reconnecting after the given number of failures in a row
+pub fn retry_delay(failures: u32) -> Duration {
+    RETRY_DELAY
+        .checked_mul(2u32.saturating_pow(failures))
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+// extracts a comma-separated list of room=chat pairs from std::env
+// a linked room shares the chain of the given chat (e.g. a Telegram group) instead of having its own
+pub fn get_links<K: FromStr + Eq + Hash>(var: &str) -> HashMap<K, i64> {
+    let links = env::var(var).unwrap_or_default();
+
+    links
+        .split(',')
+        .map(|link| link.trim())
+        .filter(|link| !link.is_empty())
+        .map(|link| {
+            let mut parts = link.splitn(2, '=');
+            let room = parts.next().unwrap_or_default().trim().parse::<K>();
+            let chat = parts.next().unwrap_or_default().trim().parse::<i64>();
+            match (room, chat) {
+                (Ok(room), Ok(chat)) => (room, chat),
+                _ => panic!("{} is not a list of room=chat pairs", var),
+            }
+        })
+        .collect()
+}
+
+// runs a command (given without COMMAND_PREFIX), returns None for commands meant for other bots
+async fn command(
+    chain: &Mutex<ChainWrapper>,
+    chat_id: i64,
+    user_id: i64,
+    line: &str,
+) -> Option<String> {
+    let mut parts = line.splitn(2, ' ');
+    let command = parts.next().unwrap_or_default();
+    let args = parts.next().unwrap_or_default().trim();
+
+    let chat_ids = if command == "speak_global" {
+        vec![chat_id, chat_key::GLOBAL]
+    } else {
+        vec![chat_id]
+    };
+    let mut chain = ChainWrapper::lock_loaded(chain, &chat_ids).await;
+
+    let is_speech = matches!(
+        command,
+        "speak" | "speak_about" | "speak_ending" | "speak_global" | "story"
+    );
+    if is_speech {
+        if let Some(msg) = chain.check_cooldown(chat_id, user_id) {
+            return Some(msg);
+        }
+    }
+
+    let msg = match command {
+        "speak" => chain.generate(chat_id, args, None),
+        "speak_about" => chain.speak_about(chat_id, args),
+        "speak_ending" => chain.speak_ending(chat_id, args),
+        "speak_global" => chain.speak_global(args),
+        "story" => match args {
+            "" => chain.story(chat_id, None),
+            args => match args.parse::<usize>() {
+                Ok(sentences) => chain.story(chat_id, Some(sentences)),
+                Err(_) => Ok(String::from("[usage: !story [number]]")),
+            },
+        },
+        "stats" => chain.stats(chat_id),
+        "help" => Ok(String::from(HELP)),
+        _ => return None,
+    };
+
+    Some(msg.unwrap_or_else(bot::failed))
+}
+
+// handles a message sent on a platform without built-in bot commands
+// commands are run, other messages are learnt and answered if they mention the bot
+// returns the reply to send, if any
+pub async fn respond(
+    chain: &Mutex<ChainWrapper>,
+    chat_id: i64,
+    incoming: batch::Incoming,
+    is_mention: bool,
+) -> Option<String> {
+    if !chain_wrapper::is_chat_allowed(chat_id) {
+        return None;
+    }
+
+    let text = incoming.text.trim();
+    if text.starts_with(COMMAND_PREFIX) {
+        return command(chain, chat_id, incoming.user_id, &text[1..]).await;
+    }
+
+    let text = incoming.text.clone();
+    batch::feed(chain, chat_id, incoming).await;
+
+    // answer people talking to the bot
+    if is_mention {
+        ChainWrapper::lock_loaded(chain, &[chat_id])
+            .await
+            .reply(chat_id, &text)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,113 @@
+use markov_bot_core::utils::exponential_retry_async;
+
+use std::{env, time::Duration};
+
+use lazy_static::lazy_static;
+use reqwest::{
+    multipart::{Form, Part},
+    Client, Url,
+};
+use serde::Deserialize;
+
+// the longest a single transcription may take before it's given up on
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// the speech-to-text configuration taken from std::env
+struct Config {
+    // the transcription endpoint, e.g. http://localhost:8000/v1/audio/transcriptions
+    url: Url,
+    // the token sent as a bearer token, if the endpoint needs one
+    token: Option<String>,
+    // the model the endpoint is asked to use
+    model: String,
+    // voice messages longer than this many seconds are not transcribed
+    max_duration: u64,
+}
+
+// the part of the endpoint's response the bot cares about
+#[derive(Deserialize)]
+struct Transcription {
+    text: String,
+}
+
+lazy_static! {
+    static ref CONFIG: Option<Config> = get_config();
+    static ref CLIENT: Client = Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("Failed to create the STT client");
+}
+
+// extracts the speech-to-text configuration from std::env
+// returns None (meaning voice messages are ignored) if STT_URL is not set
+fn get_config() -> Option<Config> {
+    let url = env::var("STT_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())?;
+    let url = Url::parse(&url).expect("STT_URL is not a valid URL");
+
+    let token = env::var("STT_TOKEN")
+        .ok()
+        .filter(|token| !token.trim().is_empty());
+
+    let model = env::var("STT_MODEL").unwrap_or_else(|_| String::from("whisper-1"));
+
+    let max_duration = match env::var("STT_MAX_DURATION") {
+        Err(_) => 120,
+        Ok(seconds) => seconds
+            .parse::<u64>()
+            .expect("STT_MAX_DURATION is not a number"),
+    };
+
+    Some(Config {
+        url,
+        token,
+        model,
+        max_duration,
+    })
+}
+
+// checks if a voice message of the given length (in seconds) should be transcribed
+pub fn accepts(duration: u64) -> bool {
+    match CONFIG.as_ref() {
+        Some(config) => duration <= config.max_duration,
+        None => false,
+    }
+}
+
+// sends a voice message (Ogg/Opus, as Telegram stores them) to the endpoint and returns its transcript
+// the endpoint is expected to speak the OpenAI transcription API, like most Whisper servers do
+pub async fn transcribe(audio: Vec<u8>) -> Result<String, String> {
+    let config = match CONFIG.as_ref() {
+        Some(config) => config,
+        None => return Err(String::from("STT_URL is not set")),
+    };
+
+    let call_result = exponential_retry_async(|| async {
+        // forms can't be cloned, so every attempt builds its own
+        let file = Part::bytes(audio.clone())
+            .file_name("voice.ogg")
+            .mime_str("audio/ogg")?;
+        let form = Form::new()
+            .part("file", file)
+            .text("model", config.model.clone())
+            .text("response_format", "json");
+
+        let mut request = CLIENT.post(config.url.clone()).multipart(form);
+        if let Some(token) = &config.token {
+            request = request.bearer_auth(token);
+        }
+
+        Ok(request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Transcription>()
+            .await?)
+    })
+    .await;
+
+    call_result
+        .map(|transcription| transcription.text.trim().to_string())
+        .map_err(|e| e.to_string())
+}
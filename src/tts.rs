@@ -0,0 +1,98 @@
+use markov_bot_core::utils::exponential_retry_async;
+
+use std::{env, time::Duration};
+
+use lazy_static::lazy_static;
+use reqwest::{Client, Url};
+use serde::Serialize;
+
+// the longest a single synthesis may take before it's given up on
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// the text-to-speech configuration taken from std::env
+struct Config {
+    // the synthesis endpoint, e.g. http://localhost:8000/v1/audio/speech
+    url: Url,
+    // the token sent as a bearer token, if the endpoint needs one
+    token: Option<String>,
+    // the model the endpoint is asked to use
+    model: String,
+    // the voice the phrases are read in
+    voice: String,
+}
+
+// the request body of the OpenAI speech API
+#[derive(Serialize)]
+struct Speech<'a> {
+    model: &'a str,
+    input: &'a str,
+    voice: &'a str,
+    // Telegram only shows Ogg/Opus files as voice notes
+    response_format: &'a str,
+}
+
+lazy_static! {
+    static ref CONFIG: Option<Config> = get_config();
+    static ref CLIENT: Client = Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("Failed to create the TTS client");
+}
+
+// extracts the text-to-speech configuration from std::env
+// returns None (meaning /speak_voice is unavailable) if TTS_URL is not set
+fn get_config() -> Option<Config> {
+    let url = env::var("TTS_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())?;
+    let url = Url::parse(&url).expect("TTS_URL is not a valid URL");
+
+    let token = env::var("TTS_TOKEN")
+        .ok()
+        .filter(|token| !token.trim().is_empty());
+
+    let model = env::var("TTS_MODEL").unwrap_or_else(|_| String::from("tts-1"));
+    let voice = env::var("TTS_VOICE").unwrap_or_else(|_| String::from("alloy"));
+
+    Some(Config {
+        url,
+        token,
+        model,
+        voice,
+    })
+}
+
+// checks if TTS_URL is set
+pub fn is_enabled() -> bool {
+    CONFIG.is_some()
+}
+
+// reads a phrase out loud using the endpoint and returns the recording (Ogg/Opus)
+// the endpoint is expected to speak the OpenAI speech API, like most self-hosted TTS servers do
+pub async fn synthesize(text: &str) -> Result<Vec<u8>, String> {
+    let config = match CONFIG.as_ref() {
+        Some(config) => config,
+        None => return Err(String::from("TTS_URL is not set")),
+    };
+
+    let body = Speech {
+        model: &config.model,
+        input: text,
+        voice: &config.voice,
+        response_format: "opus",
+    };
+
+    let call_result = exponential_retry_async(|| async {
+        let mut request = CLIENT.post(config.url.clone()).json(&body);
+        if let Some(token) = &config.token {
+            request = request.bearer_auth(token);
+        }
+
+        Ok(request.send().await?.error_for_status()?.bytes().await?)
+    })
+    .await;
+
+    call_result
+        .map(|bytes| bytes.to_vec())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,108 @@
+use crate::health;
+use markov_bot_core::alerts;
+
+use std::{env, fs};
+
+use tbot::{
+    connectors::Connector,
+    event_loop::{webhook::https::Identity, EventLoop},
+};
+
+// the webhook configuration taken from std::env
+struct Config {
+    // the public HTTPS URL Telegram sends updates to
+    url: String,
+    // the local port the server listens on
+    port: u16,
+    // the contents of a self-signed certificate (PEM), shared with Telegram
+    certificate: Option<String>,
+    // a PKCS #12 archive with the server's certificate and private key, along with its password
+    // the server speaks plain HTTP without one (e.g. behind a TLS-terminating proxy)
+    identity: Option<(Vec<u8>, String)>,
+}
+
+// reads a file named by an environment variable, if it's set
+fn read_file_from_env(var: &str) -> Option<Vec<u8>> {
+    env::var(var)
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+        .map(|path| fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e)))
+}
+
+// extracts the webhook configuration from std::env
+// returns None (meaning long polling) if WEBHOOK_URL is not set
+fn get_config() -> Option<Config> {
+    let url = env::var("WEBHOOK_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())?;
+
+    let port = match env::var("WEBHOOK_PORT") {
+        Err(_) => 8443,
+        Ok(port) => port.parse::<u16>().expect("WEBHOOK_PORT is not a number"),
+    };
+
+    let certificate = read_file_from_env("WEBHOOK_CERT")
+        .map(|pem| String::from_utf8(pem).expect("WEBHOOK_CERT is not a PEM file"));
+
+    let identity = read_file_from_env("WEBHOOK_IDENTITY").map(|archive| {
+        let password = env::var("WEBHOOK_IDENTITY_PASSWORD").unwrap_or_default();
+        (archive, password)
+    });
+
+    Some(Config {
+        url,
+        port,
+        certificate,
+        identity,
+    })
+}
+
+// receives updates until an unrecoverable error occurs
+// uses a webhook if one is configured, long polling otherwise
+pub async fn receive_updates<C: Connector>(bot: EventLoop<C>) {
+    health::set_receiving(true);
+
+    let config = match get_config() {
+        Some(config) => config,
+        None => {
+            // receiving_updates is set again by the next update that gets through
+            let polling = bot.polling().error_handler(|err| async move {
+                health::set_receiving(false);
+                alerts::report(&format!("Polling failed, retrying: {:?}", err));
+            });
+
+            // polling only ever stops on an error
+            let Err(err) = polling.start().await;
+            alerts::report(&format!("Polling stopped: {:?}", err));
+            dbg!(err);
+            health::set_receiving(false);
+            return;
+        }
+    };
+
+    // tbot only accepts updates sent to /, a path in WEBHOOK_URL has to be stripped by a proxy
+    let mut webhook = bot.webhook(&config.url, config.port);
+
+    if let Some(certificate) = &config.certificate {
+        webhook = webhook.certificate(certificate);
+    }
+
+    let res = match config.identity {
+        Some((archive, password)) => {
+            let identity =
+                Identity::from_pkcs12(&archive, &password).expect("Invalid WEBHOOK_IDENTITY");
+            webhook
+                .https(identity)
+                .start()
+                .await
+                .map_err(|e| e.to_string())
+        }
+        None => webhook.http().start().await.map_err(|e| e.to_string()),
+    };
+
+    // the server only ever stops on an error
+    let Err(err) = res;
+    alerts::report(&format!("Webhook server stopped: {}", err));
+    dbg!(err);
+    health::set_receiving(false);
+}